@@ -17,6 +17,8 @@
     Copyright (c) 2019-2022, The Eruption Development Team
 */
 
+use std::fmt;
+use std::str::FromStr;
 use std::sync::Arc;
 
 pub use backends::{AudioBackend, PulseAudioBackend};
@@ -44,23 +46,109 @@ pub enum AudioError {
 
     #[error("Audio player error: {description}")]
     PlayerError { description: String },
+
+    #[error("Invalid capture source: {description}")]
+    InvalidCaptureSource { description: String },
+}
+
+/// The device or application that audio samples are recorded from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureSource {
+    /// The monitor of the default sink, i.e. everything that is played back
+    DefaultMonitor,
+
+    /// The default source, e.g. a microphone
+    DefaultSource,
+
+    /// The monitor of the sink with the given name
+    Monitor(String),
+
+    /// The source with the given name
+    Source(String),
+
+    /// The playback stream (sink-input) of the application with the given name
+    Application(String),
+}
+
+impl Default for CaptureSource {
+    fn default() -> Self {
+        CaptureSource::DefaultMonitor
+    }
+}
+
+impl FromStr for CaptureSource {
+    type Err = AudioError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+
+        let named = |name: &str, f: fn(String) -> CaptureSource| {
+            if name.trim().is_empty() {
+                Err(AudioError::InvalidCaptureSource {
+                    description: format!("Missing name in '{}'", s),
+                })
+            } else {
+                Ok(f(name.trim().to_string()))
+            }
+        };
+
+        match s.split_once(':') {
+            None if s.is_empty() || s == "default-monitor" => Ok(CaptureSource::DefaultMonitor),
+            None if s == "default-source" => Ok(CaptureSource::DefaultSource),
+
+            Some(("monitor", name)) => named(name, CaptureSource::Monitor),
+            Some(("source", name)) => named(name, CaptureSource::Source),
+            Some(("application", name)) => named(name, CaptureSource::Application),
+
+            _ => Err(AudioError::InvalidCaptureSource {
+                description: format!(
+                    "'{}', expected one of: default-monitor, default-source, monitor:<sink>, source:<source>, application:<name>",
+                    s
+                ),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for CaptureSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureSource::DefaultMonitor => write!(f, "default-monitor"),
+            CaptureSource::DefaultSource => write!(f, "default-source"),
+            CaptureSource::Monitor(name) => write!(f, "monitor:{}", name),
+            CaptureSource::Source(name) => write!(f, "source:{}", name),
+            CaptureSource::Application(name) => write!(f, "application:{}", name),
+        }
+    }
 }
 
 mod backends {
+    use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::Arc;
-
-    use libpulse_binding::{sample, stream::Direction};
+    use std::thread;
+    use std::time::Duration;
+
+    use flume::{Receiver, RecvTimeoutError, Sender, TrySendError};
+    use libpulse_binding::context::{self, Context};
+    use libpulse_binding::error::PAErr;
+    use libpulse_binding::mainloop::threaded::Mainloop;
+    use libpulse_binding::sample;
+    use libpulse_binding::stream::{self, Direction, PeekResult, Stream};
     use libpulse_simple_binding::Simple;
-    use parking_lot::RwLock;
-    use pulsectl::controllers::{DeviceControl, SinkController};
+    use log::*;
+    use parking_lot::{Mutex, RwLock};
+    use pulsectl::controllers::{AppControl, DeviceControl, SinkController, SourceController};
     use std::cell::RefCell;
+    use std::rc::Rc;
 
-    use crate::audio::AudioError;
+    use crate::audio::{AudioError, CaptureSource};
+    use crate::constants;
 
     use super::Result;
 
     thread_local! {
         pub static SINK_CONTROLLER: RefCell<SinkController> = RefCell::new(SinkController::create());
+        pub static SOURCE_CONTROLLER: RefCell<SourceController> = RefCell::new(SourceController::create());
     }
 
     pub trait AudioBackend {
@@ -73,12 +161,13 @@ mod backends {
         fn close_playback(&mut self) -> Result<()>;
         fn close(&mut self) -> Result<()>;
 
+        fn capture_source(&self) -> &CaptureSource;
+        fn set_capture_source(&mut self, source: CaptureSource) -> Result<()>;
+
         fn get_audio_volume(&self) -> Result<i32>;
         fn set_audio_volume(&mut self, vol: i32) -> Result<()>;
         fn is_audio_muted(&self) -> Result<bool>;
 
-        fn play_sfx(&self, id: u32) -> Result<()>;
-
         fn play_samples(&self, data: &[u8]) -> Result<()>;
         fn record_samples(&self) -> Result<()>;
     }
@@ -88,16 +177,13 @@ mod backends {
         pub player_handle: Arc<RwLock<Option<Simple>>>,
         pub is_playback_open: bool,
         pub is_recorder_open: bool,
+        capture_source: CaptureSource,
+        application_recorder: Option<ApplicationRecorder>,
     }
 
     impl Default for PulseAudioBackend {
         fn default() -> Self {
-            Self {
-                recorder_handle: Arc::new(RwLock::new(None)),
-                player_handle: Arc::new(RwLock::new(None)),
-                is_playback_open: false,
-                is_recorder_open: false,
-            }
+            Self::new()
         }
     }
 
@@ -108,8 +194,338 @@ mod backends {
                 player_handle: Arc::new(RwLock::new(None)),
                 is_playback_open: false,
                 is_recorder_open: false,
+                capture_source: CaptureSource::default(),
+                application_recorder: None,
+            }
+        }
+
+        /// Query the volume and the muted state of the device or application that we
+        /// are currently recording from
+        fn device_state(&self) -> Result<(i32, bool)> {
+            let query_error = |_e| AudioError::ConnectionError {
+                description: "Could not query PulseAudio/PipeWire".to_owned(),
+            };
+
+            let (volume, muted) = match &self.capture_source {
+                CaptureSource::DefaultMonitor => SINK_CONTROLLER.with(|handler| {
+                    let device = handler
+                        .borrow_mut()
+                        .get_default_device()
+                        .map_err(query_error)?;
+
+                    Ok::<_, AudioError>((device.volume.avg().0, device.mute))
+                })?,
+
+                CaptureSource::Monitor(name) => SINK_CONTROLLER.with(|handler| {
+                    let device = handler
+                        .borrow_mut()
+                        .get_device_by_name(name)
+                        .map_err(query_error)?;
+
+                    Ok::<_, AudioError>((device.volume.avg().0, device.mute))
+                })?,
+
+                CaptureSource::DefaultSource => SOURCE_CONTROLLER.with(|handler| {
+                    let device = handler
+                        .borrow_mut()
+                        .get_default_device()
+                        .map_err(query_error)?;
+
+                    Ok::<_, AudioError>((device.volume.avg().0, device.mute))
+                })?,
+
+                CaptureSource::Source(name) => SOURCE_CONTROLLER.with(|handler| {
+                    let device = handler
+                        .borrow_mut()
+                        .get_device_by_name(name)
+                        .map_err(query_error)?;
+
+                    Ok::<_, AudioError>((device.volume.avg().0, device.mute))
+                })?,
+
+                CaptureSource::Application(name) => {
+                    let application =
+                        find_application(name)?.ok_or_else(|| AudioError::ConnectionError {
+                            description: format!("Application '{}' is not playing audio", name),
+                        })?;
+
+                    (application.volume, application.mute)
+                }
+            };
+
+            Ok((volume as i32, muted))
+        }
+
+        fn open_simple_recorder(&mut self, device: &str) -> Result<()> {
+            let spec = sample::Spec {
+                format: sample::Format::S16NE,
+                channels: 2,
+                rate: 44100,
+            };
+
+            assert!(spec.is_valid());
+
+            let result = Simple::new(
+                None,
+                "Eruption",
+                Direction::Record,
+                Some(device),
+                "Audio Grabber",
+                &spec,
+                None,
+                None,
+            )
+            .map_err(|e| AudioError::ConnectionError {
+                description: format!("Could not open PulseAudio/PipeWire recording device: {}", e),
+            })?;
+
+            *self.recorder_handle.write() = Some(result);
+
+            Ok(())
+        }
+    }
+
+    /// A running application that plays back audio
+    struct Application {
+        sink_input: u32,
+        volume: u32,
+        mute: bool,
+    }
+
+    /// Find the playback stream (sink-input) of an application, by its name or by the
+    /// name of its executable
+    fn find_application(name: &str) -> Result<Option<Application>> {
+        SINK_CONTROLLER.with(|handler| {
+            let applications = handler.borrow_mut().list_applications().map_err(|_e| {
+                AudioError::ConnectionError {
+                    description: "Could not query PulseAudio/PipeWire".to_owned(),
+                }
+            })?;
+
+            let result = applications
+                .into_iter()
+                .find(|app| {
+                    [
+                        app.proplist.get_str("application.name"),
+                        app.proplist.get_str("application.process.binary"),
+                        app.name.clone(),
+                    ]
+                    .iter()
+                    .flatten()
+                    .any(|n| n.eq_ignore_ascii_case(name))
+                })
+                .map(|app| Application {
+                    sink_input: app.index,
+                    volume: app.volume.avg().0,
+                    mute: app.mute,
+                });
+
+            Ok(result)
+        })
+    }
+
+    /// Records the samples of a single application. The PulseAudio "simple" API does not
+    /// support monitoring a sink-input, so we run a stream on a dedicated thread
+    struct ApplicationRecorder {
+        rx: Receiver<Vec<u8>>,
+        pending: Mutex<Vec<u8>>,
+        quit: Arc<AtomicBool>,
+    }
+
+    impl ApplicationRecorder {
+        fn new(sink_input: u32) -> Result<Self> {
+            let (tx, rx) = flume::bounded(constants::APPLICATION_STREAM_QUEUE_LEN);
+            let quit = Arc::new(AtomicBool::new(false));
+
+            let quit_flag = quit.clone();
+            thread::Builder::new()
+                .name("app-stream".to_owned())
+                .spawn(move || {
+                    run_application_stream(sink_input, &tx, &quit_flag).unwrap_or_else(|e| {
+                        error!("Could not record the samples of an application: {}", e)
+                    });
+                })?;
+
+            Ok(Self {
+                rx,
+                pending: Mutex::new(Vec::new()),
+                quit,
+            })
+        }
+
+        /// Fill `buf`, or record silence if the application does not play anything
+        fn read(&self, buf: &mut [u8]) -> Result<()> {
+            let mut pending = self.pending.lock();
+
+            while pending.len() < buf.len() {
+                match self.rx.recv_timeout(Duration::from_millis(
+                    constants::APPLICATION_STREAM_TIMEOUT_MILLIS,
+                )) {
+                    Ok(data) => pending.extend_from_slice(&data),
+
+                    Err(RecvTimeoutError::Timeout) => pending.resize(buf.len(), 0x00),
+
+                    Err(RecvTimeoutError::Disconnected) => {
+                        return Err(AudioError::GrabberError {
+                            description: "The application stream has been closed".to_owned(),
+                        }
+                        .into())
+                    }
+                }
+            }
+
+            buf.copy_from_slice(&pending[..buf.len()]);
+            pending.drain(..buf.len());
+
+            Ok(())
+        }
+    }
+
+    impl Drop for ApplicationRecorder {
+        fn drop(&mut self) {
+            self.quit.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn run_application_stream(
+        sink_input: u32,
+        tx: &Sender<Vec<u8>>,
+        quit: &AtomicBool,
+    ) -> Result<()> {
+        let mainloop = Rc::new(RefCell::new(Mainloop::new().ok_or_else(|| {
+            AudioError::ConnectionError {
+                description: "Could not create a main loop".to_owned(),
+            }
+        })?));
+
+        let context = Rc::new(RefCell::new(
+            Context::new(&*mainloop.borrow(), "Eruption").ok_or_else(|| {
+                AudioError::ConnectionError {
+                    description: "Could not create a context".to_owned(),
+                }
+            })?,
+        ));
+
+        {
+            let mainloop = mainloop.clone();
+            context
+                .borrow_mut()
+                .set_state_callback(Some(Box::new(move || unsafe {
+                    (*mainloop.as_ptr()).signal(false);
+                })));
+        }
+
+        context
+            .borrow_mut()
+            .connect(None, context::FlagSet::NOFLAGS, None)?;
+
+        mainloop.borrow_mut().lock();
+        mainloop.borrow_mut().start()?;
+
+        // the main loop has to be unlocked and stopped on all paths
+        let result = record_application_stream(&mainloop, &context, sink_input, tx, quit);
+
+        mainloop.borrow_mut().unlock();
+        mainloop.borrow_mut().stop();
+
+        result
+    }
+
+    /// Forward the samples of the sink-input to `tx`, the main loop has to be locked
+    fn record_application_stream(
+        mainloop: &Rc<RefCell<Mainloop>>,
+        context: &Rc<RefCell<Context>>,
+        sink_input: u32,
+        tx: &Sender<Vec<u8>>,
+        quit: &AtomicBool,
+    ) -> Result<()> {
+        let pulse_error = |e: PAErr| AudioError::ConnectionError {
+            description: format!("PulseAudio/PipeWire error: {}", e),
+        };
+
+        loop {
+            match context.borrow().get_state() {
+                context::State::Ready => break,
+
+                context::State::Failed | context::State::Terminated => {
+                    return Err(AudioError::ConnectionError {
+                        description: "Could not connect to PulseAudio/PipeWire".to_owned(),
+                    }
+                    .into());
+                }
+
+                _ => mainloop.borrow_mut().wait(),
+            }
+        }
+
+        let spec = sample::Spec {
+            format: sample::Format::S16NE,
+            channels: 2,
+            rate: 44100,
+        };
+
+        let mut stream = Stream::new(&mut context.borrow_mut(), "Audio Grabber", &spec, None)
+            .ok_or_else(|| AudioError::ConnectionError {
+                description: "Could not create a recording stream".to_owned(),
+            })?;
+
+        {
+            let mainloop = mainloop.clone();
+            stream.set_read_callback(Some(Box::new(move |_len| unsafe {
+                (*mainloop.as_ptr()).signal(false);
+            })));
+        }
+
+        {
+            let mainloop = mainloop.clone();
+            stream.set_state_callback(Some(Box::new(move || unsafe {
+                (*mainloop.as_ptr()).signal(false);
+            })));
+        }
+
+        stream.set_monitor_stream(sink_input).map_err(pulse_error)?;
+        stream
+            .connect_record(None, None, stream::FlagSet::NOFLAGS)
+            .map_err(pulse_error)?;
+
+        while !quit.load(Ordering::SeqCst) {
+            match stream.get_state() {
+                stream::State::Ready => {}
+
+                // the application has been closed
+                stream::State::Failed | stream::State::Terminated => break,
+
+                _ => {
+                    mainloop.borrow_mut().wait();
+                    continue;
+                }
+            }
+
+            let data = match stream.peek().map_err(pulse_error)? {
+                PeekResult::Empty => {
+                    mainloop.borrow_mut().wait();
+                    continue;
+                }
+
+                PeekResult::Hole(_) => None,
+                PeekResult::Data(data) => Some(data.to_vec()),
+            };
+
+            stream.discard().map_err(pulse_error)?;
+
+            if let Some(data) = data {
+                match tx.try_send(data) {
+                    // drop stale samples, if the recorder does not keep up
+                    Ok(()) | Err(TrySendError::Full(_)) => {}
+
+                    Err(TrySendError::Disconnected(_)) => break,
+                }
             }
         }
+
+        stream.disconnect().ok();
+
+        Ok(())
     }
 
     impl AudioBackend for PulseAudioBackend {
@@ -119,32 +535,33 @@ mod backends {
 
         fn open_recorder(&mut self) -> Result<()> {
             if !self.is_recorder_open {
-                let spec = sample::Spec {
-                    format: sample::Format::S16NE,
-                    channels: 2,
-                    rate: 44100,
-                };
-
-                assert!(spec.is_valid());
-
-                let result = Simple::new(
-                    None,
-                    "Eruption",
-                    Direction::Record,
-                    Some("@DEFAULT_MONITOR@"),
-                    "Audio Grabber",
-                    &spec,
-                    None,
-                    None,
-                )
-                .map_err(|e| AudioError::ConnectionError {
-                    description: format!(
-                        "Could not open PulseAudio/PipeWire recording device: {}",
-                        e
-                    ),
-                })?;
-
-                *self.recorder_handle.write() = Some(result);
+                match self.capture_source.clone() {
+                    CaptureSource::DefaultMonitor => {
+                        self.open_simple_recorder("@DEFAULT_MONITOR@")?
+                    }
+                    CaptureSource::DefaultSource => {
+                        self.open_simple_recorder("@DEFAULT_SOURCE@")?
+                    }
+                    CaptureSource::Monitor(sink) => {
+                        self.open_simple_recorder(&format!("{}.monitor", sink))?
+                    }
+                    CaptureSource::Source(source) => self.open_simple_recorder(&source)?,
+
+                    CaptureSource::Application(name) => match find_application(&name)? {
+                        Some(application) => {
+                            self.application_recorder =
+                                Some(ApplicationRecorder::new(application.sink_input)?);
+                        }
+
+                        None => {
+                            // try again later, the application may not have been started yet
+                            return Err(AudioError::ConnectionError {
+                                description: format!("Application '{}' is not playing audio", name),
+                            }
+                            .into());
+                        }
+                    },
+                }
 
                 let spec = sample::Spec {
                     format: sample::Format::S16NE,
@@ -227,6 +644,7 @@ mod backends {
         fn close_recorder(&mut self) -> Result<()> {
             if self.is_recorder_open {
                 *self.recorder_handle.write() = None;
+                self.application_recorder = None;
 
                 self.is_recorder_open = false;
             }
@@ -238,6 +656,7 @@ mod backends {
             if self.is_recorder_open || self.is_playback_open {
                 *self.recorder_handle.write() = None;
                 *self.player_handle.write() = None;
+                self.application_recorder = None;
 
                 self.is_recorder_open = false;
                 self.is_playback_open = false;
@@ -246,58 +665,36 @@ mod backends {
             Ok(())
         }
 
-        fn get_audio_volume(&self) -> Result<i32> {
-            SINK_CONTROLLER.with(|handler| {
-                let mut handler = handler.borrow_mut();
-
-                let result = handler
-                    .get_default_device()
-                    .map_err(|_e| AudioError::ConnectionError {
-                        description: "Could not query PulseAudio/PipeWire".to_owned(),
-                    })?
-                    .volume
-                    .avg()
-                    .0;
-
-                Ok(result as i32)
-            })
+        fn capture_source(&self) -> &CaptureSource {
+            &self.capture_source
         }
 
-        fn set_audio_volume(&mut self, _vol: i32) -> Result<()> {
-            todo!()
-        }
+        fn set_capture_source(&mut self, source: CaptureSource) -> Result<()> {
+            if source != self.capture_source {
+                info!("Recording audio from: {}", source);
 
-        fn is_audio_muted(&self) -> Result<bool> {
-            SINK_CONTROLLER.with(|handler| {
-                let mut handler = handler.borrow_mut();
+                self.capture_source = source;
 
-                let result = handler
-                    .get_default_device()
-                    .map_err(|_e| AudioError::ConnectionError {
-                        description: "Could not query PulseAudio/PipeWire".to_owned(),
-                    })?
-                    .mute;
+                // re-open the recorder, if we are recording already
+                if self.is_recorder_open {
+                    self.close_recorder()?;
+                    self.open_recorder()?;
+                }
+            }
 
-                Ok(result)
-            })
+            Ok(())
         }
 
-        fn play_sfx(&self, id: u32) -> Result<()> {
-            if let Some(player) = &*self.player_handle.read() {
-                let sfx_map = crate::SOUND_FX.read();
-                let data = &sfx_map[&id];
+        fn get_audio_volume(&self) -> Result<i32> {
+            Ok(self.device_state()?.0)
+        }
 
-                player.write(data).map_err(|e| AudioError::PlayerError {
-                    description: format!("Error during playback: {}", e),
-                })?;
+        fn set_audio_volume(&mut self, _vol: i32) -> Result<()> {
+            todo!()
+        }
 
-                Ok(())
-            } else {
-                Err(AudioError::PlayerError {
-                    description: "Audio subsystem is not available".to_string(),
-                }
-                .into())
-            }
+        fn is_audio_muted(&self) -> Result<bool> {
+            Ok(self.device_state()?.1)
         }
 
         fn play_samples(&self, data: &[u8]) -> Result<()> {
@@ -318,7 +715,9 @@ mod backends {
         fn record_samples(&self) -> Result<()> {
             let mut buf = super::AUDIO_BUFFER.write();
 
-            if let Some(grabber) = &*self.recorder_handle.read() {
+            if let Some(recorder) = &self.application_recorder {
+                recorder.read(&mut buf)
+            } else if let Some(grabber) = &*self.recorder_handle.read() {
                 grabber
                     .read(&mut buf)
                     .map_err(|e| AudioError::GrabberError {
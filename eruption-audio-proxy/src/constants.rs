@@ -17,6 +17,9 @@
     Copyright (c) 2019-2022, The Eruption Development Team
 */
 
+/// Default configuration file
+pub const AUDIO_PROXY_CONFIG_FILE: &str = "/etc/eruption/audio-proxy.conf";
+
 /// Eruption daemon audio data UNIX domain socket
 pub const AUDIO_SOCKET_NAME: &str = "/run/eruption/audio.sock";
 
@@ -24,7 +27,26 @@ pub const AUDIO_SOCKET_NAME: &str = "/run/eruption/audio.sock";
 pub const AUDIO_BUFFER_SIZE: usize = 4096 - 16;
 
 /// The capacity of the buffer used for sending audio samples/commands over a socket
-pub const NET_BUFFER_CAPACITY: usize = 4096;
+pub const NET_BUFFER_CAPACITY: usize = 8192;
+
+/// Version of the protocol spoken with the Eruption daemon
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Time to wait for the handshake of the Eruption daemon, in milliseconds
+pub const HANDSHAKE_TIMEOUT_MILLIS: u64 = 2000;
+
+/// The sample rate of the recorded audio data
+pub const AUDIO_SAMPLE_RATE: u32 = 44100;
+
+/// The number of interleaved channels of the recorded audio data
+pub const AUDIO_CHANNELS: u32 = 2;
+
+/// The number of bits per sample of the recorded audio data
+pub const AUDIO_BITS_PER_SAMPLE: u32 = 16;
+
+/// Max. number of audio frames that are queued while the Eruption daemon does not
+/// keep up with the stream, the oldest frames are dropped first
+pub const MAX_QUEUED_AUDIO_FRAMES: usize = 4;
 
 // /// Timeout of D-Bus operations
 // pub const DBUS_TIMEOUT_MILLIS: u64 = 5000;
@@ -38,3 +60,40 @@ pub const SLEEP_TIME_TIMEOUT: u64 = 2000;
 
 /// Main loop sleep time, when we are disconnected from Eruption
 pub const SLEEP_TIME_WHILE_DISCONNECTED: u64 = 1000;
+
+/// Main loop sleep time, after connecting to an incompatible version of Eruption
+pub const SLEEP_TIME_WHILE_INCOMPATIBLE: u64 = 10000;
+
+/// Max. number of chunks of samples that are queued while recording a single application
+pub const APPLICATION_STREAM_QUEUE_LEN: usize = 64;
+
+/// Time in milliseconds to wait for samples of a single application, before we record
+/// silence, e.g. while the application is paused
+pub const APPLICATION_STREAM_TIMEOUT_MILLIS: u64 = 50;
+
+/// System-wide directory that holds the sound effects packs
+pub const SFX_DIR: &str = "/usr/share/eruption/sfx";
+
+/// Directory that holds the sound effects packs of the user, relative to `$HOME`
+pub const USER_SFX_DIR: &str = ".local/share/eruption/sfx";
+
+/// Name of the manifest file of a sound effects pack
+pub const SFX_MANIFEST_FILE: &str = "manifest.toml";
+
+/// The sound effects pack that is used if neither the profile nor the configuration selects one
+pub const DEFAULT_SFX_PACK: &str = "default";
+
+/// Sample rate of the playback device
+pub const PLAYBACK_SAMPLE_RATE: u32 = 44100;
+
+/// Number of interleaved channels of the playback device
+pub const PLAYBACK_CHANNELS: usize = 2;
+
+/// Length in milliseconds of each chunk of mixed sound effects written to the playback device
+pub const MIXER_PERIOD_MILLIS: u64 = 10;
+
+/// Max. number of overlapping sound effects, the oldest ones are dropped first
+pub const MAX_MIXER_VOICES: usize = 16;
+
+/// Max. gain of a single sound effect
+pub const MAX_VOICE_GAIN: f32 = 4.0;
@@ -17,8 +17,7 @@
     Copyright (c) 2019-2022, The Eruption Development Team
 */
 
-use std::collections::HashMap;
-use std::io::Cursor;
+use std::collections::VecDeque;
 use std::mem::MaybeUninit;
 use std::os::unix::io::AsRawFd;
 use std::sync::atomic::AtomicI32;
@@ -31,6 +30,7 @@ use std::{env, thread};
 
 use clap::{IntoApp, Parser};
 use clap_complete::Shell;
+use config::Config;
 use flume::{unbounded, Receiver};
 use i18n_embed::{
     fluent::{fluent_language_loader, FluentLanguageLoader},
@@ -49,10 +49,11 @@ use tokio::io::{self};
 use protocol::Command;
 use protocol::CommandType;
 
-use crate::audio::AudioBackend;
+use crate::audio::{AudioBackend, CaptureSource};
 
 mod audio;
 mod constants;
+mod sfx;
 mod util;
 
 #[derive(RustEmbed)]
@@ -73,7 +74,7 @@ lazy_static! {
     pub static ref AUDIO_MUTED: AtomicBool = AtomicBool::new(false);
 
     /// A queue of packets that will be send to the Eruption daemon
-    pub static ref PACKET_TX_QUEUE: Arc<Mutex<Vec<Vec<u8>>>> = Arc::new(Mutex::new(Vec::new()));
+    pub static ref PACKET_TX_QUEUE: Arc<Mutex<VecDeque<Vec<u8>>>> = Arc::new(Mutex::new(VecDeque::new()));
 }
 
 #[allow(unused)]
@@ -99,17 +100,20 @@ pub mod protocol {
 
 type Result<T> = std::result::Result<T, eyre::Error>;
 
-type SoundFxMap = HashMap<u32, Vec<u8>>;
-
 lazy_static! {
     // /// Global command line options
     // pub static ref OPTIONS: Arc<Mutex<Option<Options>>> = Arc::new(Mutex::new(None));
 
     pub static ref AUDIO_BACKEND: Arc<Mutex<audio::PulseAudioBackend>> =  Arc::new(Mutex::new(audio::PulseAudioBackend::new()));
 
-    pub static ref SOUND_FX: Arc<RwLock<SoundFxMap>> = Arc::new(RwLock::new(SoundFxMap::new()));
+    /// The active sound effects pack
+    pub static ref SOUND_PACK: Arc<RwLock<Option<sfx::SoundPack>>> = Arc::new(RwLock::new(None));
+
+    /// The sound effects pack selected in the configuration file
+    pub static ref DEFAULT_SFX_PACK: Arc<RwLock<String>> = Arc::new(RwLock::new(constants::DEFAULT_SFX_PACK.to_string()));
 
-    pub static ref PENDING_SFX_ID: Arc<Mutex<Option<u32>>> = Arc::new(Mutex::new(None));
+    /// Mixes overlapping sound effects
+    pub static ref MIXER: Arc<Mutex<Option<sfx::Mixer>>> = Arc::new(Mutex::new(None));
 
     /// Global "quit" status flag
     pub static ref QUIT: AtomicBool = AtomicBool::new(false);
@@ -175,6 +179,105 @@ fn print_header() {
     println!();
 }
 
+/// Switch to the capture source requested by the Eruption daemon
+fn switch_capture_source(source: &str) {
+    match source.parse::<CaptureSource>() {
+        Ok(source) => AUDIO_BACKEND
+            .lock()
+            .set_capture_source(source)
+            .unwrap_or_else(|e| error!("Could not switch the capture source: {}", e)),
+
+        Err(e) => error!("{}", e),
+    }
+}
+
+/// Switch to the sound effects pack `id`, an empty id selects the pack from the configuration file
+fn switch_sfx_pack(id: &str) {
+    let id = if id.is_empty() {
+        DEFAULT_SFX_PACK.read().clone()
+    } else {
+        id.to_string()
+    };
+
+    if SOUND_PACK.read().as_ref().map(|pack| &pack.id) == Some(&id) {
+        return;
+    }
+
+    match sfx::SoundPack::find(&id) {
+        Ok(pack) => {
+            info!(
+                "Loaded sound effects pack: {} ({}): {}",
+                pack.name, pack.id, pack.description
+            );
+
+            *SOUND_PACK.write() = Some(pack);
+        }
+
+        Err(e) => error!("Could not load the sound effects pack: {}", e),
+    }
+}
+
+/// Play the sound of the active sound effects pack for the event or effect `name`
+fn play_sound(name: &str, params: sfx::VoiceParams) {
+    let sound = SOUND_PACK
+        .read()
+        .as_ref()
+        .and_then(|pack| pack.lookup(name));
+
+    if let Some(sound) = sound {
+        if let Err(e) = AUDIO_BACKEND.lock().open_playback() {
+            error!("{}", e);
+            return;
+        }
+
+        if let Some(mixer) = &*MIXER.lock() {
+            mixer.play(sound, params);
+        }
+    } else {
+        trace!("The sound effects pack has no sound for: {}", name);
+    }
+}
+
+/// Encode a response packet
+fn encode_response(
+    response_type: CommandType,
+    payload: protocol::response::Payload,
+) -> Result<Vec<u8>> {
+    let mut response = protocol::Response::default();
+
+    response.set_response_type(response_type);
+    response.payload = Some(payload);
+
+    let mut buf = Vec::new();
+    response.encode_length_delimited(&mut buf)?;
+
+    Ok(buf)
+}
+
+/// Transmit the queued packets, returns `Ok(false)` if the socket is not able to accept more data
+fn transmit(socket: &Socket, queue: &mut VecDeque<Vec<u8>>) -> io::Result<bool> {
+    while let Some(buf) = queue.pop_front() {
+        trace!("Sending a protocol packet...");
+
+        match socket.send(&buf) {
+            Ok(n) => {
+                trace!("Wrote {} bytes to audio proxy socket", n);
+            }
+
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                // retry later
+                queue.push_front(buf);
+
+                return Ok(false);
+            }
+
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(true)
+}
+
 pub async fn run_main_loop(_ctrl_c_rx: &Receiver<bool>) -> Result<()> {
     unsafe fn assume_init(buf: &[MaybeUninit<u8>]) -> &[u8] {
         &*(buf as *const [MaybeUninit<u8>] as *const [u8])
@@ -200,6 +303,29 @@ pub async fn run_main_loop(_ctrl_c_rx: &Receiver<bool>) -> Result<()> {
                 socket.set_send_buffer_size(constants::NET_BUFFER_CAPACITY * 2)?;
                 socket.set_recv_buffer_size(constants::NET_BUFFER_CAPACITY * 2)?;
 
+                // never block while sending, so that we are able to drop audio frames
+                // if the Eruption daemon does not keep up with the stream
+                socket.set_nonblocking(true)?;
+
+                // packets that were queued for a previous connection are stale
+                PACKET_TX_QUEUE.lock().clear();
+
+                // the handshake: both peers send their protocol version
+                PACKET_TX_QUEUE.lock().push_back(encode_response(
+                    CommandType::Hello,
+                    protocol::response::Payload::Hello(protocol::Hello {
+                        protocol_version: constants::PROTOCOL_VERSION,
+                        peer: format!("eruption-audio-proxy {}", env!("CARGO_PKG_VERSION")),
+                    }),
+                )?);
+
+                let connected_at = Instant::now();
+                let mut handshake_completed = false;
+
+                // recorded audio frames, numbered consecutively for this connection
+                let mut audio_frames = VecDeque::new();
+                let mut sequence: u64 = 0;
+
                 let mut last_status_update = Instant::now();
                 let mut last_device_update =
                     Instant::now() - Duration::from_millis(constants::DEVICE_POLL_INTERVAL + 1);
@@ -209,17 +335,38 @@ pub async fn run_main_loop(_ctrl_c_rx: &Receiver<bool>) -> Result<()> {
                         break 'MAIN_LOOP Ok(());
                     }
 
+                    if !handshake_completed
+                        && connected_at.elapsed()
+                            >= Duration::from_millis(constants::HANDSHAKE_TIMEOUT_MILLIS)
+                    {
+                        error!("The Eruption daemon did not complete the handshake, it is probably outdated");
+
+                        thread::sleep(Duration::from_millis(
+                            constants::SLEEP_TIME_WHILE_INCOMPATIBLE,
+                        ));
+
+                        break 'EVENT_LOOP;
+                    }
+
                     // poll PipeWire/PulseAudio every n milliseconds for device status updates
                     if last_device_update.elapsed()
                         >= Duration::from_millis(constants::DEVICE_POLL_INTERVAL)
                     {
                         let audio_backend = AUDIO_BACKEND.lock();
 
-                        let volume = audio_backend.get_audio_volume()?;
-                        let muted = audio_backend.is_audio_muted()?;
+                        // the selected device may be unavailable, e.g. if it has been unplugged
+                        let state = audio_backend
+                            .get_audio_volume()
+                            .and_then(|volume| Ok((volume, audio_backend.is_audio_muted()?)));
+
+                        match state {
+                            Ok((volume, muted)) => {
+                                MASTER_VOLUME.store(volume, Ordering::SeqCst);
+                                AUDIO_MUTED.store(muted, Ordering::SeqCst);
+                            }
 
-                        MASTER_VOLUME.store(volume, Ordering::SeqCst);
-                        AUDIO_MUTED.store(muted, Ordering::SeqCst);
+                            Err(e) => debug!("Could not query the audio device: {}", e),
+                        }
 
                         last_device_update = Instant::now();
                     }
@@ -234,24 +381,31 @@ pub async fn run_main_loop(_ctrl_c_rx: &Receiver<bool>) -> Result<()> {
                             thread::sleep(Duration::from_millis(constants::SLEEP_TIME_TIMEOUT));
 
                             debug!("Re-opening audio device");
-                            audio_backend.open_recorder()?;
-                        }
-                    }
-
-                    // play back pending sound effects
-                    let mut sfx_played = false;
-
-                    if let Some(sfx_id) = *PENDING_SFX_ID.lock() {
-                        let mut audio_backend = AUDIO_BACKEND.lock();
-
-                        audio_backend.open_playback()?;
-                        audio_backend.play_sfx(sfx_id)?;
-
-                        sfx_played = true;
-                    }
+                            audio_backend.open_recorder().unwrap_or_else(|e| {
+                                warn!("Could not open the audio device: {}", e)
+                            });
+                        } else {
+                            // if the Eruption daemon does not keep up, drop the oldest frame; the
+                            // daemon accounts for the gap in the sequence numbers
+                            if audio_frames.len() >= constants::MAX_QUEUED_AUDIO_FRAMES {
+                                audio_frames.pop_front();
+
+                                debug!("Dropped an audio frame");
+                            }
 
-                    if sfx_played {
-                        *PENDING_SFX_ID.lock() = None;
+                            audio_frames.push_back(encode_response(
+                                CommandType::AudioData,
+                                protocol::response::Payload::Frame(protocol::AudioFrame {
+                                    sequence,
+                                    sample_rate: constants::AUDIO_SAMPLE_RATE,
+                                    channels: constants::AUDIO_CHANNELS,
+                                    bits_per_sample: constants::AUDIO_BITS_PER_SAMPLE,
+                                    data: audio::AUDIO_BUFFER.read().clone(),
+                                }),
+                            )?);
+
+                            sequence += 1;
+                        }
                     }
 
                     // wait for socket to be ready
@@ -286,18 +440,75 @@ pub async fn run_main_loop(_ctrl_c_rx: &Receiver<bool>) -> Result<()> {
                                     break 'EVENT_LOOP;
                                 }
 
-                                Ok(_n) => {
-                                    let buf = unsafe { assume_init(&tmp[..tmp.len()]) };
-                                    match Command::decode_length_delimited(&mut Cursor::new(buf)) {
+                                Ok(n) => {
+                                    let buf = unsafe { assume_init(&tmp[..n]) };
+                                    match Command::decode_length_delimited(buf) {
                                         Ok(message) => {
+                                            // an outdated daemon does not send a handshake
+                                            if !handshake_completed
+                                                && message.command_type() != CommandType::Hello
+                                            {
+                                                warn!("Protocol error: Expected a handshake");
+
+                                                continue 'EVENT_LOOP;
+                                            }
+
                                             let mut response = protocol::Response::default();
 
                                             match message.command_type() {
+                                                CommandType::Hello => {
+                                                    match message.payload {
+                                                        Some(
+                                                            protocol::command::Payload::Hello(
+                                                                hello,
+                                                            ),
+                                                        ) => {
+                                                            if hello.protocol_version
+                                                                != constants::PROTOCOL_VERSION
+                                                            {
+                                                                error!(
+                                                                    "Incompatible Eruption daemon: protocol version {}, expected version {}",
+                                                                    hello.protocol_version,
+                                                                    constants::PROTOCOL_VERSION
+                                                                );
+
+                                                                thread::sleep(Duration::from_millis(
+                                                                    constants::SLEEP_TIME_WHILE_INCOMPATIBLE,
+                                                                ));
+
+                                                                break 'EVENT_LOOP;
+                                                            }
+
+                                                            info!(
+                                                                "Handshake completed: {}",
+                                                                hello.peer
+                                                            );
+
+                                                            handshake_completed = true;
+                                                        }
+
+                                                        _ => {
+                                                            error!(
+                                                                "Protocol error: Invalid payload"
+                                                            );
+                                                        }
+                                                    }
+
+                                                    response.set_response_type(CommandType::Noop);
+                                                }
+
                                                 CommandType::StartRecording => {
                                                     info!("Opening audio device");
 
                                                     let mut audio_backend = AUDIO_BACKEND.lock();
-                                                    audio_backend.open_recorder()?;
+                                                    audio_backend.open_recorder().unwrap_or_else(
+                                                        |e| {
+                                                            warn!(
+                                                                "Could not open the audio device: {}",
+                                                                e
+                                                            )
+                                                        },
+                                                    );
 
                                                     RECORDING.store(true, Ordering::SeqCst);
 
@@ -318,9 +529,8 @@ pub async fn run_main_loop(_ctrl_c_rx: &Receiver<bool>) -> Result<()> {
                                                 CommandType::AudioVolume => {
                                                     trace!("Request for audio volume");
 
-                                                    let audio_backend = AUDIO_BACKEND.lock();
                                                     let volume =
-                                                        audio_backend.get_audio_volume()?;
+                                                        MASTER_VOLUME.load(Ordering::SeqCst);
 
                                                     response.set_response_type(
                                                         CommandType::AudioVolume,
@@ -333,8 +543,7 @@ pub async fn run_main_loop(_ctrl_c_rx: &Receiver<bool>) -> Result<()> {
                                                 CommandType::AudioMutedState => {
                                                     trace!("Request for audio muted state");
 
-                                                    let audio_backend = AUDIO_BACKEND.lock();
-                                                    let muted = audio_backend.is_audio_muted()?;
+                                                    let muted = AUDIO_MUTED.load(Ordering::SeqCst);
 
                                                     response.set_response_type(
                                                         CommandType::AudioMutedState,
@@ -354,7 +563,21 @@ pub async fn run_main_loop(_ctrl_c_rx: &Receiver<bool>) -> Result<()> {
                                                                 id
                                                             );
 
-                                                            *PENDING_SFX_ID.lock() = Some(id);
+                                                            // numeric IDs are used by previous versions of Eruption
+                                                            match id {
+                                                                0 => play_sound(
+                                                                    "key-down",
+                                                                    sfx::VoiceParams::default(),
+                                                                ),
+                                                                1 => play_sound(
+                                                                    "key-up",
+                                                                    sfx::VoiceParams::default(),
+                                                                ),
+
+                                                                _ => {
+                                                                    warn!("Unknown SFX ID: {}", id)
+                                                                }
+                                                            }
                                                         }
 
                                                         _ => {
@@ -367,6 +590,61 @@ pub async fn run_main_loop(_ctrl_c_rx: &Receiver<bool>) -> Result<()> {
                                                     response.set_response_type(CommandType::Noop);
                                                 }
 
+                                                CommandType::PlaySound => {
+                                                    if let Some(
+                                                        protocol::command::Payload::Sound(sound),
+                                                    ) = message.payload
+                                                    {
+                                                        debug!(
+                                                            "Request to play sound: {:?}",
+                                                            sound
+                                                        );
+
+                                                        play_sound(
+                                                            &sound.name,
+                                                            sfx::VoiceParams::new(
+                                                                sound.gain, sound.pan,
+                                                            ),
+                                                        );
+                                                    } else {
+                                                        error!("Protocol error: Invalid payload");
+                                                    }
+
+                                                    response.set_response_type(CommandType::Noop);
+                                                }
+
+                                                CommandType::SetSfxPack => {
+                                                    if let Some(protocol::command::Payload::Name(
+                                                        name,
+                                                    )) = message.payload
+                                                    {
+                                                        switch_sfx_pack(&name);
+                                                    } else {
+                                                        error!("Protocol error: Invalid payload");
+                                                    }
+
+                                                    response.set_response_type(CommandType::Noop);
+                                                }
+
+                                                CommandType::SetCaptureSource => {
+                                                    if let Some(
+                                                        protocol::command::Payload::Source(source),
+                                                    ) = message.payload
+                                                    {
+                                                        switch_capture_source(&source);
+
+                                                        // report the state of the newly selected device right away
+                                                        last_device_update = Instant::now()
+                                                            - Duration::from_millis(
+                                                                constants::DEVICE_POLL_INTERVAL + 1,
+                                                            );
+                                                    } else {
+                                                        error!("Protocol error: Invalid payload");
+                                                    }
+
+                                                    response.set_response_type(CommandType::Noop);
+                                                }
+
                                                 _ => {
                                                     error!("Protocol error: Unknown command");
                                                 }
@@ -376,7 +654,7 @@ pub async fn run_main_loop(_ctrl_c_rx: &Receiver<bool>) -> Result<()> {
                                             response.encode_length_delimited(&mut buf)?;
 
                                             // enqueue the response packet
-                                            PACKET_TX_QUEUE.lock().push(buf);
+                                            PACKET_TX_QUEUE.lock().push_back(buf);
                                         }
 
                                         Err(e) => {
@@ -385,6 +663,10 @@ pub async fn run_main_loop(_ctrl_c_rx: &Receiver<bool>) -> Result<()> {
                                     }
                                 }
 
+                                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                                    // not an error, nothing to receive
+                                }
+
                                 Err(e) => {
                                     error!(
                                         "Error occurred during receive from audio proxy socket: {}",
@@ -395,86 +677,60 @@ pub async fn run_main_loop(_ctrl_c_rx: &Receiver<bool>) -> Result<()> {
                         }
 
                         if poll_fds[0].revents().unwrap().contains(PollFlags::POLLOUT) {
-                            if RECORDING.load(Ordering::SeqCst) {
-                                let samples = audio::AUDIO_BUFFER.read().clone();
-
-                                let mut response = protocol::Response::default();
-
-                                response.set_response_type(CommandType::AudioData);
-                                response.payload = Some(protocol::response::Payload::Data(samples));
-
-                                let mut buf = Vec::new();
-                                response.encode_length_delimited(&mut buf)?;
-
-                                PACKET_TX_QUEUE.lock().push(buf);
-                            }
-
                             // send unsolicited audio state updates every n milliseconds
-                            if last_status_update.elapsed() >= Duration::from_millis(50) {
-                                // audio volume
+                            if handshake_completed
+                                && last_status_update.elapsed() >= Duration::from_millis(50)
+                            {
                                 let volume = MASTER_VOLUME.load(Ordering::SeqCst);
-
-                                let mut response = protocol::Response::default();
-                                response.set_response_type(CommandType::AudioVolume);
-                                response.payload =
-                                    Some(protocol::response::Payload::Volume(volume));
-
-                                let mut buf = Vec::new();
-                                response.encode_length_delimited(&mut buf)?;
-
-                                PACKET_TX_QUEUE.lock().push(buf);
-
-                                // audio muted state
                                 let muted = AUDIO_MUTED.load(Ordering::SeqCst);
 
-                                let mut response = protocol::Response::default();
+                                let mut queue = PACKET_TX_QUEUE.lock();
 
-                                response.set_response_type(CommandType::AudioMutedState);
-                                response.payload = Some(protocol::response::Payload::Muted(muted));
+                                queue.push_back(encode_response(
+                                    CommandType::AudioVolume,
+                                    protocol::response::Payload::Volume(volume),
+                                )?);
 
-                                let mut buf = Vec::new();
-                                response.encode_length_delimited(&mut buf)?;
-
-                                PACKET_TX_QUEUE.lock().push(buf);
+                                queue.push_back(encode_response(
+                                    CommandType::AudioMutedState,
+                                    protocol::response::Payload::Muted(muted),
+                                )?);
 
                                 last_status_update = Instant::now();
                             }
 
-                            // transmit the queue of packets to the Eruption daemon
-                            while let Some(buf) = PACKET_TX_QUEUE.lock().pop() {
-                                trace!("Sending a protocol packet...");
-
-                                // send data
-                                match socket.send(&buf) {
-                                    Ok(n) => {
-                                        if QUIT.load(Ordering::SeqCst) {
-                                            break 'MAIN_LOOP Ok(());
-                                        }
-
-                                        trace!("Wrote {} bytes to audio proxy socket", n);
-                                    }
-
-                                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                                        if QUIT.load(Ordering::SeqCst) {
-                                            break 'MAIN_LOOP Ok(());
-                                        }
-
-                                        // not an error, so continue
-                                        continue 'EVENT_LOOP;
+                            // transmit the queue of packets to the Eruption daemon, then the
+                            // audio frames
+                            let result =
+                                transmit(&socket, &mut PACKET_TX_QUEUE.lock()).and_then(|done| {
+                                    if done {
+                                        transmit(&socket, &mut audio_frames)
+                                    } else {
+                                        Ok(false)
                                     }
+                                });
 
-                                    Err(e) => {
-                                        error!("An error occurred during socket write: {}", e);
+                            if let Err(e) = result {
+                                error!("An error occurred during socket write: {}", e);
 
-                                        break 'EVENT_LOOP;
-                                    }
-                                }
+                                break 'EVENT_LOOP;
                             }
                         }
                     }
 
                     thread::sleep(Duration::from_millis(15));
                 }
+
+                // stop recording until the Eruption daemon requests it again, e.g. after the
+                // daemon has been restarted
+                if RECORDING.swap(false, Ordering::SeqCst) {
+                    info!("Closing audio device");
+
+                    AUDIO_BACKEND
+                        .lock()
+                        .close_recorder()
+                        .unwrap_or_else(|e| error!("Could not close the audio device: {}", e));
+                }
             }
 
             Err(e)
@@ -592,28 +848,62 @@ pub async fn async_main() -> std::result::Result<(), eyre::Error> {
             })
             .unwrap_or_else(|e| error!("Could not set CTRL-C handler: {}", e));
 
-            // load sound effects
-            info!("Loading sound effects...");
+            // process configuration file
+            let config_file = opts
+                .config
+                .unwrap_or_else(|| constants::AUDIO_PROXY_CONFIG_FILE.to_string());
 
-            let sample_data_key_down_fx =
-                util::load_audio_file("/usr/share/eruption/sfx/key-down.wav").unwrap_or_else(|e| {
-                    error!("Could not load waveform audio file: {}", e);
-                    Vec::new()
-                });
-
-            let sample_data_key_up_fx = util::load_audio_file("/usr/share/eruption/sfx/key-up.wav")
+            let config = Config::builder()
+                .add_source(
+                    config::File::new(&config_file, config::FileFormat::Toml).required(false),
+                )
+                .build()
                 .unwrap_or_else(|e| {
-                    error!("Could not load waveform audio file: {}", e);
-                    Vec::new()
+                    error!("Could not parse configuration file: {}", e);
+                    Config::default()
                 });
 
-            {
-                let mut sound_fx = SOUND_FX.write();
+            let capture_source = config
+                .get_string("global.capture_source")
+                .ok()
+                .and_then(|source| {
+                    source
+                        .parse::<CaptureSource>()
+                        .map_err(|e| error!("{}", e))
+                        .ok()
+                })
+                .unwrap_or_default();
 
-                sound_fx.insert(0, sample_data_key_down_fx);
-                sound_fx.insert(1, sample_data_key_up_fx);
+            AUDIO_BACKEND.lock().set_capture_source(capture_source)?;
+
+            // load sound effects
+            info!("Loading sound effects...");
+
+            if let Ok(pack) = config.get_string("global.sfx_pack") {
+                *DEFAULT_SFX_PACK.write() = pack;
             }
 
+            switch_sfx_pack("");
+
+            let player = AUDIO_BACKEND.lock().player_handle.clone();
+
+            let mixer = sfx::Mixer::spawn(move |data| {
+                match &*player.read() {
+                    Some(player) => player.write(data)?,
+
+                    None => {
+                        return Err(audio::AudioError::PlayerError {
+                            description: "Audio subsystem is not available".to_string(),
+                        }
+                        .into())
+                    }
+                }
+
+                Ok(())
+            })?;
+
+            MIXER.lock().replace(mixer);
+
             info!("Startup completed");
 
             // enter the main loop
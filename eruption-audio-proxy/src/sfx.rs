@@ -0,0 +1,404 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use config::Config;
+use flume::{unbounded, Receiver, RecvTimeoutError, Sender};
+use log::*;
+use serde::Deserialize;
+
+use crate::{constants, util};
+
+pub type Result<T> = std::result::Result<T, eyre::Error>;
+
+/// Decoded samples of a sound effect, in the format of the playback device
+pub type Sound = Arc<Vec<i16>>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SfxError {
+    #[error("Sound effects pack not found: {name}")]
+    PackNotFound { name: String },
+
+    #[error("Invalid sound effects pack manifest: {description}")]
+    ManifestError { description: String },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Manifest {
+    pack: PackInfo,
+
+    /// Maps names of events and sound effects to audio files, relative to the pack directory
+    #[serde(default)]
+    sounds: HashMap<String, PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct PackInfo {
+    name: String,
+
+    #[serde(default)]
+    description: String,
+
+    /// Volume of all sounds of the pack, in the range [0..1]
+    #[serde(default = "default_volume")]
+    volume: f32,
+}
+
+fn default_volume() -> f32 {
+    1.0
+}
+
+/// A set of sound effects, loaded from a directory that contains a `manifest.toml` file
+#[derive(Debug, Clone)]
+pub struct SoundPack {
+    /// The name of the directory of the pack
+    pub id: String,
+    pub name: String,
+    pub description: String,
+
+    sounds: HashMap<String, Sound>,
+}
+
+impl SoundPack {
+    /// Find the sound effects pack `id` in the user's and in the system-wide sfx directory
+    pub fn find(id: &str) -> Result<Self> {
+        let is_valid = !id.is_empty() && !id.contains('/') && id != "." && id != "..";
+
+        let dir = pack_dirs()
+            .into_iter()
+            .map(|dir| dir.join(id))
+            .find(|dir| is_valid && dir.join(constants::SFX_MANIFEST_FILE).is_file())
+            .ok_or_else(|| SfxError::PackNotFound { name: id.into() })?;
+
+        Self::load(id, &dir)
+    }
+
+    /// Load the manifest and decode all sounds of the sound effects pack in `dir`
+    pub fn load(id: &str, dir: &Path) -> Result<Self> {
+        let manifest = Config::builder()
+            .add_source(config::File::new(
+                &dir.join(constants::SFX_MANIFEST_FILE).to_string_lossy(),
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .and_then(|config| config.try_deserialize::<Manifest>())
+            .map_err(|e| SfxError::ManifestError {
+                description: format!("{}: {}", dir.display(), e),
+            })?;
+
+        let volume = manifest.pack.volume.clamp(0.0, 1.0);
+
+        let mut sounds = HashMap::new();
+
+        for (name, file) in manifest.sounds.iter() {
+            match util::load_audio_file(dir.join(file)) {
+                Ok(mut samples) => {
+                    if volume < 1.0 {
+                        samples
+                            .iter_mut()
+                            .for_each(|sample| *sample = (*sample as f32 * volume) as i16);
+                    }
+
+                    sounds.insert(name.to_owned(), Arc::new(samples));
+                }
+
+                Err(e) => error!("Could not load sound '{}': {}", name, e),
+            }
+        }
+
+        Ok(Self {
+            id: id.to_owned(),
+            name: manifest.pack.name,
+            description: manifest.pack.description,
+            sounds,
+        })
+    }
+
+    /// Look up the sound for `name`. Names may be qualified by classes, separated by dots,
+    /// e.g. `key-down.modifier`; if the pack has no sound for the qualified name, the
+    /// class is dropped and the more generic name `key-down` is tried instead
+    pub fn lookup(&self, name: &str) -> Option<Sound> {
+        let mut name = name;
+
+        loop {
+            if let Some(sound) = self.sounds.get(name) {
+                return Some(sound.clone());
+            }
+
+            name = &name[..name.rfind('.')?];
+        }
+    }
+}
+
+/// Directories that are searched for sound effects packs, in order of precedence
+fn pack_dirs() -> Vec<PathBuf> {
+    let mut result = Vec::new();
+
+    if let Ok(home) = std::env::var("HOME") {
+        result.push(Path::new(&home).join(constants::USER_SFX_DIR));
+    }
+
+    result.push(PathBuf::from(constants::SFX_DIR));
+
+    result
+}
+
+/// Gain and stereo position of a sound effect
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VoiceParams {
+    /// Gain in the range [0..`MAX_VOICE_GAIN`], 1.0 plays the sound at its original level
+    pub gain: f32,
+
+    /// Stereo position in the range [-1..1], from left to right
+    pub pan: f32,
+}
+
+impl Default for VoiceParams {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            pan: 0.0,
+        }
+    }
+}
+
+impl VoiceParams {
+    pub fn new(gain: f32, pan: f32) -> Self {
+        Self {
+            gain: if gain.is_finite() {
+                gain.clamp(0.0, constants::MAX_VOICE_GAIN)
+            } else {
+                1.0
+            },
+
+            pan: if pan.is_finite() {
+                pan.clamp(-1.0, 1.0)
+            } else {
+                0.0
+            },
+        }
+    }
+
+    /// Gains of the left and the right channel. Panning attenuates the opposite
+    /// channel only, so that a centered sound is played at its original level
+    fn channel_gains(&self) -> [f32; 2] {
+        [
+            self.gain * (1.0 - self.pan).min(1.0),
+            self.gain * (1.0 + self.pan).min(1.0),
+        ]
+    }
+}
+
+/// A sound effect that is currently being played back
+struct Voice {
+    sound: Sound,
+    gains: [f32; 2],
+    position: usize,
+}
+
+impl Voice {
+    fn new(sound: Sound, params: VoiceParams) -> Self {
+        Self {
+            sound,
+            gains: params.channel_gains(),
+            position: 0,
+        }
+    }
+}
+
+/// Mixes overlapping sound effects into a single stream, on a dedicated thread
+pub struct Mixer {
+    tx: Sender<Voice>,
+}
+
+impl Mixer {
+    /// Spawn the mixer thread, mixed samples will be written to `sink`. The sink
+    /// is expected to block until the playback device is able to accept more data
+    pub fn spawn<F>(sink: F) -> Result<Self>
+    where
+        F: FnMut(&[u8]) -> Result<()> + Send + 'static,
+    {
+        let (tx, rx) = unbounded();
+
+        thread::Builder::new()
+            .name("mixer".into())
+            .spawn(move || run_mixer(rx, sink))?;
+
+        Ok(Self { tx })
+    }
+
+    pub fn play(&self, sound: Sound, params: VoiceParams) {
+        self.tx
+            .send(Voice::new(sound, params))
+            .unwrap_or_else(|e| error!("Could not play a sound effect: {}", e));
+    }
+}
+
+fn run_mixer<F>(rx: Receiver<Voice>, mut sink: F)
+where
+    F: FnMut(&[u8]) -> Result<()>,
+{
+    let period = constants::PLAYBACK_SAMPLE_RATE as usize
+        * constants::PLAYBACK_CHANNELS
+        * constants::MIXER_PERIOD_MILLIS as usize
+        / 1000;
+
+    let mut voices: Vec<Voice> = Vec::new();
+
+    loop {
+        if crate::QUIT.load(Ordering::SeqCst) {
+            break;
+        }
+
+        // sleep while there is nothing to play
+        if voices.is_empty() {
+            match rx.recv_timeout(Duration::from_millis(constants::SLEEP_TIME_TIMEOUT)) {
+                Ok(voice) => add_voice(&mut voices, voice),
+
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        for voice in rx.try_iter() {
+            add_voice(&mut voices, voice);
+        }
+
+        let samples = mix(&mut voices, period);
+
+        let data = samples
+            .iter()
+            .flat_map(|sample| sample.to_ne_bytes())
+            .collect::<Vec<u8>>();
+
+        sink(&data).unwrap_or_else(|e| {
+            error!("Error during playback: {}", e);
+
+            // the playback device is unavailable, discard all pending sounds
+            voices.clear();
+        });
+    }
+}
+
+/// Add a voice, the oldest voice is dropped if too many sound effects overlap
+fn add_voice(voices: &mut Vec<Voice>, voice: Voice) {
+    if voices.len() >= constants::MAX_MIXER_VOICES {
+        voices.remove(0);
+    }
+
+    voices.push(voice);
+}
+
+/// Mix the next `len` interleaved stereo samples of all voices, finished voices are removed
+fn mix(voices: &mut Vec<Voice>, len: usize) -> Vec<i16> {
+    let mut buffer = vec![0.0_f32; len];
+
+    for voice in voices.iter_mut() {
+        let remaining = &voice.sound[voice.position..];
+        let count = remaining.len().min(len);
+
+        for (index, (acc, sample)) in buffer.iter_mut().zip(remaining[..count].iter()).enumerate() {
+            *acc += *sample as f32 * voice.gains[index % constants::PLAYBACK_CHANNELS];
+        }
+
+        voice.position += count;
+    }
+
+    voices.retain(|voice| voice.position < voice.sound.len());
+
+    buffer
+        .iter()
+        .map(|sample| sample.clamp(i16::MIN as f32, i16::MAX as f32) as i16)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    fn sound(samples: &[i16]) -> Sound {
+        Arc::new(samples.to_vec())
+    }
+
+    #[test]
+    fn mix_voices() {
+        let mut voices = vec![
+            Voice::new(sound(&[1000, 1000, 1000, 1000]), VoiceParams::default()),
+            Voice::new(sound(&[500, -500]), VoiceParams::default()),
+        ];
+
+        // the second voice ends after the first frame
+        assert_eq!(mix(&mut voices, 2), vec![1500, 500]);
+        assert_eq!(voices.len(), 1);
+
+        // the first voice ends within this period, the rest is silence
+        assert_eq!(mix(&mut voices, 4), vec![1000, 1000, 0, 0]);
+        assert!(voices.is_empty());
+
+        // overlapping loud voices are clipped
+        let mut voices = vec![
+            Voice::new(sound(&[30000, -30000]), VoiceParams::default()),
+            Voice::new(sound(&[30000, -30000]), VoiceParams::default()),
+        ];
+
+        assert_eq!(mix(&mut voices, 2), vec![i16::MAX, i16::MIN]);
+    }
+
+    #[test]
+    fn gain_and_pan() {
+        let samples = [10000, 10000];
+
+        let mut voices = vec![Voice::new(sound(&samples), VoiceParams::new(0.5, 0.0))];
+        assert_eq!(mix(&mut voices, 2), vec![5000, 5000]);
+
+        let mut voices = vec![Voice::new(sound(&samples), VoiceParams::new(1.0, -1.0))];
+        assert_eq!(mix(&mut voices, 2), vec![10000, 0]);
+
+        let mut voices = vec![Voice::new(sound(&samples), VoiceParams::new(1.0, 0.5))];
+        assert_eq!(mix(&mut voices, 2), vec![5000, 10000]);
+
+        // out of range parameters are clamped
+        assert_eq!(VoiceParams::new(f32::NAN, 5.0), VoiceParams::new(1.0, 1.0));
+    }
+
+    #[test]
+    fn voice_limit() {
+        let mut voices = Vec::new();
+
+        for index in 0..constants::MAX_MIXER_VOICES + 2 {
+            add_voice(
+                &mut voices,
+                Voice::new(sound(&[index as i16, 0]), VoiceParams::default()),
+            );
+        }
+
+        assert_eq!(voices.len(), constants::MAX_MIXER_VOICES);
+
+        // the oldest voices have been dropped
+        assert_eq!(voices[0].sound[0], 2);
+    }
+}
@@ -17,23 +17,183 @@
     Copyright (c) 2019-2022, The Eruption Development Team
 */
 
+use std::fs::File;
 use std::path::Path;
 
-use byteorder::{LittleEndian, WriteBytesExt};
+use crate::constants;
 
 pub type Result<T> = std::result::Result<T, eyre::Error>;
 
-pub fn load_audio_file<P: AsRef<Path>>(file: P) -> Result<Vec<u8>> {
-    let mut reader = hound::WavReader::open(file.as_ref())?;
+#[derive(Debug, thiserror::Error)]
+pub enum DecoderError {
+    #[error("Unsupported audio file format: {path}")]
+    UnsupportedFormat { path: String },
+
+    #[error("Could not decode audio file: {description}")]
+    DecodeError { description: String },
+}
+
+/// Load a WAV, FLAC or Ogg Vorbis file, and convert it to the sample format of the
+/// playback device: interleaved stereo i16 samples at `PLAYBACK_SAMPLE_RATE`
+pub fn load_audio_file<P: AsRef<Path>>(file: P) -> Result<Vec<i16>> {
+    let file = file.as_ref();
+
+    let extension = file
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    let (samples, channels, rate) = match extension.as_str() {
+        "wav" => decode_wav(file)?,
+        "flac" => decode_flac(file)?,
+        "ogg" | "oga" => decode_ogg(file)?,
+
+        _ => {
+            return Err(DecoderError::UnsupportedFormat {
+                path: file.display().to_string(),
+            }
+            .into())
+        }
+    };
+
+    if channels == 0 || rate == 0 {
+        return Err(DecoderError::DecodeError {
+            description: format!("Invalid stream parameters in {}", file.display()),
+        }
+        .into());
+    }
+
+    Ok(convert_samples(&samples, channels, rate))
+}
+
+fn decode_wav(file: &Path) -> Result<(Vec<f32>, usize, u32)> {
+    let mut reader = hound::WavReader::open(file)?;
+    let spec = reader.spec();
+
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Float => reader
+            .samples::<f32>()
+            .collect::<std::result::Result<_, _>>()?,
+
+        hound::SampleFormat::Int => {
+            let scale = (1_i64 << (spec.bits_per_sample - 1)) as f32;
+
+            reader
+                .samples::<i32>()
+                .map(|sample| sample.map(|sample| sample as f32 / scale))
+                .collect::<std::result::Result<_, _>>()?
+        }
+    };
+
+    Ok((samples, spec.channels as usize, spec.sample_rate))
+}
+
+fn decode_flac(file: &Path) -> Result<(Vec<f32>, usize, u32)> {
+    let mut reader = claxon::FlacReader::open(file)?;
+    let info = reader.streaminfo();
+
+    let scale = (1_i64 << (info.bits_per_sample - 1)) as f32;
+
     let samples = reader
-        .samples::<i16>()
-        .map(|sample| sample.unwrap())
-        .collect::<Vec<i16>>();
+        .samples()
+        .map(|sample| sample.map(|sample| sample as f32 / scale))
+        .collect::<std::result::Result<_, _>>()?;
+
+    Ok((samples, info.channels as usize, info.sample_rate))
+}
+
+fn decode_ogg(file: &Path) -> Result<(Vec<f32>, usize, u32)> {
+    let mut reader = lewton::inside_ogg::OggStreamReader::new(File::open(file)?)?;
+
+    let channels = reader.ident_hdr.audio_channels as usize;
+    let rate = reader.ident_hdr.audio_sample_rate;
 
-    let mut buffer: Vec<u8> = vec![];
-    for s in samples {
-        buffer.write_i16::<LittleEndian>(s)?;
+    let mut samples = Vec::new();
+
+    while let Some(packet) = reader.read_dec_packet_itl()? {
+        samples.extend(packet.iter().map(|sample| *sample as f32 / 32768.0));
+    }
+
+    Ok((samples, channels, rate))
+}
+
+/// Convert interleaved samples with an arbitrary number of channels and sample rate
+/// to the format of the playback device. Mono is duplicated to both channels, additional
+/// channels are dropped, the sample rate is converted by linear interpolation
+fn convert_samples(samples: &[f32], channels: usize, rate: u32) -> Vec<i16> {
+    let frames = samples.len() / channels;
+
+    let frame = |index: usize| {
+        let left = samples[index * channels];
+        let right = if channels > 1 {
+            samples[index * channels + 1]
+        } else {
+            left
+        };
+
+        (left, right)
+    };
+
+    let ratio = rate as f64 / constants::PLAYBACK_SAMPLE_RATE as f64;
+    let output_frames = (frames as f64 / ratio) as usize;
+
+    let mut result = Vec::with_capacity(output_frames * constants::PLAYBACK_CHANNELS);
+
+    for index in 0..output_frames {
+        let position = index as f64 * ratio;
+        let offset = position as usize;
+        let fraction = (position - offset as f64) as f32;
+
+        let (left, right) = frame(offset);
+        let (next_left, next_right) = frame((offset + 1).min(frames - 1));
+
+        for sample in [
+            left + (next_left - left) * fraction,
+            right + (next_right - right) * fraction,
+        ] {
+            result.push((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16);
+        }
     }
 
-    Ok(buffer)
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn convert_mono_to_stereo() {
+        // half the sample rate of the playback device, every frame is interpolated once
+        let samples = [0.0, 0.5, 1.0, 0.5];
+        let result = convert_samples(&samples, 1, constants::PLAYBACK_SAMPLE_RATE / 2);
+
+        let expected = [0.0, 0.25, 0.5, 0.75, 1.0, 0.75, 0.5, 0.5]
+            .iter()
+            .flat_map(|sample| {
+                let sample = (sample * i16::MAX as f32) as i16;
+                [sample, sample]
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn convert_multichannel() {
+        // additional channels are dropped, the sample rate is halved
+        let samples = [
+            0.5, -0.5, 0.1, 0.25, -0.25, 0.1, 1.0, -1.0, 0.1, 0.0, 0.0, 0.1,
+        ];
+        let result = convert_samples(&samples, 3, constants::PLAYBACK_SAMPLE_RATE * 2);
+
+        let scale = |sample: f32| (sample * i16::MAX as f32) as i16;
+
+        assert_eq!(
+            result,
+            vec![scale(0.5), scale(-0.5), scale(1.0), scale(-1.0)]
+        );
+    }
 }
@@ -20,12 +20,12 @@
 #![allow(dead_code)]
 
 use crate::{constants, util::RGBA};
-use dbus::arg::RefArg;
+use dbus::arg::{PropMap, RefArg};
 use dbus::blocking::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
 use dbus::blocking::Connection;
 use glib::clone;
 use std::time::Duration;
-use std::{path::Path, thread};
+use std::{fs, path::Path, thread};
 
 /// Messages received via D-Bus
 #[derive(Debug)]
@@ -289,6 +289,30 @@ pub fn write_file<P: AsRef<Path>>(path: &P, data: &str) -> Result<()> {
     }
 }
 
+/// Instruct the daemon to install a profile bundle, returns the file name of the installed profile
+pub fn import_bundle<P: AsRef<Path>>(bundle_file: &P, overwrite: bool) -> Result<String> {
+    use self::profile::OrgEruptionProfile;
+
+    // the daemon is sent the contents of the bundle, it does not open files on our behalf
+    let bundle = fs::read(bundle_file)?;
+
+    let conn = Connection::new_system()?;
+    let proxy = conn.with_proxy(
+        "org.eruption",
+        "/org/eruption/profile",
+        Duration::from_secs(35),
+    );
+
+    proxy.import_bundle(bundle, overwrite).map_err(|e| {
+        log::error!("{}", e);
+
+        DbusClientError::MethodFailed {
+            description: format!("{}", e),
+        }
+        .into()
+    })
+}
+
 pub fn ping() -> Result<()> {
     use self::config::OrgEruptionConfig;
 
@@ -370,7 +394,8 @@ pub fn set_parameter(
 //     Ok(result)
 // }
 
-pub fn enumerate_process_monitor_rules() -> Result<Vec<(String, String, String, String)>> {
+/// Fetches the rules of the eruption-process-monitor daemon
+pub fn get_process_monitor_rules() -> Result<Vec<PropMap>> {
     use process_monitor::OrgEruptionProcessMonitorRules;
 
     let conn = Connection::new_session()?;
@@ -380,12 +405,61 @@ pub fn enumerate_process_monitor_rules() -> Result<Vec<(String, String, String,
         Duration::from_secs(constants::DBUS_TIMEOUT_MILLIS as u64),
     );
 
-    let result = proxy.enum_rules()?;
+    let result = proxy.get_rules()?;
 
     Ok(result)
 }
 
-pub fn transmit_process_monitor_rules(rules: &[(&str, &str, &str, &str)]) -> Result<()> {
+/// Adds a rule to the ruleset of the eruption-process-monitor daemon, returns the index of the new rule
+pub fn add_process_monitor_rule(rule: PropMap) -> Result<u64> {
+    use process_monitor::OrgEruptionProcessMonitorRules;
+
+    let conn = Connection::new_session()?;
+    let proxy = conn.with_proxy(
+        "org.eruption.process_monitor",
+        "/org/eruption/process_monitor/rules",
+        Duration::from_secs(constants::DBUS_TIMEOUT_MILLIS as u64),
+    );
+
+    let result = proxy.add_rule(rule)?;
+
+    Ok(result)
+}
+
+/// Replaces the rule at `index` in the ruleset of the eruption-process-monitor daemon
+pub fn update_process_monitor_rule(index: u64, rule: PropMap) -> Result<()> {
+    use process_monitor::OrgEruptionProcessMonitorRules;
+
+    let conn = Connection::new_session()?;
+    let proxy = conn.with_proxy(
+        "org.eruption.process_monitor",
+        "/org/eruption/process_monitor/rules",
+        Duration::from_secs(constants::DBUS_TIMEOUT_MILLIS as u64),
+    );
+
+    proxy.update_rule(index, rule)?;
+
+    Ok(())
+}
+
+/// Removes the rule at `index` from the ruleset of the eruption-process-monitor daemon
+pub fn remove_process_monitor_rule(index: u64) -> Result<()> {
+    use process_monitor::OrgEruptionProcessMonitorRules;
+
+    let conn = Connection::new_session()?;
+    let proxy = conn.with_proxy(
+        "org.eruption.process_monitor",
+        "/org/eruption/process_monitor/rules",
+        Duration::from_secs(constants::DBUS_TIMEOUT_MILLIS as u64),
+    );
+
+    proxy.remove_rule(index)?;
+
+    Ok(())
+}
+
+/// Enables or disables the rule at `index`
+pub fn set_process_monitor_rule_enabled(index: u64, enabled: bool) -> Result<()> {
     use process_monitor::OrgEruptionProcessMonitorRules;
 
     let conn = Connection::new_session()?;
@@ -395,7 +469,23 @@ pub fn transmit_process_monitor_rules(rules: &[(&str, &str, &str, &str)]) -> Res
         Duration::from_secs(constants::DBUS_TIMEOUT_MILLIS as u64),
     );
 
-    proxy.set_rules(rules.to_vec())?;
+    proxy.set_rule_enabled(index, enabled)?;
+
+    Ok(())
+}
+
+/// Moves the rule at index `from` to index `to`
+pub fn move_process_monitor_rule(from: u64, to: u64) -> Result<()> {
+    use process_monitor::OrgEruptionProcessMonitorRules;
+
+    let conn = Connection::new_session()?;
+    let proxy = conn.with_proxy(
+        "org.eruption.process_monitor",
+        "/org/eruption/process_monitor/rules",
+        Duration::from_secs(constants::DBUS_TIMEOUT_MILLIS as u64),
+    );
+
+    proxy.move_rule(from, to)?;
 
     Ok(())
 }
@@ -625,6 +715,7 @@ mod profile {
 
     pub trait OrgEruptionProfile {
         fn enum_profiles(&self) -> Result<Vec<(String, String)>, dbus::Error>;
+        fn import_bundle(&self, bundle: Vec<u8>, overwrite: bool) -> Result<String, dbus::Error>;
         fn set_parameter(
             &self,
             profile_file: &str,
@@ -686,6 +777,11 @@ mod profile {
                 .and_then(|r: (Vec<(String, String)>,)| Ok(r.0))
         }
 
+        fn import_bundle(&self, bundle: Vec<u8>, overwrite: bool) -> Result<String, dbus::Error> {
+            self.method_call("org.eruption.Profile", "ImportBundle", (bundle, overwrite))
+                .and_then(|r: (String,)| Ok(r.0))
+        }
+
         fn set_parameter(
             &self,
             profile_file: &str,
@@ -1361,6 +1457,12 @@ mod process_monitor {
     pub trait OrgEruptionProcessMonitorRules {
         fn enum_rules(&self) -> Result<Vec<(String, String, String, String)>, dbus::Error>;
         fn set_rules(&self, rules: Vec<(&str, &str, &str, &str)>) -> Result<(), dbus::Error>;
+        fn get_rules(&self) -> Result<Vec<arg::PropMap>, dbus::Error>;
+        fn add_rule(&self, rule: arg::PropMap) -> Result<u64, dbus::Error>;
+        fn update_rule(&self, index: u64, rule: arg::PropMap) -> Result<(), dbus::Error>;
+        fn remove_rule(&self, index: u64) -> Result<(), dbus::Error>;
+        fn set_rule_enabled(&self, index: u64, enabled: bool) -> Result<(), dbus::Error>;
+        fn move_rule(&self, from: u64, to: u64) -> Result<(), dbus::Error>;
     }
 
     impl<'a, T: blocking::BlockingSender, C: ::std::ops::Deref<Target = T>>
@@ -1374,11 +1476,45 @@ mod process_monitor {
         fn set_rules(&self, rules: Vec<(&str, &str, &str, &str)>) -> Result<(), dbus::Error> {
             self.method_call("org.eruption.process_monitor.Rules", "SetRules", (rules,))
         }
+
+        fn get_rules(&self) -> Result<Vec<arg::PropMap>, dbus::Error> {
+            self.method_call("org.eruption.process_monitor.Rules", "GetRules", ())
+                .and_then(|r: (Vec<arg::PropMap>,)| Ok(r.0))
+        }
+
+        fn add_rule(&self, rule: arg::PropMap) -> Result<u64, dbus::Error> {
+            self.method_call("org.eruption.process_monitor.Rules", "AddRule", (rule,))
+                .and_then(|r: (u64,)| Ok(r.0))
+        }
+
+        fn update_rule(&self, index: u64, rule: arg::PropMap) -> Result<(), dbus::Error> {
+            self.method_call(
+                "org.eruption.process_monitor.Rules",
+                "UpdateRule",
+                (index, rule),
+            )
+        }
+
+        fn remove_rule(&self, index: u64) -> Result<(), dbus::Error> {
+            self.method_call("org.eruption.process_monitor.Rules", "RemoveRule", (index,))
+        }
+
+        fn set_rule_enabled(&self, index: u64, enabled: bool) -> Result<(), dbus::Error> {
+            self.method_call(
+                "org.eruption.process_monitor.Rules",
+                "SetRuleEnabled",
+                (index, enabled),
+            )
+        }
+
+        fn move_rule(&self, from: u64, to: u64) -> Result<(), dbus::Error> {
+            self.method_call("org.eruption.process_monitor.Rules", "MoveRule", (from, to))
+        }
     }
 
     #[derive(Debug)]
     pub struct OrgEruptionProcessMonitorRulesRulesChanged {
-        pub rules: Vec<(String, String, String, String)>,
+        pub rules: Vec<arg::PropMap>,
     }
 
     impl arg::AppendAll for OrgEruptionProcessMonitorRulesRulesChanged {
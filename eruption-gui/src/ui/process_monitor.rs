@@ -44,7 +44,7 @@ pub fn update_rules_view(builder: &gtk::Builder) -> Result<()> {
     let rules_box: gtk::Box = builder.object("rules_box").unwrap();
     let rules_treeview: gtk::TreeView = builder.object("rules_treeview").unwrap();
 
-    if let Ok(rules) = dbus_client::enumerate_process_monitor_rules() {
+    if let Ok(rules) = dbus_client::get_process_monitor_rules() {
         // rules list
         let rules_treestore = gtk::TreeStore::new(&[
             glib::Type::BOOL,
@@ -55,24 +55,19 @@ pub fn update_rules_view(builder: &gtk::Builder) -> Result<()> {
             String::static_type(),
         ]);
 
-        for (index, rule) in rules.iter().enumerate() {
-            let enabled = rule.3.contains("enabled");
-
-            let sensor = &rule.0;
-            let selector = &rule.1;
-            let action = &rule.2;
-            let metadata = &rule.3;
+        for (index, rule) in rules.iter().map(rule::Rule::from_dbus).enumerate() {
+            let index = rule.index.unwrap_or(index) as u64;
 
             rules_treestore.insert_with_values(
                 None,
                 None,
                 &[
-                    (0, &enabled),
-                    (1, &(index as u64)),
-                    (2, &sensor),
-                    (3, &selector),
-                    (4, &action),
-                    (5, &metadata),
+                    (0, &rule.enabled),
+                    (1, &index),
+                    (2, &rule.sensor),
+                    (3, &rule.selector),
+                    (4, &rule.action),
+                    (5, &rule.metadata),
                 ],
             );
         }
@@ -99,53 +94,66 @@ pub fn update_rules_view(builder: &gtk::Builder) -> Result<()> {
     }
 }
 
-/// Updates the ruleset of the eruption-process-monitor daemon to match the current state of the GUI
-pub fn transmit_rules_to_process_monitor(builder: &gtk::Builder) -> Result<()> {
-    let rules_treeview: gtk::TreeView = builder.object("rules_treeview").unwrap();
-
-    let mut rules: Vec<(String, String, String, String)> = Vec::new();
+/// Returns the index of the rule displayed at `path`
+fn rule_index(rules_treeview: &gtk::TreeView, path: &gtk::TreePath) -> u64 {
+    let model = rules_treeview.model().unwrap();
 
-    // generate a Vec<_> from the ruleset
-    rules_treeview
-        .model()
+    model
+        .value(&model.iter(path).unwrap(), 1)
+        .get::<u64>()
         .unwrap()
-        .foreach(|model, _path, iter| {
-            let metadata = model.value(iter, 5).get::<String>().unwrap();
-
-            if !metadata.contains("internal") {
-                let enabled = model.value(iter, 0).get::<bool>().unwrap();
-
-                let sensor = model.value(iter, 2).get::<String>().unwrap();
-                let selector = model.value(iter, 3).get::<String>().unwrap();
-                let action = model.value(iter, 4).get::<String>().unwrap();
+}
 
-                let metadata = format!(
-                    "{},user-defined",
-                    if enabled { "enabled" } else { "disabled" },
-                );
+/// Show an error dialog, after a request to the eruption-process-monitor daemon failed
+fn show_process_monitor_error(main_window: &gtk::ApplicationWindow, e: &eyre::Error) {
+    log::error!("{}", e);
 
-                rules.push((sensor, selector, action, metadata));
-            } else {
-                let sensor = model.value(iter, 2).get::<String>().unwrap();
-                let selector = model.value(iter, 3).get::<String>().unwrap();
-                let action = model.value(iter, 4).get::<String>().unwrap();
-                let metadata = model.value(iter, 5).get::<String>().unwrap();
+    let message = "Could not update ruleset".to_string();
+    let secondary = format!(
+        "Could not update the ruleset of the eruption-process-monitor daemon: {}",
+        e
+    );
 
-                rules.push((sensor, selector, action, metadata));
-            }
+    let message_dialog = MessageDialogBuilder::new()
+        .parent(main_window)
+        .destroy_with_parent(true)
+        .decorated(true)
+        .message_type(gtk::MessageType::Error)
+        .text(&message)
+        .secondary_text(&secondary)
+        .title("Error")
+        .buttons(gtk::ButtonsType::Ok)
+        .build();
 
-            false
-        });
+    message_dialog.run();
+    message_dialog.hide();
+}
 
-    // send full ruleset to the eruption-process-monitor daemon
-    dbus_client::transmit_process_monitor_rules(
-        &rules
-            .iter()
-            .map(|e| (e.0.as_str(), e.1.as_str(), e.2.as_str(), e.3.as_str()))
-            .collect::<Vec<(&str, &str, &str, &str)>>(),
-    )?;
+/// Move the selected rule up (`offset` < 0) or down (`offset` > 0) in the ruleset
+fn move_selected_rule(
+    builder: &gtk::Builder,
+    main_window: &gtk::ApplicationWindow,
+    rules_treeview: &gtk::TreeView,
+    offset: i64,
+) {
+    let selection = rules_treeview.selection().selected_rows().0;
+
+    if let Some(p) = selection.first() {
+        let from = rule_index(rules_treeview, p);
+        let to = from as i64 + offset;
+
+        if to < 0 {
+            return;
+        }
 
-    Ok(())
+        if let Err(e) = dbus_client::move_process_monitor_rule(from, to as u64) {
+            show_process_monitor_error(main_window, &e);
+        } else if update_rules_view(builder).is_ok() {
+            rules_treeview
+                .selection()
+                .select_path(&gtk::TreePath::from_indicesv(&[to as i32]));
+        }
+    }
 }
 
 /// Initialize page "Process Monitor"
@@ -168,56 +176,13 @@ pub fn initialize_process_monitor_page<A: IsA<gtk::Application>>(
 
     // register actions
     let add_rule = gio::SimpleAction::new("add-rule", None);
-    add_rule.connect_activate(clone!(@weak main_window, @weak builder, @weak rules_treeview, @weak notification_box, @weak rules_box => move |_, _| {
+    add_rule.connect_activate(clone!(@weak main_window, @weak builder, @weak notification_box, @weak rules_box => move |_, _| {
         let (response, rule) = rule::show_new_rule_dialog(&main_window);
         if response == gtk::ResponseType::Ok {
             let rule = rule.unwrap();
 
-            let tree_model = rules_treeview.model().unwrap();
-
-            let mut counter = 1;
-            tree_model.foreach(|_m, _p, _i| { counter += 1; true });
-
-            let index = counter + 1;
-            let metadata = format!(
-                "{},user-defined",
-                if rule.enabled { "enabled" } else { "disabled" },
-            );
-
-            let rules_treestore = tree_model.downcast::<gtk::TreeStore>().unwrap();
-            rules_treestore.insert_with_values(
-                None,
-                None,
-                &[
-                    (0, &rule.enabled),
-                    (1, &(index as u64)),
-                    (2, &rule.sensor),
-                    (3, &rule.selector),
-                    (4, &rule.action),
-                    (5, &metadata),
-                ],
-            );
-
-            if let Err(e) = transmit_rules_to_process_monitor(&builder) {
-                log::error!("{}", e);
-
-                let message = "Could not transmit ruleset".to_string();
-                let secondary =
-                    format!("Could not transmit the ruleset to the eruption-process-monitor daemon {}", e);
-
-                let message_dialog = MessageDialogBuilder::new()
-                    .parent(&main_window)
-                    .destroy_with_parent(true)
-                    .decorated(true)
-                    .message_type(gtk::MessageType::Error)
-                    .text(&message)
-                    .secondary_text(&secondary)
-                    .title("Error")
-                    .buttons(gtk::ButtonsType::Ok)
-                    .build();
-
-                message_dialog.run();
-                message_dialog.hide();
+            if let Err(e) = dbus_client::add_process_monitor_rule(rule.to_dbus()) {
+                show_process_monitor_error(&main_window, &e);
             }
 
             if let Err(_e) = update_rules_view(&builder) {
@@ -233,45 +198,34 @@ pub fn initialize_process_monitor_page<A: IsA<gtk::Application>>(
     application.set_accels_for_action("app.add-rule", &["<primary><shift>n"]);
 
     let remove_rule = gio::SimpleAction::new("remove-rule", None);
-    remove_rule.connect_activate(clone!(@weak builder, @weak notification_box, @weak rules_box,
-                                        @weak main_window, @weak rules_treeview => move |_, _| {
+    remove_rule.connect_activate(
+        clone!(@weak builder, @weak notification_box, @weak rules_box, @weak main_window, @weak rules_treeview => move |_, _| {
+            let selection = &rules_treeview.selection().selected_rows().0;
 
-        let selection = &rules_treeview.selection().selected_rows().0;
-        let rules_treestore: gtk::TreeStore = rules_treeview.model().unwrap().downcast::<gtk::TreeStore>().unwrap();
+            if !selection.is_empty() {
+                // remove from the back, so that the remaining indices stay valid
+                let mut indices = selection
+                    .iter()
+                    .map(|p| rule_index(&rules_treeview, p))
+                    .collect::<Vec<_>>();
 
-        if !selection.is_empty() {
-            for p in selection.iter() {
-                rules_treestore.remove(&rules_treestore.iter(p).unwrap());
-            }
+                indices.sort_unstable();
 
-            if let Err(e) = transmit_rules_to_process_monitor(&builder) {
-                log::error!("{}", e);
-
-                let message = "Could not transmit ruleset".to_string();
-                let secondary =
-                    format!("Could not transmit the ruleset to the eruption-process-monitor daemon {}", e);
-
-                let message_dialog = MessageDialogBuilder::new()
-                    .parent(&main_window)
-                    .destroy_with_parent(true)
-                    .decorated(true)
-                    .message_type(gtk::MessageType::Error)
-                    .text(&message)
-                    .secondary_text(&secondary)
-                    .title("Error")
-                    .buttons(gtk::ButtonsType::Ok)
-                    .build();
-
-                message_dialog.run();
-                message_dialog.hide();
-            }
+                for index in indices.iter().rev() {
+                    if let Err(e) = dbus_client::remove_process_monitor_rule(*index) {
+                        show_process_monitor_error(&main_window, &e);
 
-            if let Err(_e) = update_rules_view(&builder) {
-                notification_box.show_now();
-                rules_box.hide();
+                        break;
+                    }
+                }
+
+                if let Err(_e) = update_rules_view(&builder) {
+                    notification_box.show_now();
+                    rules_box.hide();
+                }
             }
-        }
-    }));
+        }),
+    );
 
     remove_rule.set_enabled(true);
 
@@ -303,67 +257,35 @@ pub fn initialize_process_monitor_page<A: IsA<gtk::Application>>(
         if !p.is_empty() {
             let p = &p[0];
 
-            let rules_treestore: gtk::TreeStore = rules_treeview.model().unwrap().downcast::<gtk::TreeStore>().unwrap();
+            let index = rule_index(&rules_treeview, p);
+
+            // fetch the current state of the rule, including fields that are not displayed
+            let rule = match dbus_client::get_process_monitor_rules() {
+                Ok(rules) => rules
+                    .iter()
+                    .map(rule::Rule::from_dbus)
+                    .find(|rule| rule.index == Some(index as usize)),
 
-            let rule_enabled = rules_treestore.value(&rules_treestore.iter(p).unwrap(), 0).get::<bool>().unwrap();
-            let index = rules_treestore.value(&rules_treestore.iter(p).unwrap(), 1).get::<u64>().unwrap();
-            let sensor = rules_treestore.value(&rules_treestore.iter(p).unwrap(), 2).get::<String>().unwrap();
-            let selector = rules_treestore.value(&rules_treestore.iter(p).unwrap(), 3).get::<String>().unwrap();
-            let action = rules_treestore.value(&rules_treestore.iter(p).unwrap(), 4).get::<String>().unwrap();
-            let metadata = rules_treestore.value(&rules_treestore.iter(p).unwrap(), 5).get::<String>().unwrap();
-
-            let rule = rule::Rule::new(Some(index as usize), rule_enabled, sensor, selector, action, metadata);
-
-            let (response, rule) = rule::show_edit_rule_dialog(&main_window, &rule);
-            if response == gtk::ResponseType::Ok {
-                let rule = rule.unwrap();
-
-                let tree_model: gtk::TreeModel = rules_treeview.model().unwrap();
-                let rules_treestore: gtk::TreeStore = tree_model.downcast::<gtk::TreeStore>().unwrap();
-
-                let index = rule.index.unwrap() as u64;
-
-                rules_treestore.insert_with_values(
-                    None,
-                    None,
-                    &[
-                        (0, &rule.enabled),
-                        (1, &index),
-                        (2, &rule.sensor),
-                        (3, &rule.selector),
-                        (4, &rule.action),
-                        (5, &rule.metadata),
-                    ],
-                );
-
-                // remove original item
-                rules_treestore.remove(&rules_treestore.iter(p).unwrap());
-
-                if let Err(e) = transmit_rules_to_process_monitor(&builder) {
-                    log::error!("{}", e);
-
-                    let message = "Could not transmit ruleset".to_string();
-                    let secondary =
-                        format!("Could not transmit the ruleset to the eruption-process-monitor daemon {}", e);
-
-                    let message_dialog = MessageDialogBuilder::new()
-                        .parent(&main_window)
-                        .destroy_with_parent(true)
-                        .decorated(true)
-                        .message_type(gtk::MessageType::Error)
-                        .text(&message)
-                        .secondary_text(&secondary)
-                        .title("Error")
-                        .buttons(gtk::ButtonsType::Ok)
-                        .build();
-
-                    message_dialog.run();
-                    message_dialog.hide();
+                Err(e) => {
+                    show_process_monitor_error(&main_window, &e);
+
+                    None
                 }
+            };
 
-                if let Err(_e) = update_rules_view(&builder) {
-                    notification_box.show_now();
-                    rules_box.hide();
+            if let Some(rule) = rule {
+                let (response, rule) = rule::show_edit_rule_dialog(&main_window, &rule);
+                if response == gtk::ResponseType::Ok {
+                    let rule = rule.unwrap();
+
+                    if let Err(e) = dbus_client::update_process_monitor_rule(index, rule.to_dbus()) {
+                        show_process_monitor_error(&main_window, &e);
+                    }
+
+                    if let Err(_e) = update_rules_view(&builder) {
+                        notification_box.show_now();
+                        rules_box.hide();
+                    }
                 }
             }
         }
@@ -374,15 +296,43 @@ pub fn initialize_process_monitor_page<A: IsA<gtk::Application>>(
     application.add_action(&edit_rule);
     application.set_accels_for_action("app.edit-rule", &[]);
 
+    let move_rule_up = gio::SimpleAction::new("move-rule-up", None);
+    move_rule_up.connect_activate(
+        clone!(@weak builder, @weak main_window, @weak rules_treeview => move |_, _| {
+            move_selected_rule(&builder, &main_window, &rules_treeview, -1);
+        }),
+    );
+
+    move_rule_up.set_enabled(false);
+
+    application.add_action(&move_rule_up);
+    application.set_accels_for_action("app.move-rule-up", &["<primary>Up"]);
+
+    let move_rule_down = gio::SimpleAction::new("move-rule-down", None);
+    move_rule_down.connect_activate(
+        clone!(@weak builder, @weak main_window, @weak rules_treeview => move |_, _| {
+            move_selected_rule(&builder, &main_window, &rules_treeview, 1);
+        }),
+    );
+
+    move_rule_down.set_enabled(false);
+
+    application.add_action(&move_rule_down);
+    application.set_accels_for_action("app.move-rule-down", &["<primary>Down"]);
+
     // enable/disable tool-buttons
     rules_treeview.selection().connect_changed(
-        clone!(@weak edit_rule, @weak remove_rule  => move |sel| {
+        clone!(@weak edit_rule, @weak remove_rule, @weak move_rule_up, @weak move_rule_down => move |sel| {
             if !sel.selected_rows().0.is_empty() {
                 edit_rule.set_enabled(true);
                 remove_rule.set_enabled(true);
+                move_rule_up.set_enabled(true);
+                move_rule_down.set_enabled(true);
             } else {
                 edit_rule.set_enabled(false);
                 remove_rule.set_enabled(false);
+                move_rule_up.set_enabled(false);
+                move_rule_down.set_enabled(false);
             }
         }),
     );
@@ -438,13 +388,17 @@ pub fn initialize_process_monitor_page<A: IsA<gtk::Application>>(
     let cell_renderer_toggle = gtk::CellRendererToggle::new();
     let cell_renderer_text = gtk::CellRendererText::new();
 
-    cell_renderer_toggle.connect_toggled(clone!(@weak builder, @weak rules_treeview => move |_cr, p| {
+    cell_renderer_toggle.connect_toggled(clone!(@weak builder, @weak main_window, @weak rules_treeview => move |_cr, p| {
             let rules_treestore: gtk::TreeStore = rules_treeview.model().unwrap().downcast::<gtk::TreeStore>().unwrap();
 
             let value = rules_treestore.value(&rules_treestore.iter(&p).unwrap(), 0).get::<bool>().unwrap();
-            rules_treestore.set_value(&rules_treestore.iter(&p).unwrap(), 0, &(!value).to_value());
+            let index = rule_index(&rules_treeview, &p);
+
+            if let Err(e) = dbus_client::set_process_monitor_rule_enabled(index, !value) {
+                show_process_monitor_error(&main_window, &e);
+            }
 
-            transmit_rules_to_process_monitor(&builder).unwrap_or_else(|e| log::error!("{}", e));
+            update_rules_view(&builder).unwrap_or_else(|e| log::error!("{}", e));
         }));
 
     enabled_column.pack_start(&cell_renderer_toggle, false);
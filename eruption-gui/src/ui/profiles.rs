@@ -1452,6 +1452,27 @@ pub fn initialize_profiles_page<A: IsA<gtk::Application>>(
         let _result = populate_stack_widget(&builder, &profile).map_err(|e| { log::error!("{}", e) });
     }));
 
+    // import profile bundles that are dropped onto the list of profiles
+    profiles_treeview.drag_dest_set(
+        gtk::DestDefaults::ALL,
+        &[gtk::TargetEntry::new(
+            "text/uri-list",
+            gtk::TargetFlags::OTHER_APP,
+            0,
+        )],
+        gtk::gdk::DragAction::COPY,
+    );
+
+    profiles_treeview.connect_drag_data_received(
+        clone!(@weak builder => move |_tv, _context, _x, _y, data, _info, _time| {
+            for uri in data.uris() {
+                if let Some(bundle_file) = gio::File::for_uri(&uri).path() {
+                    let _result = import_bundle(&builder, &bundle_file).map_err(|e| { log::error!("{}", e) });
+                }
+            }
+        }),
+    );
+
     profiles_treeview.show_all();
 
     update_profile_state(builder)?;
@@ -1460,6 +1481,80 @@ pub fn initialize_profiles_page<A: IsA<gtk::Application>>(
     Ok(())
 }
 
+/// Instruct the daemon to import a profile bundle, and add the imported profile to the list
+fn import_bundle(builder: &Builder, bundle_file: &Path) -> Result<()> {
+    let main_window: gtk::ApplicationWindow = builder.object("main_window").unwrap();
+    let profiles_treeview: TreeView = builder.object("profiles_treeview").unwrap();
+
+    match dbus_client::import_bundle(&bundle_file, false) {
+        Ok(profile_file) => {
+            log::info!("Imported profile bundle: {}", bundle_file.display());
+
+            let model = profiles_treeview
+                .model()
+                .unwrap()
+                .downcast::<TreeStore>()
+                .unwrap();
+
+            // the profile may have replaced a profile that is already listed
+            let mut listed = false;
+
+            model.foreach(|model, _path, iter| {
+                listed = model.value(iter, 3).get::<String>().ok().as_ref() == Some(&profile_file);
+                listed
+            });
+
+            if !listed {
+                let profile = Profile::from(Path::new(&profile_file))?;
+
+                let index = model.iter_n_children(None) as u64;
+                let filename = Path::new(&profile_file)
+                    .file_name()
+                    .unwrap_or_else(|| OsStr::new("<error>"))
+                    .to_string_lossy()
+                    .to_string();
+
+                model.insert_with_values(
+                    None,
+                    None,
+                    &[
+                        (0, &index),
+                        (1, &profile.name),
+                        (2, &filename),
+                        (3, &profile_file),
+                    ],
+                );
+            }
+
+            Ok(())
+        }
+
+        Err(e) => {
+            let message = "Could not import the profile bundle".to_string();
+            let secondary = format!("Error importing {}: {}", bundle_file.display(), e);
+
+            let message_dialog = MessageDialogBuilder::new()
+                .parent(&main_window)
+                .destroy_with_parent(true)
+                .decorated(true)
+                .message_type(MessageType::Error)
+                .text(&message)
+                .secondary_text(&secondary)
+                .title("Error")
+                .buttons(ButtonsType::Ok)
+                .build();
+
+            message_dialog.run();
+            message_dialog.hide();
+
+            Err(ProfilesError::MethodCallError {
+                description: "Could not import the profile bundle".to_string(),
+            }
+            .into())
+        }
+    }
+}
+
 /// Register global actions and keyboard accelerators
 fn register_actions<A: IsA<gtk::Application>>(application: &A, builder: &Builder) -> Result<()> {
     let application = application.as_ref();
@@ -18,6 +18,7 @@
 */
 
 // use eyre;
+use dbus::arg::{prop_cast, PropMap, RefArg, Variant};
 use glib::clone;
 use gtk::glib;
 use gtk::prelude::*;
@@ -38,6 +39,8 @@ pub struct Rule {
     pub selector: String,
     pub action: String,
     pub metadata: String,
+    pub name: Option<String>,
+    pub description: Option<String>,
 }
 
 impl Rule {
@@ -56,8 +59,84 @@ impl Rule {
             selector,
             action,
             metadata,
+            name: None,
+            description: None,
         }
     }
+
+    /// Create a rule from the structured representation used by the
+    /// eruption-process-monitor D-Bus interface
+    pub fn from_dbus(map: &PropMap) -> Self {
+        let get_string = |key: &str| prop_cast::<String>(map, key).cloned();
+
+        let enabled = prop_cast::<bool>(map, "enabled").cloned().unwrap_or(true);
+        let internal = prop_cast::<bool>(map, "internal").cloned().unwrap_or(false);
+
+        // profiles are shown by their file name, slots by their (1-based) number
+        // and brightness values as a percentage
+        let action = get_string("profile").unwrap_or_else(|| {
+            prop_cast::<u64>(map, "slot")
+                .map(|slot| format!("{}", slot))
+                .or_else(|| {
+                    prop_cast::<i64>(map, "brightness").map(|brightness| format!("{}%", brightness))
+                })
+                .unwrap_or_default()
+        });
+
+        let metadata = format!(
+            "{},{}",
+            if enabled { "enabled" } else { "disabled" },
+            if internal { "internal" } else { "user-defined" }
+        );
+
+        Self {
+            index: prop_cast::<u64>(map, "index").map(|index| *index as usize),
+            enabled,
+            sensor: get_string("sensor").unwrap_or_default(),
+            selector: get_string("selector").unwrap_or_default(),
+            action,
+            metadata,
+            name: get_string("name"),
+            description: get_string("description"),
+        }
+    }
+
+    /// Convert the rule to the structured representation used by the
+    /// eruption-process-monitor D-Bus interface
+    pub fn to_dbus(&self) -> PropMap {
+        fn variant<T: RefArg + 'static>(value: T) -> Variant<Box<dyn RefArg>> {
+            Variant(Box::new(value))
+        }
+
+        let mut result = PropMap::new();
+
+        result.insert("enabled".to_string(), variant(self.enabled));
+        result.insert("sensor".to_string(), variant(self.sensor.clone()));
+        result.insert("selector".to_string(), variant(self.selector.clone()));
+
+        let action = self.action.trim();
+
+        if let Some(brightness) = action
+            .strip_suffix('%')
+            .and_then(|brightness| brightness.trim().parse::<i64>().ok())
+        {
+            result.insert("brightness".to_string(), variant(brightness));
+        } else if let Ok(slot) = action.parse::<u64>() {
+            result.insert("slot".to_string(), variant(slot));
+        } else {
+            result.insert("profile".to_string(), variant(action.to_string()));
+        }
+
+        if let Some(name) = &self.name {
+            result.insert("name".to_string(), variant(name.clone()));
+        }
+
+        if let Some(description) = &self.description {
+            result.insert("description".to_string(), variant(description.clone()));
+        }
+
+        result
+    }
 }
 
 /// Shows the "new rule" dialog
@@ -153,14 +232,18 @@ pub fn show_edit_rule_dialog<W: IsA<gtk::Window>>(
 
     let response = rule_dialog.run();
 
-    let result = Some(Rule::new(
-        rule.index,
-        rule_enabled.is_active(),
-        sensor.active_id().unwrap().to_string(),
-        selector.text().to_string(),
-        action.text().to_string(),
-        "".to_string(),
-    ));
+    let result = Some(Rule {
+        name: rule.name.clone(),
+        description: rule.description.clone(),
+        ..Rule::new(
+            rule.index,
+            rule_enabled.is_active(),
+            sensor.active_id().unwrap().to_string(),
+            selector.text().to_string(),
+            action.text().to_string(),
+            "".to_string(),
+        )
+    });
 
     (response, result)
 }
@@ -20,6 +20,7 @@
 
 about = Ein Keymap Editor für Eruption
 verbose-about = Verboser Modus (-v, -vv, -vvv, etc.)
+layout-about = Das XKB Tastaturlayout, z.B. de oder fr(azerty); standardmäßig wird das Layout des Systems verwendet
 completions-about = Shell Definitionen für Autovervollständigung erzeugen
 assistant-about = Startet einen Assistenten zum Erstellen eines Tasten Mappings
 list-about = Listet alle verfügbaren Keymaps auf
@@ -33,6 +34,12 @@ mapping-add-about = Ein Tasten Mapping von `source` nach `action` hinzufügen
 mapping-remove-about = Das Tasten Mapping von `source` entfernen
 mapping-enable-about = Aktiviert eine Mapping Regel
 mapping-disable-about = Deaktiviert eine Mapping Regel
+layer-about = Deklariert oder entfernt eine benannte Ebene
+layer-add-about = Deklariert eine benannte Ebene, oder benennt eine bestehende Ebene um
+layer-remove-about = Entfernt den Namen einer Ebene; die Mappings der Ebene bleiben erhalten
+record-about = Nimmt ein Makro auf und speichert es in einer Keymap
+import-about = Importiert eine Keymap aus einer keyd, xremap oder QMK/VIA Datei
+export-about = Exportiert eine Keymap in ein anderes Format (keyd)
 
 source = Quelle
 action = Aktion
@@ -42,10 +49,22 @@ flags = Attribute
 enabled = Aktiviert
 disabled = Deaktiviert
 
+layer = Ebene
+no-layer = Mappings ohne Ebenen
+
+recording = Aufnahme läuft, Enter drücken zum Beenden...
+recorded-macro = Makro "{$name}" mit {$events} Ereignissen aufgenommen ({$millis} ms)
+imported = {$mappings} Mappings und {$layers} Ebenen importiert, {$warnings} Konstrukte konnten nicht konvertiert werden
+macro = Makro
+events = Ereignisse
+duration = Dauer
+
 n-a = N.a.
 
 symbol = Symbol
 code = Code
+keycap = Tastenbeschriftung
+layout = Layout
 
 compiling = Übersetze Keymap: 
 
@@ -56,6 +75,7 @@ functions-in-file = Funktionen in Lua Datei:
 action-not-implemented = Diese Aktion ist nicht implementiert
 action-disabled = Diese Aktion ist deaktiviert
 action-disabled-null = Diese Aktion ist deaktiviert: null
+export-not-supported = Dieses Mapping kann im Zielformat nicht ausgedrückt werden
 
 license-header = Eruption ist freie Software. Sie können es unter den Bedingungen der
  GNU General Public License, wie von der Free Software Foundation
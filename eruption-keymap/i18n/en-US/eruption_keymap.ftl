@@ -20,6 +20,7 @@
 
 about = A CLI keymap editor for Eruption
 verbose-about = Verbose mode (-v, -vv, -vvv, etc.)
+layout-about = The XKB keyboard layout, e.g. de or fr(azerty); the layout of the system is used by default
 completions-about = Generate shell completions
 assistant-about = Run an assistant that guides you through creating one or more key mappings
 list-about = List all available keymaps
@@ -33,6 +34,12 @@ mapping-add-about = Add a mapping rule for `source` that executes `action`
 mapping-remove-about = Remove the mapping rule for `source`
 mapping-enable-about = Enable a mapping rule
 mapping-disable-about = Disable a mapping rule
+layer-about = Declare or remove a named layer
+layer-add-about = Declare a named layer, or rename an existing one
+layer-remove-about = Remove the name of a layer; the mappings on the layer are kept
+record-about = Record a macro and save it to a keymap
+import-about = Import a keymap from a keyd, xremap or QMK/VIA file
+export-about = Export a keymap to another format (keyd)
 
 source = Source
 action = Action
@@ -42,10 +49,22 @@ flags = Flags
 enabled = Enabled
 disabled = Disabled
 
+layer = Layer
+no-layer = Mappings without layers
+
+recording = Recording, press Enter to stop...
+recorded-macro = Recorded macro "{$name}" with {$events} events ({$millis} ms)
+imported = Imported {$mappings} mappings and {$layers} layers, {$warnings} constructs could not be converted
+macro = Macro
+events = Events
+duration = Duration
+
 n-a = N.a.
 
 symbol = Symbol
 code = Code
+keycap = Keycap
+layout = Layout
 
 compiling = Compiling keymap: 
 
@@ -56,6 +75,7 @@ functions-in-file = Functions in Lua file:
 action-not-implemented = This action is not implemented
 action-disabled = This action is disabled
 action-disabled-null = This action is disabled: null
+export-not-supported = This mapping can not be expressed in the target format
 
 license-header = Eruption is free software: you can redistribute it and/or modify
  it under the terms of the GNU General Public License as published by
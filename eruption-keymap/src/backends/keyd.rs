@@ -0,0 +1,371 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::{fs, path::Path};
+
+use chrono::Utc;
+use colored::Colorize;
+
+use crate::constants;
+use crate::mapping::{Action, Event, Key, KeyMappingTable};
+use crate::{messages, tr, util};
+
+pub type Result<T> = std::result::Result<T, eyre::Error>;
+
+/// The modifier keys, along with the prefix and the name of the layer that keyd uses for them
+const MODIFIERS: &[(&str, &str, &str)] = &[
+    ("KEY_LEFTCTRL", "C", "control"),
+    ("KEY_RIGHTCTRL", "C", "control"),
+    ("KEY_LEFTSHIFT", "S", "shift"),
+    ("KEY_RIGHTSHIFT", "S", "shift"),
+    ("KEY_LEFTALT", "A", "alt"),
+    ("KEY_LEFTMETA", "M", "meta"),
+    ("KEY_RIGHTMETA", "M", "meta"),
+    ("KEY_RIGHTALT", "G", "altgr"),
+];
+
+/// Generates a keyd configuration file, see keyd(1)
+#[derive(Debug)]
+pub struct KeydBackend {}
+
+impl KeydBackend {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl super::Backend for KeydBackend {
+    fn generate(&self, table: &KeyMappingTable) -> Result<String> {
+        let mut text = String::new();
+
+        writeln!(
+            &mut text,
+            "# Exported from: {}\n# Exported at: {}\n#\n# {}\n\n[ids]\n\n*",
+            table.file_name().display(),
+            Utc::now(),
+            table.description()
+        )?;
+
+        // the lines of the [main] section and of each layer
+        let mut main = Vec::new();
+        let mut layers: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+
+        // tap and hold actions are combined to a single `overload()`
+        let mut tap_holds: BTreeMap<Key, (u64, Option<&Action>, Option<&Action>)> = BTreeMap::new();
+
+        for (index, (source, rule)) in table.mappings().iter().enumerate() {
+            if !rule.enabled {
+                main.push(format!("# disabled: {} -> {}", source, rule.action));
+                continue;
+            }
+
+            let action = keyd_action(&rule.action, table);
+
+            match (&source.event, action) {
+                (Event::SimpleKeyDown(key), Some(action)) => {
+                    main.push(format!("{} = {}", key_name(key), action));
+                }
+
+                (Event::EasyShiftKeyDown(key), Some(action)) => {
+                    for layer in &source.layers.0 {
+                        layers.entry(*layer).or_default().push(format!(
+                            "{} = {}",
+                            key_name(key),
+                            action
+                        ));
+                    }
+                }
+
+                (Event::KeyChord(chord), Some(action)) => {
+                    let keys = chord.keys.iter().map(key_name).collect::<Vec<_>>();
+
+                    main.push(format!("{} = {}", keys.join("+"), action));
+                }
+
+                (Event::KeyHold(tap_hold), _) => {
+                    let entry = tap_holds.entry(tap_hold.key.clone()).or_insert((
+                        tap_hold.threshold_millis,
+                        None,
+                        None,
+                    ));
+
+                    entry.1 = Some(&rule.action);
+                }
+
+                (Event::KeyTap(tap_hold), _) => {
+                    let entry = tap_holds.entry(tap_hold.key.clone()).or_insert((
+                        tap_hold.threshold_millis,
+                        None,
+                        None,
+                    ));
+
+                    entry.2 = Some(&rule.action);
+                }
+
+                _ => {
+                    messages::warning!(
+                        "Rule: {}: {}",
+                        &format!("#{:0>2}", index + 1),
+                        tr!("export-not-supported").yellow()
+                    );
+
+                    main.push(format!("# not supported: {} -> {}", source, rule.action));
+                }
+            }
+        }
+
+        for (key, (threshold_millis, hold, tap)) in tap_holds {
+            let overload = match (hold, tap) {
+                (Some(hold), Some(tap)) => overload(hold, tap, threshold_millis, table),
+                _ => None,
+            };
+
+            match overload {
+                Some(overload) => main.push(format!("{} = {}", key_name(&key), overload)),
+
+                None => {
+                    messages::warning!(
+                        "{}: {}",
+                        key_name(&key),
+                        tr!("export-not-supported").yellow()
+                    );
+
+                    main.push(format!("# not supported: tap/hold of {}", key));
+                }
+            }
+        }
+
+        writeln!(&mut text, "\n[main]\n")?;
+
+        for line in main {
+            writeln!(&mut text, "{}", line)?;
+        }
+
+        for (layer, lines) in layers {
+            writeln!(&mut text, "\n[{}]\n", layer_name(layer, table))?;
+
+            for line in lines {
+                writeln!(&mut text, "{}", line)?;
+            }
+        }
+
+        Ok(text)
+    }
+
+    fn write_to_file<P: AsRef<Path>>(&self, path: P, table: &KeyMappingTable) -> Result<()> {
+        let path = path.as_ref();
+
+        let data = self.generate(table)?;
+        fs::write(path, &data)?;
+
+        Ok(())
+    }
+}
+
+/// keyd uses the lower case Linux key names, except for the control keys
+fn event_name(event: u32) -> String {
+    let name = util::evdev_event_code_to_string(event);
+
+    match name.as_str() {
+        "KEY_LEFTCTRL" => "leftcontrol".to_string(),
+        "KEY_RIGHTCTRL" => "rightcontrol".to_string(),
+
+        name => name.trim_start_matches("KEY_").to_lowercase(),
+    }
+}
+
+fn key_name(key: &Key) -> String {
+    util::key_index_to_symbol(key.key_index + 1, (key.usb_vid, key.usb_pid))
+        .and_then(|symbol| util::evdev_event_code_from_string(&symbol))
+        .map(|event| event_name(event as u32))
+        .unwrap_or_else(|| format!("{}", key.key_index))
+}
+
+/// keyd layer names are identifiers, unnamed layers are called `layer<n>`
+fn layer_name(layer: usize, table: &KeyMappingTable) -> String {
+    match table.layers().get(&layer) {
+        Some(info) => info.name.clone(),
+        None => format!("layer{}", layer),
+    }
+}
+
+fn modifier(event: u32) -> Option<(&'static str, &'static str)> {
+    let name = util::evdev_event_code_to_string(event);
+
+    MODIFIERS
+        .iter()
+        .find(|(key, _, _)| *key == name)
+        .map(|(_, prefix, layer)| (*prefix, *layer))
+}
+
+/// Convert an action to a key expression, like `C-S-t`
+fn key_expression(action: &Action) -> Option<String> {
+    match action {
+        Action::InjectKey(key) => Some(event_name(key.event)),
+
+        Action::KeyCombo(keys) => {
+            let (modifiers, keys): (Vec<_>, Vec<_>) =
+                keys.iter().partition(|key| modifier(key.event).is_some());
+
+            match keys.as_slice() {
+                [key] => {
+                    let mut expression = String::new();
+
+                    for key in modifiers {
+                        let (prefix, _) = modifier(key.event)?;
+                        write!(&mut expression, "{}-", prefix).ok()?;
+                    }
+
+                    expression.push_str(&event_name(key.event));
+
+                    Some(expression)
+                }
+
+                _ => None,
+            }
+        }
+
+        _ => None,
+    }
+}
+
+fn macro_tokens(action: &Action, tokens: &mut Vec<String>) -> Option<()> {
+    match action {
+        Action::Delay(millis) => tokens.push(format!("{}ms", millis)),
+
+        Action::TypeText(text) => {
+            for (index, word) in text.split(' ').enumerate() {
+                if index > 0 {
+                    tokens.push("space".to_string());
+                }
+
+                if !word.is_empty() {
+                    tokens.push(word.to_string());
+                }
+            }
+        }
+
+        Action::Sequence(actions) => {
+            for action in actions {
+                macro_tokens(action, tokens)?;
+            }
+        }
+
+        action => tokens.push(key_expression(action)?),
+    }
+
+    Some(())
+}
+
+fn keyd_action(action: &Action, table: &KeyMappingTable) -> Option<String> {
+    match action {
+        Action::Null => Some("noop".to_string()),
+
+        Action::MomentaryLayer(layer) => Some(format!("layer({})", layer_name(*layer, table))),
+        Action::ToggleLayer(layer) => Some(format!("toggle({})", layer_name(*layer, table))),
+        Action::OneShotLayer(layer) => Some(format!("oneshot({})", layer_name(*layer, table))),
+
+        Action::Sequence(_) | Action::TypeText(_) => {
+            let mut tokens = Vec::new();
+            macro_tokens(action, &mut tokens)?;
+
+            Some(format!("macro({})", tokens.join(" ")))
+        }
+
+        action => key_expression(action),
+    }
+}
+
+fn overload(
+    hold: &Action,
+    tap: &Action,
+    threshold_millis: u64,
+    table: &KeyMappingTable,
+) -> Option<String> {
+    let layer = match hold {
+        Action::MomentaryLayer(layer) => layer_name(*layer, table),
+        Action::InjectKey(key) => modifier(key.event)?.1.to_string(),
+
+        _ => return None,
+    };
+
+    let tap = key_expression(tap)?;
+
+    if threshold_millis == constants::DEFAULT_TAP_HOLD_THRESHOLD_MILLIS {
+        Some(format!("overload({}, {})", layer, tap))
+    } else {
+        Some(format!(
+            "overloadt({}, {}, {})",
+            layer, tap, threshold_millis
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use evdev_rs::enums::EV_KEY;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::backends::Backend;
+    use crate::importers::{self, tests::*};
+    use crate::mapping::{Rule, Source};
+
+    /// The mappings of a table without the descriptions of the rules, which name their origin
+    fn mappings(table: &KeyMappingTable) -> Vec<(Source, bool, Action)> {
+        table
+            .mappings()
+            .iter()
+            .map(|(source, rule)| (source.clone(), rule.enabled, rule.action.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let imported = importers::import("keyd", &fixture("example.keyd.conf"), DEVICE)?;
+
+        let text = KeydBackend::new().generate(&imported.table)?;
+        let reimported = importers::import("keyd", &text, DEVICE)?;
+
+        assert_eq!(mappings(&reimported.table), mappings(&imported.table));
+        assert_eq!(reimported.table.layers(), imported.table.layers());
+        assert_eq!(reimported.warnings, Vec::<String>::new());
+
+        Ok(())
+    }
+
+    #[test]
+    fn disabled_rules_are_commented_out() -> Result<()> {
+        let mut table = KeyMappingTable::new();
+
+        table.insert(
+            Source::new(Event::SimpleKeyDown(key(EV_KEY::KEY_F1))),
+            Rule::new(Action::Null, "disabled", false),
+        );
+
+        let text = KeydBackend::new().generate(&table)?;
+
+        assert!(text.contains("\n# disabled: "), "{}", text);
+        assert!(!text.contains("\nf1 = "), "{}", text);
+
+        Ok(())
+    }
+}
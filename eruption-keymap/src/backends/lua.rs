@@ -301,6 +301,24 @@ impl super::Backend for LuaBackend {
                             tr!("action-not-implemented").yellow()
                         );
                     }
+
+                    // timed and mouse sources are only supported by the native keymap engine
+                    Event::KeyTap(_)
+                    | Event::KeyHold(_)
+                    | Event::KeyMultiTap(_)
+                    | Event::KeyChord(_)
+                    | Event::KeySequence(_)
+                    | Event::MouseGesture(_)
+                    | Event::MouseWheelTilt(_)
+                    | Event::MouseWheelSpeed(_) => {
+                        messages::warning!(
+                            "Rule: {}: {}",
+                            &format!("#{:0>2}", index + 1),
+                            tr!("action-not-implemented").yellow()
+                        );
+
+                        writeln!(&mut text, "-- ACTION IS NOT IMPLEMENTED")?;
+                    }
                 },
 
                 Action::Call(call) => {
@@ -510,8 +528,36 @@ impl super::Backend for LuaBackend {
 
                             writeln!(&mut text, "-- ACTION IS DISABLED")?;
                         }
+
+                        Event::KeyTap(_)
+                        | Event::KeyHold(_)
+                        | Event::KeyMultiTap(_)
+                        | Event::KeyChord(_)
+                        | Event::KeySequence(_)
+                        | Event::MouseGesture(_)
+                        | Event::MouseWheelTilt(_)
+                        | Event::MouseWheelSpeed(_) => {
+                            messages::warning!(
+                                "Rule: {}: {}",
+                                &format!("#{:0>2}", index + 1),
+                                tr!("action-not-implemented").yellow()
+                            );
+
+                            writeln!(&mut text, "-- ACTION IS NOT IMPLEMENTED")?;
+                        }
                     }
                 }
+
+                // these actions are only supported by the native keymap engine of the daemon
+                _ => {
+                    messages::warning!(
+                        "Rule: {}: {}",
+                        &format!("#{:0>2}", index + 1),
+                        tr!("action-not-implemented").yellow()
+                    );
+
+                    writeln!(&mut text, "-- ACTION IS NOT IMPLEMENTED")?;
+                }
             }
 
             // insert a newline
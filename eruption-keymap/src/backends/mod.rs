@@ -21,6 +21,7 @@ use std::path::Path;
 
 use crate::mapping::KeyMappingTable;
 
+pub mod keyd;
 pub mod lua;
 pub mod native;
 
@@ -78,6 +78,61 @@ pub fn get_managed_devices() -> Result<(Vec<(u16, u16)>, Vec<(u16, u16)>, Vec<(u
     Ok(result)
 }
 
+/// Start recording a macro in the eruption daemon
+pub fn start_recording() -> Result<()> {
+    use keymap::OrgEruptionKeymap;
+
+    let conn = Connection::new_system()?;
+    let keymap_proxy = conn.with_proxy(
+        "org.eruption",
+        "/org/eruption/keymap",
+        Duration::from_secs(constants::DBUS_TIMEOUT_MILLIS as u64),
+    );
+
+    let _result = keymap_proxy.start_recording()?;
+
+    Ok(())
+}
+
+/// Stop recording a macro, returns the recorded steps serialized as JSON
+pub fn stop_recording() -> Result<String> {
+    use keymap::OrgEruptionKeymap;
+
+    let conn = Connection::new_system()?;
+    let keymap_proxy = conn.with_proxy(
+        "org.eruption",
+        "/org/eruption/keymap",
+        Duration::from_secs(constants::DBUS_TIMEOUT_MILLIS as u64),
+    );
+
+    let result = keymap_proxy.stop_recording()?;
+
+    Ok(result)
+}
+
+mod keymap {
+    use dbus::blocking;
+
+    pub trait OrgEruptionKeymap {
+        fn start_recording(&self) -> Result<bool, dbus::Error>;
+        fn stop_recording(&self) -> Result<String, dbus::Error>;
+    }
+
+    impl<'a, C: ::std::ops::Deref<Target = blocking::Connection>> OrgEruptionKeymap
+        for blocking::Proxy<'a, C>
+    {
+        fn start_recording(&self) -> Result<bool, dbus::Error> {
+            self.method_call("org.eruption.Keymap", "StartRecording", ())
+                .map(|r: (bool,)| r.0)
+        }
+
+        fn stop_recording(&self) -> Result<String, dbus::Error> {
+            self.method_call("org.eruption.Keymap", "StopRecording", ())
+                .map(|r: (String,)| r.0)
+        }
+    }
+}
+
 mod slot {
     // This code was autogenerated with `dbus-codegen-rust -s -d org.eruption -p /org/eruption/slot -m None`, see https://github.com/diwic/dbus-rs
     use dbus::arg;
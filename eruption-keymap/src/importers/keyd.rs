@@ -0,0 +1,550 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+//! Import of keyd configuration files, see keyd(1)
+
+use std::collections::BTreeSet;
+
+use evdev_rs::enums::EV_KEY;
+
+use crate::constants;
+use crate::mapping::*;
+use crate::parsers::action::key_name_to_event;
+
+use super::{key_action, Import, ImportError, Importer, Result};
+
+/// The layers that keyd activates together with a modifier key
+const MODIFIER_LAYERS: &[(&str, EV_KEY)] = &[
+    ("control", EV_KEY::KEY_LEFTCTRL),
+    ("shift", EV_KEY::KEY_LEFTSHIFT),
+    ("alt", EV_KEY::KEY_LEFTALT),
+    ("meta", EV_KEY::KEY_LEFTMETA),
+    ("altgr", EV_KEY::KEY_RIGHTALT),
+];
+
+/// The modifier prefixes of a keyd key expression, like in `C-S-t`
+const MODIFIER_PREFIXES: &[(&str, EV_KEY)] = &[
+    ("C", EV_KEY::KEY_LEFTCTRL),
+    ("S", EV_KEY::KEY_LEFTSHIFT),
+    ("A", EV_KEY::KEY_LEFTALT),
+    ("M", EV_KEY::KEY_LEFTMETA),
+    ("G", EV_KEY::KEY_RIGHTALT),
+];
+
+/// Names that keyd uses in place of the Linux key names
+const KEY_ALIASES: &[(&str, EV_KEY)] = &[
+    ("leftcontrol", EV_KEY::KEY_LEFTCTRL),
+    ("rightcontrol", EV_KEY::KEY_RIGHTCTRL),
+    ("control", EV_KEY::KEY_LEFTCTRL),
+    ("shift", EV_KEY::KEY_LEFTSHIFT),
+    ("alt", EV_KEY::KEY_LEFTALT),
+    ("meta", EV_KEY::KEY_LEFTMETA),
+    ("altgr", EV_KEY::KEY_RIGHTALT),
+    ("print", EV_KEY::KEY_SYSRQ),
+];
+
+/// The right hand side of a keyd binding
+enum Binding {
+    Simple(Action),
+
+    /// `overload(layer, key)`: act as `hold` when held down and as `tap` when tapped
+    Overload {
+        hold: Action,
+        tap: Action,
+        threshold_millis: u64,
+    },
+}
+
+pub fn import(data: &str, device: (u16, u16)) -> Result<Import> {
+    let mut importer = Importer::new(device);
+
+    // `None` while inside of a section that is skipped
+    let mut section: Option<(String, Option<usize>)> = None;
+
+    for (index, line) in data.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            let name = line
+                .strip_prefix('[')
+                .and_then(|l| l.strip_suffix(']'))
+                .ok_or_else(|| ImportError::SyntaxError {
+                    line: line_number,
+                    description: format!("Invalid section header: {}", line),
+                })?
+                .trim();
+
+            section = if name == "ids" {
+                None
+            } else if name == "main" {
+                Some((name.to_string(), None))
+            } else if name == "global" || name == "aliases" {
+                importer.warn(format!(
+                    "line {}: The section [{}] is not supported",
+                    line_number, name
+                ));
+
+                None
+            } else if MODIFIER_LAYERS.iter().any(|(layer, _)| *layer == name) {
+                importer.warn(format!(
+                    "line {}: The modifier layer [{}] is not supported",
+                    line_number, name
+                ));
+
+                None
+            } else if let Some((layer, modifiers)) = name.split_once(':') {
+                importer.warn(format!(
+                    "line {}: The modifiers '{}' of layer [{}] are ignored",
+                    line_number, modifiers, layer
+                ));
+
+                Some((layer.to_string(), Some(importer.layer(layer))))
+            } else {
+                Some((name.to_string(), Some(importer.layer(name))))
+            };
+
+            continue;
+        }
+
+        let (section_name, layer) = match &section {
+            Some(section) => section.clone(),
+            None => continue,
+        };
+
+        let (lhs, rhs) = line
+            .split_once('=')
+            .ok_or_else(|| ImportError::SyntaxError {
+                line: line_number,
+                description: format!("Expected a binding of the form 'key = action': {}", line),
+            })?;
+
+        let (lhs, rhs) = (lhs.trim(), rhs.trim());
+
+        let binding = match parse_binding(rhs, &mut importer) {
+            Ok(binding) => binding,
+
+            Err(reason) => {
+                importer.warn(format!("line {}: {}: {}", line_number, rhs, reason));
+                continue;
+            }
+        };
+
+        let keys = lhs
+            .split('+')
+            .map(|name| {
+                key_code(name.trim())
+                    .and_then(|code| importer.key(code))
+                    .ok_or_else(|| name.trim().to_string())
+            })
+            .collect::<std::result::Result<Vec<Key>, _>>();
+
+        let keys = match keys {
+            Ok(keys) => keys,
+
+            Err(name) => {
+                importer.warn(format!(
+                    "line {}: Unknown key or key not present on the device: {}",
+                    line_number, name
+                ));
+                continue;
+            }
+        };
+
+        let description = format!("keyd [{}]: {}", section_name, line);
+
+        match binding {
+            Binding::Simple(action) => {
+                if keys.len() > 1 {
+                    if layer.is_some() {
+                        importer.warn(format!(
+                            "line {}: Chords are only supported in the [main] section",
+                            line_number
+                        ));
+                        continue;
+                    }
+
+                    let chord = KeyCombination {
+                        keys,
+                        timeout_millis: constants::DEFAULT_CHORD_TIMEOUT_MILLIS,
+                    };
+
+                    importer.add(Event::KeyChord(chord), None, action, &description);
+                } else if let Some(layer) = layer {
+                    let event = Event::EasyShiftKeyDown(keys[0].clone());
+
+                    importer.add(event, Some(layer), action, &description);
+                } else {
+                    let event = Event::SimpleKeyDown(keys[0].clone());
+
+                    importer.add(event, None, action, &description);
+                }
+            }
+
+            Binding::Overload {
+                hold,
+                tap,
+                threshold_millis,
+            } => {
+                if keys.len() > 1 || layer.is_some() {
+                    importer.warn(format!(
+                        "line {}: Overloading is only supported for single keys in [main]",
+                        line_number
+                    ));
+                    continue;
+                }
+
+                let tap_hold = TapHold {
+                    key: keys[0].clone(),
+                    threshold_millis,
+                };
+
+                importer.add(Event::KeyHold(tap_hold.clone()), None, hold, &description);
+                importer.add(Event::KeyTap(tap_hold), None, tap, &description);
+            }
+        }
+    }
+
+    Ok(importer.finish("Imported from keyd"))
+}
+
+/// Resolve a keyd key name
+fn key_code(name: &str) -> Option<u32> {
+    // numbers other than the digit keys are text, not event codes
+    if name.len() > 1 && name.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    KEY_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map(|(_, key)| *key as u32)
+        .or_else(|| key_name_to_event(name))
+}
+
+/// Parse a key expression with optional modifiers, like `esc` or `C-S-t`
+fn parse_key_expression(expression: &str) -> Option<Action> {
+    let mut modifiers = BTreeSet::new();
+    let mut rest = expression;
+
+    while let Some((prefix, tail)) = rest.split_once('-') {
+        let (_, modifier) = MODIFIER_PREFIXES.iter().find(|(p, _)| *p == prefix)?;
+        modifiers.insert(*modifier as u32);
+
+        rest = tail;
+    }
+
+    key_code(rest).map(|key| key_action(&modifiers, key))
+}
+
+/// Resolve the argument of a layer function, returns the modifier key for modifier layers
+fn parse_layer(name: &str, importer: &mut Importer) -> std::result::Result<usize, u32> {
+    match MODIFIER_LAYERS.iter().find(|(layer, _)| *layer == name) {
+        Some((_, key)) => Err(*key as u32),
+        None => Ok(importer.layer(name)),
+    }
+}
+
+fn parse_binding(rhs: &str, importer: &mut Importer) -> std::result::Result<Binding, &'static str> {
+    if rhs == "noop" {
+        return Ok(Binding::Simple(Action::Null));
+    }
+
+    let (function, args) = match rhs.split_once('(') {
+        Some((function, args)) => (
+            function.trim(),
+            args.strip_suffix(')')
+                .ok_or("Missing closing parenthesis")?,
+        ),
+
+        None => {
+            return parse_key_expression(rhs)
+                .map(Binding::Simple)
+                .ok_or("Unknown key");
+        }
+    };
+
+    let args: Vec<&str> = if function == "macro" {
+        vec![args]
+    } else {
+        args.split(',').map(str::trim).collect()
+    };
+
+    match (function, args.as_slice()) {
+        ("layer", [layer]) => Ok(Binding::Simple(match parse_layer(layer, importer) {
+            Ok(layer) => Action::MomentaryLayer(layer),
+            Err(modifier) => Action::InjectKey(EvdevEvent { event: modifier }),
+        })),
+
+        ("toggle", [layer]) => parse_layer(layer, importer)
+            .map(|layer| Binding::Simple(Action::ToggleLayer(layer)))
+            .map_err(|_| "Toggling a modifier is not supported"),
+
+        ("oneshot", [layer]) => parse_layer(layer, importer)
+            .map(|layer| Binding::Simple(Action::OneShotLayer(layer)))
+            .map_err(|_| "One-shot modifiers are not supported"),
+
+        ("overload", [layer, tap]) => overload(
+            layer,
+            tap,
+            constants::DEFAULT_TAP_HOLD_THRESHOLD_MILLIS,
+            importer,
+        ),
+
+        ("overloadt" | "overloadt2", [layer, tap, timeout]) => {
+            let threshold_millis = timeout.parse().map_err(|_| "Invalid timeout")?;
+
+            overload(layer, tap, threshold_millis, importer)
+        }
+
+        ("macro", [expression]) => parse_macro(expression).map(Binding::Simple),
+
+        _ => Err("Unsupported action"),
+    }
+}
+
+fn overload(
+    layer: &str,
+    tap: &str,
+    threshold_millis: u64,
+    importer: &mut Importer,
+) -> std::result::Result<Binding, &'static str> {
+    let hold = match parse_layer(layer, importer) {
+        Ok(layer) => Action::MomentaryLayer(layer),
+        Err(modifier) => Action::InjectKey(EvdevEvent { event: modifier }),
+    };
+
+    let tap = parse_key_expression(tap).ok_or("Unknown key")?;
+
+    Ok(Binding::Overload {
+        hold,
+        tap,
+        threshold_millis,
+    })
+}
+
+/// Parse the argument of `macro()`; tokens are keys, key expressions, delays like `100ms` or text
+fn parse_macro(expression: &str) -> std::result::Result<Action, &'static str> {
+    let steps = expression
+        .split_whitespace()
+        .map(|token| {
+            if let Some(millis) = token
+                .strip_suffix("ms")
+                .and_then(|millis| millis.parse::<u64>().ok())
+            {
+                Action::Delay(millis)
+            } else if let Some(action) = parse_key_expression(token) {
+                action
+            } else {
+                Action::TypeText(token.to_string())
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if steps.is_empty() {
+        Err("Empty macro")
+    } else {
+        Ok(Action::Sequence(steps))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::importers::tests::*;
+    use crate::importers::FIRST_IMPORTED_LAYER;
+
+    const NAV: usize = FIRST_IMPORTED_LAYER;
+    const SYMBOLS: usize = FIRST_IMPORTED_LAYER + 1;
+
+    fn example() -> Import {
+        import(&fixture("example.keyd.conf"), DEVICE).unwrap()
+    }
+
+    fn simple(import: &Import, code: EV_KEY) -> Option<&Action> {
+        action(import, Event::SimpleKeyDown(key(code)), None)
+    }
+
+    #[test]
+    fn bindings() {
+        let import = example();
+
+        assert_eq!(
+            simple(&import, EV_KEY::KEY_ESC),
+            Some(&inject(EV_KEY::KEY_CAPSLOCK))
+        );
+
+        assert_eq!(
+            simple(&import, EV_KEY::KEY_F1),
+            Some(&combo(&[
+                EV_KEY::KEY_LEFTCTRL,
+                EV_KEY::KEY_LEFTSHIFT,
+                EV_KEY::KEY_T
+            ]))
+        );
+
+        assert_eq!(
+            simple(&import, EV_KEY::KEY_F2),
+            Some(&Action::Sequence(vec![
+                Action::TypeText("Hello".to_string()),
+                Action::Delay(100),
+                combo(&[EV_KEY::KEY_LEFTCTRL, EV_KEY::KEY_A]),
+            ]))
+        );
+
+        assert_eq!(simple(&import, EV_KEY::KEY_F5), Some(&Action::Null));
+
+        // activating a modifier layer holds the modifier key
+        assert_eq!(
+            simple(&import, EV_KEY::KEY_F6),
+            Some(&inject(EV_KEY::KEY_LEFTCTRL))
+        );
+
+        // the last binding of a key wins
+        assert_eq!(
+            simple(&import, EV_KEY::KEY_F10),
+            Some(&inject(EV_KEY::KEY_B))
+        );
+    }
+
+    #[test]
+    fn layers() {
+        let import = example();
+
+        assert_eq!(import.table.layer_name(NAV), "nav");
+        assert_eq!(import.table.layer_name(SYMBOLS), "symbols");
+        assert_eq!(import.table.layers().len(), 2);
+
+        assert_eq!(
+            simple(&import, EV_KEY::KEY_F3),
+            Some(&Action::ToggleLayer(NAV))
+        );
+
+        assert_eq!(
+            simple(&import, EV_KEY::KEY_F4),
+            Some(&Action::OneShotLayer(SYMBOLS))
+        );
+
+        let layered = |code: EV_KEY, layer: usize| {
+            action(&import, Event::EasyShiftKeyDown(key(code)), Some(layer)).cloned()
+        };
+
+        assert_eq!(layered(EV_KEY::KEY_H, NAV), Some(inject(EV_KEY::KEY_LEFT)));
+        assert_eq!(layered(EV_KEY::KEY_J, NAV), Some(inject(EV_KEY::KEY_DOWN)));
+
+        assert_eq!(
+            layered(EV_KEY::KEY_1, SYMBOLS),
+            Some(combo(&[EV_KEY::KEY_LEFTSHIFT, EV_KEY::KEY_1]))
+        );
+    }
+
+    #[test]
+    fn chords() {
+        let import = example();
+
+        let chord = KeyCombination {
+            keys: vec![key(EV_KEY::KEY_J), key(EV_KEY::KEY_K)],
+            timeout_millis: constants::DEFAULT_CHORD_TIMEOUT_MILLIS,
+        };
+
+        assert_eq!(
+            action(&import, Event::KeyChord(chord), None),
+            Some(&inject(EV_KEY::KEY_ESC))
+        );
+    }
+
+    #[test]
+    fn tap_hold() {
+        let import = example();
+
+        let capslock = TapHold {
+            key: key(EV_KEY::KEY_CAPSLOCK),
+            threshold_millis: constants::DEFAULT_TAP_HOLD_THRESHOLD_MILLIS,
+        };
+
+        assert_eq!(
+            action(&import, Event::KeyHold(capslock.clone()), None),
+            Some(&Action::MomentaryLayer(NAV))
+        );
+
+        assert_eq!(
+            action(&import, Event::KeyTap(capslock), None),
+            Some(&inject(EV_KEY::KEY_ESC))
+        );
+
+        let tab = TapHold {
+            key: key(EV_KEY::KEY_TAB),
+            threshold_millis: 150,
+        };
+
+        assert_eq!(
+            action(&import, Event::KeyHold(tab.clone()), None),
+            Some(&inject(EV_KEY::KEY_LEFTCTRL))
+        );
+
+        assert_eq!(
+            action(&import, Event::KeyTap(tab), None),
+            Some(&inject(EV_KEY::KEY_TAB))
+        );
+    }
+
+    #[test]
+    fn unsupported_constructs() {
+        let import = example();
+
+        assert_warning(&import, "The section [global] is not supported");
+        assert_warning(&import, "swap(nav): Unsupported action");
+        assert_warning(
+            &import,
+            "toggle(shift): Toggling a modifier is not supported",
+        );
+        assert_warning(&import, "nosuchkey: Unknown key");
+        assert_warning(
+            &import,
+            "Unknown key or key not present on the device: nosuchkey",
+        );
+        assert_warning(&import, "Mapped more than once, the last mapping wins");
+        assert_warning(&import, "Chords are only supported in the [main] section");
+        assert_warning(&import, "The modifiers 'C' of layer [symbols] are ignored");
+        assert_warning(&import, "The modifier layer [shift] is not supported");
+
+        // nothing of the skipped sections is imported
+        assert_eq!(simple(&import, EV_KEY::KEY_A), None);
+        assert_eq!(
+            action(&import, Event::SimpleKeyDown(key(EV_KEY::KEY_F7)), None),
+            None
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let result = import("[main]\n\ncapslock esc\n", DEVICE);
+
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<ImportError>(),
+            Some(ImportError::SyntaxError { line: 3, .. })
+        ));
+    }
+}
@@ -0,0 +1,279 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+use std::collections::BTreeSet;
+
+use crate::mapping::*;
+use crate::util;
+
+pub mod keyd;
+pub mod qmk;
+pub mod xremap;
+
+pub type Result<T> = std::result::Result<T, eyre::Error>;
+
+/// Layers declared by an import are numbered from here on, above the Easy Shift+ layers
+pub const FIRST_IMPORTED_LAYER: usize = 7;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError {
+    #[error("Unknown format: {format}")]
+    UnknownFormat { format: String },
+
+    #[error("Syntax error in line {line}: {description}")]
+    SyntaxError { line: usize, description: String },
+
+    #[error("Invalid file: {description}")]
+    InvalidFile { description: String },
+}
+
+/// A keymap table that has been imported, along with the constructs that could not be converted
+#[derive(Debug)]
+pub struct Import {
+    pub table: KeyMappingTable,
+    pub warnings: Vec<String>,
+}
+
+/// Import the keymap `data` in the given `format`, for the device with the specified USB IDs
+pub fn import(format: &str, data: &str, device: (u16, u16)) -> Result<Import> {
+    match format.to_lowercase().as_str() {
+        "keyd" => keyd::import(data, device),
+        "xremap" => xremap::import(data, device),
+        "qmk" | "via" => qmk::import(data, device),
+
+        _ => Err(ImportError::UnknownFormat {
+            format: format.to_string(),
+        }
+        .into()),
+    }
+}
+
+/// Shared state of the importers
+#[derive(Debug)]
+pub(crate) struct Importer {
+    table: KeyMappingTable,
+    device: (u16, u16),
+    warnings: Vec<String>,
+}
+
+impl Importer {
+    pub fn new(device: (u16, u16)) -> Self {
+        Self {
+            table: KeyMappingTable::new(),
+            device,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Returns the key of the device that emits the evdev event `code`
+    pub fn key(&self, code: u32) -> Option<Key> {
+        util::symbol_to_key_index(&util::evdev_event_code_to_string(code), self.device)
+            .map(|key_index| Key::new(key_index, self.device))
+    }
+
+    /// Returns the index of the layer `name`, the layer is declared if it does not exist yet
+    pub fn layer(&mut self, name: &str) -> usize {
+        if let Some(index) = self.table.find_layer(name) {
+            return index;
+        }
+
+        let index = self
+            .table
+            .layers()
+            .keys()
+            .copied()
+            .max()
+            .map_or(FIRST_IMPORTED_LAYER, |max| {
+                (max + 1).max(FIRST_IMPORTED_LAYER)
+            });
+
+        self.table.layers_mut().insert(
+            index,
+            Layer {
+                name: name.to_string(),
+                color: None,
+            },
+        );
+
+        index
+    }
+
+    /// Add a mapping rule, `layer` restricts the rule to a layer
+    pub fn add(&mut self, event: Event, layer: Option<usize>, action: Action, description: &str) {
+        let source = match layer {
+            Some(layer) => Source::new_with_layers(event, &[layer]),
+            None => Source::new(event),
+        };
+
+        if self
+            .table
+            .insert(source.clone(), Rule::new(action, description, true))
+            .is_some()
+        {
+            self.warn(format!(
+                "{}: Mapped more than once, the last mapping wins",
+                source
+            ));
+        }
+    }
+
+    /// Report a construct that could not be converted
+    pub fn warn<S: Into<String>>(&mut self, warning: S) {
+        self.warnings.push(warning.into());
+    }
+
+    pub fn finish(self, description: &str) -> Import {
+        let mut table = self.table;
+        table.set_description(description);
+
+        Import {
+            table,
+            warnings: self.warnings,
+        }
+    }
+}
+
+/// Build the action that presses `key` together with `modifiers`
+pub(crate) fn key_action(modifiers: &BTreeSet<u32>, key: u32) -> Action {
+    if modifiers.is_empty() {
+        Action::InjectKey(EvdevEvent { event: key })
+    } else {
+        let mut keys: Vec<EvdevEvent> = modifiers
+            .iter()
+            .map(|modifier| EvdevEvent { event: *modifier })
+            .collect();
+
+        keys.push(EvdevEvent { event: key });
+
+        Action::KeyCombo(keys)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{fs, path::PathBuf};
+
+    use evdev_rs::enums::EV_KEY;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    /// The ROCCAT Vulcan 100/12x
+    pub const DEVICE: (u16, u16) = (0x1e7d, 0x3098);
+
+    pub fn fixture(name: &str) -> String {
+        let path = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+
+        fs::read_to_string(path.join("../support/tests/assets/keymaps/").join(name)).unwrap()
+    }
+
+    pub fn key(code: EV_KEY) -> Key {
+        Importer::new(DEVICE).key(code as u32).unwrap()
+    }
+
+    pub fn inject(code: EV_KEY) -> Action {
+        Action::InjectKey(EvdevEvent { event: code as u32 })
+    }
+
+    pub fn combo(codes: &[EV_KEY]) -> Action {
+        Action::KeyCombo(
+            codes
+                .iter()
+                .map(|code| EvdevEvent {
+                    event: *code as u32,
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns the action that is mapped to `event`, on `layer` or without a layer
+    pub fn action(import: &Import, event: Event, layer: Option<usize>) -> Option<&Action> {
+        let source = match layer {
+            Some(layer) => Source::new_with_layers(event, &[layer]),
+            None => Source::new(event),
+        };
+
+        import
+            .table
+            .mappings()
+            .get(&source)
+            .map(|rule| &rule.action)
+    }
+
+    pub fn assert_warning(import: &Import, warning: &str) {
+        assert!(
+            import.warnings.iter().any(|w| w.contains(warning)),
+            "Missing warning '{}' in: {:#?}",
+            warning,
+            import.warnings
+        );
+    }
+
+    #[test]
+    fn unknown_format() {
+        let result = import("karabiner", "{}", DEVICE);
+
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<ImportError>(),
+            Some(ImportError::UnknownFormat { format }) if format == "karabiner"
+        ));
+    }
+
+    #[test]
+    fn layers_are_declared_above_the_easy_shift_layers() {
+        let mut importer = Importer::new(DEVICE);
+
+        assert_eq!(importer.layer("nav"), FIRST_IMPORTED_LAYER);
+        assert_eq!(importer.layer("symbols"), FIRST_IMPORTED_LAYER + 1);
+        assert_eq!(importer.layer("NAV"), FIRST_IMPORTED_LAYER);
+
+        let import = importer.finish("test");
+
+        assert_eq!(import.table.layer_name(FIRST_IMPORTED_LAYER + 1), "symbols");
+    }
+
+    #[test]
+    fn duplicate_mappings_are_reported() {
+        let mut importer = Importer::new(DEVICE);
+        let event = Event::SimpleKeyDown(key(EV_KEY::KEY_F1));
+
+        importer.add(event.clone(), None, inject(EV_KEY::KEY_A), "first");
+        importer.add(event.clone(), None, inject(EV_KEY::KEY_B), "second");
+
+        let import = importer.finish("test");
+
+        assert_eq!(action(&import, event, None), Some(&inject(EV_KEY::KEY_B)));
+        assert_warning(&import, "Mapped more than once, the last mapping wins");
+    }
+
+    #[test]
+    fn key_actions() {
+        let modifiers = BTreeSet::from([EV_KEY::KEY_LEFTSHIFT as u32, EV_KEY::KEY_LEFTCTRL as u32]);
+
+        assert_eq!(
+            key_action(&BTreeSet::new(), EV_KEY::KEY_T as u32),
+            inject(EV_KEY::KEY_T)
+        );
+
+        assert_eq!(
+            key_action(&modifiers, EV_KEY::KEY_T as u32),
+            combo(&[EV_KEY::KEY_LEFTCTRL, EV_KEY::KEY_LEFTSHIFT, EV_KEY::KEY_T])
+        );
+    }
+}
@@ -0,0 +1,591 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+//! Import of QMK `keymap.json` files and VIA backups
+//!
+//! Neither format describes which physical key a position of the layout refers to, so the
+//! keycodes of the base layer (layer 0) are assumed to be the keys of the device. Layers above
+//! the base layer are imported as named layers.
+
+use std::collections::BTreeSet;
+
+use evdev_rs::enums::EV_KEY;
+use serde_json::Value;
+
+use crate::constants;
+use crate::mapping::*;
+use crate::util;
+
+use super::{key_action, Import, ImportError, Importer, Result};
+
+/// QMK keycodes whose name differs from the Linux key name
+const KEYCODE_ALIASES: &[(&str, EV_KEY)] = &[
+    ("KC_ENT", EV_KEY::KEY_ENTER),
+    ("KC_ESCAPE", EV_KEY::KEY_ESC),
+    ("KC_BSPC", EV_KEY::KEY_BACKSPACE),
+    ("KC_SPC", EV_KEY::KEY_SPACE),
+    ("KC_MINS", EV_KEY::KEY_MINUS),
+    ("KC_EQL", EV_KEY::KEY_EQUAL),
+    ("KC_LBRC", EV_KEY::KEY_LEFTBRACE),
+    ("KC_LEFT_BRACKET", EV_KEY::KEY_LEFTBRACE),
+    ("KC_RBRC", EV_KEY::KEY_RIGHTBRACE),
+    ("KC_RIGHT_BRACKET", EV_KEY::KEY_RIGHTBRACE),
+    ("KC_BSLS", EV_KEY::KEY_BACKSLASH),
+    ("KC_NUHS", EV_KEY::KEY_BACKSLASH),
+    ("KC_NONUS_HASH", EV_KEY::KEY_BACKSLASH),
+    ("KC_NUBS", EV_KEY::KEY_102ND),
+    ("KC_NONUS_BACKSLASH", EV_KEY::KEY_102ND),
+    ("KC_SCLN", EV_KEY::KEY_SEMICOLON),
+    ("KC_QUOT", EV_KEY::KEY_APOSTROPHE),
+    ("KC_QUOTE", EV_KEY::KEY_APOSTROPHE),
+    ("KC_GRV", EV_KEY::KEY_GRAVE),
+    ("KC_COMM", EV_KEY::KEY_COMMA),
+    ("KC_SLSH", EV_KEY::KEY_SLASH),
+    ("KC_CAPS", EV_KEY::KEY_CAPSLOCK),
+    ("KC_PSCR", EV_KEY::KEY_SYSRQ),
+    ("KC_PRINT_SCREEN", EV_KEY::KEY_SYSRQ),
+    ("KC_SCRL", EV_KEY::KEY_SCROLLLOCK),
+    ("KC_SLCK", EV_KEY::KEY_SCROLLLOCK),
+    ("KC_PAUS", EV_KEY::KEY_PAUSE),
+    ("KC_BRK", EV_KEY::KEY_PAUSE),
+    ("KC_INS", EV_KEY::KEY_INSERT),
+    ("KC_DEL", EV_KEY::KEY_DELETE),
+    ("KC_PGUP", EV_KEY::KEY_PAGEUP),
+    ("KC_PGDN", EV_KEY::KEY_PAGEDOWN),
+    ("KC_RGHT", EV_KEY::KEY_RIGHT),
+    ("KC_NUM", EV_KEY::KEY_NUMLOCK),
+    ("KC_NLCK", EV_KEY::KEY_NUMLOCK),
+    ("KC_PSLS", EV_KEY::KEY_KPSLASH),
+    ("KC_PAST", EV_KEY::KEY_KPASTERISK),
+    ("KC_PMNS", EV_KEY::KEY_KPMINUS),
+    ("KC_PPLS", EV_KEY::KEY_KPPLUS),
+    ("KC_PENT", EV_KEY::KEY_KPENTER),
+    ("KC_PDOT", EV_KEY::KEY_KPDOT),
+    ("KC_APP", EV_KEY::KEY_COMPOSE),
+    ("KC_APPLICATION", EV_KEY::KEY_COMPOSE),
+    ("KC_LCTL", EV_KEY::KEY_LEFTCTRL),
+    ("KC_LSFT", EV_KEY::KEY_LEFTSHIFT),
+    ("KC_LALT", EV_KEY::KEY_LEFTALT),
+    ("KC_LOPT", EV_KEY::KEY_LEFTALT),
+    ("KC_LGUI", EV_KEY::KEY_LEFTMETA),
+    ("KC_LEFT_GUI", EV_KEY::KEY_LEFTMETA),
+    ("KC_LCMD", EV_KEY::KEY_LEFTMETA),
+    ("KC_LWIN", EV_KEY::KEY_LEFTMETA),
+    ("KC_RCTL", EV_KEY::KEY_RIGHTCTRL),
+    ("KC_RSFT", EV_KEY::KEY_RIGHTSHIFT),
+    ("KC_RALT", EV_KEY::KEY_RIGHTALT),
+    ("KC_ROPT", EV_KEY::KEY_RIGHTALT),
+    ("KC_ALGR", EV_KEY::KEY_RIGHTALT),
+    ("KC_RGUI", EV_KEY::KEY_RIGHTMETA),
+    ("KC_RIGHT_GUI", EV_KEY::KEY_RIGHTMETA),
+    ("KC_RCMD", EV_KEY::KEY_RIGHTMETA),
+    ("KC_RWIN", EV_KEY::KEY_RIGHTMETA),
+    ("KC_MUTE", EV_KEY::KEY_MUTE),
+    ("KC_AUDIO_MUTE", EV_KEY::KEY_MUTE),
+    ("KC_VOLU", EV_KEY::KEY_VOLUMEUP),
+    ("KC_AUDIO_VOL_UP", EV_KEY::KEY_VOLUMEUP),
+    ("KC_VOLD", EV_KEY::KEY_VOLUMEDOWN),
+    ("KC_AUDIO_VOL_DOWN", EV_KEY::KEY_VOLUMEDOWN),
+    ("KC_MNXT", EV_KEY::KEY_NEXTSONG),
+    ("KC_MEDIA_NEXT_TRACK", EV_KEY::KEY_NEXTSONG),
+    ("KC_MPRV", EV_KEY::KEY_PREVIOUSSONG),
+    ("KC_MEDIA_PREV_TRACK", EV_KEY::KEY_PREVIOUSSONG),
+    ("KC_MPLY", EV_KEY::KEY_PLAYPAUSE),
+    ("KC_MEDIA_PLAY_PAUSE", EV_KEY::KEY_PLAYPAUSE),
+    ("KC_MSTP", EV_KEY::KEY_STOPCD),
+    ("KC_MEDIA_STOP", EV_KEY::KEY_STOPCD),
+];
+
+/// Modifier functions like `LCTL(kc)` and mod-tap functions like `LCTL_T(kc)`
+const MODIFIERS: &[(&str, EV_KEY)] = &[
+    ("LCTL", EV_KEY::KEY_LEFTCTRL),
+    ("CTL", EV_KEY::KEY_LEFTCTRL),
+    ("C", EV_KEY::KEY_LEFTCTRL),
+    ("LSFT", EV_KEY::KEY_LEFTSHIFT),
+    ("SFT", EV_KEY::KEY_LEFTSHIFT),
+    ("S", EV_KEY::KEY_LEFTSHIFT),
+    ("LALT", EV_KEY::KEY_LEFTALT),
+    ("LOPT", EV_KEY::KEY_LEFTALT),
+    ("ALT", EV_KEY::KEY_LEFTALT),
+    ("A", EV_KEY::KEY_LEFTALT),
+    ("LGUI", EV_KEY::KEY_LEFTMETA),
+    ("LCMD", EV_KEY::KEY_LEFTMETA),
+    ("LWIN", EV_KEY::KEY_LEFTMETA),
+    ("GUI", EV_KEY::KEY_LEFTMETA),
+    ("G", EV_KEY::KEY_LEFTMETA),
+    ("RCTL", EV_KEY::KEY_RIGHTCTRL),
+    ("RSFT", EV_KEY::KEY_RIGHTSHIFT),
+    ("RALT", EV_KEY::KEY_RIGHTALT),
+    ("ROPT", EV_KEY::KEY_RIGHTALT),
+    ("ALGR", EV_KEY::KEY_RIGHTALT),
+    ("RGUI", EV_KEY::KEY_RIGHTMETA),
+    ("RCMD", EV_KEY::KEY_RIGHTMETA),
+    ("RWIN", EV_KEY::KEY_RIGHTMETA),
+];
+
+/// A parsed QMK keycode
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Keycode {
+    Transparent,
+    None,
+
+    /// A key, optionally pressed together with modifiers
+    Key(BTreeSet<u32>, u32),
+
+    Momentary(usize),
+    Toggle(usize),
+    OneShot(usize),
+
+    /// `LT(layer, kc)`: activate the layer while held, send the key when tapped
+    LayerTap(usize, u32),
+
+    /// `LCTL_T(kc)`: hold the modifier while held, send the key when tapped
+    ModTap(u32, u32),
+
+    Unsupported,
+}
+
+impl Keycode {
+    /// The key that identifies a position on the base layer
+    fn physical_key(&self) -> Option<u32> {
+        match self {
+            Keycode::Key(modifiers, key) if modifiers.is_empty() => Some(*key),
+            Keycode::LayerTap(_, key) | Keycode::ModTap(_, key) => Some(*key),
+
+            _ => None,
+        }
+    }
+}
+
+pub fn import(data: &str, device: (u16, u16)) -> Result<Import> {
+    let mut importer = Importer::new(device);
+
+    let config: Value = serde_json::from_str(data)?;
+
+    let layers = config["layers"]
+        .as_array()
+        .ok_or_else(|| ImportError::InvalidFile {
+            description: "Expected an array of layers".to_string(),
+        })?
+        .iter()
+        .map(|layer| {
+            layer
+                .as_array()
+                .map(|keycodes| {
+                    keycodes
+                        .iter()
+                        .map(|keycode| keycode.as_str().unwrap_or_default().to_string())
+                        .collect::<Vec<_>>()
+                })
+                .ok_or_else(|| ImportError::InvalidFile {
+                    description: "Expected an array of keycodes".to_string(),
+                })
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    let base = match layers.first() {
+        Some(base) => base
+            .iter()
+            .map(|name| parse_keycode(name))
+            .collect::<Vec<_>>(),
+        None => Vec::new(),
+    };
+
+    for (position, keycode) in base.iter().enumerate() {
+        let name = &layers[0][position];
+
+        match keycode {
+            Keycode::LayerTap(layer, key) => {
+                let hold = Action::MomentaryLayer(layer_index(*layer, &mut importer));
+                let tap = Action::InjectKey(EvdevEvent { event: *key });
+
+                add_tap_hold(&mut importer, *key, hold, tap, name);
+            }
+
+            Keycode::ModTap(modifier, key) => {
+                let hold = Action::InjectKey(EvdevEvent { event: *modifier });
+                let tap = Action::InjectKey(EvdevEvent { event: *key });
+
+                add_tap_hold(&mut importer, *key, hold, tap, name);
+            }
+
+            Keycode::Key(modifiers, _) if modifiers.is_empty() => {}
+            Keycode::Transparent | Keycode::None => {}
+
+            _ => importer.warn(format!(
+                "layer 0, position {}: {} does not identify a key, please map it manually",
+                position, name
+            )),
+        }
+    }
+
+    for (index, layer) in layers.iter().enumerate().skip(1) {
+        for (position, name) in layer.iter().enumerate() {
+            let keycode = parse_keycode(name);
+
+            let action = match &keycode {
+                Keycode::Transparent => continue,
+                Keycode::None => Action::Null,
+                Keycode::Key(modifiers, key) => key_action(modifiers, *key),
+                Keycode::Momentary(layer) => {
+                    Action::MomentaryLayer(layer_index(*layer, &mut importer))
+                }
+                Keycode::Toggle(layer) => Action::ToggleLayer(layer_index(*layer, &mut importer)),
+                Keycode::OneShot(layer) => Action::OneShotLayer(layer_index(*layer, &mut importer)),
+
+                Keycode::LayerTap(..) | Keycode::ModTap(..) => {
+                    importer.warn(format!(
+                        "layer {}, position {}: {}: Tap/hold is only supported on the base layer",
+                        index, position, name
+                    ));
+                    continue;
+                }
+
+                Keycode::Unsupported => {
+                    importer.warn(format!(
+                        "layer {}, position {}: Unsupported keycode: {}",
+                        index, position, name
+                    ));
+                    continue;
+                }
+            };
+
+            let key = base
+                .get(position)
+                .and_then(Keycode::physical_key)
+                .and_then(|code| importer.key(code));
+
+            let key = match key {
+                Some(key) => key,
+
+                None => {
+                    importer.warn(format!(
+                        "layer {}, position {}: {}: The base layer key is not on the device",
+                        index, position, name
+                    ));
+                    continue;
+                }
+            };
+
+            let layer = layer_index(index, &mut importer);
+            let description = format!("QMK layer {}: {}", index, name);
+
+            importer.add(
+                Event::EasyShiftKeyDown(key),
+                Some(layer),
+                action,
+                &description,
+            );
+        }
+    }
+
+    let description = match config["keymap"]
+        .as_str()
+        .or_else(|| config["name"].as_str())
+    {
+        Some(name) => format!("Imported from QMK keymap '{}'", name),
+        None => "Imported from QMK".to_string(),
+    };
+
+    Ok(importer.finish(&description))
+}
+
+fn add_tap_hold(importer: &mut Importer, code: u32, hold: Action, tap: Action, name: &str) {
+    let key = match importer.key(code) {
+        Some(key) => key,
+
+        None => {
+            importer.warn(format!("{}: The key is not present on the device", name));
+            return;
+        }
+    };
+
+    let tap_hold = TapHold {
+        key,
+        threshold_millis: constants::DEFAULT_TAP_HOLD_THRESHOLD_MILLIS,
+    };
+
+    let description = format!("QMK layer 0: {}", name);
+
+    importer.add(Event::KeyHold(tap_hold.clone()), None, hold, &description);
+    importer.add(Event::KeyTap(tap_hold), None, tap, &description);
+}
+
+/// QMK layers above the base layer are imported as the named layers `layer1`, `layer2`, ...
+fn layer_index(layer: usize, importer: &mut Importer) -> usize {
+    importer.layer(&format!("layer{}", layer))
+}
+
+fn basic_keycode(name: &str) -> Option<u32> {
+    if let Some((_, key)) = KEYCODE_ALIASES.iter().find(|(alias, _)| *alias == name) {
+        return Some(*key as u32);
+    }
+
+    // e.g. `KC_A`, `KC_PAGE_UP`, `KC_KP_1` and `KC_P1`
+    let name = name.strip_prefix("KC_")?.replace('_', "");
+    let name = match name.strip_prefix('P') {
+        Some(digit) if digit.len() == 1 && digit.chars().all(|c| c.is_ascii_digit()) => {
+            format!("KP{}", digit)
+        }
+
+        _ => name,
+    };
+
+    util::evdev_event_code_from_string(&format!("KEY_{}", name)).map(|key| key as u32)
+}
+
+fn parse_layer(layer: &str) -> Option<usize> {
+    layer.trim().parse().ok()
+}
+
+fn parse_keycode(name: &str) -> Keycode {
+    let name = name.trim();
+
+    match name {
+        "KC_TRNS" | "KC_TRANSPARENT" | "_______" => return Keycode::Transparent,
+        "KC_NO" | "XXXXXXX" => return Keycode::None,
+
+        _ => {}
+    }
+
+    let (function, args) = match name.split_once('(') {
+        Some((function, args)) => match args.strip_suffix(')') {
+            Some(args) => (function, args),
+            None => return Keycode::Unsupported,
+        },
+
+        None => {
+            return basic_keycode(name)
+                .map(|key| Keycode::Key(BTreeSet::new(), key))
+                .unwrap_or(Keycode::Unsupported)
+        }
+    };
+
+    let modifier = |name: &str| {
+        MODIFIERS
+            .iter()
+            .find(|(modifier, _)| *modifier == name)
+            .map(|(_, key)| *key as u32)
+    };
+
+    let result = match function {
+        "MO" => parse_layer(args).map(Keycode::Momentary),
+        "TG" => parse_layer(args).map(Keycode::Toggle),
+        "OSL" => parse_layer(args).map(Keycode::OneShot),
+
+        "LT" => args.split_once(',').and_then(|(layer, key)| {
+            Some(Keycode::LayerTap(
+                parse_layer(layer)?,
+                basic_keycode(key.trim())?,
+            ))
+        }),
+
+        function => match function.strip_suffix("_T") {
+            Some(function) => modifier(function)
+                .zip(basic_keycode(args.trim()))
+                .map(|(modifier, key)| Keycode::ModTap(modifier, key)),
+
+            // nested modifier functions, like `LCTL(LSFT(KC_T))`
+            None => modifier(function).and_then(|modifier| match parse_keycode(args) {
+                Keycode::Key(mut modifiers, key) => {
+                    modifiers.insert(modifier);
+
+                    Some(Keycode::Key(modifiers, key))
+                }
+
+                _ => None,
+            }),
+        },
+    };
+
+    result.unwrap_or(Keycode::Unsupported)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::importers::tests::*;
+    use crate::importers::FIRST_IMPORTED_LAYER;
+
+    const LAYER1: usize = FIRST_IMPORTED_LAYER;
+    const LAYER2: usize = FIRST_IMPORTED_LAYER + 1;
+
+    fn example() -> Import {
+        import(&fixture("example.qmk.json"), DEVICE).unwrap()
+    }
+
+    /// The action of the key at the position of `code` on the base layer
+    fn layered(import: &Import, code: EV_KEY, layer: usize) -> Option<&Action> {
+        action(import, Event::EasyShiftKeyDown(key(code)), Some(layer))
+    }
+
+    #[test]
+    fn keycodes() {
+        assert_eq!(
+            parse_keycode("KC_A"),
+            Keycode::Key(BTreeSet::new(), EV_KEY::KEY_A as u32)
+        );
+        assert_eq!(
+            parse_keycode("KC_PGUP"),
+            Keycode::Key(BTreeSet::new(), EV_KEY::KEY_PAGEUP as u32)
+        );
+        assert_eq!(
+            parse_keycode("KC_PAGE_UP"),
+            Keycode::Key(BTreeSet::new(), EV_KEY::KEY_PAGEUP as u32)
+        );
+        assert_eq!(
+            parse_keycode("KC_P1"),
+            Keycode::Key(BTreeSet::new(), EV_KEY::KEY_KP1 as u32)
+        );
+        assert_eq!(parse_keycode("MO(3)"), Keycode::Momentary(3));
+        assert_eq!(parse_keycode("_______"), Keycode::Transparent);
+        assert_eq!(parse_keycode("XXXXXXX"), Keycode::None);
+        assert_eq!(parse_keycode("QK_BOOT"), Keycode::Unsupported);
+        assert_eq!(parse_keycode("LCTL(KC_A"), Keycode::Unsupported);
+    }
+
+    #[test]
+    fn bindings() {
+        let import = example();
+
+        assert_eq!(
+            import.table.description(),
+            "Imported from QMK keymap 'test'"
+        );
+
+        assert_eq!(
+            layered(&import, EV_KEY::KEY_ESC, LAYER1),
+            Some(&inject(EV_KEY::KEY_GRAVE))
+        );
+
+        // the position of a mod-tap key is identified by its tap key
+        assert_eq!(
+            layered(&import, EV_KEY::KEY_A, LAYER1),
+            Some(&inject(EV_KEY::KEY_HOME))
+        );
+
+        assert_eq!(
+            layered(&import, EV_KEY::KEY_S, LAYER1),
+            Some(&combo(&[
+                EV_KEY::KEY_LEFTCTRL,
+                EV_KEY::KEY_LEFTSHIFT,
+                EV_KEY::KEY_T
+            ]))
+        );
+
+        assert_eq!(
+            layered(&import, EV_KEY::KEY_S, LAYER2),
+            Some(&inject(EV_KEY::KEY_KP1))
+        );
+
+        assert_eq!(
+            layered(&import, EV_KEY::KEY_ESC, LAYER2),
+            Some(&Action::Null)
+        );
+
+        // transparent keys are not mapped
+        assert_eq!(layered(&import, EV_KEY::KEY_D, LAYER2), None);
+    }
+
+    #[test]
+    fn layers() {
+        let import = example();
+
+        assert_eq!(import.table.layer_name(LAYER1), "layer1");
+        assert_eq!(import.table.layer_name(LAYER2), "layer2");
+        assert_eq!(import.table.layers().len(), 2);
+
+        assert_eq!(
+            layered(&import, EV_KEY::KEY_D, LAYER1),
+            Some(&Action::ToggleLayer(LAYER2))
+        );
+
+        assert_eq!(
+            layered(&import, EV_KEY::KEY_CAPSLOCK, LAYER2),
+            Some(&Action::OneShotLayer(LAYER1))
+        );
+    }
+
+    #[test]
+    fn tap_hold() {
+        let import = example();
+
+        let tap_hold = |code: EV_KEY| TapHold {
+            key: key(code),
+            threshold_millis: constants::DEFAULT_TAP_HOLD_THRESHOLD_MILLIS,
+        };
+
+        // LT(1, KC_CAPS)
+        assert_eq!(
+            action(
+                &import,
+                Event::KeyHold(tap_hold(EV_KEY::KEY_CAPSLOCK)),
+                None
+            ),
+            Some(&Action::MomentaryLayer(LAYER1))
+        );
+        assert_eq!(
+            action(&import, Event::KeyTap(tap_hold(EV_KEY::KEY_CAPSLOCK)), None),
+            Some(&inject(EV_KEY::KEY_CAPSLOCK))
+        );
+
+        // LCTL_T(KC_A)
+        assert_eq!(
+            action(&import, Event::KeyHold(tap_hold(EV_KEY::KEY_A)), None),
+            Some(&inject(EV_KEY::KEY_LEFTCTRL))
+        );
+        assert_eq!(
+            action(&import, Event::KeyTap(tap_hold(EV_KEY::KEY_A)), None),
+            Some(&inject(EV_KEY::KEY_A))
+        );
+    }
+
+    #[test]
+    fn unsupported_constructs() {
+        let import = example();
+
+        assert_warning(
+            &import,
+            "layer 0, position 5: MO(1) does not identify a key, please map it manually",
+        );
+        assert_warning(
+            &import,
+            "layer 1, position 6: LT(2, KC_X): Tap/hold is only supported on the base layer",
+        );
+        assert_warning(&import, "layer 2, position 2: Unsupported keycode: KC_BOOT");
+        assert_warning(
+            &import,
+            "layer 2, position 5: KC_VOLU: The base layer key is not on the device",
+        );
+
+        assert_eq!(import.warnings.len(), 4, "{:#?}", import.warnings);
+    }
+
+    #[test]
+    fn invalid_file() {
+        let result = import(r#"{ "layers": "KC_A" }"#, DEVICE);
+
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<ImportError>(),
+            Some(ImportError::InvalidFile { .. })
+        ));
+    }
+}
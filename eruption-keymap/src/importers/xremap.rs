@@ -0,0 +1,405 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+//! Import of xremap configuration files (YAML)
+
+use std::collections::BTreeSet;
+
+use evdev_rs::enums::EV_KEY;
+use serde_yaml::Value;
+
+use crate::constants;
+use crate::mapping::*;
+use crate::parsers::action::key_name_to_event;
+
+use super::{key_action, Import, ImportError, Importer, Result};
+
+/// The modifier prefixes of an xremap key expression, like in `C-Shift-t`
+const MODIFIER_PREFIXES: &[(&str, EV_KEY)] = &[
+    ("c", EV_KEY::KEY_LEFTCTRL),
+    ("ctrl", EV_KEY::KEY_LEFTCTRL),
+    ("control", EV_KEY::KEY_LEFTCTRL),
+    ("shift", EV_KEY::KEY_LEFTSHIFT),
+    ("m", EV_KEY::KEY_LEFTALT),
+    ("alt", EV_KEY::KEY_LEFTALT),
+    ("super", EV_KEY::KEY_LEFTMETA),
+    ("win", EV_KEY::KEY_LEFTMETA),
+    ("windows", EV_KEY::KEY_LEFTMETA),
+];
+
+/// Names that xremap accepts in addition to the Linux key names
+const KEY_ALIASES: &[(&str, EV_KEY)] = &[
+    ("shift_l", EV_KEY::KEY_LEFTSHIFT),
+    ("shift_r", EV_KEY::KEY_RIGHTSHIFT),
+    ("control_l", EV_KEY::KEY_LEFTCTRL),
+    ("control_r", EV_KEY::KEY_RIGHTCTRL),
+    ("ctrl_l", EV_KEY::KEY_LEFTCTRL),
+    ("ctrl_r", EV_KEY::KEY_RIGHTCTRL),
+    ("alt_l", EV_KEY::KEY_LEFTALT),
+    ("alt_r", EV_KEY::KEY_RIGHTALT),
+    ("super_l", EV_KEY::KEY_LEFTMETA),
+    ("super_r", EV_KEY::KEY_RIGHTMETA),
+    ("win_l", EV_KEY::KEY_LEFTMETA),
+    ("win_r", EV_KEY::KEY_RIGHTMETA),
+];
+
+pub fn import(data: &str, device: (u16, u16)) -> Result<Import> {
+    let mut importer = Importer::new(device);
+
+    let config: Value = serde_yaml::from_str(data)?;
+
+    if !config.is_mapping() {
+        return Err(ImportError::InvalidFile {
+            description: "Expected a mapping at the top level".to_string(),
+        }
+        .into());
+    }
+
+    for entry in sequence(&config["modmap"]) {
+        let name = entry_name(entry, "modmap");
+
+        if !is_global(entry, &name, &mut importer) {
+            continue;
+        }
+
+        for (source, target) in mapping(&entry["remap"]) {
+            let key = match source.as_str().and_then(|s| resolve_key(s, &importer)) {
+                Some(key) => key,
+
+                None => {
+                    importer.warn(format!("{}: Unknown key: {:?}", name, source));
+                    continue;
+                }
+            };
+
+            let description = format!("xremap {}: {}", name, value_to_string(source));
+
+            if target.is_mapping() {
+                // a multi-purpose key
+                let (hold, tap) = match (single_key(&target["held"]), single_key(&target["alone"]))
+                {
+                    (Some(hold), Some(tap)) => (hold, tap),
+
+                    _ => {
+                        importer.warn(format!(
+                            "{}: {}: Multi-purpose keys need a single 'held' and 'alone' key",
+                            name,
+                            value_to_string(source)
+                        ));
+                        continue;
+                    }
+                };
+
+                let tap_hold = TapHold {
+                    key,
+                    threshold_millis: target["alone_timeout_millis"]
+                        .as_u64()
+                        .unwrap_or(constants::DEFAULT_TAP_HOLD_THRESHOLD_MILLIS),
+                };
+
+                let hold = Action::InjectKey(EvdevEvent { event: hold });
+                let tap = Action::InjectKey(EvdevEvent { event: tap });
+
+                importer.add(Event::KeyHold(tap_hold.clone()), None, hold, &description);
+                importer.add(Event::KeyTap(tap_hold), None, tap, &description);
+            } else if let Some(target) = single_key(target) {
+                let action = Action::InjectKey(EvdevEvent { event: target });
+
+                importer.add(Event::SimpleKeyDown(key), None, action, &description);
+            } else {
+                importer.warn(format!(
+                    "{}: {}: Unsupported target: {}",
+                    name,
+                    value_to_string(source),
+                    value_to_string(target)
+                ));
+            }
+        }
+    }
+
+    for entry in sequence(&config["keymap"]) {
+        let name = entry_name(entry, "keymap");
+
+        if !is_global(entry, &name, &mut importer) {
+            continue;
+        }
+
+        if !entry["mode"].is_null() {
+            importer.warn(format!("{}: Modes are not supported", name));
+            continue;
+        }
+
+        for (source, target) in mapping(&entry["remap"]) {
+            let source_name = value_to_string(source);
+
+            // keymap entries are usually bound to a key together with modifiers,
+            // only plain keys can be mapped
+            let key = match parse_key_expression(&source_name) {
+                Some((modifiers, key)) if modifiers.is_empty() => importer.key(key),
+
+                Some(_) => {
+                    importer.warn(format!(
+                        "{}: {}: Keys with modifiers are not supported as a source",
+                        name, source_name
+                    ));
+                    continue;
+                }
+
+                None => None,
+            };
+
+            let key = match key {
+                Some(key) => key,
+
+                None => {
+                    importer.warn(format!("{}: Unknown key: {}", name, source_name));
+                    continue;
+                }
+            };
+
+            match parse_target(target) {
+                Some(action) => {
+                    let description = format!("xremap {}: {}", name, source_name);
+
+                    importer.add(Event::SimpleKeyDown(key), None, action, &description);
+                }
+
+                None => importer.warn(format!(
+                    "{}: {}: Unsupported action: {}",
+                    name,
+                    source_name,
+                    value_to_string(target)
+                )),
+            }
+        }
+    }
+
+    Ok(importer.finish("Imported from xremap"))
+}
+
+fn sequence(value: &Value) -> impl Iterator<Item = &Value> {
+    value.as_sequence().into_iter().flatten()
+}
+
+fn mapping(value: &Value) -> impl Iterator<Item = (&Value, &Value)> {
+    value.as_mapping().into_iter().flatten()
+}
+
+fn entry_name(entry: &Value, kind: &str) -> String {
+    entry["name"]
+        .as_str()
+        .map(str::to_string)
+        .unwrap_or_else(|| kind.to_string())
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => serde_yaml::to_string(value)
+            .map(|s| s.split_whitespace().collect::<Vec<_>>().join(" "))
+            .unwrap_or_default(),
+    }
+}
+
+/// Entries that are restricted to certain applications, windows or devices are skipped
+fn is_global(entry: &Value, name: &str, importer: &mut Importer) -> bool {
+    for filter in ["application", "window", "device"] {
+        if !entry[filter].is_null() {
+            importer.warn(format!(
+                "{}: '{}' filters are not supported, use a per-application keymap instead",
+                name, filter
+            ));
+
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Resolve an xremap key name, like `CapsLock`, `KEY_ESC` or `Ctrl_L`
+fn key_code(name: &str) -> Option<u32> {
+    let name = name.to_lowercase();
+
+    KEY_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map(|(_, key)| *key as u32)
+        .or_else(|| key_name_to_event(&name))
+}
+
+fn resolve_key(name: &str, importer: &Importer) -> Option<Key> {
+    key_code(name).and_then(|code| importer.key(code))
+}
+
+/// A key name, or a list containing exactly one key name
+fn single_key(value: &Value) -> Option<u32> {
+    match value {
+        Value::String(name) => key_code(name),
+        Value::Sequence(keys) if keys.len() == 1 => single_key(&keys[0]),
+
+        _ => None,
+    }
+}
+
+/// Parse a key expression with optional modifiers, like `Esc` or `C-Shift-t`
+fn parse_key_expression(expression: &str) -> Option<(BTreeSet<u32>, u32)> {
+    let mut modifiers = BTreeSet::new();
+    let mut rest = expression;
+
+    while let Some((prefix, tail)) = rest.split_once('-') {
+        let prefix = prefix.to_lowercase();
+        let (_, modifier) = MODIFIER_PREFIXES.iter().find(|(p, _)| *p == prefix)?;
+        modifiers.insert(*modifier as u32);
+
+        rest = tail;
+    }
+
+    key_code(rest).map(|key| (modifiers, key))
+}
+
+fn parse_target(target: &Value) -> Option<Action> {
+    match target {
+        Value::Null => Some(Action::Null),
+
+        Value::String(expression) => {
+            parse_key_expression(expression).map(|(modifiers, key)| key_action(&modifiers, key))
+        }
+
+        Value::Sequence(targets) if targets.is_empty() => Some(Action::Null),
+        Value::Sequence(targets) if targets.len() == 1 => parse_target(&targets[0]),
+
+        Value::Sequence(targets) => targets
+            .iter()
+            .map(parse_target)
+            .collect::<Option<Vec<_>>>()
+            .map(Action::Sequence),
+
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::importers::tests::*;
+
+    fn example() -> Import {
+        import(&fixture("example.xremap.yml"), DEVICE).unwrap()
+    }
+
+    fn simple(import: &Import, code: EV_KEY) -> Option<&Action> {
+        action(import, Event::SimpleKeyDown(key(code)), None)
+    }
+
+    #[test]
+    fn modmap() {
+        let import = example();
+
+        assert_eq!(
+            simple(&import, EV_KEY::KEY_CAPSLOCK),
+            Some(&inject(EV_KEY::KEY_ESC))
+        );
+    }
+
+    #[test]
+    fn keymap() {
+        let import = example();
+
+        assert_eq!(
+            simple(&import, EV_KEY::KEY_F1),
+            Some(&combo(&[
+                EV_KEY::KEY_LEFTCTRL,
+                EV_KEY::KEY_LEFTSHIFT,
+                EV_KEY::KEY_T
+            ]))
+        );
+
+        assert_eq!(
+            simple(&import, EV_KEY::KEY_F2),
+            Some(&Action::Sequence(vec![
+                inject(EV_KEY::KEY_ESC),
+                inject(EV_KEY::KEY_ENTER)
+            ]))
+        );
+
+        assert_eq!(simple(&import, EV_KEY::KEY_F3), Some(&Action::Null));
+
+        // xremap has no layers
+        assert!(import.table.layers().is_empty());
+    }
+
+    #[test]
+    fn tap_hold() {
+        let import = example();
+
+        let space = TapHold {
+            key: key(EV_KEY::KEY_SPACE),
+            threshold_millis: 150,
+        };
+
+        assert_eq!(
+            action(&import, Event::KeyHold(space.clone()), None),
+            Some(&inject(EV_KEY::KEY_LEFTSHIFT))
+        );
+
+        assert_eq!(
+            action(&import, Event::KeyTap(space), None),
+            Some(&inject(EV_KEY::KEY_SPACE))
+        );
+    }
+
+    #[test]
+    fn unsupported_constructs() {
+        let import = example();
+
+        assert_warning(
+            &import,
+            "Global: Alt_R: Multi-purpose keys need a single 'held' and 'alone' key",
+        );
+        assert_warning(&import, "Global: Unknown key: String(\"NoSuchKey\")");
+        assert_warning(
+            &import,
+            "Firefox only: 'application' filters are not supported",
+        );
+        assert_warning(
+            &import,
+            "Shortcuts: C-x: Keys with modifiers are not supported as a source",
+        );
+        assert_warning(&import, "Shortcuts: F4: Unsupported action");
+        assert_warning(&import, "Emacs mode: Modes are not supported");
+
+        // the skipped entries are not imported
+        assert_eq!(simple(&import, EV_KEY::KEY_F5), None);
+        assert_eq!(simple(&import, EV_KEY::KEY_F4), None);
+        assert_eq!(simple(&import, EV_KEY::KEY_RIGHTALT), None);
+    }
+
+    #[test]
+    fn invalid_file() {
+        let result = import("- just a list", DEVICE);
+
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<ImportError>(),
+            Some(ImportError::InvalidFile { .. })
+        ));
+    }
+}
@@ -35,8 +35,8 @@ use log::*;
 use parking_lot::Mutex;
 use rust_embed::RustEmbed;
 use std::{
-    collections::BTreeMap,
-    env,
+    collections::{BTreeMap, BTreeSet},
+    env, fs, io,
     path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -47,9 +47,9 @@ use walkdir::WalkDir;
 
 use crate::{
     backends::Backend,
-    backends::{lua::LuaBackend, native::NativeBackend},
+    backends::{keyd::KeydBackend, lua::LuaBackend, native::NativeBackend},
     lua_introspection::LuaSyntaxIntrospection,
-    mapping::{KeyMappingTable, Rule},
+    mapping::{KeyMappingTable, Layer, RecordedMacro, Rule},
 };
 
 // mod assistants;
@@ -58,11 +58,13 @@ mod constants;
 mod dbus_client;
 mod device;
 mod hwdevices;
+mod importers;
 mod lua_introspection;
 mod mapping;
 mod messages;
 mod parsers;
 mod util;
+mod xkb;
 
 #[allow(unused)]
 type Result<T> = std::result::Result<T, eyre::Error>;
@@ -103,11 +105,30 @@ lazy_static! {
 pub enum MainError {
     #[error("Unknown error: {description}")]
     UnknownError { description: String },
+
+    #[error("Unknown layer: {layer}")]
+    UnknownLayer { layer: String },
+
+    #[error("Invalid color: {color}")]
+    InvalidColor { color: String },
+
+    #[error("The recorded macro is empty")]
+    EmptyMacro {},
+
+    #[error("Invalid device")]
+    InvalidDevice {},
+
+    #[error("The keymap already exists: {path}")]
+    KeymapExists { path: String },
+
+    #[error("Unsupported export format: {format}")]
+    UnsupportedFormat { format: String },
 }
 
 lazy_static! {
     static ref ABOUT: String = tr!("about");
     static ref VERBOSE_ABOUT: String = tr!("verbose-about");
+    static ref LAYOUT_ABOUT: String = tr!("layout-about");
     static ref COMPLETIONS_ABOUT: String = tr!("completions-about");
     static ref ASSISTANT_ABOUT: String = tr!("assistant-about");
     static ref LIST_ABOUT: String = tr!("list-about");
@@ -121,6 +142,12 @@ lazy_static! {
     static ref MAPPING_REMOVE_ABOUT: String = tr!("mapping-remove-about");
     static ref MAPPING_ENABLE_ABOUT: String = tr!("mapping-enable-about");
     static ref MAPPING_DISABLE_ABOUT: String = tr!("mapping-disable-about");
+    static ref LAYER_ABOUT: String = tr!("layer-about");
+    static ref LAYER_ADD_ABOUT: String = tr!("layer-add-about");
+    static ref LAYER_REMOVE_ABOUT: String = tr!("layer-remove-about");
+    static ref RECORD_ABOUT: String = tr!("record-about");
+    static ref IMPORT_ABOUT: String = tr!("import-about");
+    static ref EXPORT_ABOUT: String = tr!("export-about");
 }
 
 /// Supported command line arguments
@@ -135,6 +162,10 @@ pub struct Options {
     #[clap(help(VERBOSE_ABOUT.as_str()), short, long, parse(from_occurrences))]
     verbose: u8,
 
+    /// The XKB keyboard layout, e.g. `de` or `fr(azerty)`; defaults to the layout of the system
+    #[clap(help(LAYOUT_ABOUT.as_str()), short, long, global = true)]
+    layout: Option<String>,
+
     #[clap(subcommand)]
     command: Subcommands,
 }
@@ -157,6 +188,70 @@ pub enum Subcommands {
         command: MappingSubcommands,
     },
 
+    /// Declare or remove a named layer
+    #[clap(about(LAYER_ABOUT.as_str()))]
+    Layer {
+        #[clap(subcommand)]
+        command: LayerSubcommands,
+    },
+
+    /// Record a macro and save it to a keymap
+    #[clap(about(RECORD_ABOUT.as_str()))]
+    Record {
+        /// Round the delays between events to a multiple of this many milliseconds
+        #[clap(required = false, short, long, default_value = "0")]
+        quantize: u64,
+
+        /// Limit the delays between events to this many milliseconds
+        #[clap(required = false, short, long)]
+        max_delay: Option<u64>,
+
+        /// Discard the recorded delays, play back all events immediately
+        #[clap(required = false, short, long)]
+        no_delays: bool,
+
+        /// The filename of the keymap
+        #[clap(required = false, short, long, default_value = "default.keymap")]
+        keymap: PathBuf,
+
+        name: String,
+    },
+
+    /// Import a keymap from a keyd, xremap or QMK/VIA file
+    #[clap(about(IMPORT_ABOUT.as_str()))]
+    Import {
+        /// Specify the device to import the keymap for
+        #[clap(required = false, short, long, default_value = "0")]
+        device: String,
+
+        /// Overwrite the keymap if it already exists
+        #[clap(required = false, short, long)]
+        force: bool,
+
+        /// The filename of the keymap
+        #[clap(required = false, short, long, default_value = "default.keymap")]
+        keymap: PathBuf,
+
+        /// The format of the file: keyd, xremap or qmk
+        format: String,
+
+        file: PathBuf,
+    },
+
+    /// Export a keymap to another format
+    #[clap(about(EXPORT_ABOUT.as_str()))]
+    Export {
+        /// The filename of the keymap
+        #[clap(required = false, short, long, default_value = "default.keymap")]
+        keymap: PathBuf,
+
+        /// The format of the file: keyd
+        format: String,
+
+        /// The file to write, the result is printed if omitted
+        file: Option<PathBuf>,
+    },
+
     /// Show or set the description of the specified keymap
     #[clap(about(DESCRIPTION_ABOUT.as_str()))]
     Description {
@@ -216,9 +311,9 @@ pub enum MappingSubcommands {
         #[clap(required = false, long, default_value = "")]
         description: String,
 
-        /// Specify a list of layers
+        /// Specify a list of layers, either by number or by name
         #[clap(required = false, short, long)]
-        layers: Vec<usize>,
+        layers: Vec<String>,
 
         /// The filename of the keymap
         #[clap(required = false, short, long, default_value = "default.keymap")]
@@ -280,6 +375,35 @@ pub enum MappingSubcommands {
     },
 }
 
+/// Subcommands of the "layer" command
+#[derive(Debug, clap::Parser)]
+pub enum LayerSubcommands {
+    /// Declare a named layer, or rename an existing one
+    #[clap(about(LAYER_ADD_ABOUT.as_str()))]
+    Add {
+        /// Specify the color (#rrggbb) that highlights the mapped keys while the layer is active
+        #[clap(required = false, short, long)]
+        color: Option<String>,
+
+        /// The filename of the keymap
+        #[clap(required = false, short, long, default_value = "default.keymap")]
+        keymap: PathBuf,
+
+        index: usize,
+        name: String,
+    },
+
+    /// Remove the name of a layer; the mappings on the layer are kept
+    #[clap(about(LAYER_REMOVE_ABOUT.as_str()))]
+    Remove {
+        /// The filename of the keymap
+        #[clap(required = false, short, long, default_value = "default.keymap")]
+        keymap: PathBuf,
+
+        index: usize,
+    },
+}
+
 /// Subcommands of the "completions" command
 #[derive(Debug, clap::Parser)]
 pub enum CompletionsSubcommands {
@@ -378,6 +502,10 @@ pub async fn async_main() -> std::result::Result<(), eyre::Error> {
     .unwrap_or_else(|e| error!("Could not set CTRL-C handler: {}", e));
 
     let opts = Options::parse();
+
+    // key names may refer to the symbols printed on the keycaps
+    xkb::init(opts.layout.as_deref())?;
+
     match opts.command {
         /* Subcommands::Assistant { keymap: _ } => {
             let mut assistants = assistants::register_assistants();
@@ -450,11 +578,22 @@ pub async fn async_main() -> std::result::Result<(), eyre::Error> {
 
                 let mut source = parsers::source::parse(&source, device)?;
                 if !layers.is_empty() {
+                    let layers = layers
+                        .iter()
+                        .map(|layer| {
+                            table
+                                .find_layer(layer)
+                                .ok_or_else(|| MainError::UnknownLayer {
+                                    layer: layer.to_string(),
+                                })
+                        })
+                        .collect::<std::result::Result<Vec<usize>, _>>()?;
+
                     source.get_layers_mut().clear();
                     source.get_layers_mut().extend(&layers);
                 }
 
-                let action = parsers::action::parse(&action)?;
+                let action = parsers::action::parse(&action, &table)?;
 
                 let rule = Rule::new(action, &description, enabled);
 
@@ -543,6 +682,195 @@ pub async fn async_main() -> std::result::Result<(), eyre::Error> {
             }
         },
 
+        Subcommands::Layer { command } => match command {
+            LayerSubcommands::Add {
+                color,
+                keymap,
+                index,
+                name,
+            } => {
+                let path = if keymap.components().count() > 1 {
+                    keymap
+                } else {
+                    PathBuf::from(constants::DEFAULT_KEYMAP_DIR).join(keymap)
+                };
+
+                let mut table = if path.exists() {
+                    NativeBackend::from_file(&path)?
+                } else {
+                    KeyMappingTable::new()
+                };
+
+                let color = match color {
+                    Some(color) => Some(
+                        u32::from_str_radix(color.trim_start_matches('#'), 16)
+                            .ok()
+                            .filter(|rgb| *rgb <= 0xffffff)
+                            .map(|rgb| 0xff000000 | rgb)
+                            .ok_or(MainError::InvalidColor { color })?,
+                    ),
+
+                    None => None,
+                };
+
+                table.layers_mut().insert(index, Layer { name, color });
+
+                NativeBackend::new().write_to_file(&path, &table)?
+            }
+
+            LayerSubcommands::Remove { keymap, index } => {
+                let path = if keymap.components().count() > 1 {
+                    keymap
+                } else {
+                    PathBuf::from(constants::DEFAULT_KEYMAP_DIR).join(keymap)
+                };
+
+                let mut table = NativeBackend::from_file(&path)?;
+
+                table.layers_mut().remove(&index);
+
+                NativeBackend::new().write_to_file(&path, &table)?
+            }
+        },
+
+        Subcommands::Record {
+            quantize,
+            max_delay,
+            no_delays,
+            keymap,
+            name,
+        } => {
+            let path = if keymap.components().count() > 1 {
+                keymap
+            } else {
+                PathBuf::from(constants::DEFAULT_KEYMAP_DIR).join(keymap)
+            };
+
+            let mut table = if path.exists() {
+                NativeBackend::from_file(&path)?
+            } else {
+                KeyMappingTable::new()
+            };
+
+            dbus_client::start_recording()?;
+
+            println!("{}", tr!("recording"));
+
+            let mut line = String::new();
+            let result = io::stdin().read_line(&mut line);
+
+            let steps = dbus_client::stop_recording()?;
+            result?;
+
+            let mut recorded = RecordedMacro::new(serde_json::from_str(&steps)?);
+
+            recorded.trim();
+
+            if no_delays {
+                recorded.adjust_delays(0, Some(0));
+            } else {
+                recorded.adjust_delays(quantize, max_delay);
+            }
+
+            if recorded.steps.is_empty() {
+                return Err(MainError::EmptyMacro {}.into());
+            }
+
+            println!(
+                "{}",
+                tr!(
+                    "recorded-macro",
+                    name = name.as_str(),
+                    events = recorded.steps.len(),
+                    millis = recorded.duration_millis()
+                )
+            );
+
+            table.macros_mut().insert(name, recorded);
+
+            NativeBackend::new().write_to_file(&path, &table)?
+        }
+
+        Subcommands::Import {
+            device,
+            force,
+            keymap,
+            format,
+            file,
+        } => {
+            let device = device.parse::<u64>()?;
+
+            let path = if keymap.components().count() > 1 {
+                keymap
+            } else {
+                PathBuf::from(constants::DEFAULT_KEYMAP_DIR).join(keymap)
+            };
+
+            if path.exists() && !force {
+                return Err(MainError::KeymapExists {
+                    path: path.display().to_string(),
+                }
+                .into());
+            }
+
+            let device_info =
+                util::get_device_info_from_index(device).ok_or(MainError::InvalidDevice {})?;
+
+            println!(
+                "Selected device: {} {}",
+                device_info.make.bold(),
+                device_info.model.bold()
+            );
+
+            let data = fs::read_to_string(&file)?;
+            let import =
+                importers::import(&format, &data, (device_info.usb_vid, device_info.usb_pid))?;
+
+            for warning in import.warnings.iter() {
+                messages::warning!("{}", warning);
+            }
+
+            let mut table = import.table;
+            table.set_file_name(&path);
+
+            println!(
+                "{}",
+                tr!(
+                    "imported",
+                    mappings = table.mappings().len(),
+                    layers = table.layers().len(),
+                    warnings = import.warnings.len()
+                )
+            );
+
+            NativeBackend::new().write_to_file(&path, &table)?
+        }
+
+        Subcommands::Export {
+            keymap,
+            format,
+            file,
+        } => {
+            let path = if keymap.components().count() > 1 {
+                keymap
+            } else {
+                PathBuf::from(constants::DEFAULT_KEYMAP_DIR).join(keymap)
+            };
+
+            let table = NativeBackend::from_file(&path)?;
+
+            let backend = match format.to_lowercase().as_str() {
+                "keyd" => KeydBackend::new(),
+
+                _ => return Err(MainError::UnsupportedFormat { format }.into()),
+            };
+
+            match file {
+                Some(file) => backend.write_to_file(&file, &table)?,
+                None => print!("{}", backend.generate(&table)?),
+            }
+        }
+
         Subcommands::Description {
             keymap,
             description,
@@ -578,42 +906,103 @@ pub async fn async_main() -> std::result::Result<(), eyre::Error> {
             println!("File: {}", &path.display().bold());
             println!("Description: {}", table.description().bold());
 
-            let mut tab = Table::new();
-            tab.load_preset(UTF8_FULL)
-                .apply_modifier(UTF8_ROUND_CORNERS)
-                .set_content_arrangement(ContentArrangement::Dynamic)
-                // .set_width(40)
-                .set_header(vec![
-                    "#",
-                    &tr!("source"),
-                    &tr!("action"),
-                    &tr!("description"),
-                    &tr!("flags"),
-                ]);
+            if let Some(layout) = xkb::LAYOUT.read().as_ref() {
+                println!("{}: {}", tr!("layout"), layout.name().bold());
+            }
 
-            for (index, (source, action)) in table.mappings().iter().enumerate() {
-                let description = if action.description.trim().is_empty() {
-                    Cell::new(&format!("{}", tr!("n-a").italic()))
-                } else {
-                    Cell::new(&format!("{}", action.description))
-                };
+            // mappings without layers are shown first, followed by one table per layer
+            let mut layers: BTreeSet<Option<usize>> = table
+                .mappings()
+                .keys()
+                .flat_map(|source| {
+                    if source.is_layered() {
+                        source.layers.0.iter().map(|layer| Some(*layer)).collect()
+                    } else {
+                        vec![None]
+                    }
+                })
+                .collect();
 
-                let enabled = if action.enabled {
-                    Cell::new(&format!("{}", tr!("enabled")))
-                } else {
-                    Cell::new(&format!("{}", tr!("disabled")))
-                };
+            layers.extend(table.layers().keys().map(|layer| Some(*layer)));
 
-                tab.add_row(vec![
-                    Cell::new(&format!("{}", index + 1)),
-                    Cell::new(&format!("{}", source)),
-                    Cell::new(&format!("{}", action)),
-                    description,
-                    enabled,
-                ]);
+            for layer in layers {
+                println!();
+
+                match layer {
+                    Some(index) => match table.layers().get(&index) {
+                        Some(info) => println!("{} {}: {}", tr!("layer"), index, info.bold()),
+                        None => println!("{} {}", tr!("layer"), index),
+                    },
+
+                    None => println!("{}", tr!("no-layer")),
+                }
+
+                let mut tab = Table::new();
+                tab.load_preset(UTF8_FULL)
+                    .apply_modifier(UTF8_ROUND_CORNERS)
+                    .set_content_arrangement(ContentArrangement::Dynamic)
+                    // .set_width(40)
+                    .set_header(vec![
+                        "#",
+                        &tr!("source"),
+                        &tr!("action"),
+                        &tr!("description"),
+                        &tr!("flags"),
+                    ]);
+
+                for (index, (source, action)) in table.mappings().iter().enumerate() {
+                    let visible = match layer {
+                        Some(layer) => source.is_layered() && source.layers.0.contains(&layer),
+                        None => !source.is_layered(),
+                    };
+
+                    if !visible {
+                        continue;
+                    }
+
+                    let description = if action.description.trim().is_empty() {
+                        Cell::new(&format!("{}", tr!("n-a").italic()))
+                    } else {
+                        Cell::new(&format!("{}", action.description))
+                    };
+
+                    let enabled = if action.enabled {
+                        Cell::new(&format!("{}", tr!("enabled")))
+                    } else {
+                        Cell::new(&format!("{}", tr!("disabled")))
+                    };
+
+                    tab.add_row(vec![
+                        Cell::new(&format!("{}", index + 1)),
+                        Cell::new(&format!("{}", source)),
+                        Cell::new(&format!("{}", action)),
+                        description,
+                        enabled,
+                    ]);
+                }
+
+                println!("{}", tab);
             }
 
-            println!("{}", tab);
+            if !table.macros().is_empty() {
+                println!();
+
+                let mut tab = Table::new();
+                tab.load_preset(UTF8_FULL)
+                    .apply_modifier(UTF8_ROUND_CORNERS)
+                    .set_content_arrangement(ContentArrangement::Dynamic)
+                    .set_header(vec![&tr!("macro"), &tr!("events"), &tr!("duration")]);
+
+                for (name, recorded) in table.macros().iter() {
+                    tab.add_row(vec![
+                        Cell::new(name),
+                        Cell::new(&format!("{}", recorded.steps.len())),
+                        Cell::new(&format!("{} ms", recorded.duration_millis())),
+                    ]);
+                }
+
+                println!("{}", tab);
+            }
         }
 
         Subcommands::Compile { keymap } => {
@@ -659,13 +1048,16 @@ pub async fn async_main() -> std::result::Result<(), eyre::Error> {
                 .apply_modifier(UTF8_ROUND_CORNERS)
                 .set_content_arrangement(ContentArrangement::Dynamic)
                 // .set_width(40)
-                .set_header(vec![&tr!("symbol"), &tr!("code")]);
+                .set_header(vec!["#", &tr!("symbol"), &tr!("code"), &tr!("keycap")]);
 
             for (index, code) in event.iter().enumerate() {
+                let keycap = util::evdev_event_code_to_keycap(util::evdev_key_event_to_int(code));
+
                 tab.add_row(vec![
                     Cell::new(&format!("{}", index + 1)),
                     Cell::new(&format!("{}", code.to_string())),
                     Cell::new(&format!("{}", util::evdev_key_event_to_int(code))),
+                    Cell::new(&keycap.unwrap_or_default()),
                 ]);
             }
 
@@ -17,37 +17,45 @@ along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 Copyright (c) 2019-2022, The Eruption Development Team
 */
 
+#![allow(dead_code)]
+
+//! The native keymap format, as written by `eruption-keymap` and loaded by the Eruption daemon
+//!
+//! This file is shared between both crates, the `Display` implementations of `Key` and
+//! `EvdevEvent` depend on the crate and are implemented outside of this file
+
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Display;
 use std::path::Path;
 use std::path::PathBuf;
 
 use chrono::{DateTime, Utc};
-use color_eyre::owo_colors::OwoColorize;
+use evdev_rs::enums::EV_KEY;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json_any_key::any_key_map;
 
-use crate::util;
-
-//pub type Result<T> = std::result::Result<T, eyre::Error>;
-
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub struct KeyMappingTable {
     pub metadata: TableMetadata,
 
     #[serde(with = "any_key_map")]
     pub mappings: BTreeMap<Source, Rule>,
+
+    /// Named layers, mappings are assigned to layers via the layer set of their source
+    #[serde(default)]
+    pub layers: BTreeMap<usize, Layer>,
+
+    /// Recorded macros, that may be played back by the `PlayMacro` action
+    #[serde(default)]
+    pub macros: BTreeMap<String, RecordedMacro>,
 }
 
 #[allow(unused)]
 impl KeyMappingTable {
     pub fn new() -> Self {
-        Self {
-            metadata: TableMetadata::default(),
-            mappings: BTreeMap::new(),
-        }
+        Self::default()
     }
 
     pub fn file_name(&self) -> &Path {
@@ -89,9 +97,64 @@ impl KeyMappingTable {
     pub fn mappings_mut(&mut self) -> &mut BTreeMap<Source, Rule> {
         &mut self.mappings
     }
+
+    pub fn layers(&self) -> &BTreeMap<usize, Layer> {
+        &self.layers
+    }
+
+    pub fn layers_mut(&mut self) -> &mut BTreeMap<usize, Layer> {
+        &mut self.layers
+    }
+
+    pub fn macros(&self) -> &BTreeMap<String, RecordedMacro> {
+        &self.macros
+    }
+
+    pub fn macros_mut(&mut self) -> &mut BTreeMap<String, RecordedMacro> {
+        &mut self.macros
+    }
+
+    /// Resolve a layer, given either by its number or by its name
+    pub fn find_layer(&self, layer: &str) -> Option<usize> {
+        layer.parse::<usize>().ok().or_else(|| {
+            self.layers
+                .iter()
+                .find(|(_, l)| l.name.eq_ignore_ascii_case(layer))
+                .map(|(index, _)| *index)
+        })
+    }
+
+    /// Returns the name of a layer, or its number if it has not been named
+    pub fn layer_name(&self, layer: usize) -> String {
+        self.layers
+            .get(&layer)
+            .map(|l| l.name.clone())
+            .unwrap_or_else(|| format!("{}", layer))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub struct Layer {
+    pub name: String,
+
+    /// The color (0xAARRGGBB) that is used to highlight mapped keys while the layer is active
+    #[serde(default)]
+    pub color: Option<u32>,
+}
+
+impl Display for Layer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.color {
+            Some(color) => {
+                f.write_str(&format!("{} (color: #{:06x})", self.name, color & 0xffffff))
+            }
+            None => f.write_str(&self.name),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub struct TableMetadata {
     pub file_name: PathBuf,
@@ -165,11 +228,10 @@ impl Source {
     pub fn get_layers_mut(&mut self) -> &mut BTreeSet<usize> {
         &mut self.layers.0
     }
-}
 
-impl Display for Source {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let has_layers = match self.event {
+    /// Returns true if the source is only in effect while one of its layers is active
+    pub fn is_layered(&self) -> bool {
+        match self.event {
             Event::Null => false,
             Event::HidKeyDown(_) => false,
             Event::HidKeyUp(_) => false,
@@ -187,9 +249,21 @@ impl Display for Source {
             Event::SimpleMouseUp(_) => false,
             Event::SimpleMouseWheel(_) => false,
             Event::SimpleMouseDpi(_) => false,
-        };
+            Event::KeyTap(_) => false,
+            Event::KeyHold(_) => false,
+            Event::KeyMultiTap(_) => false,
+            Event::KeyChord(_) => false,
+            Event::KeySequence(_) => false,
+            Event::MouseGesture(_) => false,
+            Event::MouseWheelTilt(_) => false,
+            Event::MouseWheelSpeed(_) => false,
+        }
+    }
+}
 
-        if has_layers {
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_layered() {
             f.write_str(&format!(
                 "Source: {} on layers: [ {}]",
                 self.event, self.layers
@@ -248,6 +322,158 @@ pub enum Action {
     Null,
     InjectKey(EvdevEvent),
     Call(Macro),
+
+    /// Press multiple keys together, e.g. `ctrl+shift+t`
+    KeyCombo(Vec<EvdevEvent>),
+
+    /// Execute actions one after another
+    Sequence(Vec<Action>),
+
+    /// Wait for the specified number of milliseconds, only valid within a sequence
+    Delay(u64),
+
+    /// Type a (unicode) string on the virtual keyboard
+    TypeText(String),
+
+    /// Repeat an action while the source is held down
+    Repeat(Repeat),
+
+    /// Switch to a slot (1-4)
+    SwitchSlot(usize),
+    SwitchProfile(String),
+
+    /// Change the global brightness (in percent)
+    Brightness(Adjustment),
+
+    /// Change the DPI setting of the mouse
+    Dpi(Adjustment),
+
+    /// Toggle a layer on or off
+    ToggleLayer(usize),
+
+    /// Activate a layer while the source is held down
+    MomentaryLayer(usize),
+
+    /// Activate a layer for the next key press only
+    OneShotLayer(usize),
+
+    /// Play back a recorded macro of the keymap table
+    PlayMacro(String),
+}
+
+/// A sequence of key and mouse events, along with their timing
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub struct RecordedMacro {
+    pub steps: Vec<MacroStep>,
+}
+
+impl RecordedMacro {
+    pub fn new(steps: Vec<MacroStep>) -> Self {
+        Self { steps }
+    }
+
+    /// Remove the events that are not part of the macro itself: releases of keys
+    /// that were already pressed when the recording started, and the press of the
+    /// Enter key that stopped the recording
+    pub fn trim(&mut self) {
+        let mut pressed = BTreeSet::new();
+
+        self.steps.retain(|step| match step.event {
+            MacroEvent::Key { code, down: true } => {
+                pressed.insert(code);
+                true
+            }
+
+            MacroEvent::Key { code, down: false } => pressed.remove(&code),
+
+            _ => true,
+        });
+
+        let enter = EV_KEY::KEY_ENTER as u32;
+
+        if let Some(index) = self.steps.iter().rposition(
+            |step| matches!(step.event, MacroEvent::Key { code, down: true } if code == enter),
+        ) {
+            self.steps.truncate(index);
+        }
+
+        if let Some(first) = self.steps.first_mut() {
+            first.delay_millis = 0;
+        }
+    }
+
+    /// Round all delays to a multiple of `quantum` milliseconds and limit them to `max_delay`
+    pub fn adjust_delays(&mut self, quantum: u64, max_delay: Option<u64>) {
+        for step in self.steps.iter_mut() {
+            if quantum > 0 {
+                step.delay_millis = (step.delay_millis + quantum / 2) / quantum * quantum;
+            }
+
+            if let Some(max_delay) = max_delay {
+                step.delay_millis = step.delay_millis.min(max_delay);
+            }
+        }
+    }
+
+    /// The total duration of the macro in milliseconds
+    pub fn duration_millis(&self) -> u64 {
+        self.steps.iter().map(|step| step.delay_millis).sum()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub struct MacroStep {
+    /// The delay since the previous event
+    pub delay_millis: u64,
+    pub event: MacroEvent,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MacroEvent {
+    /// A key event, `code` is the evdev key code
+    Key {
+        code: u32,
+        down: bool,
+    },
+
+    /// A mouse button event, `button` is the button index of the virtual mouse
+    MouseButton {
+        button: u32,
+        down: bool,
+    },
+
+    MouseWheel {
+        direction: u32,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub struct Repeat {
+    pub action: Box<Action>,
+    pub interval_millis: u64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum Adjustment {
+    /// Set to an absolute value
+    Set(i64),
+
+    /// Increase or decrease by a value
+    Relative(i64),
+}
+
+impl Adjustment {
+    pub fn apply(&self, value: i64) -> i64 {
+        match *self {
+            Adjustment::Set(val) => val,
+            Adjustment::Relative(delta) => value + delta,
+        }
+    }
 }
 
 impl Display for Action {
@@ -257,6 +483,53 @@ impl Display for Action {
 
             Action::InjectKey(key) => f.write_str(&format!("Event: {}", key)),
             Action::Call(call) => f.write_str(&format!("Call: {}", call)),
+
+            Action::KeyCombo(keys) => f.write_str(&format!(
+                "Combo: {}",
+                keys.iter()
+                    .map(|key| key.to_string())
+                    .collect::<Vec<_>>()
+                    .join("+")
+            )),
+
+            Action::Sequence(actions) => f.write_str(&format!(
+                "Sequence: [{}]",
+                actions
+                    .iter()
+                    .map(|action| action.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+
+            Action::Delay(millis) => f.write_str(&format!("Delay: {}ms", millis)),
+            Action::TypeText(text) => f.write_str(&format!("Type: {:?}", text)),
+
+            Action::Repeat(repeat) => f.write_str(&format!(
+                "Repeat: {} every {}ms",
+                repeat.action, repeat.interval_millis
+            )),
+
+            Action::SwitchSlot(slot) => f.write_str(&format!("Switch to slot {}", slot)),
+            Action::SwitchProfile(profile) => {
+                f.write_str(&format!("Switch to profile {}", profile))
+            }
+
+            Action::Brightness(adjustment) => f.write_str(&format!("Brightness: {}", adjustment)),
+            Action::Dpi(adjustment) => f.write_str(&format!("DPI: {}", adjustment)),
+
+            Action::ToggleLayer(layer) => f.write_str(&format!("Toggle layer {}", layer)),
+            Action::MomentaryLayer(layer) => f.write_str(&format!("Momentary layer {}", layer)),
+            Action::OneShotLayer(layer) => f.write_str(&format!("One-shot layer {}", layer)),
+            Action::PlayMacro(name) => f.write_str(&format!("Play macro {}", name)),
+        }
+    }
+}
+
+impl Display for Adjustment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Adjustment::Set(val) => f.write_str(&format!("{}", val)),
+            Adjustment::Relative(delta) => f.write_str(&format!("{:+}", delta)),
         }
     }
 }
@@ -283,30 +556,12 @@ impl Key {
     }
 }
 
-impl Display for Key {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&format!(
-            "{} ({})",
-            util::key_index_to_symbol(self.key_index + 1, (self.usb_vid, self.usb_pid))
-                .unwrap_or_else(|| "<invalid symbol>".italic().to_string()),
-            self.key_index
-        ))
-    }
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash, Ord, PartialOrd)]
 #[serde(rename_all = "lowercase")]
 pub struct EvdevEvent {
     pub event: u32,
 }
 
-impl Display for EvdevEvent {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        //f.write_str(&format!("Event:{}", self.key_index))
-        f.write_str(&format!("{}", util::evdev_event_code_to_string(self.event)))
-    }
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash, Ord, PartialOrd)]
 #[serde(rename_all = "lowercase")]
 pub struct Macro {
@@ -342,6 +597,16 @@ pub enum Event {
     SimpleMouseUp(Key),
     SimpleMouseWheel(Direction),
     SimpleMouseDpi(Direction),
+
+    KeyTap(TapHold),
+    KeyHold(TapHold),
+    KeyMultiTap(MultiTap),
+    KeyChord(KeyCombination),
+    KeySequence(KeyCombination),
+
+    MouseGesture(Gesture),
+    MouseWheelTilt(WheelTilt),
+    MouseWheelSpeed(WheelSpeed),
 }
 
 impl Display for Event {
@@ -373,10 +638,137 @@ impl Display for Event {
                 f.write_str(&format!("mouse-wheel: {}", direction))
             }
             Event::SimpleMouseDpi(direction) => f.write_str(&format!("mouse-dpi: {}", direction)),
+
+            Event::KeyTap(tap) => f.write_str(&format!("tap: {}", tap)),
+            Event::KeyHold(hold) => f.write_str(&format!("hold: {}", hold)),
+            Event::KeyMultiTap(taps) => f.write_str(&format!("{}", taps)),
+            Event::KeyChord(chord) => f.write_str(&format!("chord: {}", chord.display(" + "))),
+            Event::KeySequence(sequence) => {
+                f.write_str(&format!("sequence: {}", sequence.display(", ")))
+            }
+            Event::MouseGesture(gesture) => f.write_str(&format!("gesture: {}", gesture)),
+            Event::MouseWheelTilt(tilt) => f.write_str(&format!("wheel-tilt: {}", tilt)),
+            Event::MouseWheelSpeed(speed) => f.write_str(&format!("fast-wheel: {}", speed)),
         }
     }
 }
 
+/// A key that behaves differently when tapped or held down
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub struct TapHold {
+    pub key: Key,
+
+    /// Keys that are held down longer than this are considered as held
+    pub threshold_millis: u64,
+}
+
+impl Display for TapHold {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!("{} @{}ms", self.key, self.threshold_millis))
+    }
+}
+
+/// A key that is tapped multiple times in a row
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub struct MultiTap {
+    pub key: Key,
+    pub count: usize,
+
+    /// Maximum time between two taps
+    pub timeout_millis: u64,
+}
+
+impl Display for MultiTap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.count {
+            2 => "double-tap".to_string(),
+            3 => "triple-tap".to_string(),
+            count => format!("{}x-tap", count),
+        };
+
+        f.write_str(&format!(
+            "{}: {} @{}ms",
+            kind, self.key, self.timeout_millis
+        ))
+    }
+}
+
+/// Multiple keys that form a chord, or a leader key sequence
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub struct KeyCombination {
+    pub keys: Vec<Key>,
+
+    /// Maximum time between the keys of the combination
+    pub timeout_millis: u64,
+}
+
+impl KeyCombination {
+    fn display(&self, separator: &str) -> String {
+        let keys: Vec<String> = self.keys.iter().map(|key| key.to_string()).collect();
+
+        format!("{} @{}ms", keys.join(separator), self.timeout_millis)
+    }
+}
+
+/// A mouse button that is held down while the mouse is moved in a direction
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub struct Gesture {
+    pub button: Key,
+    pub direction: Direction,
+
+    /// Distance the mouse has to be moved, in device units
+    pub distance: u32,
+}
+
+impl Display for Gesture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!(
+            "button {} + {} @{}",
+            self.button.key_index, self.direction, self.distance
+        ))
+    }
+}
+
+/// The scroll wheel is tilted to the left or to the right
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub struct WheelTilt {
+    pub direction: Direction,
+
+    /// Wheel events that follow each other within this time belong to the same tilt
+    pub timeout_millis: u64,
+}
+
+impl Display for WheelTilt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!("{} @{}ms", self.direction, self.timeout_millis))
+    }
+}
+
+/// The scroll wheel is turned faster than a threshold
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub struct WheelSpeed {
+    pub direction: Direction,
+
+    /// Minimum number of wheel events within `window_millis`
+    pub notches: usize,
+    pub window_millis: u64,
+}
+
+impl Display for WheelSpeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!(
+            "{} @{}/{}ms",
+            self.direction, self.notches, self.window_millis
+        ))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Hash, Eq, PartialEq, Ord, PartialOrd)]
 #[serde(rename_all = "lowercase")]
 pub enum Direction {
@@ -387,7 +779,7 @@ pub enum Direction {
 }
 
 impl Direction {
-    pub fn as_int(&self) -> i32 {
+    pub fn as_int(&self) -> u8 {
         match self {
             Direction::Up => 1,
             Direction::Down => 2,
@@ -18,6 +18,7 @@
 */
 
 use eyre::eyre;
+use pest::iterators::{Pair, Pairs};
 use pest::Parser;
 use pest_derive::Parser;
 
@@ -29,38 +30,183 @@ pub type Result<T> = std::result::Result<T, eyre::Error>;
 #[grammar = "parsers/grammar/action.pest"]
 pub struct ActionParser;
 
-pub fn parse(action: &str) -> Result<Action> {
-    let mut result = None;
-
+/// Parse an action expression; layer names are resolved using the layers of `table`
+pub fn parse(action: &str, table: &KeyMappingTable) -> Result<Action> {
     let pairs = ActionParser::parse(Rule::Action, action)?.next().unwrap();
 
-    for pair in pairs.into_inner() {
-        match pair.as_rule() {
-            Rule::Event => {
-                let text = pair.into_inner().as_str();
-
-                if let Some(code) = util::evdev_event_code_from_string(text) {
-                    let event = code as u32;
-                    result = Some(Action::InjectKey(EvdevEvent { event }));
-                } else {
-                    let event = text.parse::<u32>()?;
-                    result = Some(Action::InjectKey(EvdevEvent { event }));
-                }
+    pairs
+        .into_inner()
+        .find(|pair| pair.as_rule() != Rule::EOI)
+        .map(|pair| parse_action(pair, table))
+        .unwrap_or_else(|| Err(eyre!("Parse error in action expression")))
+}
+
+fn parse_action(pair: Pair<Rule>, table: &KeyMappingTable) -> Result<Action> {
+    let rule = pair.as_rule();
+    let mut inner = pair.into_inner();
+
+    let result = match rule {
+        Rule::Null => Action::Null,
+
+        Rule::Call => {
+            let function_name = inner.as_str().to_string();
+            Action::Call(Macro { function_name })
+        }
+
+        Rule::Event => {
+            let text = inner.as_str();
+
+            if let Some(code) = util::evdev_event_code_from_string(text) {
+                let event = code as u32;
+                Action::InjectKey(EvdevEvent { event })
+            } else {
+                let event = text.parse::<u32>()?;
+                Action::InjectKey(EvdevEvent { event })
+            }
+        }
+
+        Rule::Combo => Action::KeyCombo(parse_keys(inner)?),
+
+        // a single key in a sequence is pressed on its own
+        Rule::Keys => {
+            let mut keys = parse_keys(inner)?;
+
+            if keys.len() == 1 {
+                Action::InjectKey(keys.remove(0))
+            } else {
+                Action::KeyCombo(keys)
+            }
+        }
+
+        Rule::Sequence => Action::Sequence(
+            inner
+                .map(|pair| parse_action(pair, table))
+                .collect::<Result<_>>()?,
+        ),
+
+        Rule::Delay => Action::Delay(inner.as_str().parse()?),
+
+        Rule::Text => {
+            let string = inner.next().unwrap().into_inner().as_str();
+            Action::TypeText(unescape(string))
+        }
+
+        Rule::Repeat => {
+            let action = parse_action(inner.next().unwrap(), table)?;
+            let interval_millis = inner.next().unwrap().into_inner().as_str().parse()?;
+
+            Action::Repeat(Repeat {
+                action: Box::new(action),
+                interval_millis,
+            })
+        }
+
+        Rule::Slot => {
+            let slot = inner.as_str().parse::<usize>()?;
+
+            if !(1..=4).contains(&slot) {
+                return Err(eyre!("Invalid slot: {}", slot));
             }
 
-            Rule::Call => {
-                let function_name = pair.into_inner().as_str().to_string();
-                result = Some(Action::Call(Macro { function_name }));
+            Action::SwitchSlot(slot)
+        }
+
+        Rule::Profile => Action::SwitchProfile(inner.as_str().to_string()),
+
+        Rule::Brightness => Action::Brightness(parse_adjustment(inner.as_str())?),
+        Rule::Dpi => Action::Dpi(parse_adjustment(inner.as_str())?),
+
+        Rule::LayerToggle => Action::ToggleLayer(parse_layer(inner.as_str(), table)?),
+        Rule::LayerMomentary => Action::MomentaryLayer(parse_layer(inner.as_str(), table)?),
+        Rule::LayerOneShot => Action::OneShotLayer(parse_layer(inner.as_str(), table)?),
+
+        Rule::PlayMacro => {
+            let name = inner.as_str();
+
+            if !table.macros().contains_key(name) {
+                return Err(eyre!("Unknown macro: {}", name));
             }
 
-            Rule::Null => result = Some(Action::Null),
+            Action::PlayMacro(name.to_string())
+        }
+
+        _ => return Err(eyre!("Parse error in action expression")),
+    };
+
+    Ok(result)
+}
+
+fn parse_keys(pairs: Pairs<Rule>) -> Result<Vec<EvdevEvent>> {
+    pairs
+        .map(|pair| {
+            let name = pair.as_str();
+
+            key_name_to_event(name)
+                .map(|event| EvdevEvent { event })
+                .ok_or_else(|| eyre!("Invalid key: {}", name))
+        })
+        .collect()
+}
+
+/// Resolve a key name like `ctrl`, `a`, `f1`, `KEY_ESC` or a numeric event code
+pub fn key_name_to_event(name: &str) -> Option<u32> {
+    let alias = match name.to_lowercase().as_str() {
+        "ctrl" | "control" => Some("KEY_LEFTCTRL"),
+        "shift" => Some("KEY_LEFTSHIFT"),
+        "alt" => Some("KEY_LEFTALT"),
+        "altgr" => Some("KEY_RIGHTALT"),
+        "super" | "meta" | "win" => Some("KEY_LEFTMETA"),
+        "esc" | "escape" => Some("KEY_ESC"),
+        "return" => Some("KEY_ENTER"),
+
+        _ => None,
+    };
+
+    let name = alias.map(str::to_string).unwrap_or_else(|| {
+        let name = name.to_uppercase();
+
+        if name.starts_with("KEY_") {
+            name
+        } else {
+            format!("KEY_{}", name)
+        }
+    });
+
+    util::evdev_event_code_from_string(&name)
+        .map(|code| code as u32)
+        .or_else(|| name.trim_start_matches("KEY_").parse::<u32>().ok())
+}
+
+fn parse_layer(layer: &str, table: &KeyMappingTable) -> Result<usize> {
+    table
+        .find_layer(layer)
+        .ok_or_else(|| eyre!("Unknown layer: {}", layer))
+}
+
+fn parse_adjustment(text: &str) -> Result<Adjustment> {
+    if text.starts_with('+') || text.starts_with('-') {
+        Ok(Adjustment::Relative(text.parse()?))
+    } else {
+        Ok(Adjustment::Set(text.parse()?))
+    }
+}
 
-            _ => {
-                /* do nothing */
-                continue;
+fn unescape(text: &str) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some(c) => result.push(c),
+                None => {}
             }
+        } else {
+            result.push(c);
         }
     }
 
-    result.ok_or_else(|| eyre!("Parse error in action expression"))
+    result
 }
@@ -19,10 +19,11 @@
 
 use color_eyre::owo_colors::OwoColorize;
 use eyre::eyre;
+use pest::iterators::Pair;
 use pest::Parser;
 use pest_derive::Parser;
 
-use crate::{mapping::*, util};
+use crate::{constants, mapping::*, util};
 
 pub type Result<T> = std::result::Result<T, eyre::Error>;
 
@@ -232,6 +233,176 @@ pub fn parse(source: &str, device: usize) -> Result<Source> {
                 }
             }
 
+            Rule::KeyTap => {
+                let (key, threshold_millis) = parse_single_key(
+                    pair,
+                    (usb_vid, usb_pid),
+                    constants::DEFAULT_TAP_HOLD_THRESHOLD_MILLIS,
+                )?;
+
+                result = Some(Source::new(Event::KeyTap(TapHold {
+                    key,
+                    threshold_millis,
+                })));
+            }
+
+            Rule::KeyHold => {
+                let (key, threshold_millis) = parse_single_key(
+                    pair,
+                    (usb_vid, usb_pid),
+                    constants::DEFAULT_TAP_HOLD_THRESHOLD_MILLIS,
+                )?;
+
+                result = Some(Source::new(Event::KeyHold(TapHold {
+                    key,
+                    threshold_millis,
+                })));
+            }
+
+            Rule::KeyDoubleTap | Rule::KeyTripleTap => {
+                let count = if pair.as_rule() == Rule::KeyDoubleTap {
+                    2
+                } else {
+                    3
+                };
+
+                let (key, timeout_millis) = parse_single_key(
+                    pair,
+                    (usb_vid, usb_pid),
+                    constants::DEFAULT_MULTI_TAP_TIMEOUT_MILLIS,
+                )?;
+
+                result = Some(Source::new(Event::KeyMultiTap(MultiTap {
+                    key,
+                    count,
+                    timeout_millis,
+                })));
+            }
+
+            Rule::KeyChord => {
+                let (mut keys, timeout_millis) = parse_keys(
+                    pair,
+                    (usb_vid, usb_pid),
+                    constants::DEFAULT_CHORD_TIMEOUT_MILLIS,
+                )?;
+
+                // the order of the keys of a chord does not matter
+                keys.sort();
+                keys.dedup();
+
+                if keys.len() < 2 {
+                    return Err(SourceError::InvalidParameter {}.into());
+                }
+
+                result = Some(Source::new(Event::KeyChord(KeyCombination {
+                    keys,
+                    timeout_millis,
+                })));
+            }
+
+            Rule::KeySequence => {
+                let (keys, timeout_millis) = parse_keys(
+                    pair,
+                    (usb_vid, usb_pid),
+                    constants::DEFAULT_SEQUENCE_TIMEOUT_MILLIS,
+                )?;
+
+                result = Some(Source::new(Event::KeySequence(KeyCombination {
+                    keys,
+                    timeout_millis,
+                })));
+            }
+
+            Rule::MouseGesture => {
+                let mut button = None;
+                let mut direction = None;
+                let mut distance = constants::DEFAULT_GESTURE_DISTANCE;
+
+                for inner in pair.into_inner() {
+                    match inner.as_rule() {
+                        Rule::button => button = Some(inner.as_str().parse::<usize>()?),
+                        Rule::direction => direction = parse_direction(inner.as_str()),
+                        Rule::distance => distance = inner.into_inner().as_str().parse::<u32>()?,
+
+                        _ => { /* do nothing */ }
+                    }
+                }
+
+                let (button, direction) = button
+                    .zip(direction)
+                    .ok_or(SourceError::InvalidParameter {})?;
+
+                result = Some(Source::new(Event::MouseGesture(Gesture {
+                    button: Key::new(button, (usb_vid, usb_pid)),
+                    direction,
+                    distance,
+                })));
+            }
+
+            Rule::MouseWheelTilt => {
+                let mut direction = None;
+                let mut timeout_millis = constants::DEFAULT_WHEEL_TILT_TIMEOUT_MILLIS;
+
+                for inner in pair.into_inner() {
+                    match inner.as_rule() {
+                        Rule::direction => direction = parse_direction(inner.as_str()),
+                        Rule::timing => {
+                            timeout_millis = inner.into_inner().as_str().parse::<u64>()?
+                        }
+
+                        _ => { /* do nothing */ }
+                    }
+                }
+
+                // the wheel can only be tilted sideways
+                let direction = direction
+                    .filter(|d| matches!(d, Direction::Left | Direction::Right))
+                    .ok_or(SourceError::InvalidParameter {})?;
+
+                result = Some(Source::new(Event::MouseWheelTilt(WheelTilt {
+                    direction,
+                    timeout_millis,
+                })));
+            }
+
+            Rule::MouseWheelSpeed => {
+                let mut direction = None;
+                let mut notches = constants::DEFAULT_FAST_WHEEL_NOTCHES;
+                let mut window_millis = constants::DEFAULT_FAST_WHEEL_WINDOW_MILLIS;
+
+                for inner in pair.into_inner() {
+                    match inner.as_rule() {
+                        Rule::direction => direction = parse_direction(inner.as_str()),
+                        Rule::speed => {
+                            for value in inner.into_inner() {
+                                match value.as_rule() {
+                                    Rule::amount => notches = value.as_str().parse::<usize>()?,
+                                    Rule::millis => {
+                                        window_millis = value.as_str().parse::<u64>()?
+                                    }
+
+                                    _ => { /* do nothing */ }
+                                }
+                            }
+                        }
+
+                        _ => { /* do nothing */ }
+                    }
+                }
+
+                let direction = direction.ok_or(SourceError::InvalidParameter {})?;
+
+                if notches < 2 {
+                    return Err(SourceError::InvalidParameter {}.into());
+                }
+
+                result = Some(Source::new(Event::MouseWheelSpeed(WheelSpeed {
+                    direction,
+                    notches,
+                    window_millis,
+                })));
+            }
+
             Rule::Null => result = Some(Source::new(Event::Null)),
 
             _ => {
@@ -243,3 +414,55 @@ pub fn parse(source: &str, device: usize) -> Result<Source> {
 
     result.ok_or_else(|| eyre!("Parse error in source expression"))
 }
+
+fn parse_direction(direction: &str) -> Option<Direction> {
+    match direction.to_ascii_lowercase().as_str() {
+        "up" => Some(Direction::Up),
+        "down" => Some(Direction::Down),
+        "left" => Some(Direction::Left),
+        "right" => Some(Direction::Right),
+
+        _ => None,
+    }
+}
+
+/// Parse the keys and the optional timing of a timed source, e.g. `chord:j+k@50ms`
+fn parse_keys(
+    pair: Pair<Rule>,
+    (usb_vid, usb_pid): (u16, u16),
+    default_millis: u64,
+) -> Result<(Vec<Key>, u64)> {
+    let mut keys = vec![];
+    let mut millis = default_millis;
+
+    for inner in pair.into_inner() {
+        match inner.as_rule() {
+            Rule::key => {
+                let key_index = util::symbol_to_key_index(inner.as_str(), (usb_vid, usb_pid))
+                    .ok_or(SourceError::InvalidParameter {})?;
+
+                keys.push(Key::new(key_index, (usb_vid, usb_pid)));
+            }
+
+            Rule::timing => millis = inner.into_inner().as_str().parse::<u64>()?,
+
+            _ => { /* do nothing */ }
+        }
+    }
+
+    Ok((keys, millis))
+}
+
+fn parse_single_key(
+    pair: Pair<Rule>,
+    device: (u16, u16),
+    default_millis: u64,
+) -> Result<(Key, u64)> {
+    let (keys, millis) = parse_keys(pair, device, default_millis)?;
+    let key = keys
+        .into_iter()
+        .next()
+        .ok_or(SourceError::InvalidParameter {})?;
+
+    Ok((key, millis))
+}
@@ -19,8 +19,10 @@
 
 #![allow(unused)]
 
+use std::fmt::Display;
 use std::io;
 
+use color_eyre::owo_colors::OwoColorize;
 use evdev_rs::enums::{int_to_ev_key, EventCode, EV_KEY};
 use io::Write;
 use maplit::hashmap;
@@ -29,6 +31,8 @@ use crate::{
     dbus_client::get_managed_devices,
     device::{get_device_info, DeviceInfo},
     hwdevices,
+    mapping::{EvdevEvent, Key},
+    xkb,
 };
 
 #[allow(unused)]
@@ -1026,11 +1030,16 @@ pub fn evdev_key_event_to_int(event: EventCode) -> u32 {
     }
 }
 
+/// Resolve a key, given by its Linux key name, its index, or the symbol printed on its keycap
 pub fn symbol_to_key_index(symbol: &str, (usb_vid, usb_pid): (u16, u16)) -> Option<usize> {
     if let Some(event) = evdev_event_code_from_string(&symbol.to_uppercase()) {
         hwdevices::ev_key_to_index(&event, (usb_vid, usb_pid)).map(|e| e + 1)
+    } else if let Ok(index) = symbol.parse::<usize>() {
+        Some(index)
     } else {
-        symbol.parse::<usize>().ok()
+        let event = xkb::find_key(symbol)?;
+
+        hwdevices::ev_key_to_index(&event, (usb_vid, usb_pid)).map(|e| e + 1)
     }
 }
 
@@ -1039,3 +1048,52 @@ pub fn key_index_to_symbol(index: usize, (usb_vid, usb_pid): (u16, u16)) -> Opti
 
     Some(evdev_event_code_to_string(ev_key as u32))
 }
+
+/// Returns the symbol printed on the keycap of the key, if it differs from the Linux key name
+pub fn key_index_to_keycap(index: usize, (usb_vid, usb_pid): (u16, u16)) -> Option<String> {
+    let ev_key = hwdevices::index_to_ev_key(index, (usb_vid, usb_pid))?;
+
+    evdev_event_code_to_keycap(ev_key as u32)
+}
+
+/// Returns the symbol printed on the keycap of `event`, if it differs from the Linux key name
+pub fn evdev_event_code_to_keycap(event: u32) -> Option<String> {
+    let keycap = xkb::keycap(event)?;
+    let name = evdev_event_code_to_string(event);
+
+    if name.trim_start_matches("KEY_") == keycap {
+        None
+    } else {
+        Some(keycap)
+    }
+}
+
+// the mapping types are shared with the Eruption daemon, so keys are displayed here, where the
+// symbols of the supported devices are known
+impl Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let device = (self.usb_vid, self.usb_pid);
+
+        let symbol = key_index_to_symbol(self.key_index + 1, device)
+            .unwrap_or_else(|| "<invalid symbol>".italic().to_string());
+
+        match key_index_to_keycap(self.key_index + 1, device) {
+            Some(keycap) => f.write_str(&format!("{} [{}] ({})", symbol, keycap, self.key_index)),
+            None => f.write_str(&format!("{} ({})", symbol, self.key_index)),
+        }
+    }
+}
+
+impl Display for EvdevEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match evdev_event_code_to_keycap(self.event) {
+            Some(keycap) => f.write_str(&format!(
+                "{} [{}]",
+                evdev_event_code_to_string(self.event),
+                keycap
+            )),
+
+            None => f.write_str(&format!("{}", evdev_event_code_to_string(self.event))),
+        }
+    }
+}
@@ -0,0 +1,721 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+//! Resolve key names using the XKB keyboard layout, so that keys may be referred to by the
+//! characters printed on their keycaps. The layout is read from the xkeyboard-config data files.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::{env, fs};
+
+use evdev_rs::enums::{int_to_ev_key, EV_KEY};
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::RwLock;
+use regex::Regex;
+
+use crate::constants;
+
+pub type Result<T> = std::result::Result<T, eyre::Error>;
+
+/// Maximum nesting depth of `include` statements
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+lazy_static! {
+    /// The keyboard layout that is used to resolve and display key names
+    pub static ref LAYOUT: RwLock<Option<Layout>> = RwLock::new(None);
+
+    static ref INCLUDE: Regex = Regex::new(r#"^\s*(?:include|augment|override|replace)\s+"([^"]*)""#).unwrap();
+    static ref KEY: Regex = Regex::new(r"^\s*(?:replace\s+|override\s+|augment\s+)?key\s+<(\w+)>\s*\{([^}]*)\}").unwrap();
+    static ref LEVELS: Regex = Regex::new(r"\[([\w\s,+]*)\]").unwrap();
+    static ref KEYCODE: Regex = Regex::new(r"^\s*<(\w+)>\s*=\s*(\d+)\s*;").unwrap();
+    static ref ALIAS: Regex = Regex::new(r"^\s*alias\s+<(\w+)>\s*=\s*<(\w+)>\s*;").unwrap();
+}
+
+/// Keysyms of the Latin-1 range, along with the character they produce
+#[rustfmt::skip]
+const KEYSYMS: &[(&str, char)] = &[
+    ("space", ' '),
+    ("exclam", '!'),
+    ("quotedbl", '"'),
+    ("numbersign", '#'),
+    ("dollar", '$'),
+    ("percent", '%'),
+    ("ampersand", '&'),
+    ("apostrophe", '\''),
+    ("parenleft", '('),
+    ("parenright", ')'),
+    ("asterisk", '*'),
+    ("plus", '+'),
+    ("comma", ','),
+    ("minus", '-'),
+    ("period", '.'),
+    ("slash", '/'),
+    ("0", '0'),
+    ("1", '1'),
+    ("2", '2'),
+    ("3", '3'),
+    ("4", '4'),
+    ("5", '5'),
+    ("6", '6'),
+    ("7", '7'),
+    ("8", '8'),
+    ("9", '9'),
+    ("colon", ':'),
+    ("semicolon", ';'),
+    ("less", '<'),
+    ("equal", '='),
+    ("greater", '>'),
+    ("question", '?'),
+    ("at", '@'),
+    ("A", 'A'),
+    ("B", 'B'),
+    ("C", 'C'),
+    ("D", 'D'),
+    ("E", 'E'),
+    ("F", 'F'),
+    ("G", 'G'),
+    ("H", 'H'),
+    ("I", 'I'),
+    ("J", 'J'),
+    ("K", 'K'),
+    ("L", 'L'),
+    ("M", 'M'),
+    ("N", 'N'),
+    ("O", 'O'),
+    ("P", 'P'),
+    ("Q", 'Q'),
+    ("R", 'R'),
+    ("S", 'S'),
+    ("T", 'T'),
+    ("U", 'U'),
+    ("V", 'V'),
+    ("W", 'W'),
+    ("X", 'X'),
+    ("Y", 'Y'),
+    ("Z", 'Z'),
+    ("bracketleft", '['),
+    ("backslash", '\\'),
+    ("bracketright", ']'),
+    ("asciicircum", '^'),
+    ("underscore", '_'),
+    ("grave", '`'),
+    ("a", 'a'),
+    ("b", 'b'),
+    ("c", 'c'),
+    ("d", 'd'),
+    ("e", 'e'),
+    ("f", 'f'),
+    ("g", 'g'),
+    ("h", 'h'),
+    ("i", 'i'),
+    ("j", 'j'),
+    ("k", 'k'),
+    ("l", 'l'),
+    ("m", 'm'),
+    ("n", 'n'),
+    ("o", 'o'),
+    ("p", 'p'),
+    ("q", 'q'),
+    ("r", 'r'),
+    ("s", 's'),
+    ("t", 't'),
+    ("u", 'u'),
+    ("v", 'v'),
+    ("w", 'w'),
+    ("x", 'x'),
+    ("y", 'y'),
+    ("z", 'z'),
+    ("braceleft", '{'),
+    ("bar", '|'),
+    ("braceright", '}'),
+    ("asciitilde", '~'),
+    ("nobreakspace", '\u{a0}'),
+    ("exclamdown", '¡'),
+    ("cent", '¢'),
+    ("sterling", '£'),
+    ("currency", '¤'),
+    ("yen", '¥'),
+    ("brokenbar", '¦'),
+    ("section", '§'),
+    ("diaeresis", '¨'),
+    ("copyright", '©'),
+    ("ordfeminine", 'ª'),
+    ("guillemotleft", '«'),
+    ("notsign", '¬'),
+    ("hyphen", '\u{ad}'),
+    ("registered", '®'),
+    ("macron", '¯'),
+    ("degree", '°'),
+    ("plusminus", '±'),
+    ("twosuperior", '²'),
+    ("threesuperior", '³'),
+    ("acute", '´'),
+    ("mu", 'µ'),
+    ("paragraph", '¶'),
+    ("periodcentered", '·'),
+    ("cedilla", '¸'),
+    ("onesuperior", '¹'),
+    ("masculine", 'º'),
+    ("guillemotright", '»'),
+    ("onequarter", '¼'),
+    ("onehalf", '½'),
+    ("threequarters", '¾'),
+    ("questiondown", '¿'),
+    ("Agrave", 'À'),
+    ("Aacute", 'Á'),
+    ("Acircumflex", 'Â'),
+    ("Atilde", 'Ã'),
+    ("Adiaeresis", 'Ä'),
+    ("Aring", 'Å'),
+    ("AE", 'Æ'),
+    ("Ccedilla", 'Ç'),
+    ("Egrave", 'È'),
+    ("Eacute", 'É'),
+    ("Ecircumflex", 'Ê'),
+    ("Ediaeresis", 'Ë'),
+    ("Igrave", 'Ì'),
+    ("Iacute", 'Í'),
+    ("Icircumflex", 'Î'),
+    ("Idiaeresis", 'Ï'),
+    ("ETH", 'Ð'),
+    ("Ntilde", 'Ñ'),
+    ("Ograve", 'Ò'),
+    ("Oacute", 'Ó'),
+    ("Ocircumflex", 'Ô'),
+    ("Otilde", 'Õ'),
+    ("Odiaeresis", 'Ö'),
+    ("multiply", '×'),
+    ("Oslash", 'Ø'),
+    ("Ooblique", 'Ø'),
+    ("Ugrave", 'Ù'),
+    ("Uacute", 'Ú'),
+    ("Ucircumflex", 'Û'),
+    ("Udiaeresis", 'Ü'),
+    ("Yacute", 'Ý'),
+    ("THORN", 'Þ'),
+    ("ssharp", 'ß'),
+    ("agrave", 'à'),
+    ("aacute", 'á'),
+    ("acircumflex", 'â'),
+    ("atilde", 'ã'),
+    ("adiaeresis", 'ä'),
+    ("aring", 'å'),
+    ("ae", 'æ'),
+    ("ccedilla", 'ç'),
+    ("egrave", 'è'),
+    ("eacute", 'é'),
+    ("ecircumflex", 'ê'),
+    ("ediaeresis", 'ë'),
+    ("igrave", 'ì'),
+    ("iacute", 'í'),
+    ("icircumflex", 'î'),
+    ("idiaeresis", 'ï'),
+    ("eth", 'ð'),
+    ("ntilde", 'ñ'),
+    ("ograve", 'ò'),
+    ("oacute", 'ó'),
+    ("ocircumflex", 'ô'),
+    ("otilde", 'õ'),
+    ("odiaeresis", 'ö'),
+    ("division", '÷'),
+    ("oslash", 'ø'),
+    ("ooblique", 'ø'),
+    ("ugrave", 'ù'),
+    ("uacute", 'ú'),
+    ("ucircumflex", 'û'),
+    ("udiaeresis", 'ü'),
+    ("yacute", 'ý'),
+    ("thorn", 'þ'),
+    ("ydiaeresis", 'ÿ'),
+    ("EuroSign", '€'),
+    ("dead_grave", '`'),
+    ("dead_acute", '´'),
+    ("dead_circumflex", '^'),
+    ("dead_tilde", '~'),
+    ("dead_diaeresis", '¨'),
+    ("dead_cedilla", '¸'),
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum XkbError {
+    #[error("Unknown keyboard layout: {layout}")]
+    UnknownLayout { layout: String },
+
+    #[error("Unknown variant '{variant}' of keyboard layout '{layout}'")]
+    UnknownVariant { layout: String, variant: String },
+}
+
+/// The symbols of the first group of a keyboard layout, indexed by evdev key code
+#[derive(Debug, Clone)]
+pub struct Layout {
+    name: String,
+    levels: HashMap<u32, Vec<String>>,
+}
+
+impl Layout {
+    /// Load a layout like `de` or `fr(azerty)` from the xkeyboard-config data files
+    pub fn load(layout: &str) -> Result<Self> {
+        Self::load_from(Path::new(constants::XKB_DATA_DIR), layout)
+    }
+
+    /// Load a layout from the xkeyboard-config data files in the directory `base`
+    pub fn load_from(base: &Path, layout: &str) -> Result<Self> {
+        let keycodes = parse_keycodes(&fs::read_to_string(base.join("keycodes/evdev"))?);
+
+        let mut symbols = Vec::new();
+        load_symbols(&base.join("symbols"), layout, &mut symbols, 0)?;
+
+        // later definitions override earlier ones, even if they refer to the key by an alias
+        let levels = symbols
+            .into_iter()
+            .filter_map(|(name, levels)| {
+                keycodes
+                    .get(&name)
+                    .filter(|code| **code >= 8)
+                    .map(|code| (code - 8, levels))
+            })
+            .collect();
+
+        Ok(Self {
+            name: layout.to_string(),
+            levels,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the character printed on the keycap of the key `code`, upper case for letters
+    pub fn keycap(&self, code: u32) -> Option<String> {
+        let level1 = self.levels.get(&code)?.first()?;
+
+        let c = keysym_to_char(level1).filter(|c| !c.is_whitespace() && !c.is_control())?;
+
+        // keycaps show letters in upper case, except for letters like `ß`
+        let mut upper = c.to_uppercase();
+
+        match (upper.next(), upper.next()) {
+            (Some(upper), None) => Some(upper.to_string()),
+            _ => Some(c.to_string()),
+        }
+    }
+
+    /// Find the key that produces `symbol`, either a character or a keysym name like `adiaeresis`
+    pub fn find_key(&self, symbol: &str) -> Option<EV_KEY> {
+        let produces = |keysym: &String| {
+            keysym == symbol || matches!(keysym_to_char(keysym), Some(c) if symbol == c.to_string())
+        };
+
+        // prefer keys that produce the symbol without modifiers, then the lowest key code
+        (0..2)
+            .find_map(|level| {
+                self.levels
+                    .iter()
+                    .filter(
+                        |(_, levels)| matches!(levels.get(level), Some(keysym) if produces(keysym)),
+                    )
+                    .map(|(code, _)| *code)
+                    .min()
+            })
+            .and_then(int_to_ev_key)
+    }
+}
+
+/// Load the specified keyboard layout, or the layout of the system if none has been specified.
+/// Without the xkeyboard-config data files, only the Linux key names are available
+pub fn init(layout: Option<&str>) -> Result<()> {
+    let layout = match layout {
+        Some(layout) => Layout::load(layout)?,
+
+        None => match system_layout().map(|layout| Layout::load(&layout)) {
+            Some(Ok(layout)) => layout,
+
+            _ => match Layout::load("us") {
+                Ok(layout) => layout,
+
+                Err(e) => {
+                    debug!("Could not load the keyboard layout: {}", e);
+                    return Ok(());
+                }
+            },
+        },
+    };
+
+    *LAYOUT.write() = Some(layout);
+
+    Ok(())
+}
+
+/// Returns the character printed on the keycap of the key `code`, using the active layout
+pub fn keycap(code: u32) -> Option<String> {
+    LAYOUT
+        .read()
+        .as_ref()
+        .and_then(|layout| layout.keycap(code))
+}
+
+/// Find the key that produces `symbol`, using the active layout
+pub fn find_key(symbol: &str) -> Option<EV_KEY> {
+    LAYOUT
+        .read()
+        .as_ref()
+        .and_then(|layout| layout.find_key(symbol))
+}
+
+/// Determine the XKB layout of the system, e.g. `de(nodeadkeys)`
+pub fn system_layout() -> Option<String> {
+    let (layout, variant) = env::var("XKB_DEFAULT_LAYOUT")
+        .ok()
+        .map(|layout| (layout, env::var("XKB_DEFAULT_VARIANT").ok()))
+        .or_else(|| read_assignments("/etc/vconsole.conf"))
+        .or_else(|| read_assignments("/etc/default/keyboard"))
+        .or_else(|| read_xorg_conf("/etc/X11/xorg.conf.d/00-keyboard.conf"))?;
+
+    // only the first of multiple layouts is used
+    let layout = layout.split(',').next()?.trim().to_string();
+    let variant = variant.and_then(|v| v.split(',').next().map(|v| v.trim().to_string()));
+
+    match variant {
+        Some(variant) if !variant.is_empty() => Some(format!("{}({})", layout, variant)),
+        _ if layout.is_empty() => None,
+        _ => Some(layout),
+    }
+}
+
+/// Read `XKBLAYOUT=` and `XKBVARIANT=` from a shell style configuration file
+fn read_assignments<P: AsRef<Path>>(path: P) -> Option<(String, Option<String>)> {
+    let text = fs::read_to_string(path).ok()?;
+
+    let value = |key: &str| {
+        text.lines()
+            .filter_map(|line| line.trim().strip_prefix(key))
+            .filter_map(|line| line.strip_prefix('='))
+            .map(|value| value.trim().trim_matches('"').to_string())
+            .next_back()
+    };
+
+    value("XKBLAYOUT").map(|layout| (layout, value("XKBVARIANT")))
+}
+
+/// Read the `XkbLayout` and `XkbVariant` options from an X.org configuration file
+fn read_xorg_conf<P: AsRef<Path>>(path: P) -> Option<(String, Option<String>)> {
+    let text = fs::read_to_string(path).ok()?;
+
+    let option = |key: &str| {
+        text.lines()
+            .filter_map(|line| line.trim().strip_prefix("Option"))
+            .filter_map(|line| {
+                let mut parts = line.split('"').map(str::trim).filter(|s| !s.is_empty());
+
+                match (parts.next(), parts.next()) {
+                    (Some(option), Some(value)) if option.eq_ignore_ascii_case(key) => {
+                        Some(value.to_string())
+                    }
+
+                    _ => None,
+                }
+            })
+            .next_back()
+    };
+
+    option("XkbLayout").map(|layout| (layout, option("XkbVariant")))
+}
+
+/// Map the keycode names of the `evdev` keycodes file to X11 keycodes
+fn parse_keycodes(text: &str) -> HashMap<String, u32> {
+    let mut result = HashMap::new();
+
+    for line in text.lines() {
+        if let Some(captures) = KEYCODE.captures(line) {
+            if let Ok(code) = captures[2].parse() {
+                result.insert(captures[1].to_string(), code);
+            }
+        }
+    }
+
+    for line in text.lines() {
+        if let Some(captures) = ALIAS.captures(line) {
+            if let Some(code) = result.get(&captures[2]).copied() {
+                result.insert(captures[1].to_string(), code);
+            }
+        }
+    }
+
+    result
+}
+
+/// Returns the body of the `xkb_symbols` section `variant`, or of the default section
+fn find_section<'a>(text: &'a str, variant: Option<&str>) -> Option<&'a str> {
+    let mut search = 0;
+    let mut first = None;
+
+    while let Some(offset) = text[search..].find("xkb_symbols") {
+        let start = search + offset;
+        let header_end = start + text[start..].find('{')?;
+        let header = &text[start..header_end];
+
+        // find the matching closing brace
+        let mut depth = 0;
+        let mut end = text.len();
+
+        for (index, c) in text[header_end..].char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+
+                    if depth == 0 {
+                        end = header_end + index;
+                        break;
+                    }
+                }
+
+                _ => {}
+            }
+        }
+
+        let body = &text[header_end + 1..end];
+        let name = header.split('"').nth(1).unwrap_or_default();
+
+        // the flags precede the `xkb_symbols` keyword
+        let is_default = matches!(
+            text[..start].rsplit([';', '}']).next(),
+            Some(flags) if flags.split_whitespace().any(|flag| flag == "default")
+        );
+
+        match variant {
+            Some(variant) if name == variant => return Some(body),
+            None if is_default => return Some(body),
+
+            _ => {}
+        }
+
+        first.get_or_insert(body);
+        search = end;
+    }
+
+    if variant.is_none() {
+        first
+    } else {
+        None
+    }
+}
+
+/// Load the symbols of `spec` (e.g. `latin(type4)`) and append them to `symbols`, in the
+/// order of their definition
+fn load_symbols(
+    dir: &Path,
+    spec: &str,
+    symbols: &mut Vec<(String, Vec<String>)>,
+    depth: usize,
+) -> Result<()> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Ok(());
+    }
+
+    let (file, variant) = match spec.split_once('(') {
+        Some((file, variant)) => (file.trim(), Some(variant.trim_end_matches(')').trim())),
+        None => (spec.trim(), None),
+    };
+
+    let text = fs::read_to_string(dir.join(file)).map_err(|_| XkbError::UnknownLayout {
+        layout: file.to_string(),
+    })?;
+
+    // strip comments
+    let text = text
+        .lines()
+        .map(|line| line.split("//").next().unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let body = find_section(&text, variant).ok_or_else(|| XkbError::UnknownVariant {
+        layout: file.to_string(),
+        variant: variant.unwrap_or_default().to_string(),
+    })?;
+
+    // statements are terminated by `;`, except for includes
+    let mut rest = body;
+
+    while !rest.trim().is_empty() {
+        if let Some(captures) = INCLUDE.captures(rest) {
+            for include in captures[1].split(['+', '|']) {
+                // includes that target other groups, like `us:2`, do not affect the first group
+                if !include.is_empty() && !include.contains(':') {
+                    load_symbols(dir, include, symbols, depth + 1).unwrap_or_else(|e| {
+                        debug!("Could not load the included symbols '{}': {}", include, e)
+                    });
+                }
+            }
+
+            rest = &rest[captures[0].len()..];
+        } else if let Some(captures) = KEY.captures(rest) {
+            let levels = LEVELS
+                .captures_iter(&captures[2])
+                .map(|levels| levels[1].to_string())
+                .find(|levels| !levels.trim().starts_with("Group"));
+
+            if let Some(levels) = levels {
+                let levels = levels
+                    .split(',')
+                    .map(|keysym| keysym.trim().to_string())
+                    .collect();
+
+                symbols.push((captures[1].to_string(), levels));
+            }
+
+            rest = &rest[captures[0].len()..];
+        } else {
+            match rest.find(';') {
+                Some(index) => rest = &rest[index + 1..],
+                None => break,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the character produced by a keysym like `a`, `ssharp`, `U017F` or `0x1001E9E`
+fn keysym_to_char(keysym: &str) -> Option<char> {
+    if let Some((_, c)) = KEYSYMS.iter().find(|(name, _)| *name == keysym) {
+        return Some(*c);
+    }
+
+    let code = if let Some(hex) = keysym.strip_prefix('U') {
+        u32::from_str_radix(hex, 16).ok()?
+    } else if let Some(hex) = keysym.strip_prefix("0x100") {
+        u32::from_str_radix(hex, 16).ok()?
+    } else {
+        return None;
+    };
+
+    char::from_u32(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn load(layout: &str) -> Result<Layout> {
+        let path = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+
+        Layout::load_from(&path.join("../support/tests/assets/xkb/"), layout)
+    }
+
+    fn keycap(layout: &Layout, key: EV_KEY) -> Option<String> {
+        layout.keycap(key as u32)
+    }
+
+    #[test]
+    fn german_layout() -> Result<()> {
+        let layout = load("de")?;
+
+        assert_eq!(layout.name(), "de");
+
+        // the keys of `z` and `y` are swapped
+        assert_eq!(layout.find_key("z"), Some(EV_KEY::KEY_Y));
+        assert_eq!(layout.find_key("y"), Some(EV_KEY::KEY_Z));
+        assert_eq!(keycap(&layout, EV_KEY::KEY_Y).as_deref(), Some("Z"));
+        assert_eq!(keycap(&layout, EV_KEY::KEY_Z).as_deref(), Some("Y"));
+
+        // letters without an upper case keycap, and keysym names
+        assert_eq!(layout.find_key("ß"), Some(EV_KEY::KEY_MINUS));
+        assert_eq!(layout.find_key("ssharp"), Some(EV_KEY::KEY_MINUS));
+        assert_eq!(keycap(&layout, EV_KEY::KEY_MINUS).as_deref(), Some("ß"));
+        assert_eq!(keycap(&layout, EV_KEY::KEY_SEMICOLON).as_deref(), Some("Ö"));
+
+        // symbols on the second level, and keys that are referred to by an alias
+        assert_eq!(layout.find_key("\""), Some(EV_KEY::KEY_2));
+        assert_eq!(layout.find_key("#"), Some(EV_KEY::KEY_BACKSLASH));
+
+        // keys of the included layouts
+        assert_eq!(layout.find_key("a"), Some(EV_KEY::KEY_A));
+        assert_eq!(layout.find_key("€"), None);
+        assert_eq!(keycap(&layout, EV_KEY::KEY_E).as_deref(), Some("E"));
+        assert_eq!(keycap(&layout, EV_KEY::KEY_SPACE), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn german_variant() -> Result<()> {
+        let basic = load("de")?;
+        let nodeadkeys = load("de(nodeadkeys)")?;
+
+        assert_eq!(basic.find_key("dead_circumflex"), Some(EV_KEY::KEY_GRAVE));
+        assert_eq!(nodeadkeys.find_key("dead_circumflex"), None);
+        assert_eq!(nodeadkeys.find_key("asciicircum"), Some(EV_KEY::KEY_GRAVE));
+
+        // the variant includes the basic layout
+        assert_eq!(nodeadkeys.find_key("z"), Some(EV_KEY::KEY_Y));
+
+        Ok(())
+    }
+
+    #[test]
+    fn french_layout() -> Result<()> {
+        let layout = load("fr")?;
+
+        // the keys of `a` and `q` are swapped, as are `z` and `w`
+        assert_eq!(layout.find_key("a"), Some(EV_KEY::KEY_Q));
+        assert_eq!(layout.find_key("q"), Some(EV_KEY::KEY_A));
+        assert_eq!(keycap(&layout, EV_KEY::KEY_Q).as_deref(), Some("A"));
+        assert_eq!(keycap(&layout, EV_KEY::KEY_A).as_deref(), Some("Q"));
+        assert_eq!(layout.find_key("z"), Some(EV_KEY::KEY_W));
+        assert_eq!(layout.find_key("w"), Some(EV_KEY::KEY_Z));
+
+        // `m` is next to `l`
+        assert_eq!(layout.find_key("m"), Some(EV_KEY::KEY_SEMICOLON));
+        assert_eq!(keycap(&layout, EV_KEY::KEY_SEMICOLON).as_deref(), Some("M"));
+
+        // digits are on the second level, keys that produce a symbol without modifiers win
+        assert_eq!(layout.find_key("&"), Some(EV_KEY::KEY_1));
+        assert_eq!(layout.find_key("1"), Some(EV_KEY::KEY_1));
+        assert_eq!(layout.find_key("é"), Some(EV_KEY::KEY_2));
+        assert_eq!(keycap(&layout, EV_KEY::KEY_2).as_deref(), Some("É"));
+        assert_eq!(keycap(&layout, EV_KEY::KEY_0).as_deref(), Some("À"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_layouts() {
+        let result = load("xx");
+
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<XkbError>(),
+            Some(XkbError::UnknownLayout { layout }) if layout == "xx"
+        ));
+
+        let result = load("de(nosuchvariant)");
+
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<XkbError>(),
+            Some(XkbError::UnknownVariant { layout, variant })
+                if layout == "de" && variant == "nosuchvariant"
+        ));
+    }
+}
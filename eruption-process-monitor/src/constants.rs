@@ -25,6 +25,21 @@ pub const PROCESS_MONITOR_CONFIG_FILE: &str = "/etc/eruption/process-monitor.con
 /// State directory
 pub const STATE_DIR: &str = "~/.local/share/eruption-process-monitor/";
 
+/// File name of the rules file, relative to the state directory
+pub const RULES_FILE: &str = "rules.toml";
+
+/// File name of the JSON based rules file used by previous versions
+pub const LEGACY_RULES_FILE: &str = "process-monitor.rules";
+
+/// Number of slots supported by the Eruption daemon
+pub const NUM_SLOTS: u64 = 4;
+
+/// Number of days to look back for time based rules, when the daemon is started
+pub const SCHEDULE_CATCH_UP_DAYS: i64 = 7;
+
+/// Amount of time the local clock may go back (e.g. at the end of DST), before the scheduler restarts
+pub const SCHEDULE_MAX_CLOCK_SKEW_MINUTES: i64 = 120;
+
 /// Main loop delay
 pub const MAIN_LOOP_SLEEP_MILLIS: u64 = 250;
 
@@ -108,6 +108,109 @@ pub fn get_active_slot() -> Result<u64> {
     Ok(result)
 }
 
+/// Set the global brightness
+pub fn set_brightness(brightness: i64) -> Result<()> {
+    use config::OrgEruptionConfig;
+
+    let conn = Connection::new_system()?;
+    let proxy = conn.with_proxy(
+        "org.eruption",
+        "/org/eruption/config",
+        Duration::from_secs(constants::DBUS_TIMEOUT_MILLIS as u64),
+    );
+
+    proxy.set_brightness(brightness)?;
+
+    Ok(())
+}
+
+pub fn get_brightness() -> Result<i64> {
+    use config::OrgEruptionConfig;
+
+    let conn = Connection::new_system()?;
+    let proxy = conn.with_proxy(
+        "org.eruption",
+        "/org/eruption/config",
+        Duration::from_secs(constants::DBUS_TIMEOUT_MILLIS as u64),
+    );
+
+    let result = proxy.brightness()?;
+
+    Ok(result)
+}
+
+/// Enumerate all available profiles, returns a list of tuples: (name, profile file)
+pub fn enumerate_profiles() -> Result<Vec<(String, String)>> {
+    use profile::OrgEruptionProfile;
+
+    let conn = Connection::new_system()?;
+    let proxy = conn.with_proxy(
+        "org.eruption",
+        "/org/eruption/profile",
+        Duration::from_secs(constants::DBUS_TIMEOUT_MILLIS as u64),
+    );
+
+    let result = proxy.enum_profiles()?;
+
+    Ok(result)
+}
+
+/// Switch to the keymap of the focused application, an empty name selects
+/// the keymap of the active profile or slot
+pub fn switch_keymap(name: &str) -> Result<()> {
+    use keymap::OrgEruptionKeymap;
+
+    let conn = Connection::new_system()?;
+    let proxy = conn.with_proxy(
+        "org.eruption",
+        "/org/eruption/keymap",
+        Duration::from_secs(constants::DBUS_TIMEOUT_MILLIS as u64),
+    );
+
+    let _result = proxy.set_application_keymap(name)?;
+
+    Ok(())
+}
+
+/// Get the keymap of the focused application, returns an empty string if none is selected
+pub fn get_application_keymap() -> Result<String> {
+    use keymap::OrgEruptionKeymap;
+
+    let conn = Connection::new_system()?;
+    let proxy = conn.with_proxy(
+        "org.eruption",
+        "/org/eruption/keymap",
+        Duration::from_secs(constants::DBUS_TIMEOUT_MILLIS as u64),
+    );
+
+    let result = proxy.get_application_keymap()?;
+
+    Ok(result)
+}
+
+pub mod keymap {
+    use dbus::blocking;
+
+    pub trait OrgEruptionKeymap {
+        fn set_application_keymap(&self, keymap: &str) -> Result<bool, dbus::Error>;
+        fn get_application_keymap(&self) -> Result<String, dbus::Error>;
+    }
+
+    impl<'a, C: ::std::ops::Deref<Target = blocking::Connection>> OrgEruptionKeymap
+        for blocking::Proxy<'a, C>
+    {
+        fn set_application_keymap(&self, keymap: &str) -> Result<bool, dbus::Error> {
+            self.method_call("org.eruption.Keymap", "SetApplicationKeymap", (keymap,))
+                .map(|r: (bool,)| r.0)
+        }
+
+        fn get_application_keymap(&self) -> Result<String, dbus::Error> {
+            self.method_call("org.eruption.Keymap", "GetApplicationKeymap", ())
+                .map(|r: (String,)| r.0)
+        }
+    }
+}
+
 pub mod slot {
     // This code was autogenerated with `dbus-codegen-rust -s -d org.eruption -p /org/eruption/slot -m None`, see https://github.com/diwic/dbus-rs
     use dbus::arg;
@@ -17,6 +17,7 @@
     Copyright (c) 2019-2022, The Eruption Development Team
 */
 
+use dbus::arg::{prop_cast, PropMap, RefArg, Variant};
 use dbus::{ffidisp::Connection, ffidisp::NameFlag, MethodErr};
 use dbus_tree::{Factory, Signal};
 use flume::Sender;
@@ -24,7 +25,8 @@ use indexmap::IndexMap;
 use log::*;
 use std::sync::Arc;
 
-use crate::{Action, RuleMetadata, Selector, WindowFocusedSelectorMode};
+use crate::rules::{self, RuleDefinition, RulesError, RulesMap};
+use crate::{Action, RuleMetadata, Selector};
 
 /// D-Bus messages and signals that are processed by the main thread
 #[derive(Debug, Clone)]
@@ -63,13 +65,18 @@ impl DbusApi {
         let c_clone = Arc::new(c);
         let f = Factory::new_fn::<()>();
 
-        let rules_changed_signal =
-            Arc::new(
-                f.signal("RulesChanged", ())
-                    .sarg::<Vec<(String, String, String, String)>, _>("rules"),
-            );
+        let rules_changed_signal = Arc::new(
+            f.signal("RulesChanged", ())
+                .sarg::<Vec<PropMap>, _>("rules"),
+        );
         let rules_changed_signal_clone = rules_changed_signal.clone();
 
+        let rules_changed_signal_add = rules_changed_signal.clone();
+        let rules_changed_signal_update = rules_changed_signal.clone();
+        let rules_changed_signal_remove = rules_changed_signal.clone();
+        let rules_changed_signal_enable = rules_changed_signal.clone();
+        let rules_changed_signal_move = rules_changed_signal.clone();
+
         let tree = f.tree(()).add(
             f.object_path("/org/eruption/process_monitor/rules", ())
                 .introspectable()
@@ -82,23 +89,8 @@ impl DbusApi {
                                 let s = rules_map
                                     .iter()
                                     .map(|(selector, (metadata, action))| {
-                                        let (sensor_val, selector_val) = match selector {
-                                            Selector::ProcessExec { comm } => {
-                                                ("exec".to_string(), comm)
-                                            }
-
-                                            Selector::WindowFocused { mode, regex } => match mode {
-                                                WindowFocusedSelectorMode::WindowName => {
-                                                    ("window-name".to_string(), regex)
-                                                }
-                                                WindowFocusedSelectorMode::WindowInstance => {
-                                                    ("window-instance".to_string(), regex)
-                                                }
-                                                WindowFocusedSelectorMode::WindowClass => {
-                                                    ("window-class".to_string(), regex)
-                                                }
-                                            },
-                                        };
+                                        let sensor_val = selector.sensor_name().to_string();
+                                        let selector_val = selector.value();
 
                                         let action_val = match action {
                                             Action::SwitchToProfile { profile_name } => {
@@ -107,6 +99,12 @@ impl DbusApi {
                                             Action::SwitchToSlot { slot_index } => {
                                                 format!("{}", slot_index)
                                             }
+                                            Action::SetBrightness { brightness } => {
+                                                format!("{}%", brightness)
+                                            }
+                                            Action::SwitchToKeymap { keymap_name } => {
+                                                keymap_name.to_string()
+                                            }
                                         };
 
                                         let mut metadata_val = String::new();
@@ -131,6 +129,12 @@ impl DbusApi {
                         )
                         .add_m(
                             f.method("SetRules", (), move |m| {
+                                if !perms::has_settings_permission(&m.msg.sender().unwrap())
+                                    .unwrap_or(false)
+                                {
+                                    return Err(MethodErr::failed("Authentication failed"));
+                                }
+
                                 let mut rules_map = IndexMap::new();
 
                                 let rules: Vec<(String, String, String, String)> = m.msg.read1()?;
@@ -148,52 +152,45 @@ impl DbusApi {
                                         metadata_val: &str,
                                     ) -> Result<(Selector, (RuleMetadata, Action))>
                                     {
-                                        let sensor;
-
                                         let action;
 
-                                        match sensor_val {
-                                            "exec" => {
-                                                sensor = Selector::ProcessExec {
-                                                    comm: selector_val.into(),
-                                                }
-                                            }
-
-                                            "window-name" => {
-                                                sensor = Selector::WindowFocused {
-                                                    mode: WindowFocusedSelectorMode::WindowName,
-                                                    regex: selector_val.into(),
-                                                }
-                                            }
-
-                                            "window-instance" => {
-                                                sensor = Selector::WindowFocused {
-                                                    mode: WindowFocusedSelectorMode::WindowInstance,
-                                                    regex: selector_val.into(),
-                                                }
-                                            }
-
-                                            "window-class" => {
-                                                sensor = Selector::WindowFocused {
-                                                    mode: WindowFocusedSelectorMode::WindowClass,
-                                                    regex: selector_val.into(),
-                                                }
-                                            }
-
-                                            _ => {
-                                                return Err(DbusApiError::InvalidArgument {}.into())
-                                            }
-                                        }
+                                        let sensor =
+                                            Selector::from_sensor(sensor_val, selector_val)
+                                                .ok_or(DbusApiError::InvalidArgument {})?;
 
                                         let enabled = metadata_val.contains("enabled");
                                         let internal = metadata_val.contains("internal");
 
-                                        let metadata = RuleMetadata { enabled, internal };
+                                        // the D-Bus representation does not carry names and
+                                        // descriptions, so keep the ones of the existing rule
+                                        let metadata = crate::RULES_MAP
+                                            .read()
+                                            .get(&sensor)
+                                            .map(|(metadata, _)| RuleMetadata {
+                                                enabled,
+                                                internal,
+                                                ..metadata.clone()
+                                            })
+                                            .unwrap_or(RuleMetadata {
+                                                enabled,
+                                                internal,
+                                                ..Default::default()
+                                            });
 
                                         if action_val.contains(".profile") {
                                             action = Action::SwitchToProfile {
                                                 profile_name: action_val.to_string(),
                                             };
+                                        } else if action_val.contains(".keymap") {
+                                            action = Action::SwitchToKeymap {
+                                                keymap_name: action_val.to_string(),
+                                            };
+                                        } else if let Some(brightness) =
+                                            action_val.strip_suffix('%')
+                                        {
+                                            action = Action::SetBrightness {
+                                                brightness: brightness.parse::<i64>()?,
+                                            };
                                         } else {
                                             action = Action::SwitchToSlot {
                                                 slot_index: action_val.parse::<u64>()?,
@@ -214,15 +211,139 @@ impl DbusApi {
                                     rules_map.insert(selector, (metadata, action));
                                 }
 
-                                *crate::RULES_MAP.write() = rules_map;
-
-                                crate::save_rules_map().map_err(|_e| {
+                                crate::save_rules_map(&rules_map).map_err(|_e| {
                                     dbus::Error::new_failed("Could not save the rules map")
                                 })?;
 
+                                *crate::RULES_MAP.write() = rules_map;
+
                                 Ok(vec![m.msg.method_return()])
                             })
                             .inarg::<Vec<(String, String, String, String)>, _>("rules"),
+                        )
+                        .add_m(
+                            f.method("GetRules", (), move |m| {
+                                if perms::has_monitor_permission(&m.msg.sender().unwrap())
+                                    .unwrap_or(false)
+                                {
+                                    Ok(vec![m.msg.method_return().append1(rules_to_dbus())])
+                                } else {
+                                    Err(MethodErr::failed("Authentication failed"))
+                                }
+                            })
+                            .outarg::<Vec<PropMap>, _>("rules"),
+                        )
+                        .add_m(
+                            f.method("AddRule", (), move |m| {
+                                if perms::has_settings_permission(&m.msg.sender().unwrap())
+                                    .unwrap_or(false)
+                                {
+                                    let rule: PropMap = m.msg.read1()?;
+                                    let rule =
+                                        rule_from_dbus(&rule).map_err(|e| MethodErr::failed(&e))?;
+
+                                    let index =
+                                        edit_rules(|rules_map| rules::add_rule(rules_map, rule))?;
+
+                                    Ok(vec![
+                                        m.msg.method_return().append1(index as u64),
+                                        rules_changed_message(&rules_changed_signal_add),
+                                    ])
+                                } else {
+                                    Err(MethodErr::failed("Authentication failed"))
+                                }
+                            })
+                            .inarg::<PropMap, _>("rule")
+                            .outarg::<u64, _>("index"),
+                        )
+                        .add_m(
+                            f.method("UpdateRule", (), move |m| {
+                                if perms::has_settings_permission(&m.msg.sender().unwrap())
+                                    .unwrap_or(false)
+                                {
+                                    let (index, rule): (u64, PropMap) = m.msg.read2()?;
+                                    let rule =
+                                        rule_from_dbus(&rule).map_err(|e| MethodErr::failed(&e))?;
+
+                                    edit_rules(|rules_map| {
+                                        rules::update_rule(rules_map, index as usize, rule)
+                                    })?;
+
+                                    Ok(vec![
+                                        m.msg.method_return(),
+                                        rules_changed_message(&rules_changed_signal_update),
+                                    ])
+                                } else {
+                                    Err(MethodErr::failed("Authentication failed"))
+                                }
+                            })
+                            .inarg::<u64, _>("index")
+                            .inarg::<PropMap, _>("rule"),
+                        )
+                        .add_m(
+                            f.method("RemoveRule", (), move |m| {
+                                if perms::has_settings_permission(&m.msg.sender().unwrap())
+                                    .unwrap_or(false)
+                                {
+                                    let index: u64 = m.msg.read1()?;
+
+                                    edit_rules(|rules_map| {
+                                        rules::remove_rule(rules_map, index as usize)
+                                    })?;
+
+                                    Ok(vec![
+                                        m.msg.method_return(),
+                                        rules_changed_message(&rules_changed_signal_remove),
+                                    ])
+                                } else {
+                                    Err(MethodErr::failed("Authentication failed"))
+                                }
+                            })
+                            .inarg::<u64, _>("index"),
+                        )
+                        .add_m(
+                            f.method("SetRuleEnabled", (), move |m| {
+                                if perms::has_settings_permission(&m.msg.sender().unwrap())
+                                    .unwrap_or(false)
+                                {
+                                    let (index, enabled): (u64, bool) = m.msg.read2()?;
+
+                                    edit_rules(|rules_map| {
+                                        rules::set_rule_enabled(rules_map, index as usize, enabled)
+                                    })?;
+
+                                    Ok(vec![
+                                        m.msg.method_return(),
+                                        rules_changed_message(&rules_changed_signal_enable),
+                                    ])
+                                } else {
+                                    Err(MethodErr::failed("Authentication failed"))
+                                }
+                            })
+                            .inarg::<u64, _>("index")
+                            .inarg::<bool, _>("enabled"),
+                        )
+                        .add_m(
+                            f.method("MoveRule", (), move |m| {
+                                if perms::has_settings_permission(&m.msg.sender().unwrap())
+                                    .unwrap_or(false)
+                                {
+                                    let (from, to): (u64, u64) = m.msg.read2()?;
+
+                                    edit_rules(|rules_map| {
+                                        rules::move_rule(rules_map, from as usize, to as usize)
+                                    })?;
+
+                                    Ok(vec![
+                                        m.msg.method_return(),
+                                        rules_changed_message(&rules_changed_signal_move),
+                                    ])
+                                } else {
+                                    Err(MethodErr::failed("Authentication failed"))
+                                }
+                            })
+                            .inarg::<u64, _>("from")
+                            .inarg::<u64, _>("to"),
                         ),
                 ),
         );
@@ -238,56 +359,10 @@ impl DbusApi {
     }
 
     pub fn notify_rules_changed(&self) {
-        let rules_map = crate::RULES_MAP.read();
-        let s = rules_map
-            .iter()
-            .map(|(selector, (metadata, action))| {
-                let (sensor_val, selector_val) = match selector {
-                    Selector::ProcessExec { comm } => ("exec".to_string(), comm),
-
-                    Selector::WindowFocused { mode, regex } => match mode {
-                        WindowFocusedSelectorMode::WindowName => ("window-name".to_string(), regex),
-                        WindowFocusedSelectorMode::WindowInstance => {
-                            ("window-instance".to_string(), regex)
-                        }
-                        WindowFocusedSelectorMode::WindowClass => {
-                            ("window-class".to_string(), regex)
-                        }
-                    },
-                };
-
-                let action_val = match action {
-                    Action::SwitchToProfile { profile_name } => profile_name.to_string(),
-                    Action::SwitchToSlot { slot_index } => {
-                        format!("{}", slot_index)
-                    }
-                };
-
-                let mut metadata_val = String::new();
-                if metadata.enabled {
-                    metadata_val.push_str("enabled");
-                } else {
-                    metadata_val.push_str("disabled");
-                }
-
-                if metadata.internal {
-                    metadata_val.push_str(",internal");
-                } else {
-                    metadata_val.push_str(",user-defined");
-                }
-
-                (sensor_val, selector_val, action_val, metadata_val)
-            })
-            .collect::<Vec<_>>();
-
         self.connection
             .as_ref()
             .unwrap()
-            .send(self.rules_changed.emit(
-                &"/org/eruption/process_monitor/rules".into(),
-                &"org.eruption.process_monitor.Rules".into(),
-                &s,
-            ))
+            .send(rules_changed_message(&self.rules_changed))
             .unwrap();
     }
 
@@ -351,6 +426,152 @@ pub fn initialize(dbus_tx: Sender<Message>) -> Result<DbusApi> {
     DbusApi::new(dbus_tx)
 }
 
+/// Convert a rule to its structured D-Bus representation
+fn rule_to_dbus(
+    index: usize,
+    selector: &Selector,
+    metadata: &RuleMetadata,
+    action: &Action,
+) -> PropMap {
+    let definition = RuleDefinition::from_rule(selector, metadata, action);
+
+    let mut result = PropMap::new();
+
+    result.insert(
+        "index".to_string(),
+        Variant(Box::new(index as u64) as Box<dyn RefArg>),
+    );
+    result.insert(
+        "sensor".to_string(),
+        Variant(Box::new(definition.sensor) as Box<dyn RefArg>),
+    );
+    result.insert(
+        "selector".to_string(),
+        Variant(Box::new(definition.selector) as Box<dyn RefArg>),
+    );
+    result.insert(
+        "enabled".to_string(),
+        Variant(Box::new(metadata.enabled) as Box<dyn RefArg>),
+    );
+    result.insert(
+        "internal".to_string(),
+        Variant(Box::new(metadata.internal) as Box<dyn RefArg>),
+    );
+
+    if let Some(name) = definition.name {
+        result.insert(
+            "name".to_string(),
+            Variant(Box::new(name) as Box<dyn RefArg>),
+        );
+    }
+
+    if let Some(description) = definition.description {
+        result.insert(
+            "description".to_string(),
+            Variant(Box::new(description) as Box<dyn RefArg>),
+        );
+    }
+
+    if let Some(profile) = definition.profile {
+        result.insert(
+            "profile".to_string(),
+            Variant(Box::new(profile) as Box<dyn RefArg>),
+        );
+    }
+
+    if let Some(slot) = definition.slot {
+        result.insert(
+            "slot".to_string(),
+            Variant(Box::new(slot) as Box<dyn RefArg>),
+        );
+    }
+
+    if let Some(brightness) = definition.brightness {
+        result.insert(
+            "brightness".to_string(),
+            Variant(Box::new(brightness) as Box<dyn RefArg>),
+        );
+    }
+
+    if let Some(keymap) = definition.keymap {
+        result.insert(
+            "keymap".to_string(),
+            Variant(Box::new(keymap) as Box<dyn RefArg>),
+        );
+    }
+
+    result
+}
+
+/// Parse and validate a rule from its structured D-Bus representation
+fn rule_from_dbus(map: &PropMap) -> Result<rules::Rule> {
+    let get_string = |key: &str| prop_cast::<String>(map, key).cloned();
+    let missing = |key: &str| RulesError::InvalidRule {
+        description: format!("Missing field '{}'", key),
+    };
+
+    let definition = RuleDefinition {
+        name: get_string("name"),
+        description: get_string("description"),
+        enabled: prop_cast::<bool>(map, "enabled").cloned().unwrap_or(true),
+        sensor: get_string("sensor").ok_or_else(|| missing("sensor"))?,
+        selector: get_string("selector").ok_or_else(|| missing("selector"))?,
+        profile: get_string("profile"),
+        slot: prop_cast::<u64>(map, "slot").cloned(),
+        brightness: prop_cast::<i64>(map, "brightness").cloned(),
+        keymap: get_string("keymap"),
+    };
+
+    let rule = definition
+        .to_rule(0)
+        .map_err(|description| RulesError::InvalidRule { description })?;
+
+    Ok(rule)
+}
+
+/// Returns the structured D-Bus representation of all rules
+fn rules_to_dbus() -> Vec<PropMap> {
+    crate::RULES_MAP
+        .read()
+        .iter()
+        .enumerate()
+        .map(|(index, (selector, (metadata, action)))| {
+            rule_to_dbus(index, selector, metadata, action)
+        })
+        .collect()
+}
+
+/// Construct a "RulesChanged" signal, carrying the current rules
+fn rules_changed_message(signal: &Signal<()>) -> dbus::Message {
+    signal.emit(
+        &"/org/eruption/process_monitor/rules".into(),
+        &"org.eruption.process_monitor.Rules".into(),
+        &[rules_to_dbus()],
+    )
+}
+
+/// Persist the rules after a modification
+/// Apply `edit` to a copy of the rules and save the copy to the rules file. The copy only
+/// replaces the rules in effect after it has been saved, so that they always match the file
+fn edit_rules<T>(
+    edit: impl FnOnce(&mut RulesMap) -> std::result::Result<T, eyre::Error>,
+) -> std::result::Result<T, MethodErr> {
+    let mut active_rules = crate::RULES_MAP.write();
+
+    let mut rules_map = active_rules.clone();
+    let result = edit(&mut rules_map).map_err(|e| MethodErr::failed(&e))?;
+
+    crate::save_rules_map(&rules_map).map_err(|e| {
+        error!("Could not save rules: {}", e);
+
+        MethodErr::failed("Could not save the rules map")
+    })?;
+
+    *active_rules = rules_map;
+
+    Ok(result)
+}
+
 #[allow(dead_code)]
 mod perms {
     use dbus::{arg::RefArg, arg::Variant, blocking::Connection};
@@ -47,11 +47,10 @@ use indexmap::IndexMap;
 use lazy_static::lazy_static;
 use log::*;
 use parking_lot::{Mutex, RwLock};
-use regex::Regex;
 use rust_embed::RustEmbed;
 use sensors::WindowSensorData;
 use serde::{Deserialize, Serialize};
-use std::{env, fmt, fs, path::PathBuf, process, sync::atomic::AtomicBool, sync::Arc};
+use std::{env, fmt, path::PathBuf, process, sync::atomic::AtomicBool, sync::Arc};
 use std::{sync::atomic::Ordering, thread, time::Duration};
 use syslog::Facility;
 
@@ -62,7 +61,10 @@ mod dbus_interface;
 mod logger;
 #[cfg(feature = "sensor-procmon")]
 mod procmon;
+mod rules;
+mod schedule;
 mod sensors;
+mod simulate;
 mod util;
 
 #[derive(RustEmbed)]
@@ -104,6 +106,13 @@ lazy_static! {
     /// Currently selected slot and profile
     pub static ref CURRENT_STATE: Arc<RwLock<(Option<u64>, Option<String>)>> = Arc::new(RwLock::new((None, None)));
 
+    /// Keymap that has been selected for the focused application, an empty string
+    /// selects the keymap of the active profile or slot
+    pub static ref CURRENT_KEYMAP: Arc<RwLock<Option<String>>> = Arc::new(RwLock::new(None));
+
+    /// Brightness that has been set by the last action, used in simulation mode
+    pub static ref CURRENT_BRIGHTNESS: Arc<RwLock<Option<i64>>> = Arc::new(RwLock::new(None));
+
     // Flags
 
     /// Global "enable experimental features" flag
@@ -112,6 +121,9 @@ lazy_static! {
     /// Signals that we initiated a profile change
     pub static ref PROFILE_CHANGING: AtomicBool = AtomicBool::new(false);
 
+    /// Global "dry-run" flag, actions are only recorded but not executed
+    pub static ref SIMULATION_MODE: AtomicBool = AtomicBool::new(false);
+
     /// Global "polling works" for the X11 sensor flag
     pub static ref X11_POLL_SUCCEEDED: AtomicBool = AtomicBool::new(false);
 
@@ -166,6 +178,28 @@ impl fmt::Display for WindowFocusedSelectorMode {
     }
 }
 
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum SunSelectorMode {
+    Sunrise,
+    Sunset,
+}
+
+impl fmt::Display for SunSelectorMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SunSelectorMode::Sunrise => {
+                write!(f, "Sunrise")?;
+            }
+
+            SunSelectorMode::Sunset => {
+                write!(f, "Sunset")?;
+            }
+        };
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum Selector {
     ProcessExec {
@@ -175,6 +209,128 @@ pub enum Selector {
         mode: WindowFocusedSelectorMode,
         regex: String,
     },
+    SteamApp {
+        app_id: u64,
+    },
+    WineExe {
+        name: String,
+    },
+    Schedule {
+        cron: String,
+    },
+    Sun {
+        mode: SunSelectorMode,
+        offset_minutes: i64,
+    },
+}
+
+impl Selector {
+    /// Construct a selector from a sensor name (as used on the command line) and its argument
+    pub fn from_sensor(sensor: &str, value: &str) -> Option<Self> {
+        match sensor {
+            "exec" => Some(Selector::ProcessExec {
+                comm: value.to_string(),
+            }),
+
+            "window-name" => Some(Selector::WindowFocused {
+                mode: WindowFocusedSelectorMode::WindowName,
+                regex: value.to_string(),
+            }),
+
+            "window-instance" => Some(Selector::WindowFocused {
+                mode: WindowFocusedSelectorMode::WindowInstance,
+                regex: value.to_string(),
+            }),
+
+            "window-class" => Some(Selector::WindowFocused {
+                mode: WindowFocusedSelectorMode::WindowClass,
+                regex: value.to_string(),
+            }),
+
+            "steam-app" => value
+                .trim()
+                .parse()
+                .ok()
+                .map(|app_id| Selector::SteamApp { app_id }),
+
+            "wine-exe" => Some(Selector::WineExe {
+                name: value.to_string(),
+            }),
+
+            "schedule" => Some(Selector::Schedule {
+                cron: value.trim().to_string(),
+            }),
+
+            "sunrise" | "sunset" => {
+                let mode = if sensor == "sunrise" {
+                    SunSelectorMode::Sunrise
+                } else {
+                    SunSelectorMode::Sunset
+                };
+
+                // an optional offset in minutes, e.g.: "+30" or "-15"
+                let offset_minutes = match value.trim() {
+                    "" => 0,
+                    offset => offset.parse().ok()?,
+                };
+
+                Some(Selector::Sun {
+                    mode,
+                    offset_minutes,
+                })
+            }
+
+            _ => None,
+        }
+    }
+
+    /// Name of the sensor that triggers this selector, as used on the command line
+    pub fn sensor_name(&self) -> &'static str {
+        match self {
+            Selector::ProcessExec { .. } => "exec",
+
+            Selector::WindowFocused { mode, .. } => match mode {
+                WindowFocusedSelectorMode::WindowName => "window-name",
+                WindowFocusedSelectorMode::WindowInstance => "window-instance",
+                WindowFocusedSelectorMode::WindowClass => "window-class",
+            },
+
+            Selector::SteamApp { .. } => "steam-app",
+
+            Selector::WineExe { .. } => "wine-exe",
+
+            Selector::Schedule { .. } => "schedule",
+
+            Selector::Sun { mode, .. } => match mode {
+                SunSelectorMode::Sunrise => "sunrise",
+                SunSelectorMode::Sunset => "sunset",
+            },
+        }
+    }
+
+    /// The argument of the selector
+    pub fn value(&self) -> String {
+        match self {
+            Selector::ProcessExec { comm } => comm.clone(),
+            Selector::WindowFocused { regex, .. } => regex.clone(),
+            Selector::SteamApp { app_id } => app_id.to_string(),
+            Selector::WineExe { name } => name.clone(),
+            Selector::Schedule { cron } => cron.clone(),
+            Selector::Sun { offset_minutes, .. } => format!("{:+}", offset_minutes),
+        }
+    }
+
+    /// The regular expression of the selector, if it uses one
+    pub fn regex(&self) -> Option<&str> {
+        match self {
+            Selector::ProcessExec { comm } => Some(comm),
+            Selector::WindowFocused { regex, .. } => Some(regex),
+            Selector::SteamApp { .. } => None,
+            Selector::WineExe { name } => Some(name),
+            Selector::Schedule { .. } => None,
+            Selector::Sun { .. } => None,
+        }
+    }
 }
 
 impl fmt::Display for Selector {
@@ -187,6 +343,25 @@ impl fmt::Display for Selector {
             Selector::WindowFocused { mode, regex } => {
                 write!(f, "On window focused: {}: '{}'", mode, regex)?;
             }
+
+            Selector::SteamApp { app_id } => {
+                write!(f, "On Steam application launch: AppID: {}", app_id)?;
+            }
+
+            Selector::WineExe { name } => {
+                write!(f, "On Wine/Proton process execution: exe: '{}'", name)?;
+            }
+
+            Selector::Schedule { cron } => {
+                write!(f, "On schedule: '{}'", cron)?;
+            }
+
+            Selector::Sun {
+                mode,
+                offset_minutes,
+            } => {
+                write!(f, "On {}: offset: {:+} minutes", mode, offset_minutes)?;
+            }
         };
 
         Ok(())
@@ -197,6 +372,8 @@ impl fmt::Display for Selector {
 pub enum Action {
     SwitchToProfile { profile_name: String },
     SwitchToSlot { slot_index: u64 },
+    SetBrightness { brightness: i64 },
+    SwitchToKeymap { keymap_name: String },
 }
 
 impl fmt::Display for Action {
@@ -209,6 +386,18 @@ impl fmt::Display for Action {
             Action::SwitchToSlot { slot_index } => {
                 write!(f, "Switch to slot: {}", slot_index + 1)?;
             }
+
+            Action::SetBrightness { brightness } => {
+                write!(f, "Set brightness: {}%", brightness)?;
+            }
+
+            Action::SwitchToKeymap { keymap_name } if keymap_name.is_empty() => {
+                write!(f, "Switch to the keymap of the profile")?;
+            }
+
+            Action::SwitchToKeymap { keymap_name } => {
+                write!(f, "Switch to keymap: {}", keymap_name)?;
+            }
         };
 
         Ok(())
@@ -222,6 +411,14 @@ pub struct RuleMetadata {
 
     /// Set to true if the rule is auto-generated
     pub internal: bool,
+
+    /// Optional human readable name of the rule
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// Optional free-form description of the rule
+    #[serde(default)]
+    pub description: Option<String>,
 }
 
 impl std::default::Default for RuleMetadata {
@@ -229,12 +426,18 @@ impl std::default::Default for RuleMetadata {
         RuleMetadata {
             enabled: true,
             internal: false,
+            name: None,
+            description: None,
         }
     }
 }
 
 impl fmt::Display for RuleMetadata {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(name) = &self.name {
+            write!(f, "name: '{}', ", name)?;
+        }
+
         write!(f, "enabled: {}", self.enabled)?;
         write!(f, ", internal: {}", self.internal)?;
 
@@ -249,6 +452,12 @@ pub enum SystemEvent {
         event: procmon::Event,
         file_name: Option<String>,
         comm: Option<String>,
+
+        /// Steam AppID, only set on the topmost process of a Steam application
+        steam_app_id: Option<u64>,
+
+        /// File name of the Windows executable, if the process is run by Wine/Proton
+        wine_exe: Option<String>,
     },
 
     ProcessExit {
@@ -288,6 +497,17 @@ pub enum Subcommands {
         command: RulesSubcommands,
     },
 
+    /// Evaluate the rules against synthetic events, without switching profiles or slots
+    Simulate {
+        /// Read events from a JSON event script
+        #[clap(short, long)]
+        script: Option<PathBuf>,
+
+        /// Events, e.g.: exec:<pid>:<comm>, steam-app:<pid>:<app-id>, wine-exe:<pid>:<exe>, exit:<pid>, window-class:<class>
+        /// or window:<name>:<instance>:<class>
+        events: Vec<String>,
+    },
+
     /// Generate shell completions
     Completions {
         // #[clap(subcommand)]
@@ -312,6 +532,9 @@ pub enum RulesSubcommands {
 
     /// Remove a rule by index
     Remove { rule_index: usize },
+
+    /// Validate the rules file and report all problems
+    Check,
 }
 
 /// Subcommands of the "completions" command
@@ -361,6 +584,90 @@ fn print_header() {
     );
 }
 
+/// Switch to the specified profile, in simulation mode the action is only recorded
+fn switch_profile(profile_name: &str) -> Result<()> {
+    if SIMULATION_MODE.load(Ordering::SeqCst) {
+        simulate::record_action(Action::SwitchToProfile {
+            profile_name: profile_name.to_string(),
+        });
+
+        Ok(())
+    } else {
+        dbus_client::switch_profile(profile_name)
+    }
+}
+
+/// Switch to the specified slot, in simulation mode the action is only recorded
+fn switch_slot(slot_index: u64) -> Result<()> {
+    if SIMULATION_MODE.load(Ordering::SeqCst) {
+        simulate::record_action(Action::SwitchToSlot { slot_index });
+
+        Ok(())
+    } else {
+        dbus_client::switch_slot(slot_index)
+    }
+}
+
+/// Set the global brightness, in simulation mode the action is only recorded
+fn set_brightness(brightness: i64) -> Result<()> {
+    if SIMULATION_MODE.load(Ordering::SeqCst) {
+        simulate::record_action(Action::SetBrightness { brightness });
+
+        Ok(())
+    } else {
+        dbus_client::set_brightness(brightness)
+    }
+}
+
+/// Switch to the specified keymap, in simulation mode the action is only recorded
+fn switch_keymap(keymap_name: &str) -> Result<()> {
+    if SIMULATION_MODE.load(Ordering::SeqCst) {
+        simulate::record_action(Action::SwitchToKeymap {
+            keymap_name: keymap_name.to_string(),
+        });
+
+        Ok(())
+    } else {
+        dbus_client::switch_keymap(keymap_name)
+    }
+}
+
+/// Get the keymap of the focused application, in simulation mode the simulated state is used
+fn get_application_keymap() -> Result<String> {
+    match CURRENT_KEYMAP.read().clone() {
+        Some(keymap_name) if SIMULATION_MODE.load(Ordering::SeqCst) => Ok(keymap_name),
+
+        _ => dbus_client::get_application_keymap(),
+    }
+}
+
+/// Get the global brightness, in simulation mode the simulated state is used
+fn get_brightness() -> Result<i64> {
+    match *CURRENT_BRIGHTNESS.read() {
+        Some(brightness) if SIMULATION_MODE.load(Ordering::SeqCst) => Ok(brightness),
+
+        _ => dbus_client::get_brightness(),
+    }
+}
+
+/// Get the active profile, in simulation mode the simulated state is used
+fn get_active_profile() -> Result<String> {
+    match CURRENT_STATE.read().1.clone() {
+        Some(profile_name) if SIMULATION_MODE.load(Ordering::SeqCst) => Ok(profile_name),
+
+        _ => dbus_client::get_active_profile(),
+    }
+}
+
+/// Get the active slot, in simulation mode the simulated state is used
+fn get_active_slot() -> Result<u64> {
+    match CURRENT_STATE.read().0 {
+        Some(slot_index) if SIMULATION_MODE.load(Ordering::SeqCst) => Ok(slot_index),
+
+        _ => dbus_client::get_active_slot(),
+    }
+}
+
 /// Execute an action
 fn process_action(action: &Action) -> Result<()> {
     match action {
@@ -372,7 +679,7 @@ fn process_action(action: &Action) -> Result<()> {
 
                 PROFILE_CHANGING.store(true, Ordering::SeqCst);
 
-                dbus_client::switch_profile(profile_name)?;
+                switch_profile(profile_name)?;
             }
 
             CURRENT_STATE.write().1 = Some(profile_name.clone());
@@ -386,16 +693,69 @@ fn process_action(action: &Action) -> Result<()> {
 
                 PROFILE_CHANGING.store(true, Ordering::SeqCst);
 
-                dbus_client::switch_slot(*slot_index)?;
+                switch_slot(*slot_index)?;
             }
 
             CURRENT_STATE.write().0 = Some(*slot_index);
         }
+
+        Action::SetBrightness { brightness } => {
+            info!("Triggered action: {}", action);
+
+            set_brightness(*brightness)?;
+
+            *CURRENT_BRIGHTNESS.write() = Some(*brightness);
+        }
+
+        Action::SwitchToKeymap { keymap_name } => {
+            if CURRENT_KEYMAP.read().as_ref() != Some(keymap_name) {
+                info!("Triggered action: {}", action);
+
+                switch_keymap(keymap_name)?;
+            }
+
+            *CURRENT_KEYMAP.write() = Some(keymap_name.clone());
+        }
     }
 
     Ok(())
 }
 
+/// Execute the action of a window focus rule. The keymap of the previously focused
+/// window is reset, unless the rule selects a keymap itself
+fn process_window_action(action: &Action) -> Result<()> {
+    let keymap_selected = matches!(&*CURRENT_KEYMAP.read(), Some(keymap) if !keymap.is_empty());
+
+    if keymap_selected && !matches!(action, Action::SwitchToKeymap { .. }) {
+        process_action(&Action::SwitchToKeymap {
+            keymap_name: String::new(),
+        })?;
+    }
+
+    process_action(action)
+}
+
+/// Update the internal default rule to use the profile `profile_name`
+fn update_default_rule(profile_name: &str) {
+    let selector = Selector::WindowFocused {
+        mode: WindowFocusedSelectorMode::WindowInstance,
+        regex: ".*".to_string(),
+    };
+
+    if let Some((_metadata, action)) = RULES_MAP.write().get_mut(&selector) {
+        info!(
+            "Updating the default rule to use the profile: {}",
+            profile_name
+        );
+
+        *action = Action::SwitchToProfile {
+            profile_name: profile_name.to_string(),
+        };
+    } else {
+        error!("Could not get the default rule");
+    }
+}
+
 /// Process system related events
 #[cfg(feature = "sensor-procmon")]
 fn process_system_event(event: &SystemEvent) -> Result<()> {
@@ -404,47 +764,70 @@ fn process_system_event(event: &SystemEvent) -> Result<()> {
             event,
             file_name: _,
             comm,
+            steam_app_id,
+            wine_exe,
         } => {
-            if let Some(comm) = comm {
-                for (selector, (metadata, action)) in RULES_MAP.read().iter() {
-                    match selector {
-                        Selector::ProcessExec { comm: regex } => {
-                            if metadata.enabled {
-                                let re = Regex::new(regex)?;
-
-                                if re.is_match(comm) {
-                                    debug!("Matching rule for: {}", comm);
-
-                                    match action {
-                                        Action::SwitchToProfile { profile_name: _ } => {
-                                            let profile_name = dbus_client::get_active_profile()?;
-                                            let return_action =
-                                                Action::SwitchToProfile { profile_name };
-                                            PREVIOUS_STATES_MAP
-                                                .write()
-                                                .insert(event.pid, return_action);
-                                        }
-
-                                        Action::SwitchToSlot { slot_index: _ } => {
-                                            let slot_index = dbus_client::get_active_slot()?;
-                                            let return_action = Action::SwitchToSlot { slot_index };
-                                            PREVIOUS_STATES_MAP
-                                                .write()
-                                                .insert(event.pid, return_action);
-                                        }
-                                    }
-
-                                    process_action(action)?;
-                                    break;
-                                }
-                            }
+            if comm.is_none() && steam_app_id.is_none() && wine_exe.is_none() {
+                debug!("Could not get the process comm. The process vanished.");
+            }
+
+            let is_match = |regex: &str, value: &Option<String>| -> Result<bool> {
+                match value {
+                    Some(value) => Ok(rules::get_regex(regex)?.is_match(value)),
+                    None => Ok(false),
+                }
+            };
+
+            for (selector, (metadata, action)) in RULES_MAP.read().iter() {
+                if !metadata.enabled {
+                    continue;
+                }
+
+                let matched = match selector {
+                    Selector::ProcessExec { comm: regex } => is_match(regex, comm)?,
+
+                    Selector::SteamApp { app_id } => *steam_app_id == Some(*app_id),
+
+                    Selector::WineExe { name: regex } => is_match(regex, wine_exe)?,
+
+                    _ => {
+                        /* Ignore others */
+                        false
+                    }
+                };
+
+                if matched {
+                    debug!("Matching rule for pid {}: {}", event.pid, selector);
+
+                    match action {
+                        Action::SwitchToProfile { profile_name: _ } => {
+                            let profile_name = get_active_profile()?;
+                            let return_action = Action::SwitchToProfile { profile_name };
+                            PREVIOUS_STATES_MAP.write().insert(event.pid, return_action);
+                        }
+
+                        Action::SwitchToSlot { slot_index: _ } => {
+                            let slot_index = get_active_slot()?;
+                            let return_action = Action::SwitchToSlot { slot_index };
+                            PREVIOUS_STATES_MAP.write().insert(event.pid, return_action);
                         }
 
-                        _ => { /* Ignore others */ }
+                        Action::SetBrightness { brightness: _ } => {
+                            let brightness = get_brightness()?;
+                            let return_action = Action::SetBrightness { brightness };
+                            PREVIOUS_STATES_MAP.write().insert(event.pid, return_action);
+                        }
+
+                        Action::SwitchToKeymap { keymap_name: _ } => {
+                            let keymap_name = get_application_keymap()?;
+                            let return_action = Action::SwitchToKeymap { keymap_name };
+                            PREVIOUS_STATES_MAP.write().insert(event.pid, return_action);
+                        }
                     }
+
+                    process_action(action)?;
+                    break;
                 }
-            } else {
-                debug!("Could not get the process comm. The process vanished.");
             }
         }
 
@@ -454,13 +837,27 @@ fn process_system_event(event: &SystemEvent) -> Result<()> {
                     Action::SwitchToProfile { profile_name } => {
                         debug!("Returning to profile: {}", profile_name);
 
-                        dbus_client::switch_profile(profile_name)?;
+                        switch_profile(profile_name)?;
                     }
 
                     Action::SwitchToSlot { slot_index } => {
                         debug!("Returning to slot: {}", slot_index + 1);
 
-                        dbus_client::switch_slot(*slot_index)?;
+                        switch_slot(*slot_index)?;
+                    }
+
+                    Action::SetBrightness { brightness } => {
+                        debug!("Returning to brightness: {}%", brightness);
+
+                        set_brightness(*brightness)?;
+                    }
+
+                    Action::SwitchToKeymap { keymap_name } => {
+                        debug!("Returning to keymap: {}", keymap_name);
+
+                        switch_keymap(keymap_name)?;
+
+                        *CURRENT_KEYMAP.write() = Some(keymap_name.clone());
                     }
                 },
 
@@ -478,17 +875,30 @@ fn process_system_event(event: &SystemEvent) -> Result<()> {
 fn process_fs_event(event: &FileSystemEvent, dbus_api_tx: &Sender<DbusApiEvent>) -> Result<()> {
     match event {
         FileSystemEvent::RulesChanged => {
+            // the rules in effect already match a rules file that we saved ourselves
+            if rules::is_saved_rules_file(&rules::rules_file_path()?) {
+                debug!("Rules file has been saved by us, skipping reload");
+
+                return Ok(());
+            }
+
             info!("Rules changed, reloading...");
 
-            RULES_MAP.write().clear();
+            // the previously loaded rules stay in effect if the new rules file is broken
+            match load_rules_map() {
+                Ok(()) => {
+                    for (selector, (metadata, action)) in RULES_MAP.read().iter() {
+                        debug!("{} => {} ({})", selector, action, metadata);
+                    }
 
-            load_rules_map().unwrap_or_else(|e| error!("Could not load rules: {}", e));
+                    dbus_api_tx.send(DbusApiEvent::RulesChanged {})?;
+                }
 
-            for (selector, (metadata, action)) in RULES_MAP.read().iter() {
-                debug!("{} => {} ({})", selector, action, metadata);
+                Err(e) => error!(
+                    "Could not reload rules, keeping the previously loaded rules: {}",
+                    e
+                ),
             }
-
-            dbus_api_tx.send(DbusApiEvent::RulesChanged {})?;
         }
     }
 
@@ -502,23 +912,7 @@ fn process_dbus_event(event: &dbus_client::Message) -> Result<()> {
             // update the default rule to use the newly selected profile,
             // but only if we did not initiate the profile change
             if !PROFILE_CHANGING.load(Ordering::SeqCst) {
-                let selector = Selector::WindowFocused {
-                    mode: WindowFocusedSelectorMode::WindowInstance,
-                    regex: ".*".to_string(),
-                };
-
-                if let Some((_metadata, action)) = RULES_MAP.write().get_mut(&selector) {
-                    info!(
-                        "Updating the default rule to use the profile: {}",
-                        profile_name
-                    );
-
-                    *action = Action::SwitchToProfile {
-                        profile_name: profile_name.clone(),
-                    };
-                } else {
-                    error!("Could not get the default rule");
-                }
+                update_default_rule(profile_name);
 
                 // update global state
                 CURRENT_STATE.write().1 = Some(profile_name.clone());
@@ -534,7 +928,6 @@ fn process_dbus_event(event: &dbus_client::Message) -> Result<()> {
     Ok(())
 }
 
-#[allow(dead_code)]
 fn process_window_event(event: &dyn WindowSensorData) -> Result<()> {
     trace!("Sensor data: {:#?}", event);
 
@@ -542,26 +935,26 @@ fn process_window_event(event: &dyn WindowSensorData) -> Result<()> {
         match selector {
             Selector::WindowFocused { mode, regex } => {
                 if metadata.enabled {
-                    let re = Regex::new(regex)?;
+                    let re = rules::get_regex(regex)?;
 
                     match mode {
                         WindowFocusedSelectorMode::WindowName => {
                             if re.is_match(event.window_name().unwrap_or_default()) {
-                                process_action(action)?;
+                                process_window_action(action)?;
                                 break;
                             }
                         }
 
                         WindowFocusedSelectorMode::WindowInstance => {
                             if re.is_match(event.window_instance().unwrap_or_default()) {
-                                process_action(action)?;
+                                process_window_action(action)?;
                                 break;
                             }
                         }
 
                         WindowFocusedSelectorMode::WindowClass => {
                             if re.is_match(event.window_class().unwrap_or_default()) {
-                                process_action(action)?;
+                                process_window_action(action)?;
                                 break;
                             }
                         }
@@ -583,6 +976,12 @@ pub fn register_filesystem_watcher(
 ) -> Result<()> {
     debug!("Registering filesystem watcher...");
 
+    // watch the parent directory, since editors usually replace the file on save
+    let rules_dir = rule_file
+        .parent()
+        .map(|p| p.to_path_buf())
+        .unwrap_or_else(|| PathBuf::from("."));
+
     thread::Builder::new()
         .name("hotwatch".to_owned())
         .spawn(
@@ -591,14 +990,18 @@ pub fn register_filesystem_watcher(
 
                 Ok(ref mut hotwatch) => {
                     hotwatch
-                        .watch(&rule_file, move |event: Event| {
+                        .watch(&rules_dir, move |event: Event| {
                             // check if we shall terminate the thread
                             if QUIT.load(Ordering::SeqCst) {
                                 return Flow::Exit;
                             }
 
                             match event {
-                                Event::Write(path) => {
+                                Event::Write(path)
+                                | Event::Create(path)
+                                | Event::Rename(_, path)
+                                    if path == rule_file =>
+                                {
                                     debug!("Rule file changed: {}", path.display());
 
                                     fsevents_tx
@@ -839,6 +1242,10 @@ pub fn run_main_loop(
 
         let _result = sel.wait_timeout(Duration::from_millis(constants::MAIN_LOOP_SLEEP_MILLIS));
 
+        // execute time based rules
+        schedule::process_schedule()
+            .unwrap_or_else(|e| error!("Could not process the schedule: {}", e));
+
         // poll all pollable sensors that do not notify us via messages
         for sensor in sensors::SENSORS.lock().iter_mut() {
             if sensor.is_pollable() && !sensor.is_failed() {
@@ -894,18 +1301,23 @@ pub fn run_main_loop(
     Ok(())
 }
 
+/// Load and validate the rules file, the rules map is only replaced if all rules are valid
 fn load_rules_map() -> Result<()> {
-    let rules_file = util::tilde_expand(constants::STATE_DIR)?.join("process-monitor.rules");
+    let rules_file = rules::rules_file_path()?;
 
-    let s = fs::read_to_string(&rules_file)?;
+    rules::migrate_legacy_rules_file(&rules_file)?;
 
-    let rules_vec: Vec<(Selector, (RuleMetadata, Action))> = serde_json::from_str(&s)?;
-    let rules_map = rules_vec
-        .iter()
-        .cloned()
+    let mut rules_map = rules::load_rules_file(&rules_file)?
+        .into_iter()
         .collect::<IndexMap<Selector, (RuleMetadata, Action)>>();
 
-    RULES_MAP.write().extend(rules_map);
+    for warning in rules::check_profiles(rules_map.iter()) {
+        warn!("{}", warning);
+    }
+
+    for warning in schedule::check_rules(rules_map.iter()) {
+        warn!("{}", warning);
+    }
 
     // add auto-generated rules
     let default_profile = crate::CONFIG
@@ -932,26 +1344,24 @@ fn load_rules_map() -> Result<()> {
         profile_name: default_profile,
     };
 
-    RULES_MAP.write().insert(selector, (metadata, action));
+    rules_map.insert(selector, (metadata, action));
+
+    rules::update_regex_cache(rules_map.keys());
+
+    *RULES_MAP.write() = rules_map;
 
     Ok(())
 }
 
-fn save_rules_map() -> Result<()> {
-    let rules_dir = util::tilde_expand(constants::STATE_DIR)?;
-    let rules_file = rules_dir.join("process-monitor.rules");
-
-    util::create_dir(&rules_dir)?;
+fn save_rules_map(rules_map: &rules::RulesMap) -> Result<()> {
+    let rules_file = rules::rules_file_path()?;
 
-    let rules_map = RULES_MAP.read();
-    let v = rules_map
-        .iter()
-        // do not save internal auto-generated rules, they will be regenerated anyway
-        .filter(|(_, (meta, _))| !meta.internal)
-        .collect::<Vec<(&Selector, &(RuleMetadata, Action))>>();
+    if let Some(rules_dir) = rules_file.parent() {
+        util::create_dir(&rules_dir)?;
+    }
 
-    let s = serde_json::to_string_pretty(&v)?;
-    fs::write(&rules_file, s)?;
+    // internal auto-generated rules are not saved, they will be regenerated anyway
+    rules::save_rules_file(&rules_file, rules_map.iter())?;
 
     Ok(())
 }
@@ -1065,10 +1475,15 @@ pub async fn async_main() -> std::result::Result<(), eyre::Error> {
     // initialize plugins
     info!("Registering plugins...");
 
-    sensors::register_sensors()?;
+    // simulation does not require any sensors
+    if !matches!(opts.command, Subcommands::Simulate { .. }) {
+        sensors::register_sensors()?;
+    }
 
     info!("Loading rules...");
-    load_rules_map().unwrap_or_else(|e| error!("Could not load rules: {}", e));
+    let rules_loaded = load_rules_map()
+        .map_err(|e| error!("Could not load rules: {}", e))
+        .is_ok();
 
     match opts.command {
         Subcommands::Daemon => {
@@ -1076,10 +1491,12 @@ pub async fn async_main() -> std::result::Result<(), eyre::Error> {
                 info!("{:3}: {} => {} ({})", index, selector, action, metadata);
             }
 
-            let rules_dir = util::tilde_expand(constants::STATE_DIR)?;
-            let rules_file = rules_dir.join("process-monitor.rules");
+            let rules_file = rules::rules_file_path()?;
+
+            if let Some(rules_dir) = rules_file.parent() {
+                util::create_dir(&rules_dir)?;
+            }
 
-            util::create_dir(&rules_dir)?;
             util::create_rules_file_if_not_exists(&rules_file)?;
 
             let (dbusevents_tx, dbusevents_rx) = unbounded();
@@ -1125,6 +1542,18 @@ pub async fn async_main() -> std::result::Result<(), eyre::Error> {
         }
 
         Subcommands::Rules { command } => match command {
+            // do not overwrite a rules file that could not be loaded
+            RulesSubcommands::Add { .. }
+            | RulesSubcommands::Enable { .. }
+            | RulesSubcommands::Disable { .. }
+            | RulesSubcommands::Remove { .. }
+                if !rules_loaded =>
+            {
+                eprintln!("Could not load the rules file, please run 'eruption-process-monitor rules check' for details");
+
+                process::exit(1);
+            }
+
             RulesSubcommands::List => {
                 for (index, (selector, (metadata, action))) in RULES_MAP.read().iter().enumerate() {
                     println!("{:3}: {} => {} ({})", index, selector, action, metadata);
@@ -1148,59 +1577,47 @@ pub async fn async_main() -> std::result::Result<(), eyre::Error> {
                     let selector = &rule[1];
                     let action = &rule[2];
 
-                    let mut parsed_selector = None;
-                    let parsed_action;
-
-                    // TODO: move this to the plugin code
-                    if sensor.contains("exec") {
-                        parsed_selector = Some(Selector::ProcessExec {
-                            comm: selector.clone(),
-                        });
-                    } else if sensor.contains("window-class") {
-                        parsed_selector = Some(Selector::WindowFocused {
-                            mode: WindowFocusedSelectorMode::WindowClass,
-                            regex: selector.clone(),
-                        });
-                    } else if sensor.contains("window-instance") {
-                        parsed_selector = Some(Selector::WindowFocused {
-                            mode: WindowFocusedSelectorMode::WindowInstance,
-                            regex: selector.clone(),
-                        });
-                    } else if sensor.contains("window-name") {
-                        parsed_selector = Some(Selector::WindowFocused {
-                            mode: WindowFocusedSelectorMode::WindowName,
-                            regex: selector.clone(),
-                        });
-                    }
-
-                    if parsed_selector.is_none() {
-                        eprintln!("Syntax error in selector");
-                        print_usage_examples();
+                    let (profile, slot, brightness, keymap) = if action.contains(".profile") {
+                        (Some(action.clone()), None, None, None)
+                    } else if action.contains(".keymap") {
+                        (None, None, None, Some(action.clone()))
+                    } else if let Some(brightness) = action.strip_suffix('%') {
+                        (None, None, Some(brightness.parse::<i64>()?), None)
                     } else {
-                        if action.contains(".profile") {
-                            parsed_action = Action::SwitchToProfile {
-                                profile_name: action.clone(),
-                            };
-
-                            RULES_MAP.write().insert(
-                                parsed_selector.clone().unwrap(),
-                                (RuleMetadata::default(), parsed_action.clone()),
-                            );
-                        } else {
-                            parsed_action = Action::SwitchToSlot {
-                                slot_index: action.parse::<u64>()? - 1,
-                            };
+                        (None, Some(action.parse::<u64>()?), None, None)
+                    };
 
-                            RULES_MAP.write().insert(
-                                parsed_selector.clone().unwrap(),
-                                (RuleMetadata::default(), parsed_action.clone()),
-                            );
-                        }
+                    // validate the rule just like the rules loader does
+                    let definition = rules::RuleDefinition {
+                        name: None,
+                        description: None,
+                        enabled: true,
+                        sensor: sensor.clone(),
+                        selector: selector.clone(),
+                        profile,
+                        slot,
+                        brightness,
+                        keymap,
+                    };
+
+                    let index = RULES_MAP.read().len();
 
-                        // print resulting action to console
-                        println!("{} => {}", parsed_selector.unwrap(), parsed_action);
+                    match definition.to_rule(index) {
+                        Ok((parsed_selector, (metadata, parsed_action))) => {
+                            // print resulting action to console
+                            println!("{} => {}", parsed_selector, parsed_action);
 
-                        save_rules_map()?;
+                            RULES_MAP
+                                .write()
+                                .insert(parsed_selector, (metadata, parsed_action));
+
+                            save_rules_map(&RULES_MAP.read())?;
+                        }
+
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            print_usage_examples();
+                        }
                     }
                 }
             }
@@ -1223,7 +1640,7 @@ pub async fn async_main() -> std::result::Result<(), eyre::Error> {
                     None => eprintln!("No matching rules found!"),
                 }
 
-                save_rules_map()?;
+                save_rules_map(&RULES_MAP.read())?;
             }
 
             RulesSubcommands::Disable { rule_index } => {
@@ -1244,7 +1661,7 @@ pub async fn async_main() -> std::result::Result<(), eyre::Error> {
                     None => eprintln!("No matching rules found!"),
                 }
 
-                save_rules_map()?;
+                save_rules_map(&RULES_MAP.read())?;
             }
 
             RulesSubcommands::Remove { rule_index } => {
@@ -1264,10 +1681,58 @@ pub async fn async_main() -> std::result::Result<(), eyre::Error> {
                     None => eprintln!("No matching rules found!"),
                 }
 
-                save_rules_map()?;
+                save_rules_map(&RULES_MAP.read())?;
+            }
+
+            RulesSubcommands::Check => {
+                let rules_file = rules::rules_file_path()?;
+
+                match rules::load_rules_file(&rules_file) {
+                    Ok(rules) => {
+                        let warnings = rules::check_profiles(
+                            rules.iter().map(|(selector, rule)| (selector, rule)),
+                        );
+
+                        for warning in warnings.iter() {
+                            eprintln!("Warning: {}", warning);
+                        }
+
+                        println!(
+                            "{}: {} rules, {} warnings",
+                            rules_file.display(),
+                            rules.len(),
+                            warnings.len()
+                        );
+                    }
+
+                    Err(e) => {
+                        eprintln!("{}", e);
+
+                        process::exit(1);
+                    }
+                }
             }
         },
 
+        Subcommands::Simulate { script, events } => {
+            if !rules_loaded {
+                eprintln!("Could not load the rules file, please run 'eruption-process-monitor rules check' for details");
+
+                process::exit(1);
+            }
+
+            let mut simulated_events = match script {
+                Some(script) => simulate::load_event_script(&script)?,
+                None => Vec::new(),
+            };
+
+            for event in events.iter() {
+                simulated_events.push(simulate::SimulatedEvent::parse(event)?);
+            }
+
+            simulate::run(&simulated_events)?;
+        }
+
         Subcommands::Completions { shell } => {
             const BIN_NAME: &str = env!("CARGO_PKG_NAME");
 
@@ -1278,9 +1743,6 @@ pub async fn async_main() -> std::result::Result<(), eyre::Error> {
         }
     }
 
-    info!("Saving rules...");
-    save_rules_map().unwrap_or_else(|e| error!("Could not save rules: {}", e));
-
     info!("Exiting now");
 
     Ok(())
@@ -0,0 +1,803 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use indexmap::IndexMap;
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::RwLock;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use toml_edit::{ArrayOfTables, Document, Item, Table};
+
+use crate::schedule::CronSchedule;
+use crate::{constants, dbus_client, util, Action, RuleMetadata, Selector};
+
+type Result<T> = std::result::Result<T, eyre::Error>;
+
+/// A single rule, as it is stored in the rules file
+pub type Rule = (Selector, (RuleMetadata, Action));
+
+/// Mapping between event selector => action, in order of evaluation
+pub type RulesMap = IndexMap<Selector, (RuleMetadata, Action)>;
+
+/// Contents of a newly created rules file
+pub const RULES_FILE_TEMPLATE: &str = r#"# Eruption - process-monitor
+# Rules file
+#
+# Rules are evaluated in order, the first matching rule wins.
+# Supported sensors: "exec", "window-name", "window-instance", "window-class",
+# "steam-app" (selector is a Steam AppID), "wine-exe" (selector matches the
+# name of the Windows executable run by Wine/Proton), "schedule" (selector is a
+# cron expression) and "sunrise"/"sunset" (selector is an offset in minutes);
+# exactly one of "profile", "slot" (1-4), "keymap" or "brightness" (0-100) has to be specified.
+#
+# [[rule]]
+# name = "Video player"
+# description = "Show a spectrum analyzer while VLC is focused"
+# enabled = true
+# sensor = "window-instance"
+# selector = ".*vlc.*"
+# profile = "spectrum-analyzer-swirl.profile"
+"#;
+
+lazy_static! {
+    /// Precompiled regular expressions of all rules, indexed by their source pattern
+    static ref REGEX_CACHE: Arc<RwLock<HashMap<String, Regex>>> = Arc::new(RwLock::new(HashMap::new()));
+
+    /// Contents of the rules file, as last saved by this process
+    static ref SAVED_RULES_FILE: Arc<RwLock<Option<String>>> = Arc::new(RwLock::new(None));
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RulesError {
+    #[error("Could not parse the rules file '{path}': {description}")]
+    ParseError { path: String, description: String },
+
+    #[error("The rules file '{path}' contains errors:\n{}", errors.join("\n"))]
+    ValidationError { path: String, errors: Vec<String> },
+
+    #[error("Invalid rule: {description}")]
+    InvalidRule { description: String },
+
+    #[error("No rule with index {index}")]
+    InvalidIndex { index: usize },
+
+    #[error("Internal (auto-generated) rules can not be modified")]
+    InternalRule {},
+
+    #[error("A rule with the same selector already exists: {selector}")]
+    DuplicateSelector { selector: String },
+}
+
+/// Top level structure of the rules file
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RulesFile {
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<RuleDefinition>,
+}
+
+/// A rule definition, as seen by the user in the rules file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleDefinition {
+    /// Optional human readable name of the rule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    /// Optional free-form description of the rule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    /// Specifies whether the rule is enabled
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    /// The sensor that triggers the rule, e.g.: "exec" or "window-class"
+    pub sensor: String,
+
+    /// The sensor specific selector, usually a regular expression
+    pub selector: String,
+
+    /// Switch to the specified profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+
+    /// Switch to the specified slot (1-based)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slot: Option<u64>,
+
+    /// Set the global brightness (in percent)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<i64>,
+
+    /// Switch to the specified keymap
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keymap: Option<String>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl RuleDefinition {
+    /// Human readable identification of the rule, used in error messages
+    fn describe(&self, index: usize) -> String {
+        match &self.name {
+            Some(name) => format!("Rule #{} ('{}')", index + 1, name),
+            None => format!("Rule #{}", index + 1),
+        }
+    }
+
+    /// Validate the rule definition and convert it to the representation used by the rules engine
+    pub fn to_rule(&self, index: usize) -> std::result::Result<Rule, String> {
+        let selector =
+            Selector::from_sensor(&self.sensor, &self.selector).ok_or_else(|| {
+                match self.sensor.as_str() {
+                    "steam-app" => format!(
+                        "{}: Invalid Steam AppID '{}'",
+                        self.describe(index),
+                        self.selector
+                    ),
+
+                    "sunrise" | "sunset" => format!(
+                        "{}: Invalid offset '{}', expected minutes, e.g.: \"+30\" or \"-15\"",
+                        self.describe(index),
+                        self.selector
+                    ),
+
+                    _ => format!("{}: Unknown sensor '{}'", self.describe(index), self.sensor),
+                }
+            })?;
+
+        if let Selector::Schedule { cron } = &selector {
+            CronSchedule::parse(cron).map_err(|e| format!("{}: {}", self.describe(index), e))?;
+        }
+
+        if let Some(regex) = selector.regex() {
+            Regex::new(regex).map_err(|e| {
+                format!(
+                    "{}: Invalid regular expression '{}': {}",
+                    self.describe(index),
+                    regex,
+                    e
+                )
+            })?;
+        }
+
+        let action = match (&self.profile, self.slot, self.brightness, &self.keymap) {
+            (Some(profile_name), None, None, None) => Action::SwitchToProfile {
+                profile_name: profile_name.clone(),
+            },
+
+            (None, Some(slot), None, None) if (1..=constants::NUM_SLOTS).contains(&slot) => {
+                Action::SwitchToSlot {
+                    slot_index: slot - 1,
+                }
+            }
+
+            (None, Some(slot), None, None) => {
+                return Err(format!(
+                    "{}: Invalid slot number {}, valid slots are 1..={}",
+                    self.describe(index),
+                    slot,
+                    constants::NUM_SLOTS
+                ))
+            }
+
+            (None, None, Some(brightness), None) if (0..=100).contains(&brightness) => {
+                Action::SetBrightness { brightness }
+            }
+
+            (None, None, Some(brightness), None) => {
+                return Err(format!(
+                    "{}: Invalid brightness {}, valid values are 0..=100",
+                    self.describe(index),
+                    brightness
+                ))
+            }
+
+            (None, None, None, Some(keymap_name)) if !keymap_name.is_empty() => {
+                Action::SwitchToKeymap {
+                    keymap_name: keymap_name.clone(),
+                }
+            }
+
+            _ => {
+                return Err(format!(
+                    "{}: Exactly one of 'profile', 'slot', 'keymap' or 'brightness' must be \
+                     specified",
+                    self.describe(index)
+                ))
+            }
+        };
+
+        let metadata = RuleMetadata {
+            enabled: self.enabled,
+            internal: false,
+            name: self.name.clone(),
+            description: self.description.clone(),
+        };
+
+        Ok((selector, (metadata, action)))
+    }
+
+    /// Convert a rule of the rules engine to its rules file representation
+    pub fn from_rule(selector: &Selector, metadata: &RuleMetadata, action: &Action) -> Self {
+        let (profile, slot, brightness, keymap) = match action {
+            Action::SwitchToProfile { profile_name } => {
+                (Some(profile_name.clone()), None, None, None)
+            }
+            Action::SwitchToSlot { slot_index } => (None, Some(slot_index + 1), None, None),
+            Action::SetBrightness { brightness } => (None, None, Some(*brightness), None),
+            Action::SwitchToKeymap { keymap_name } => (None, None, None, Some(keymap_name.clone())),
+        };
+
+        Self {
+            name: metadata.name.clone(),
+            description: metadata.description.clone(),
+            enabled: metadata.enabled,
+            sensor: selector.sensor_name().to_string(),
+            selector: selector.value().to_string(),
+            profile,
+            slot,
+            brightness,
+            keymap,
+        }
+    }
+}
+
+/// Returns the path of the rules file, honoring the `global.rules_file` setting
+pub fn rules_file_path() -> Result<PathBuf> {
+    let configured = crate::CONFIG
+        .lock()
+        .as_ref()
+        .and_then(|config| config.get_string("global.rules_file").ok());
+
+    match configured {
+        Some(path) => util::tilde_expand(&path),
+
+        None => Ok(util::tilde_expand(constants::STATE_DIR)?.join(constants::RULES_FILE)),
+    }
+}
+
+/// Parse and validate the rules from a TOML string
+///
+/// Either all rules are valid, or an error describing every problem is returned
+pub fn parse_rules(path: &Path, s: &str) -> Result<Vec<Rule>> {
+    let rules_file: RulesFile = toml::from_str(s).map_err(|e| RulesError::ParseError {
+        path: path.display().to_string(),
+        description: e.to_string(),
+    })?;
+
+    let mut errors = Vec::new();
+    let mut result = Vec::new();
+
+    for (index, definition) in rules_file.rules.iter().enumerate() {
+        match definition.to_rule(index) {
+            Ok(rule) => result.push(rule),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(result)
+    } else {
+        Err(RulesError::ValidationError {
+            path: path.display().to_string(),
+            errors,
+        }
+        .into())
+    }
+}
+
+/// Load and validate the rules file, a missing rules file contains no rules
+pub fn load_rules_file(path: &Path) -> Result<Vec<Rule>> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+
+    let s = fs::read_to_string(path)?;
+
+    parse_rules(path, &s)
+}
+
+/// Save the rules to the rules file, internal rules are skipped
+///
+/// The rules file is updated in place: comments and the formatting of rules that have not
+/// been modified are preserved, rules that have been added or modified are re-formatted
+pub fn save_rules_file<'a>(
+    path: &Path,
+    rules: impl Iterator<Item = (&'a Selector, &'a (RuleMetadata, Action))>,
+) -> Result<()> {
+    let definitions = rules
+        .filter(|(_, (metadata, _))| !metadata.internal)
+        .map(|(selector, (metadata, action))| RuleDefinition::from_rule(selector, metadata, action))
+        .collect();
+
+    let s = if path.exists() {
+        fs::read_to_string(path)?
+    } else {
+        RULES_FILE_TEMPLATE.to_string()
+    };
+
+    let s = update_rules(path, &s, definitions)?;
+
+    fs::write(path, &s)?;
+
+    *SAVED_RULES_FILE.write() = Some(s);
+
+    Ok(())
+}
+
+/// Returns true if the rules file is unchanged since we saved it, e.g. when the filesystem
+/// watcher reports our own write
+pub fn is_saved_rules_file(path: &Path) -> bool {
+    match SAVED_RULES_FILE.read().as_ref() {
+        Some(saved) => fs::read_to_string(path)
+            .map(|s| s == *saved)
+            .unwrap_or(false),
+
+        None => false,
+    }
+}
+
+/// Replace the rules of the rules file `s` with `definitions`
+fn update_rules(path: &Path, s: &str, definitions: Vec<RuleDefinition>) -> Result<String> {
+    // never overwrite a rules file that the user has to fix first
+    let mut document: Document = s.parse().map_err(|e| RulesError::ParseError {
+        path: path.display().to_string(),
+        description: format!("{}", e),
+    })?;
+
+    let mut existing: Vec<Table> = document
+        .remove("rule")
+        .and_then(|item| item.into_array_of_tables().ok())
+        .map(|tables| tables.into_iter().collect())
+        .unwrap_or_default();
+
+    // comments at the top of the file are attached to the first rule,
+    // or trail the document if it does not contain any rules
+    let header = match existing.first_mut() {
+        Some(table) => {
+            let header = table.decor().prefix().unwrap_or_default().to_string();
+            table.decor_mut().set_prefix("\n");
+
+            header
+        }
+
+        None => {
+            let header = document.trailing().to_string();
+            document.set_trailing("");
+
+            header
+        }
+    };
+
+    let mut existing: Vec<(Option<RuleDefinition>, Table)> = existing
+        .into_iter()
+        .map(|table| (toml::from_str(&table.to_string()).ok(), table))
+        .collect();
+
+    let mut tables = ArrayOfTables::new();
+
+    for (index, definition) in definitions.into_iter().enumerate() {
+        let unmodified = existing
+            .iter()
+            .position(|(existing, _)| existing.as_ref() == Some(&definition));
+
+        let mut table = match unmodified {
+            Some(unmodified) => existing.remove(unmodified).1,
+
+            None => {
+                let document: Document = toml::to_string(&definition)?.parse()?;
+
+                let mut table = document.as_table().clone();
+                table.decor_mut().set_prefix("\n");
+
+                table
+            }
+        };
+
+        // tables are written in the order of their positions
+        table.set_position(index + 1);
+
+        tables.push(table);
+    }
+
+    match tables.get_mut(0) {
+        Some(table) => {
+            let prefix = table.decor().prefix().unwrap_or_default();
+
+            let prefix = if header.trim().is_empty() {
+                prefix.trim_start_matches('\n').to_string()
+            } else {
+                format!("{}\n{}", header.trim_end(), prefix)
+            };
+
+            table.decor_mut().set_prefix(prefix);
+        }
+
+        None => document.set_trailing(&header),
+    }
+
+    if !tables.is_empty() {
+        document.insert("rule", Item::ArrayOfTables(tables));
+    }
+
+    Ok(document.to_string())
+}
+
+/// Convert the rules file of previous versions (JSON) to the current format
+pub fn migrate_legacy_rules_file(path: &Path) -> Result<()> {
+    let legacy_file = util::tilde_expand(constants::STATE_DIR)?.join(constants::LEGACY_RULES_FILE);
+
+    if path.exists() || !legacy_file.exists() {
+        return Ok(());
+    }
+
+    info!(
+        "Converting legacy rules file '{}' to '{}'",
+        legacy_file.display(),
+        path.display()
+    );
+
+    let s = fs::read_to_string(&legacy_file)?;
+    let rules: Vec<Rule> = serde_json::from_str(&s)?;
+
+    save_rules_file(path, rules.iter().map(|(selector, rule)| (selector, rule)))?;
+
+    Ok(())
+}
+
+/// Verify that all profiles referenced by the rules are known to the Eruption daemon
+///
+/// Returns a list of warnings, since the daemon may not be running or the profile
+/// may be installed later on
+pub fn check_profiles<'a>(
+    rules: impl Iterator<Item = (&'a Selector, &'a (RuleMetadata, Action))>,
+) -> Vec<String> {
+    let profiles = match dbus_client::enumerate_profiles() {
+        Ok(profiles) => profiles,
+
+        Err(e) => {
+            return vec![format!(
+                "Could not verify the profiles referenced by the rules: {}",
+                e
+            )]
+        }
+    };
+
+    let is_known = |profile_name: &str| {
+        let file_name = Path::new(profile_name).file_name();
+
+        profiles.iter().any(|(_name, profile_file)| {
+            profile_file == profile_name || Path::new(profile_file).file_name() == file_name
+        })
+    };
+
+    rules
+        .filter_map(|(selector, (_metadata, action))| match action {
+            Action::SwitchToProfile { profile_name } if !is_known(profile_name) => Some(format!(
+                "{} => {}: The profile does not exist",
+                selector, action
+            )),
+
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns the index of the first internal rule, user-defined rules are kept in front of it
+fn first_internal_index(rules_map: &RulesMap) -> usize {
+    rules_map
+        .values()
+        .position(|(metadata, _)| metadata.internal)
+        .unwrap_or(rules_map.len())
+}
+
+/// Returns an error if the rule at `index` does not exist or is an internal rule
+fn check_user_defined(rules_map: &RulesMap, index: usize) -> Result<()> {
+    match rules_map.get_index(index) {
+        Some((_, (metadata, _))) if metadata.internal => Err(RulesError::InternalRule {}.into()),
+
+        Some(_) => Ok(()),
+
+        None => Err(RulesError::InvalidIndex { index }.into()),
+    }
+}
+
+/// Add a new user-defined rule in front of all internal rules, returns the index of the new rule
+pub fn add_rule(rules_map: &mut RulesMap, rule: Rule) -> Result<usize> {
+    if rules_map.contains_key(&rule.0) {
+        return Err(RulesError::DuplicateSelector {
+            selector: rule.0.to_string(),
+        }
+        .into());
+    }
+
+    let index = first_internal_index(rules_map);
+
+    let mut rules = rules_map.drain(..).collect::<Vec<_>>();
+    rules.insert(index, rule);
+    rules_map.extend(rules);
+
+    Ok(index)
+}
+
+/// Replace the user-defined rule at `index`
+pub fn update_rule(rules_map: &mut RulesMap, index: usize, rule: Rule) -> Result<()> {
+    check_user_defined(rules_map, index)?;
+
+    if let Some(other) = rules_map.get_index_of(&rule.0) {
+        if other != index {
+            return Err(RulesError::DuplicateSelector {
+                selector: rule.0.to_string(),
+            }
+            .into());
+        }
+    }
+
+    let mut rules = rules_map.drain(..).collect::<Vec<_>>();
+    rules[index] = rule;
+    rules_map.extend(rules);
+
+    Ok(())
+}
+
+/// Remove the user-defined rule at `index`
+pub fn remove_rule(rules_map: &mut RulesMap, index: usize) -> Result<Rule> {
+    check_user_defined(rules_map, index)?;
+
+    Ok(rules_map.shift_remove_index(index).unwrap())
+}
+
+/// Enable or disable the user-defined rule at `index`
+pub fn set_rule_enabled(rules_map: &mut RulesMap, index: usize, enabled: bool) -> Result<()> {
+    check_user_defined(rules_map, index)?;
+
+    if let Some((_, (metadata, _))) = rules_map.get_index_mut(index) {
+        metadata.enabled = enabled;
+    }
+
+    Ok(())
+}
+
+/// Move the user-defined rule at index `from` to index `to`, internal rules stay in place
+pub fn move_rule(rules_map: &mut RulesMap, from: usize, to: usize) -> Result<()> {
+    check_user_defined(rules_map, from)?;
+
+    if to >= first_internal_index(rules_map) {
+        return Err(RulesError::InvalidIndex { index: to }.into());
+    }
+
+    let mut rules = rules_map.drain(..).collect::<Vec<_>>();
+    let rule = rules.remove(from);
+    rules.insert(to, rule);
+    rules_map.extend(rules);
+
+    Ok(())
+}
+
+/// Replace the cache of precompiled regular expressions with the ones used by `rules`
+pub fn update_regex_cache<'a>(rules: impl Iterator<Item = &'a Selector>) {
+    let mut cache = HashMap::new();
+
+    for selector in rules {
+        if let Some(pattern) = selector.regex() {
+            match Regex::new(pattern) {
+                Ok(regex) => {
+                    cache.insert(pattern.to_string(), regex);
+                }
+
+                Err(e) => error!("Invalid regular expression '{}': {}", pattern, e),
+            }
+        }
+    }
+
+    *REGEX_CACHE.write() = cache;
+}
+
+/// Returns the precompiled regular expression for `pattern`, compiling it on a cache miss
+pub fn get_regex(pattern: &str) -> Result<Regex> {
+    if let Some(regex) = REGEX_CACHE.read().get(pattern) {
+        return Ok(regex.clone());
+    }
+
+    let regex = Regex::new(pattern)?;
+    REGEX_CACHE
+        .write()
+        .insert(pattern.to_string(), regex.clone());
+
+    Ok(regex)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{Action, RuleMetadata, Selector, WindowFocusedSelectorMode};
+
+    #[test]
+    fn parse_rules_file() -> super::Result<()> {
+        let rules = super::parse_rules(
+            Path::new("test.toml"),
+            r#"
+            # a comment
+            [[rule]]
+            name = "Calculator"
+            sensor = "exec"
+            selector = "gnome-calc.*"
+            slot = 2
+
+            [[rule]]
+            enabled = false
+            sensor = "window-class"
+            selector = ".*vlc.*"
+            profile = "spectrum-analyzer-swirl.profile"
+            "#,
+        )?;
+
+        assert_eq!(rules.len(), 2);
+
+        assert_eq!(
+            rules[0].0,
+            Selector::ProcessExec {
+                comm: "gnome-calc.*".to_string()
+            }
+        );
+        assert_eq!((rules[0].1).0.name.as_deref(), Some("Calculator"));
+        assert!(matches!(
+            (rules[0].1).1,
+            Action::SwitchToSlot { slot_index: 1 }
+        ));
+
+        assert_eq!(
+            rules[1].0,
+            Selector::WindowFocused {
+                mode: WindowFocusedSelectorMode::WindowClass,
+                regex: ".*vlc.*".to_string()
+            }
+        );
+        assert!(!(rules[1].1).0.enabled);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_rules() {
+        let result = super::parse_rules(
+            Path::new("test.toml"),
+            r#"
+            [[rule]]
+            name = "Broken regex"
+            sensor = "exec"
+            selector = "gnome-calc.*("
+            slot = 2
+
+            [[rule]]
+            sensor = "window-name"
+            selector = ".*"
+            "#,
+        );
+
+        let message = result.unwrap_err().to_string();
+
+        assert!(message.contains("Rule #1 ('Broken regex'): Invalid regular expression"));
+        assert!(
+            message.contains("Rule #2: Exactly one of 'profile', 'slot', 'keymap' or 'brightness'")
+        );
+    }
+
+    #[test]
+    fn edit_rules() -> super::Result<()> {
+        let exec = |comm: &str| Selector::ProcessExec {
+            comm: comm.to_string(),
+        };
+
+        let rule = |comm: &str, internal: bool| {
+            (
+                exec(comm),
+                (
+                    RuleMetadata {
+                        enabled: true,
+                        internal,
+                        ..Default::default()
+                    },
+                    Action::SwitchToSlot { slot_index: 0 },
+                ),
+            )
+        };
+
+        let mut rules_map = super::RulesMap::new();
+        rules_map.extend([rule("internal", true)]);
+
+        // new rules are inserted in front of the internal rules
+        assert_eq!(super::add_rule(&mut rules_map, rule("a", false))?, 0);
+        assert_eq!(super::add_rule(&mut rules_map, rule("b", false))?, 1);
+        assert!(super::add_rule(&mut rules_map, rule("a", false)).is_err());
+
+        super::move_rule(&mut rules_map, 1, 0)?;
+        assert_eq!(rules_map.get_index(0).unwrap().0, &exec("b"));
+
+        // internal rules can neither be modified, nor be overtaken
+        assert!(super::move_rule(&mut rules_map, 0, 2).is_err());
+        assert!(super::set_rule_enabled(&mut rules_map, 2, false).is_err());
+        assert!(super::remove_rule(&mut rules_map, 2).is_err());
+
+        super::set_rule_enabled(&mut rules_map, 1, false)?;
+        assert!(!(rules_map.get_index(1).unwrap().1).0.enabled);
+
+        super::update_rule(&mut rules_map, 1, rule("c", false))?;
+        assert!(super::update_rule(&mut rules_map, 1, rule("b", false)).is_err());
+
+        super::remove_rule(&mut rules_map, 0)?;
+        assert_eq!(rules_map.get_index(0).unwrap().0, &exec("c"));
+        assert_eq!(rules_map.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn update_rules_keeps_comments() -> super::Result<()> {
+        let path = Path::new("test.toml");
+
+        let s = r#"# header
+
+# the calculator
+[[rule]]
+sensor = "exec"
+selector = "gnome-calc.*"
+slot = 2 # second slot
+
+# the video player
+[[rule]]
+sensor = "window-class"
+selector = ".*vlc.*"
+profile = "spectrum-analyzer-swirl.profile"
+"#;
+
+        let mut definitions: Vec<super::RuleDefinition> = super::parse_rules(path, s)?
+            .iter()
+            .map(|(selector, (metadata, action))| {
+                super::RuleDefinition::from_rule(selector, metadata, action)
+            })
+            .collect();
+
+        // swap the rules and modify the calculator rule
+        definitions.swap(0, 1);
+        definitions[1].slot = Some(3);
+
+        let result = super::update_rules(path, s, definitions)?;
+
+        assert!(result.starts_with("# header\n"));
+        assert!(result.find("# the video player").unwrap() < result.find("slot = 3").unwrap());
+        assert!(!result.contains("# second slot"));
+        assert_eq!(super::parse_rules(path, &result)?.len(), 2);
+
+        // a rules file with syntax errors is never overwritten
+        assert!(super::update_rules(path, "[[rule]\n", vec![]).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,584 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+//! Time based rules: cron-like expressions and sunrise/sunset
+//!
+//! Schedules are evaluated in local wall-clock time. The scheduler remembers the last point in
+//! time it has checked, and on every invocation looks for the most recent event in between. This
+//! way events that were missed while the system was suspended are caught up after resume, local
+//! times that are skipped at the start of DST fire right after the gap, and local times that are
+//! repeated at the end of DST fire only once.
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc};
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+
+use crate::rules::RulesMap;
+use crate::{constants, Action, Selector, SunSelectorMode};
+
+type Result<T> = std::result::Result<T, eyre::Error>;
+
+lazy_static! {
+    /// The local time up to which the schedule has been evaluated
+    static ref LAST_CHECKED: Arc<Mutex<Option<NaiveDateTime>>> = Arc::new(Mutex::new(None));
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScheduleError {
+    #[error("Invalid schedule '{expression}': {description}")]
+    InvalidExpression {
+        expression: String,
+        description: String,
+    },
+}
+
+/// Geographic location, used to compute the times of sunrise and sunset
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Location {
+    /// Returns the location configured in the `[schedule]` section of the config file
+    pub fn from_config() -> Option<Self> {
+        let config = crate::CONFIG.lock();
+        let config = config.as_ref()?;
+
+        let latitude = config.get_float("schedule.latitude").ok()?;
+        let longitude = config.get_float("schedule.longitude").ok()?;
+
+        Some(Self {
+            latitude,
+            longitude,
+        })
+    }
+}
+
+/// A parsed cron expression: `<minute> <hour> <day of month> <month> <day of week>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+
+    /// Day of month and day of week are combined with OR if both are restricted
+    days_of_month_restricted: bool,
+    days_of_week_restricted: bool,
+}
+
+const MONTH_NAMES: &[&str] = &[
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+const WEEKDAY_NAMES: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+impl CronSchedule {
+    /// Parse a cron expression, the macros `@hourly`, `@daily`, `@weekly`, `@monthly` and
+    /// `@yearly` are supported as well
+    pub fn parse(expression: &str) -> Result<Self> {
+        let error = |description: &str| ScheduleError::InvalidExpression {
+            expression: expression.to_string(),
+            description: description.to_string(),
+        };
+
+        let expanded = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+
+        let fields = expanded.split_whitespace().collect::<Vec<_>>();
+
+        if fields.len() != 5 {
+            return Err(
+                error("Expected 5 fields: minute hour day-of-month month day-of-week").into(),
+            );
+        }
+
+        let field = |index: usize, min: u32, max: u32, names: &[&str]| {
+            parse_field(fields[index], min, max, names).map_err(|e| error(&e))
+        };
+
+        let mut days_of_week = field(4, 0, 7, WEEKDAY_NAMES)?;
+
+        // both 0 and 7 denote sunday
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week = (days_of_week | 1) & !(1 << 7);
+        }
+
+        Ok(Self {
+            minutes: field(0, 0, 59, &[])?,
+            hours: field(1, 0, 23, &[])?,
+            days_of_month: field(2, 1, 31, &[])?,
+            months: field(3, 1, 12, MONTH_NAMES)?,
+            days_of_week,
+
+            days_of_month_restricted: fields[2] != "*",
+            days_of_week_restricted: fields[4] != "*",
+        })
+    }
+
+    /// Returns true if the schedule fires at the (local) time `time`
+    pub fn matches(&self, time: &NaiveDateTime) -> bool {
+        let bit = |mask: u64, value: u32| mask & (1 << value) != 0;
+
+        let day_of_month = bit(self.days_of_month, time.day());
+        let day_of_week = bit(self.days_of_week, time.weekday().num_days_from_sunday());
+
+        let day = match (self.days_of_month_restricted, self.days_of_week_restricted) {
+            (true, true) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        };
+
+        day && bit(self.minutes, time.minute())
+            && bit(self.hours, time.hour())
+            && bit(self.months, time.month())
+    }
+}
+
+/// Parse a single field of a cron expression into a bit mask
+fn parse_field(
+    field: &str,
+    min: u32,
+    max: u32,
+    names: &[&str],
+) -> std::result::Result<u64, String> {
+    let value = |s: &str| -> std::result::Result<u32, String> {
+        let lower = s.to_lowercase();
+
+        let value = match names.iter().position(|name| *name == lower) {
+            // month names start at 1, weekday names at 0
+            Some(index) => index as u32 + min,
+            None => s
+                .parse::<u32>()
+                .map_err(|_| format!("Invalid value '{}'", s))?,
+        };
+
+        if value < min || value > max {
+            Err(format!("Value {} out of range {}-{}", value, min, max))
+        } else {
+            Ok(value)
+        }
+    };
+
+    let mut result = 0;
+
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| format!("Invalid step '{}'", step))?,
+            ),
+
+            None => (item, 1),
+        };
+
+        let (start, end) = match range {
+            "*" => (min, max),
+
+            range => match range.split_once('-') {
+                Some((start, end)) => (value(start)?, value(end)?),
+
+                // `a/n` means: starting at a, every n
+                None if step > 1 => (value(range)?, max),
+
+                None => (value(range)?, value(range)?),
+            },
+        };
+
+        if start > end {
+            return Err(format!("Invalid range '{}'", range));
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            result |= 1 << value;
+        }
+    }
+
+    Ok(result)
+}
+
+/// Compute the time of sunrise or sunset (in UTC) on the specified day, returns `None` during
+/// polar day or polar night
+///
+/// See: <https://en.wikipedia.org/wiki/Sunrise_equation>
+pub fn sun_event(
+    date: NaiveDate,
+    location: &Location,
+    mode: SunSelectorMode,
+) -> Option<NaiveDateTime> {
+    let to_radians = |degrees: f64| degrees * PI / 180.0;
+    let to_degrees = |radians: f64| radians * 180.0 / PI;
+
+    // days since the J2000.0 epoch
+    let epoch = NaiveDate::from_ymd_opt(2000, 1, 1)?;
+    let n = (date - epoch).num_days() as f64;
+
+    // mean solar time
+    let j_star = n - location.longitude / 360.0;
+
+    // solar mean anomaly
+    let m = (357.5291 + 0.98560028 * j_star).rem_euclid(360.0);
+    let m_rad = to_radians(m);
+
+    // equation of the center
+    let c = 1.9148 * m_rad.sin() + 0.0200 * (2.0 * m_rad).sin() + 0.0003 * (3.0 * m_rad).sin();
+
+    // ecliptic longitude
+    let lambda = to_radians((m + c + 180.0 + 102.9372).rem_euclid(360.0));
+
+    // solar transit, as a Julian date
+    let j_transit = 2451545.0 + j_star + 0.0053 * m_rad.sin() - 0.0069 * (2.0 * lambda).sin();
+
+    // declination of the sun
+    let sin_delta = lambda.sin() * to_radians(23.4397).sin();
+    let cos_delta = sin_delta.asin().cos();
+
+    // hour angle, including atmospheric refraction and the solar disc diameter
+    let phi = to_radians(location.latitude);
+    let cos_omega = (to_radians(-0.833).sin() - phi.sin() * sin_delta) / (phi.cos() * cos_delta);
+
+    if !(-1.0..=1.0).contains(&cos_omega) {
+        return None;
+    }
+
+    let omega = to_degrees(cos_omega.acos());
+
+    let julian_date = match mode {
+        SunSelectorMode::Sunrise => j_transit - omega / 360.0,
+        SunSelectorMode::Sunset => j_transit + omega / 360.0,
+    };
+
+    let timestamp = ((julian_date - 2440587.5) * 86400.0).round() as i64;
+
+    Utc.timestamp_opt(timestamp, 0)
+        .single()
+        .map(|time| time.naive_utc())
+}
+
+/// Convert a time in UTC to local wall-clock time
+pub fn utc_to_local(time: NaiveDateTime) -> NaiveDateTime {
+    Local.from_utc_datetime(&time).naive_local()
+}
+
+/// Returns the most recent (local) time in `(from, to]` at which the schedule of `selector` fires
+fn last_event(
+    selector: &Selector,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    location: Option<&Location>,
+    to_local: &dyn Fn(NaiveDateTime) -> NaiveDateTime,
+) -> Option<NaiveDateTime> {
+    match selector {
+        Selector::Schedule { cron } => {
+            let schedule = CronSchedule::parse(cron).ok()?;
+
+            let mut time = to.with_second(0)?.with_nanosecond(0)?;
+
+            while time > from {
+                if schedule.matches(&time) {
+                    return Some(time);
+                }
+
+                time -= Duration::minutes(1);
+            }
+
+            None
+        }
+
+        Selector::Sun {
+            mode,
+            offset_minutes,
+        } => {
+            let location = location?;
+
+            // the offset may shift the event to another day
+            let mut date = to.date() + Duration::days(1);
+
+            while date >= from.date() - Duration::days(1) {
+                if let Some(time) = sun_event(date, location, *mode) {
+                    let time = to_local(time) + Duration::minutes(*offset_minutes);
+
+                    if time > from && time <= to {
+                        return Some(time);
+                    }
+                }
+
+                date -= Duration::days(1);
+            }
+
+            None
+        }
+
+        _ => None,
+    }
+}
+
+/// Returns the time based rule that fired most recently in `(from, to]`. If multiple rules fired
+/// at the same time, the first one wins
+pub fn find_due_rule(
+    rules_map: &RulesMap,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    location: Option<&Location>,
+    to_local: &dyn Fn(NaiveDateTime) -> NaiveDateTime,
+) -> Option<(NaiveDateTime, Selector, Action)> {
+    let mut result: Option<(NaiveDateTime, Selector, Action)> = None;
+
+    for (selector, (metadata, action)) in rules_map.iter() {
+        if !metadata.enabled {
+            continue;
+        }
+
+        if let Some(time) = last_event(selector, from, to, location, to_local) {
+            if result.as_ref().map(|(t, _, _)| time > *t).unwrap_or(true) {
+                result = Some((time, selector.clone(), action.clone()));
+            }
+        }
+    }
+
+    result
+}
+
+/// Determine the interval that has to be checked for events, given the time of the last check
+/// and the current local time. Returns `None` if there is nothing to check
+pub fn interval_to_check(
+    last_checked: Option<NaiveDateTime>,
+    now: NaiveDateTime,
+) -> (Option<(NaiveDateTime, NaiveDateTime)>, NaiveDateTime) {
+    match last_checked {
+        // on startup, apply the most recent event of the past days
+        None => (
+            Some((now - Duration::days(constants::SCHEDULE_CATCH_UP_DAYS), now)),
+            now,
+        ),
+
+        // the clock has been set back: start over, without re-running events
+        Some(last)
+            if now < last - Duration::minutes(constants::SCHEDULE_MAX_CLOCK_SKEW_MINUTES) =>
+        {
+            warn!("The system clock has been set back, restarting the scheduler");
+
+            (None, now)
+        }
+
+        // no time has passed, or the local time has been set back by the end of DST:
+        // the repeated local times have already been checked
+        Some(last) if now <= last => (None, last),
+
+        // includes the time the system has been suspended
+        Some(last) => (Some((last, now)), now),
+    }
+}
+
+/// Check whether a time based rule is due, and execute its action
+pub fn process_schedule() -> Result<()> {
+    process_schedule_at(Local::now().naive_local())
+}
+
+/// Check whether a time based rule is due at the (local) time `now`, and execute its action
+pub fn process_schedule_at(now: NaiveDateTime) -> Result<()> {
+    let interval = {
+        let mut last_checked = LAST_CHECKED.lock();
+
+        let (interval, checked) = interval_to_check(*last_checked, now);
+        *last_checked = Some(checked);
+
+        interval
+    };
+
+    if let Some((from, to)) = interval {
+        let location = Location::from_config();
+
+        let due = find_due_rule(
+            &crate::RULES_MAP.read(),
+            from,
+            to,
+            location.as_ref(),
+            &utc_to_local,
+        );
+
+        if let Some((time, selector, action)) = due {
+            debug!("Schedule is due ({}): {}", time, selector);
+
+            crate::process_action(&action)?;
+
+            // the scheduled profile becomes the new default, so that it stays
+            // active after other rules have been reverted
+            if let Action::SwitchToProfile { profile_name } = &action {
+                crate::update_default_rule(profile_name);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns warnings for time based rules that can not fire
+pub fn check_rules<'a>(
+    rules: impl Iterator<Item = (&'a Selector, &'a (crate::RuleMetadata, Action))>,
+) -> Vec<String> {
+    let location = Location::from_config();
+
+    rules
+        .filter_map(|(selector, _)| match selector {
+            Selector::Sun { .. } if location.is_none() => Some(format!(
+                "Rule '{}' requires 'latitude' and 'longitude' in the [schedule] section of the config file",
+                selector
+            )),
+
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    use super::{CronSchedule, Location};
+    use crate::rules::RulesMap;
+    use crate::{Action, RuleMetadata, Selector, SunSelectorMode};
+
+    fn time(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn cron_expressions() -> super::Result<()> {
+        let schedule = CronSchedule::parse("*/15 9-17 * * mon-fri")?;
+
+        assert!(schedule.matches(&time("2022-10-14 09:45"))); // friday
+        assert!(!schedule.matches(&time("2022-10-14 09:50")));
+        assert!(!schedule.matches(&time("2022-10-14 18:00")));
+        assert!(!schedule.matches(&time("2022-10-15 10:00"))); // saturday
+
+        // day of month and day of week are combined with OR
+        let schedule = CronSchedule::parse("0 0 1 * sun")?;
+
+        assert!(schedule.matches(&time("2022-10-01 00:00"))); // saturday, the 1st
+        assert!(schedule.matches(&time("2022-10-16 00:00"))); // sunday
+        assert!(!schedule.matches(&time("2022-10-17 00:00")));
+
+        assert_eq!(
+            CronSchedule::parse("@daily")?,
+            CronSchedule::parse("0 0 * * *")?
+        );
+        assert_eq!(
+            CronSchedule::parse("0 0 * * 7")?,
+            CronSchedule::parse("0 0 * * 0")?
+        );
+
+        assert!(CronSchedule::parse("0 24 * * *").is_err());
+        assert!(CronSchedule::parse("0 0 * *").is_err());
+        assert!(CronSchedule::parse("*/0 * * * *").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn sunrise_and_sunset() {
+        let berlin = Location {
+            latitude: 52.52,
+            longitude: 13.405,
+        };
+
+        let date = NaiveDate::from_ymd_opt(2022, 6, 21).unwrap();
+
+        let sunrise = super::sun_event(date, &berlin, SunSelectorMode::Sunrise).unwrap();
+        let sunset = super::sun_event(date, &berlin, SunSelectorMode::Sunset).unwrap();
+
+        // 04:43 and 21:33 CEST
+        assert!((sunrise - time("2022-06-21 02:43")).num_minutes().abs() <= 2);
+        assert!((sunset - time("2022-06-21 19:33")).num_minutes().abs() <= 2);
+
+        // polar night
+        let tromso = Location {
+            latitude: 69.65,
+            longitude: 18.96,
+        };
+
+        let date = NaiveDate::from_ymd_opt(2022, 12, 21).unwrap();
+
+        assert!(super::sun_event(date, &tromso, SunSelectorMode::Sunrise).is_none());
+    }
+
+    #[test]
+    fn schedule_catch_up_and_dst() -> super::Result<()> {
+        let rule = |cron: &str, slot_index: u64| {
+            (
+                Selector::Schedule {
+                    cron: cron.to_string(),
+                },
+                (RuleMetadata::default(), Action::SwitchToSlot { slot_index }),
+            )
+        };
+
+        let mut rules_map = RulesMap::new();
+        rules_map.extend([
+            rule("0 22 * * *", 1),
+            rule("30 2 * * *", 2),
+            rule("0 7 * * *", 3),
+        ]);
+
+        let due = |from: &str, to: &str| {
+            super::find_due_rule(&rules_map, time(from), time(to), None, &|t| t)
+                .map(|(time, _, action)| (time, action))
+        };
+
+        // nothing happened in between
+        assert!(due("2022-10-14 12:00", "2022-10-14 12:01").is_none());
+
+        // suspended over night: only the most recent event is applied after resume
+        let (at, action) = due("2022-10-14 21:00", "2022-10-15 08:00").unwrap();
+        assert_eq!(at, time("2022-10-15 07:00"));
+        assert!(matches!(action, Action::SwitchToSlot { slot_index: 3 }));
+
+        // start of DST: 02:30 does not exist, it fires once the clock jumps to 03:00
+        let (interval, _) =
+            super::interval_to_check(Some(time("2022-03-27 01:59")), time("2022-03-27 03:00"));
+        let (from, to) = interval.unwrap();
+        let (at, _, _) = super::find_due_rule(&rules_map, from, to, None, &|t| t).unwrap();
+        assert_eq!(at, time("2022-03-27 02:30"));
+
+        // end of DST: 02:00 - 02:59 are repeated, but 02:30 only fires once
+        let (interval, checked) =
+            super::interval_to_check(Some(time("2022-10-30 02:59")), time("2022-10-30 02:00"));
+        assert!(interval.is_none());
+        assert_eq!(checked, time("2022-10-30 02:59"));
+
+        let (interval, _) = super::interval_to_check(Some(checked), time("2022-10-30 02:30"));
+        assert!(interval.is_none());
+
+        Ok(())
+    }
+}
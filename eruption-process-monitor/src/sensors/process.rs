@@ -98,6 +98,8 @@ impl ProcessSensor {
                                     event,
                                     file_name: util::get_process_file_name(pid).ok(),
                                     comm: util::get_process_comm(pid).ok(),
+                                    steam_app_id: util::get_steam_app_root_id(pid),
+                                    wine_exe: util::get_wine_exe_name(pid),
                                 })
                                 .unwrap_or_else(|e| error!("Could not send on a channel: {}", e));
                         }
@@ -145,6 +147,13 @@ rules add exec <comm> [<profile-name.profile>|<slot number>]
 
 rules add exec gnome-calc.* /var/lib/eruption/profiles/profile1.profile
 rules add exec gnome-calc.* 2
+
+Games (Steam/Proton):
+rules add steam-app <AppID> [<profile-name.profile>|<slot number>]
+rules add wine-exe <exe-name> [<profile-name.profile>|<slot number>]
+
+rules add steam-app 1245620 /var/lib/eruption/profiles/profile1.profile
+rules add wine-exe (?i)eldenring\.exe 2
 "#
         .to_string()
     }
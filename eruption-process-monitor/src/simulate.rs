@@ -0,0 +1,365 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+//! Dry-run mode: feed synthetic events through the rules engine and print the
+//! resulting actions, without switching profiles or slots
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::dbus_client::{self, Message};
+use crate::sensors::{SensorData, WindowSensorData};
+use crate::{constants, Action, CURRENT_STATE, SIMULATION_MODE};
+
+type Result<T> = std::result::Result<T, eyre::Error>;
+
+lazy_static! {
+    /// Actions that would have been executed while processing the current event
+    static ref RECORDED_ACTIONS: Arc<Mutex<Vec<Action>>> = Arc::new(Mutex::new(Vec::new()));
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SimulationError {
+    #[error("Invalid event definition: '{definition}'")]
+    InvalidEvent { definition: String },
+
+    #[cfg(not(feature = "sensor-procmon"))]
+    #[error("Process events are not supported, since the procmon sensor is not available")]
+    NotSupported {},
+}
+
+/// A synthetic event, as specified on the command line or in an event script
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum SimulatedEvent {
+    /// A process has been executed
+    ProcessExec {
+        pid: i32,
+        #[serde(default)]
+        comm: String,
+        #[serde(default)]
+        steam_app_id: Option<u64>,
+        #[serde(default)]
+        wine_exe: Option<String>,
+    },
+
+    /// A process has exited
+    ProcessExit { pid: i32 },
+
+    /// A window has been focused
+    WindowFocused {
+        #[serde(default)]
+        name: String,
+        #[serde(default)]
+        instance: String,
+        #[serde(default)]
+        class: String,
+    },
+
+    /// The local time has advanced to the specified point in time
+    Time { time: NaiveDateTime },
+}
+
+impl SimulatedEvent {
+    /// Parse an event from its command line representation, e.g.: `exec:1234:gnome-calculator`,
+    /// `steam-app:1234:1245620`, `wine-exe:1234:eldenring.exe`, `exit:1234`, `window-class:Vlc`
+    /// `window:<name>:<instance>:<class>` or `time:2022-10-30T02:30`
+    pub fn parse(definition: &str) -> Result<Self> {
+        let invalid = || SimulationError::InvalidEvent {
+            definition: definition.to_string(),
+        };
+
+        let (kind, args) = definition.split_once(':').ok_or_else(invalid)?;
+
+        let event = match kind {
+            "exec" => {
+                let (pid, comm) = args.split_once(':').ok_or_else(invalid)?;
+
+                SimulatedEvent::ProcessExec {
+                    pid: pid.parse().map_err(|_| invalid())?,
+                    comm: comm.to_string(),
+                    steam_app_id: None,
+                    wine_exe: None,
+                }
+            }
+
+            "steam-app" => {
+                let (pid, app_id) = args.split_once(':').ok_or_else(invalid)?;
+
+                SimulatedEvent::ProcessExec {
+                    pid: pid.parse().map_err(|_| invalid())?,
+                    comm: String::new(),
+                    steam_app_id: Some(app_id.parse().map_err(|_| invalid())?),
+                    wine_exe: None,
+                }
+            }
+
+            "wine-exe" => {
+                let (pid, name) = args.split_once(':').ok_or_else(invalid)?;
+
+                SimulatedEvent::ProcessExec {
+                    pid: pid.parse().map_err(|_| invalid())?,
+                    // the kernel truncates the comm of a process to 15 characters
+                    comm: name.chars().take(15).collect(),
+                    steam_app_id: None,
+                    wine_exe: Some(name.to_string()),
+                }
+            }
+
+            "exit" => SimulatedEvent::ProcessExit {
+                pid: args.parse().map_err(|_| invalid())?,
+            },
+
+            "window-name" => SimulatedEvent::WindowFocused {
+                name: args.to_string(),
+                instance: String::new(),
+                class: String::new(),
+            },
+
+            "window-instance" => SimulatedEvent::WindowFocused {
+                name: String::new(),
+                instance: args.to_string(),
+                class: String::new(),
+            },
+
+            "window-class" => SimulatedEvent::WindowFocused {
+                name: String::new(),
+                instance: String::new(),
+                class: args.to_string(),
+            },
+
+            "window" => {
+                let mut parts = args.splitn(3, ':');
+
+                SimulatedEvent::WindowFocused {
+                    name: parts.next().unwrap_or_default().to_string(),
+                    instance: parts.next().unwrap_or_default().to_string(),
+                    class: parts.next().unwrap_or_default().to_string(),
+                }
+            }
+
+            "time" => SimulatedEvent::Time {
+                time: NaiveDateTime::parse_from_str(args, "%Y-%m-%dT%H:%M")
+                    .or_else(|_| NaiveDateTime::parse_from_str(args, "%Y-%m-%dT%H:%M:%S"))
+                    .map_err(|_| invalid())?,
+            },
+
+            _ => return Err(invalid().into()),
+        };
+
+        Ok(event)
+    }
+}
+
+impl fmt::Display for SimulatedEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SimulatedEvent::ProcessExec {
+                pid,
+                comm,
+                steam_app_id,
+                wine_exe,
+            } => {
+                write!(f, "Process executed: pid: {}, comm: '{}'", pid, comm)?;
+
+                if let Some(app_id) = steam_app_id {
+                    write!(f, ", Steam AppID: {}", app_id)?;
+                }
+
+                if let Some(wine_exe) = wine_exe {
+                    write!(f, ", Wine exe: '{}'", wine_exe)?;
+                }
+
+                Ok(())
+            }
+
+            SimulatedEvent::ProcessExit { pid } => write!(f, "Process exited: pid: {}", pid),
+
+            SimulatedEvent::WindowFocused {
+                name,
+                instance,
+                class,
+            } => write!(
+                f,
+                "Window focused: Name: '{}', Instance: '{}', Class: '{}'",
+                name, instance, class
+            ),
+
+            SimulatedEvent::Time { time } => write!(f, "Time: {}", time),
+        }
+    }
+}
+
+/// Window sensor data of a synthetic window focus event
+#[derive(Debug, Clone)]
+pub struct SimulatedWindowData {
+    pub window_name: String,
+    pub window_instance: String,
+    pub window_class: String,
+}
+
+impl SensorData for SimulatedWindowData {
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+impl WindowSensorData for SimulatedWindowData {
+    fn window_name(&self) -> Option<&str> {
+        Some(&self.window_name)
+    }
+
+    fn window_instance(&self) -> Option<&str> {
+        Some(&self.window_instance)
+    }
+
+    fn window_class(&self) -> Option<&str> {
+        Some(&self.window_class)
+    }
+}
+
+/// Record an action that would have been executed
+pub fn record_action(action: Action) {
+    RECORDED_ACTIONS.lock().push(action);
+}
+
+/// Load a JSON event script, consisting of an array of events
+pub fn load_event_script(path: &Path) -> Result<Vec<SimulatedEvent>> {
+    let s = fs::read_to_string(path)?;
+    let events = serde_json::from_str(&s)?;
+
+    Ok(events)
+}
+
+/// Feed a single synthetic event through the rules engine
+fn process_event(event: &SimulatedEvent) -> Result<()> {
+    match event {
+        #[cfg(feature = "sensor-procmon")]
+        SimulatedEvent::ProcessExec {
+            pid,
+            comm,
+            steam_app_id,
+            wine_exe,
+        } => {
+            crate::process_system_event(&crate::SystemEvent::ProcessExec {
+                event: synthetic_procmon_event(crate::procmon::EventType::Exec, *pid),
+                file_name: None,
+                comm: Some(comm.clone()),
+                steam_app_id: *steam_app_id,
+                wine_exe: wine_exe.clone(),
+            })?;
+        }
+
+        #[cfg(feature = "sensor-procmon")]
+        SimulatedEvent::ProcessExit { pid } => {
+            crate::process_system_event(&crate::SystemEvent::ProcessExit {
+                event: synthetic_procmon_event(crate::procmon::EventType::Exit, *pid),
+            })?;
+        }
+
+        #[cfg(not(feature = "sensor-procmon"))]
+        SimulatedEvent::ProcessExec { .. } | SimulatedEvent::ProcessExit { .. } => {
+            return Err(SimulationError::NotSupported {}.into());
+        }
+
+        SimulatedEvent::WindowFocused {
+            name,
+            instance,
+            class,
+        } => {
+            crate::process_window_event(&SimulatedWindowData {
+                window_name: name.clone(),
+                window_instance: instance.clone(),
+                window_class: class.clone(),
+            })?;
+        }
+
+        SimulatedEvent::Time { time } => {
+            crate::schedule::process_schedule_at(*time)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "sensor-procmon")]
+fn synthetic_procmon_event(
+    event_type: crate::procmon::EventType,
+    pid: i32,
+) -> crate::procmon::Event {
+    crate::procmon::Event {
+        event_type,
+        pid,
+        ppid: 0,
+        tgid: pid,
+    }
+}
+
+/// Run the simulation and print the resulting sequence of actions
+pub fn run(events: &[SimulatedEvent]) -> Result<()> {
+    SIMULATION_MODE.store(true, Ordering::SeqCst);
+
+    // start from the state of the Eruption daemon, if it is reachable
+    let profile_name = dbus_client::get_active_profile()
+        .unwrap_or_else(|_| constants::DEFAULT_PROFILE.to_string());
+    let slot_index = dbus_client::get_active_slot().unwrap_or(0);
+
+    println!(
+        "Initial state: profile: {}, slot: {}",
+        profile_name,
+        slot_index + 1
+    );
+
+    *CURRENT_STATE.write() = (Some(slot_index), Some(profile_name));
+
+    for (index, event) in events.iter().enumerate() {
+        println!("{:3}: {}", index, event);
+
+        RECORDED_ACTIONS.lock().clear();
+
+        if let Err(e) = process_event(event) {
+            println!("     Error: {}", e);
+        }
+
+        let actions = RECORDED_ACTIONS.lock().clone();
+
+        if actions.is_empty() {
+            println!("     => No action");
+        }
+
+        for action in actions.iter() {
+            println!("     => {}", action);
+
+            // the Eruption daemon would notify us about the profile change afterwards
+            if let Action::SwitchToProfile { profile_name } = action {
+                crate::process_dbus_event(&Message::ProfileChanged(profile_name.clone()))?;
+            }
+        }
+    }
+
+    Ok(())
+}
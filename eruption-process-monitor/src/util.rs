@@ -17,6 +17,8 @@
     Copyright (c) 2019-2022, The Eruption Development Team
 */
 
+#[cfg(feature = "sensor-procmon")]
+use std::collections::HashMap;
 use std::env;
 use std::{fs, path::Path};
 use std::{io, path::PathBuf};
@@ -51,6 +53,149 @@ pub fn get_process_file_name(pid: i32) -> Result<String> {
         .map_err(|_| UtilError::OpFailed {})?)
 }
 
+/// Returns the environment of the process `pid`
+#[cfg(feature = "sensor-procmon")]
+pub fn get_process_environ(pid: i32) -> Result<HashMap<String, String>> {
+    let environ = fs::read(format!("/proc/{}/environ", pid))?;
+
+    let result = environ
+        .split(|c| *c == 0)
+        .filter_map(|var| {
+            let var = String::from_utf8_lossy(var);
+
+            var.split_once('=')
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+        })
+        .collect();
+
+    Ok(result)
+}
+
+/// Returns the command line arguments of the process `pid`, including argv[0]
+#[cfg(feature = "sensor-procmon")]
+pub fn get_process_cmdline(pid: i32) -> Result<Vec<String>> {
+    let cmdline = fs::read(format!("/proc/{}/cmdline", pid))?;
+
+    let result = cmdline
+        .split(|c| *c == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).to_string())
+        .collect();
+
+    Ok(result)
+}
+
+/// Returns the pid of the parent of the process `pid`
+#[cfg(feature = "sensor-procmon")]
+pub fn get_parent_pid(pid: i32) -> Result<i32> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid))?;
+
+    // the comm field may contain spaces and parentheses, so skip past the last ')'
+    let ppid = stat
+        .rsplit_once(')')
+        .and_then(|(_, fields)| fields.split_whitespace().nth(1))
+        .ok_or(UtilError::OpFailed {})?
+        .parse()?;
+
+    Ok(ppid)
+}
+
+/// Returns the Steam AppID from the environment of the process `pid`, as set
+/// by the Steam client and by Proton
+#[cfg(feature = "sensor-procmon")]
+pub fn get_steam_app_id(pid: i32) -> Option<u64> {
+    let environ = get_process_environ(pid).ok()?;
+
+    ["SteamAppId", "STEAM_COMPAT_APP_ID"]
+        .iter()
+        .filter_map(|key| environ.get(*key))
+        .filter_map(|value| value.trim().parse::<u64>().ok())
+        .find(|app_id| *app_id != 0)
+}
+
+/// Returns the Steam AppID of the process `pid`, but only if it is the topmost process
+/// of the Steam application. All child processes inherit the environment of their parent,
+/// so rules should only be triggered once per launch of the application
+#[cfg(feature = "sensor-procmon")]
+pub fn get_steam_app_root_id(pid: i32) -> Option<u64> {
+    let app_id = get_steam_app_id(pid)?;
+
+    let parent_app_id = get_parent_pid(pid).ok().and_then(get_steam_app_id);
+
+    if parent_app_id != Some(app_id) {
+        Some(app_id)
+    } else {
+        None
+    }
+}
+
+/// File names of the executables of the Wine loader
+#[cfg(feature = "sensor-procmon")]
+const WINE_LOADERS: &[&str] = &["wine", "wine64", "wine-preloader", "wine64-preloader"];
+
+/// Returns the file name of the Windows executable run by the Wine/Proton process `pid`
+#[cfg(feature = "sensor-procmon")]
+pub fn get_wine_exe_name(pid: i32) -> Option<String> {
+    // other programs accept .exe files as arguments too, e.g.: `7z x setup.exe`
+    if !is_wine_process(pid) {
+        return None;
+    }
+
+    let cmdline = get_process_cmdline(pid).ok()?;
+
+    wine_exe_name(&cmdline)
+}
+
+/// Returns true if the process `pid` runs in Wine/Proton: either its executable is the
+/// Wine loader, it has been started by the Wine server, or Wine has set up its environment
+#[cfg(feature = "sensor-procmon")]
+pub fn is_wine_process(pid: i32) -> bool {
+    if get_process_file_name(pid)
+        .map(|file_name| is_wine_loader(&file_name))
+        .unwrap_or(false)
+    {
+        return true;
+    }
+
+    if get_parent_pid(pid)
+        .and_then(get_process_comm)
+        .map(|comm| comm == "wineserver")
+        .unwrap_or(false)
+    {
+        return true;
+    }
+
+    get_process_environ(pid)
+        .map(|environ| environ.contains_key("WINELOADER"))
+        .unwrap_or(false)
+}
+
+/// Returns true if `path` is the executable of the Wine loader
+#[cfg(feature = "sensor-procmon")]
+pub fn is_wine_loader(path: &str) -> bool {
+    let file_name = Path::new(path)
+        .file_name()
+        .and_then(|file_name| file_name.to_str())
+        .unwrap_or(path);
+
+    WINE_LOADERS.contains(&file_name)
+}
+
+/// Extracts the file name of the Windows executable from a Wine command line. Wine itself
+/// and the helper processes of Wine (e.g. `services.exe` or `winedevice.exe`) are skipped
+#[cfg(feature = "sensor-procmon")]
+pub fn wine_exe_name(cmdline: &[String]) -> Option<String> {
+    cmdline
+        .iter()
+        .filter(|arg| arg.to_lowercase().ends_with(".exe"))
+        .find(|arg| {
+            let path = arg.to_lowercase().replace('/', "\\");
+
+            !path.contains("\\windows\\system32\\") && !path.contains("\\windows\\syswow64\\")
+        })
+        .map(|arg| arg.rsplit(['\\', '/']).next().unwrap_or(arg).to_string())
+}
+
 pub fn tilde_expand(path: &str) -> Result<PathBuf> {
     let home = env::var("HOME")?;
 
@@ -70,8 +215,52 @@ pub fn create_rules_file_if_not_exists<P: AsRef<Path>>(path: &P) -> io::Result<(
     let path = path.as_ref();
 
     if fs::metadata(&path).is_err() {
-        fs::write(&path, "[]")?;
+        fs::write(&path, crate::rules::RULES_FILE_TEMPLATE)?;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "sensor-procmon")]
+    #[test]
+    fn wine_exe_name() {
+        let cmdline = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            super::wine_exe_name(&cmdline(&[
+                "Z:\\home\\user\\.steam\\steamapps\\common\\ELDEN RING\\Game\\eldenring.exe"
+            ])),
+            Some("eldenring.exe".to_string())
+        );
+
+        // Proton launches the game via its steam.exe stub
+        assert_eq!(
+            super::wine_exe_name(&cmdline(&[
+                "c:\\windows\\system32\\steam.exe",
+                "/home/user/.steam/steamapps/common/Cyberpunk 2077/bin/x64/Cyberpunk2077.exe",
+            ])),
+            Some("Cyberpunk2077.exe".to_string())
+        );
+
+        assert_eq!(
+            super::wine_exe_name(&cmdline(&["C:\\windows\\system32\\services.exe"])),
+            None
+        );
+        assert_eq!(super::wine_exe_name(&cmdline(&["/usr/bin/bash"])), None);
+    }
+
+    #[cfg(feature = "sensor-procmon")]
+    #[test]
+    fn is_wine_loader() {
+        assert!(super::is_wine_loader("/usr/bin/wine64-preloader"));
+        assert!(super::is_wine_loader(
+            "/home/user/.steam/steamapps/common/Proton 7.0/dist/bin/wine"
+        ));
+
+        assert!(!super::is_wine_loader("/usr/bin/7z"));
+        assert!(!super::is_wine_loader("/usr/bin/mono"));
+        assert!(!super::is_wine_loader("/usr/bin/winecfg"));
+    }
+}
@@ -0,0 +1,614 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+//! Onset and beat detection, tempo estimation and per-band energy of an audio signal
+//!
+//! The signal is analyzed in overlapping frames. Onsets are peaks in the spectral flux
+//! that exceed an adaptive threshold, the tempo is estimated from the autocorrelation of
+//! the spectral flux, and a flywheel that is nudged by the onsets tracks the beat phase.
+//! All timing is derived from the number of processed samples, so the analysis yields
+//! the same results for recorded audio as for live input
+
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+use rustfft::algorithm::Radix4;
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftDirection};
+
+use crate::constants;
+
+/// Number of samples per analysis frame
+const FRAME_SIZE: usize = 1024;
+
+/// Number of samples between the starts of two consecutive frames
+const HOP_SIZE: usize = FRAME_SIZE / 2;
+
+/// Gain applied before the logarithmic compression of the magnitude spectrum
+const COMPRESSION_GAIN: f32 = 100.0;
+
+/// Length of the history that the adaptive onset threshold is computed from
+const THRESHOLD_WINDOW_SECS: f32 = 1.0;
+
+/// Number of standard deviations the spectral flux has to exceed its mean by
+const THRESHOLD_SENSITIVITY: f32 = 1.5;
+
+/// Spectral flux below this value is never considered an onset, e.g. during silence
+const MIN_ONSET_FLUX: f32 = 0.01;
+
+/// Minimum time between two onsets
+const MIN_ONSET_INTERVAL_SECS: f32 = 0.1;
+
+/// Length of the history that the tempo is estimated from
+const TEMPO_WINDOW_SECS: f32 = 6.0;
+
+/// Time between two tempo estimations
+const TEMPO_UPDATE_SECS: f32 = 0.5;
+
+/// Tempo that is preferred when the autocorrelation is ambiguous, e.g. between
+/// half and double time
+const PREFERRED_BPM: f32 = 120.0;
+
+/// Width of the preference for `PREFERRED_BPM`, in octaves
+const TEMPO_SPREAD_OCTAVES: f32 = 1.0;
+
+/// Maximum distance of an onset from a predicted beat, as a fraction of the beat period,
+/// for the onset to correct the beat phase
+const PHASE_TOLERANCE: f32 = 0.25;
+
+/// Fraction of the phase error that is corrected by each onset
+const PHASE_CORRECTION: f32 = 0.5;
+
+/// The beat is lost if there have been no onsets for this long
+const BEAT_TIMEOUT_SECS: f32 = 2.0;
+
+/// Per-frame decay of the peak values that are used for normalization
+const PEAK_DECAY: f32 = 0.999;
+
+/// Each band is normalized to no less than this fraction of the peak of the loudest band,
+/// so that a quiet band is not amplified to full scale
+const MIN_RELATIVE_BAND_PEAK: f32 = 0.1;
+
+/// Per-frame decay of the onset strength
+const ONSET_DECAY: f32 = 0.85;
+
+/// Energy of the bass, mid and treble bands, each normalized to the range [0..1]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct BandEnergy {
+    pub bass: f32,
+    pub mid: f32,
+    pub treble: f32,
+}
+
+/// A detected beat
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Beat {
+    /// The estimated tempo, or 0 if the tempo is not known yet
+    pub bpm: f32,
+
+    /// The strength of the onset at the beat, in the range [0..1]
+    pub strength: f32,
+}
+
+/// Analyzes a stream of interleaved 16-bit samples
+pub struct BeatDetector {
+    sample_rate: u32,
+    channels: usize,
+    crossover: (f32, f32),
+
+    fft: Radix4<f32>,
+    window: Vec<f32>,
+
+    /// Downmixed samples that have not been analyzed completely yet
+    samples: Vec<f32>,
+
+    /// Log-compressed magnitude spectrum of the previous frame
+    spectrum: Vec<f32>,
+
+    /// Spectral flux of the most recent frames
+    flux: VecDeque<f32>,
+    flux_peak: f32,
+
+    bands: BandEnergy,
+    band_peaks: [f32; 3],
+
+    onset: f32,
+    frames_since_onset: usize,
+    frames_since_tempo_update: usize,
+
+    /// The beat period in frames, if the tempo is known
+    period: Option<f32>,
+    phase: f32,
+}
+
+impl BeatDetector {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let window = (0..FRAME_SIZE)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FRAME_SIZE as f32).cos())
+            .collect();
+
+        Self {
+            sample_rate,
+            channels: channels.max(1),
+            crossover: (
+                constants::DEFAULT_AUDIO_CROSSOVER_LOW_HZ,
+                constants::DEFAULT_AUDIO_CROSSOVER_HIGH_HZ,
+            ),
+
+            fft: Radix4::new(FRAME_SIZE, FftDirection::Forward),
+            window,
+
+            samples: Vec::with_capacity(FRAME_SIZE),
+            spectrum: vec![0.0; FRAME_SIZE / 2],
+
+            flux: VecDeque::new(),
+            flux_peak: 0.0,
+
+            bands: BandEnergy::default(),
+            band_peaks: [0.0; 3],
+
+            onset: 0.0,
+            frames_since_onset: usize::MAX,
+            frames_since_tempo_update: 0,
+
+            period: None,
+            phase: 0.0,
+        }
+    }
+
+    /// Set the crossover frequencies between the bass and the mid band, and between
+    /// the mid and the treble band, in Hz
+    pub fn set_crossover(&mut self, low: f32, high: f32) {
+        self.crossover = (low, high);
+    }
+
+    /// The estimated tempo, or 0 if the tempo is not known yet
+    pub fn bpm(&self) -> f32 {
+        self.period
+            .map(|period| 60.0 * self.frame_rate() / period)
+            .unwrap_or(0.0)
+    }
+
+    /// The position within the current beat, in the range [0..1)
+    pub fn beat_phase(&self) -> f32 {
+        self.phase
+    }
+
+    /// The strength of the most recent onset, decaying over time
+    pub fn onset(&self) -> f32 {
+        self.onset
+    }
+
+    pub fn bands(&self) -> BandEnergy {
+        self.bands
+    }
+
+    /// Analyze a buffer of interleaved samples. Returns the last beat that occurred
+    /// within the buffer, if any
+    pub fn process(&mut self, samples: &[i16]) -> Option<Beat> {
+        let mut result = None;
+
+        for frame in samples.chunks_exact(self.channels) {
+            let sample = frame.iter().map(|s| *s as f32).sum::<f32>()
+                / (self.channels as f32 * i16::MAX as f32);
+
+            self.samples.push(sample);
+
+            if self.samples.len() >= FRAME_SIZE {
+                if let Some(beat) = self.analyze_frame() {
+                    result = Some(beat);
+                }
+
+                self.samples.drain(..HOP_SIZE);
+            }
+        }
+
+        result
+    }
+
+    fn frame_rate(&self) -> f32 {
+        self.sample_rate as f32 / HOP_SIZE as f32
+    }
+
+    fn frames(&self, secs: f32) -> usize {
+        (secs * self.frame_rate()).round().max(1.0) as usize
+    }
+
+    fn analyze_frame(&mut self) -> Option<Beat> {
+        let mut data: Vec<Complex<f32>> = self
+            .samples
+            .iter()
+            .zip(&self.window)
+            .map(|(s, w)| Complex::new(s * w, 0.0))
+            .collect();
+
+        self.fft.process(&mut data);
+
+        let magnitudes: Vec<f32> = data[..FRAME_SIZE / 2].iter().map(|c| c.norm()).collect();
+
+        self.update_bands(&magnitudes);
+
+        // spectral flux: the sum of the increases of the log-compressed magnitudes
+        let spectrum: Vec<f32> = magnitudes
+            .iter()
+            .map(|m| (1.0 + COMPRESSION_GAIN * m).ln())
+            .collect();
+
+        let flux = spectrum
+            .iter()
+            .zip(&self.spectrum)
+            .map(|(current, previous)| (current - previous).max(0.0))
+            .sum::<f32>()
+            / spectrum.len() as f32;
+
+        self.spectrum = spectrum;
+        self.flux_peak = (self.flux_peak * PEAK_DECAY).max(flux);
+
+        let is_onset = self.detect_onset(flux);
+
+        self.flux.push_back(flux);
+        while self.flux.len() > self.frames(TEMPO_WINDOW_SECS) {
+            self.flux.pop_front();
+        }
+
+        self.frames_since_onset = self.frames_since_onset.saturating_add(1);
+
+        if is_onset {
+            self.onset = flux / self.flux_peak.max(f32::EPSILON);
+            self.frames_since_onset = 0;
+        } else {
+            self.onset *= ONSET_DECAY;
+        }
+
+        self.frames_since_tempo_update += 1;
+        if self.frames_since_tempo_update >= self.frames(TEMPO_UPDATE_SECS) {
+            self.frames_since_tempo_update = 0;
+            self.update_tempo();
+        }
+
+        self.track_beat(is_onset)
+    }
+
+    fn update_bands(&mut self, magnitudes: &[f32]) {
+        let bin_width = self.sample_rate as f32 / FRAME_SIZE as f32;
+
+        let mut energy = [0.0; 3];
+        for (i, m) in magnitudes.iter().enumerate().skip(1) {
+            let frequency = i as f32 * bin_width;
+
+            let band = if frequency < self.crossover.0 {
+                0
+            } else if frequency < self.crossover.1 {
+                1
+            } else {
+                2
+            };
+
+            energy[band] += m * m;
+        }
+
+        for (e, peak) in energy.iter_mut().zip(self.band_peaks.iter_mut()) {
+            *e = f32::sqrt(*e);
+            *peak = (*peak * PEAK_DECAY).max(*e);
+        }
+
+        let loudest = self.band_peaks.iter().cloned().fold(0.0, f32::max);
+
+        for (e, peak) in energy.iter_mut().zip(self.band_peaks.iter()) {
+            let peak = peak.max(loudest * MIN_RELATIVE_BAND_PEAK);
+
+            *e = if peak > f32::EPSILON { *e / peak } else { 0.0 };
+        }
+
+        self.bands = BandEnergy {
+            bass: energy[0],
+            mid: energy[1],
+            treble: energy[2],
+        };
+    }
+
+    fn detect_onset(&self, flux: f32) -> bool {
+        let window = self.frames(THRESHOLD_WINDOW_SECS);
+        let count = self.flux.len().min(window);
+
+        if count < window / 2 || flux < MIN_ONSET_FLUX {
+            return false;
+        }
+
+        let recent = || self.flux.iter().rev().take(count);
+
+        let mean = recent().sum::<f32>() / count as f32;
+        let variance = recent().map(|f| (f - mean).powi(2)).sum::<f32>() / count as f32;
+
+        let threshold = mean + THRESHOLD_SENSITIVITY * variance.sqrt();
+
+        flux > threshold && self.frames_since_onset >= self.frames(MIN_ONSET_INTERVAL_SECS)
+    }
+
+    fn update_tempo(&mut self) {
+        let period = match self.estimate_period() {
+            Some(period) => period,
+            None => return,
+        };
+
+        self.period = Some(match self.period {
+            // smooth small changes, but follow a new tempo immediately
+            Some(previous) if (period - previous).abs() < previous * 0.1 => {
+                previous * 0.7 + period * 0.3
+            }
+
+            Some(_) | None => {
+                if self.frames_since_onset != usize::MAX {
+                    self.phase = (self.frames_since_onset as f32 / period).fract();
+                }
+
+                period
+            }
+        });
+    }
+
+    /// Estimate the beat period in frames from the autocorrelation of the spectral flux
+    fn estimate_period(&self) -> Option<f32> {
+        let len = self.flux.len();
+        if len < self.frames(TEMPO_WINDOW_SECS) / 2 {
+            return None;
+        }
+
+        let mean = self.flux.iter().sum::<f32>() / len as f32;
+        let envelope: Vec<f32> = self.flux.iter().map(|f| f - mean).collect();
+
+        let autocorrelation = |lag: usize| {
+            envelope[lag..]
+                .iter()
+                .zip(&envelope)
+                .map(|(a, b)| a * b)
+                .sum::<f32>()
+                / (len - lag) as f32
+        };
+
+        let min_lag =
+            ((60.0 * self.frame_rate() / constants::MAX_AUDIO_BPM).floor() as usize).max(2);
+        let max_lag =
+            ((60.0 * self.frame_rate() / constants::MIN_AUDIO_BPM).ceil() as usize).min(len - 2);
+
+        if min_lag >= max_lag {
+            return None;
+        }
+
+        let (lag, score) = (min_lag..=max_lag)
+            .map(|lag| {
+                let bpm = 60.0 * self.frame_rate() / lag as f32;
+                let weight =
+                    (-0.5 * ((bpm / PREFERRED_BPM).log2() / TEMPO_SPREAD_OCTAVES).powi(2)).exp();
+
+                (lag, autocorrelation(lag) * weight)
+            })
+            .fold((0, 0.0), |best, candidate| {
+                if candidate.1 > best.1 {
+                    candidate
+                } else {
+                    best
+                }
+            });
+
+        if score <= 0.0 {
+            return None;
+        }
+
+        // refine the lag by fitting a parabola through the neighbouring values
+        let (a, b, c) = (
+            autocorrelation(lag - 1),
+            autocorrelation(lag),
+            autocorrelation(lag + 1),
+        );
+
+        let denominator = a - 2.0 * b + c;
+        let offset = if denominator.abs() > f32::EPSILON {
+            (0.5 * (a - c) / denominator).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        };
+
+        Some(lag as f32 + offset)
+    }
+
+    /// Advance the beat phase by one frame, and return a beat if the phase wrapped around
+    fn track_beat(&mut self, is_onset: bool) -> Option<Beat> {
+        if self.frames_since_onset > self.frames(BEAT_TIMEOUT_SECS) {
+            self.period = None;
+            self.phase = 0.0;
+        }
+
+        match self.period {
+            Some(period) => {
+                if is_onset {
+                    // pull the phase towards onsets that lie near a predicted beat
+                    let error = if self.phase < 0.5 {
+                        self.phase
+                    } else {
+                        self.phase - 1.0
+                    };
+
+                    if error.abs() < PHASE_TOLERANCE {
+                        self.phase -= error * PHASE_CORRECTION;
+                    }
+                }
+
+                self.phase += 1.0 / period;
+
+                if self.phase >= 1.0 {
+                    self.phase = self.phase.fract();
+
+                    Some(Beat {
+                        bpm: self.bpm(),
+                        strength: self.onset,
+                    })
+                } else {
+                    None
+                }
+            }
+
+            // without a tempo estimate, every onset is a beat
+            None if is_onset => Some(Beat {
+                bpm: 0.0,
+                strength: self.onset,
+            }),
+
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::BeatDetector;
+
+    /// Read a PCM WAV file with 16-bit samples, returns the sample rate, the number of
+    /// channels and the interleaved samples
+    fn read_wav(file_name: &str) -> (u32, usize, Vec<i16>) {
+        let path = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap())
+            .join("../support/tests/assets/")
+            .join(file_name);
+
+        let data = fs::read(&path).unwrap();
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(&data[8..12], b"WAVE");
+
+        let (mut format, mut samples) = (None, None);
+
+        let mut offset = 12;
+        while offset + 8 <= data.len() {
+            let id = &data[offset..offset + 4];
+            let len = u32::from_le_bytes(data[offset + 4..offset + 8].try_into().unwrap()) as usize;
+            let chunk = &data[offset + 8..(offset + 8 + len).min(data.len())];
+
+            match id {
+                b"fmt " => {
+                    let channels = u16::from_le_bytes([chunk[2], chunk[3]]) as usize;
+                    let sample_rate = u32::from_le_bytes(chunk[4..8].try_into().unwrap());
+                    let bits_per_sample = u16::from_le_bytes([chunk[14], chunk[15]]);
+                    assert_eq!(bits_per_sample, 16);
+
+                    format = Some((sample_rate, channels));
+                }
+
+                b"data" => {
+                    samples = Some(
+                        chunk
+                            .chunks_exact(2)
+                            .map(|c| i16::from_le_bytes([c[0], c[1]]))
+                            .collect::<Vec<_>>(),
+                    );
+                }
+
+                _ => (),
+            }
+
+            offset += 8 + len + (len & 1);
+        }
+
+        let (sample_rate, channels) = format.unwrap();
+
+        (sample_rate, channels, samples.unwrap())
+    }
+
+    #[test]
+    fn detect_beats_in_wav_file() {
+        let (sample_rate, channels, samples) = read_wav("beat-120bpm.wav");
+        let mut detector = BeatDetector::new(sample_rate, channels);
+
+        // feed the samples in buffers of the same size as the ones sent by the audio proxy
+        let mut beats = vec![];
+        let mut onsets = 0;
+        for (i, buffer) in samples.chunks(1020 * channels).enumerate() {
+            let previous_onset = detector.onset();
+
+            if let Some(beat) = detector.process(buffer) {
+                beats.push((i * 1020) as f32 / sample_rate as f32);
+                assert!(beat.strength > 0.0 && beat.strength <= 1.0, "{:?}", beat);
+            }
+
+            if detector.onset() > previous_onset {
+                onsets += 1;
+            }
+        }
+
+        assert!(
+            (detector.bpm() - 120.0).abs() < 3.0,
+            "BPM: {}",
+            detector.bpm()
+        );
+        assert!((14..=18).contains(&onsets), "Onsets: {}", onsets);
+
+        // once the tempo is known, beats are half a second apart
+        let intervals: Vec<f32> = beats
+            .windows(2)
+            .filter(|w| w[0] > 4.0)
+            .map(|w| w[1] - w[0])
+            .collect();
+
+        assert!(intervals.len() >= 6, "Beats: {:?}", beats);
+        assert!(
+            intervals.iter().all(|i| (i - 0.5).abs() < 0.1),
+            "Beats: {:?}",
+            beats
+        );
+    }
+
+    #[test]
+    fn band_energy() {
+        let sample_rate = 44100;
+        let mut detector = BeatDetector::new(sample_rate, 2);
+
+        let tone = |frequency: f32| -> Vec<i16> {
+            (0..sample_rate as usize)
+                .flat_map(|i| {
+                    let t = i as f32 / sample_rate as f32;
+                    let s = (f32::sin(2.0 * std::f32::consts::PI * frequency * t) * 16000.0) as i16;
+
+                    [s, s]
+                })
+                .collect()
+        };
+
+        detector.process(&tone(100.0));
+        let bands = detector.bands();
+        assert!(bands.bass > 0.9 && bands.mid < 0.1, "{:?}", bands);
+
+        detector.process(&tone(8000.0));
+        let bands = detector.bands();
+        assert!(bands.treble > 0.9 && bands.bass < 0.1, "{:?}", bands);
+
+        detector.set_crossover(2000.0, 10000.0);
+        detector.process(&tone(8000.0));
+        let bands = detector.bands();
+        assert!(bands.mid > 0.9 && bands.treble < 0.1, "{:?}", bands);
+
+        // silence contains no onsets, and the beat is lost after a while
+        detector.process(&vec![0; sample_rate as usize * 2 * 3]);
+        assert!(detector.onset() < 0.01);
+        assert_eq!(detector.bpm(), 0.0);
+        assert!(detector
+            .process(&vec![0; sample_rate as usize * 2])
+            .is_none());
+    }
+}
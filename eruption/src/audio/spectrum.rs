@@ -0,0 +1,466 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+//! A configurable spectrum analyzer that aggregates the FFT bins into bands on a
+//! perceptual frequency scale, with windowing, dB scaling and attack/decay smoothing
+//!
+//! Each distinct configuration that has been requested recently is updated for every
+//! buffer of samples. Configurations that use the same FFT size and window function
+//! share a single FFT
+
+use std::collections::{HashMap, VecDeque};
+use std::f32::consts::PI;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use rustfft::algorithm::Radix4;
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftDirection};
+
+/// Smallest supported FFT size
+pub const MIN_FFT_SIZE: usize = 256;
+
+/// Largest supported FFT size
+pub const MAX_FFT_SIZE: usize = 8192;
+
+/// The range that is mapped to [0..1] if dB scaling is requested, relative to full scale
+pub const DEFAULT_DB_RANGE: (i32, i32) = (-60, 0);
+
+/// A configuration is no longer updated if it has not been requested for this long
+const EXPIRY_TIME: Duration = Duration::from_secs(5);
+
+#[derive(Debug, thiserror::Error)]
+pub enum SpectrumError {
+    #[error("Unknown window function: {0}")]
+    UnknownWindowFunction(String),
+
+    #[error("Unknown frequency scale: {0}")]
+    UnknownFrequencyScale(String),
+
+    #[error("Invalid spectrum configuration: {description}")]
+    InvalidConfiguration { description: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WindowFunction {
+    Rectangular,
+    Hann,
+    Hamming,
+    BlackmanHarris,
+}
+
+impl WindowFunction {
+    fn coefficients(&self, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| {
+                let x = 2.0 * PI * i as f32 / len as f32;
+
+                match self {
+                    WindowFunction::Rectangular => 1.0,
+                    WindowFunction::Hann => 0.5 - 0.5 * x.cos(),
+                    WindowFunction::Hamming => 0.54 - 0.46 * x.cos(),
+                    WindowFunction::BlackmanHarris => {
+                        0.35875 - 0.48829 * x.cos() + 0.14128 * (2.0 * x).cos()
+                            - 0.01168 * (3.0 * x).cos()
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+impl FromStr for WindowFunction {
+    type Err = SpectrumError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "rectangular" | "none" => Ok(WindowFunction::Rectangular),
+            "hann" => Ok(WindowFunction::Hann),
+            "hamming" => Ok(WindowFunction::Hamming),
+            "blackman-harris" => Ok(WindowFunction::BlackmanHarris),
+
+            _ => Err(SpectrumError::UnknownWindowFunction(s.to_string())),
+        }
+    }
+}
+
+/// The scale on which the bands are spaced evenly
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrequencyScale {
+    Linear,
+    Log,
+    Mel,
+    Bark,
+}
+
+impl FrequencyScale {
+    fn hz_to_scale(&self, hz: f32) -> f32 {
+        match self {
+            FrequencyScale::Linear => hz,
+            FrequencyScale::Log => hz.ln(),
+            FrequencyScale::Mel => 2595.0 * (1.0 + hz / 700.0).log10(),
+            FrequencyScale::Bark => 26.81 * hz / (1960.0 + hz) - 0.53,
+        }
+    }
+
+    fn scale_to_hz(&self, value: f32) -> f32 {
+        match self {
+            FrequencyScale::Linear => value,
+            FrequencyScale::Log => value.exp(),
+            FrequencyScale::Mel => 700.0 * (10_f32.powf(value / 2595.0) - 1.0),
+            FrequencyScale::Bark => 1960.0 * (value + 0.53) / (26.28 - value),
+        }
+    }
+}
+
+impl FromStr for FrequencyScale {
+    type Err = SpectrumError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "linear" => Ok(FrequencyScale::Linear),
+            "log" => Ok(FrequencyScale::Log),
+            "mel" => Ok(FrequencyScale::Mel),
+            "bark" => Ok(FrequencyScale::Bark),
+
+            _ => Err(SpectrumError::UnknownFrequencyScale(s.to_string())),
+        }
+    }
+}
+
+/// The configuration of a spectrum, as requested by a Lua script
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SpectrumConfig {
+    pub bands: usize,
+    pub fft_size: usize,
+    pub window: WindowFunction,
+    pub scale: FrequencyScale,
+
+    /// Frequency range covered by the bands, in Hz
+    pub min_frequency: u32,
+    pub max_frequency: u32,
+
+    /// Smoothing time constants, in milliseconds
+    pub attack_millis: u32,
+    pub decay_millis: u32,
+
+    /// If set, map the range [min_db..max_db] (relative to full scale) to [0..1]
+    pub db_range: Option<(i32, i32)>,
+}
+
+impl Default for SpectrumConfig {
+    fn default() -> Self {
+        Self {
+            bands: 32,
+            fft_size: 2048,
+            window: WindowFunction::Hann,
+            scale: FrequencyScale::Log,
+            min_frequency: 20,
+            max_frequency: 20000,
+            attack_millis: 10,
+            decay_millis: 150,
+            db_range: None,
+        }
+    }
+}
+
+impl SpectrumConfig {
+    pub fn validate(&self, sample_rate: u32) -> Result<(), SpectrumError> {
+        let invalid = |description: &str| {
+            Err(SpectrumError::InvalidConfiguration {
+                description: description.to_string(),
+            })
+        };
+
+        if self.bands == 0 || self.bands > self.fft_size / 2 {
+            invalid("The number of bands must lie in the range [1..fft_size / 2]")
+        } else if !self.fft_size.is_power_of_two()
+            || !(MIN_FFT_SIZE..=MAX_FFT_SIZE).contains(&self.fft_size)
+        {
+            invalid("The FFT size must be a power of two in the range [256..8192]")
+        } else if self.min_frequency == 0
+            || self.min_frequency >= self.max_frequency
+            || self.max_frequency > sample_rate / 2
+        {
+            invalid("The frequency range must lie within the range [1..sample_rate / 2]")
+        } else if matches!(self.db_range, Some((min, max)) if min >= max) {
+            invalid("The lower end of the dB range must lie below the upper end")
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A shared FFT, along with the magnitudes computed from the most recent samples
+struct Transform {
+    fft: Radix4<f32>,
+    window: Vec<f32>,
+    magnitudes: Vec<f32>,
+}
+
+impl Transform {
+    fn new(fft_size: usize, window: WindowFunction) -> Self {
+        Self {
+            fft: Radix4::new(fft_size, FftDirection::Forward),
+            window: window.coefficients(fft_size),
+            magnitudes: vec![0.0; fft_size / 2],
+        }
+    }
+
+    fn update(&mut self, samples: &VecDeque<f32>) {
+        let fft_size = self.window.len();
+
+        // zero-pad the beginning, if there are not enough samples yet
+        let padding = fft_size.saturating_sub(samples.len());
+        let mut data = vec![Complex::new(0.0, 0.0); fft_size];
+
+        let recent = samples.iter().skip(samples.len().saturating_sub(fft_size));
+        for (d, (s, w)) in data[padding..]
+            .iter_mut()
+            .zip(recent.zip(&self.window[padding..]))
+        {
+            *d = Complex::new(s * w, 0.0);
+        }
+
+        self.fft.process(&mut data);
+
+        // scale the magnitudes so that a full scale sine wave has a magnitude of 1
+        let gain = 2.0 / self.window.iter().sum::<f32>();
+
+        for (m, c) in self.magnitudes.iter_mut().zip(&data) {
+            *m = c.norm() * gain;
+        }
+    }
+}
+
+struct Spectrum {
+    /// Frequency range of each band, in Hz
+    edges: Vec<(f32, f32)>,
+    values: Vec<f32>,
+    last_used: Instant,
+}
+
+/// Computes the spectra of all recently requested configurations
+pub struct SpectrumAnalyzer {
+    sample_rate: u32,
+    channels: usize,
+
+    /// The most recent downmixed samples
+    samples: VecDeque<f32>,
+
+    transforms: HashMap<(usize, WindowFunction), Transform>,
+    spectra: HashMap<SpectrumConfig, Spectrum>,
+}
+
+impl SpectrumAnalyzer {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        Self {
+            sample_rate,
+            channels: channels.max(1),
+            samples: VecDeque::with_capacity(MAX_FFT_SIZE),
+            transforms: HashMap::new(),
+            spectra: HashMap::new(),
+        }
+    }
+
+    /// Returns the most recent values of the spectrum with the given configuration, and
+    /// keeps it updated for a while. The configuration has to be valid
+    pub fn get(&mut self, config: &SpectrumConfig, now: Instant) -> Vec<f32> {
+        let spectrum = self.spectra.entry(config.clone()).or_insert_with(|| {
+            let (min, max) = (
+                config.scale.hz_to_scale(config.min_frequency as f32),
+                config.scale.hz_to_scale(config.max_frequency as f32),
+            );
+
+            let step = (max - min) / config.bands as f32;
+            let edges = (0..config.bands)
+                .map(|i| {
+                    (
+                        config.scale.scale_to_hz(min + step * i as f32),
+                        config.scale.scale_to_hz(min + step * (i + 1) as f32),
+                    )
+                })
+                .collect();
+
+            Spectrum {
+                edges,
+                values: vec![0.0; config.bands],
+                last_used: now,
+            }
+        });
+
+        spectrum.last_used = now;
+
+        spectrum.values.clone()
+    }
+
+    /// Analyze a buffer of interleaved samples, and update all spectra
+    pub fn process(&mut self, samples: &[i16], now: Instant) {
+        let mut count = 0;
+
+        for frame in samples.chunks_exact(self.channels) {
+            let sample = frame.iter().map(|s| *s as f32).sum::<f32>()
+                / (self.channels as f32 * i16::MAX as f32);
+
+            self.samples.push_back(sample);
+            count += 1;
+        }
+
+        while self.samples.len() > MAX_FFT_SIZE {
+            self.samples.pop_front();
+        }
+
+        self.spectra
+            .retain(|_, spectrum| now.saturating_duration_since(spectrum.last_used) < EXPIRY_TIME);
+
+        self.transforms.retain(|(fft_size, window), _| {
+            self.spectra
+                .keys()
+                .any(|c| c.fft_size == *fft_size && c.window == *window)
+        });
+
+        for config in self.spectra.keys() {
+            self.transforms
+                .entry((config.fft_size, config.window))
+                .or_insert_with(|| Transform::new(config.fft_size, config.window));
+        }
+
+        for transform in self.transforms.values_mut() {
+            transform.update(&self.samples);
+        }
+
+        let elapsed = count as f32 / self.sample_rate as f32;
+
+        for (config, spectrum) in self.spectra.iter_mut() {
+            let transform = &self.transforms[&(config.fft_size, config.window)];
+            let bin_width = self.sample_rate as f32 / config.fft_size as f32;
+
+            let smoothing = |millis: u32| {
+                if millis == 0 {
+                    1.0
+                } else {
+                    1.0 - (-elapsed * 1000.0 / millis as f32).exp()
+                }
+            };
+
+            let (attack, decay) = (
+                smoothing(config.attack_millis),
+                smoothing(config.decay_millis),
+            );
+
+            for (value, (low, high)) in spectrum.values.iter_mut().zip(&spectrum.edges) {
+                let magnitude = band_magnitude(&transform.magnitudes, bin_width, *low, *high);
+
+                let target = match config.db_range {
+                    Some((min_db, max_db)) => {
+                        let db = 20.0 * magnitude.max(f32::EPSILON).log10();
+
+                        ((db - min_db as f32) / (max_db - min_db) as f32).clamp(0.0, 1.0)
+                    }
+
+                    None => magnitude,
+                };
+
+                let factor = if target > *value { attack } else { decay };
+                *value += (target - *value) * factor;
+            }
+        }
+    }
+}
+
+/// The combined magnitude of all bins within a band. A band that is narrower than a
+/// bin is interpolated from the neighbouring bins at its center frequency
+fn band_magnitude(magnitudes: &[f32], bin_width: f32, low: f32, high: f32) -> f32 {
+    let first = (low / bin_width).ceil() as usize;
+    let last = ((high / bin_width).ceil() as usize).min(magnitudes.len());
+
+    if first < last {
+        magnitudes[first..last]
+            .iter()
+            .map(|m| m * m)
+            .sum::<f32>()
+            .sqrt()
+    } else {
+        let position = (low + high) / 2.0 / bin_width;
+        let index = (position.floor() as usize).min(magnitudes.len() - 1);
+        let next = (index + 1).min(magnitudes.len() - 1);
+        let fraction = position - index as f32;
+
+        magnitudes[index] * (1.0 - fraction) + magnitudes[next] * fraction
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{FrequencyScale, SpectrumAnalyzer, SpectrumConfig, WindowFunction};
+
+    #[test]
+    fn log_bands() {
+        let sample_rate = 44100;
+        let mut analyzer = SpectrumAnalyzer::new(sample_rate, 2);
+
+        let config = SpectrumConfig {
+            bands: 10,
+            window: WindowFunction::BlackmanHarris,
+            scale: FrequencyScale::Log,
+            min_frequency: 20,
+            max_frequency: 20480,
+            attack_millis: 0,
+            decay_millis: 0,
+            db_range: Some((-60, 0)),
+            ..Default::default()
+        };
+
+        config.validate(sample_rate).unwrap();
+
+        // a full scale sine wave at 1 kHz, one buffer at a time
+        let now = Instant::now();
+        analyzer.get(&config, now);
+
+        let mut phase = 0.0_f32;
+        for _ in 0..8 {
+            let buffer: Vec<i16> = (0..1020)
+                .flat_map(|_| {
+                    phase += 2.0 * std::f32::consts::PI * 1000.0 / sample_rate as f32;
+                    let s = (phase.sin() * i16::MAX as f32) as i16;
+
+                    [s, s]
+                })
+                .collect();
+
+            analyzer.process(&buffer, now);
+        }
+
+        // the bands are one octave wide, 1 kHz lies in the band of [640..1280] Hz
+        let values = analyzer.get(&config, now);
+        let loudest = (0..values.len())
+            .max_by(|a, b| values[*a].total_cmp(&values[*b]))
+            .unwrap();
+
+        assert_eq!(loudest, 5, "{:?}", values);
+        assert!(values[5] > 0.95, "{:?}", values);
+        assert!(values[0] < 0.1 && values[9] < 0.1, "{:?}", values);
+
+        // unused configurations expire
+        analyzer.process(&[0; 2040], now + Duration::from_secs(10));
+        assert!(analyzer.spectra.is_empty() && analyzer.transforms.is_empty());
+    }
+}
@@ -0,0 +1,505 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+//! Transport of the connection to the audio proxy
+//!
+//! After connecting, the audio proxy and the daemon exchange `HELLO` packets that carry
+//! the version of the protocol; peers that speak a different version are disconnected.
+//! Recorded samples are sent as numbered frames that describe their sample format, gaps
+//! in the sequence numbers are accounted for as frames that have been dropped, e.g. by
+//! the audio proxy when the daemon did not keep up with the stream
+
+use std::collections::HashMap;
+use std::mem::MaybeUninit;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::*;
+use nix::poll::{poll, PollFd, PollFlags};
+use prost::Message;
+use socket2::Socket;
+
+use crate::constants;
+use crate::plugins::audio::protocol;
+
+pub type Result<T> = std::result::Result<T, eyre::Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TransportError {
+    #[error("The audio proxy did not complete the handshake in time")]
+    HandshakeTimeout,
+
+    #[error("Invalid handshake: {description}")]
+    InvalidHandshake { description: String },
+
+    #[error("Incompatible audio proxy: protocol version {version}, expected version {expected}")]
+    IncompatibleVersion { version: u32, expected: u32 },
+
+    #[error("Lost connection to the audio proxy")]
+    ConnectionLost,
+}
+
+/// Statistics of the connection to the audio proxy, the counters are retained across reconnects
+#[derive(Debug, Default)]
+pub struct TransportStats {
+    connected: AtomicBool,
+    protocol_version: AtomicU32,
+
+    connections: AtomicU64,
+    frames_received: AtomicU64,
+    frames_dropped: AtomicU64,
+    frames_rejected: AtomicU64,
+    protocol_errors: AtomicU64,
+}
+
+impl TransportStats {
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    /// Protocol version of the most recently connected audio proxy
+    pub fn protocol_version(&self) -> u32 {
+        self.protocol_version.load(Ordering::SeqCst)
+    }
+
+    pub fn counters(&self) -> HashMap<String, u64> {
+        [
+            ("connections", &self.connections),
+            ("frames-received", &self.frames_received),
+            ("frames-dropped", &self.frames_dropped),
+            ("frames-rejected", &self.frames_rejected),
+            ("protocol-errors", &self.protocol_errors),
+        ]
+        .iter()
+        .map(|(name, counter)| (name.to_string(), counter.load(Ordering::SeqCst)))
+        .collect()
+    }
+}
+
+/// An established connection to the audio proxy
+pub struct Connection {
+    socket: Socket,
+    stats: Arc<TransportStats>,
+
+    /// Name and version of the audio proxy, as sent in its handshake
+    peer: String,
+
+    /// Sequence number of the next expected audio frame
+    next_sequence: u64,
+    last_receive: Instant,
+}
+
+impl Connection {
+    /// Perform the handshake with a newly connected audio proxy
+    pub fn accept(socket: Socket, stats: Arc<TransportStats>, timeout: Duration) -> Result<Self> {
+        let mut connection = Self {
+            socket,
+            stats,
+            peer: String::new(),
+            next_sequence: 0,
+            last_receive: Instant::now(),
+        };
+
+        if !connection.wait_readable(timeout)? {
+            return Err(TransportError::HandshakeTimeout.into());
+        }
+
+        let hello = match connection.receive()? {
+            Some(protocol::Response {
+                payload: Some(protocol::response::Payload::Hello(hello)),
+                ..
+            }) => hello,
+
+            _ => {
+                return Err(TransportError::InvalidHandshake {
+                    description: "Expected a HELLO packet".to_owned(),
+                }
+                .into())
+            }
+        };
+
+        // reply in any case, so that an incompatible audio proxy is able to report the mismatch too
+        let mut command = protocol::Command::default();
+        command.set_command_type(protocol::CommandType::Hello);
+        command.payload = Some(protocol::command::Payload::Hello(protocol::Hello {
+            protocol_version: constants::AUDIO_PROXY_PROTOCOL_VERSION,
+            peer: format!("eruption {}", env!("CARGO_PKG_VERSION")),
+        }));
+
+        connection.send(&command)?;
+
+        if hello.protocol_version != constants::AUDIO_PROXY_PROTOCOL_VERSION {
+            return Err(TransportError::IncompatibleVersion {
+                version: hello.protocol_version,
+                expected: constants::AUDIO_PROXY_PROTOCOL_VERSION,
+            }
+            .into());
+        }
+
+        connection.peer = hello.peer;
+
+        connection.stats.connected.store(true, Ordering::SeqCst);
+        connection
+            .stats
+            .protocol_version
+            .store(hello.protocol_version, Ordering::SeqCst);
+        connection.stats.connections.fetch_add(1, Ordering::SeqCst);
+
+        Ok(connection)
+    }
+
+    pub fn peer(&self) -> &str {
+        &self.peer
+    }
+
+    pub fn send(&self, command: &protocol::Command) -> Result<()> {
+        let mut buf = Vec::new();
+        command.encode_length_delimited(&mut buf)?;
+
+        match self.socket.send(&buf) {
+            Ok(_n) => Ok(()),
+
+            Err(_e) => Err(TransportError::ConnectionLost.into()),
+        }
+    }
+
+    /// Receive the next packet, blocks until a packet is available. Returns `Ok(None)`
+    /// if the packet could not be decoded
+    pub fn receive(&mut self) -> Result<Option<protocol::Response>> {
+        unsafe fn assume_init(buf: &[MaybeUninit<u8>]) -> &[u8] {
+            &*(buf as *const [MaybeUninit<u8>] as *const [u8])
+        }
+
+        let mut tmp = [MaybeUninit::zeroed(); constants::NET_BUFFER_CAPACITY];
+
+        let n = match self.socket.recv(&mut tmp) {
+            Ok(0) | Err(_) => return Err(TransportError::ConnectionLost.into()),
+
+            Ok(n) => n,
+        };
+
+        trace!("Read {} bytes from audio socket", n);
+
+        self.last_receive = Instant::now();
+
+        let tmp = unsafe { assume_init(&tmp[..n]) };
+
+        match protocol::Response::decode_length_delimited(tmp) {
+            Ok(response) => Ok(Some(response)),
+
+            Err(e) => {
+                error!("Protocol error: {}", e);
+
+                self.stats.protocol_errors.fetch_add(1, Ordering::SeqCst);
+
+                Ok(None)
+            }
+        }
+    }
+
+    /// Validate the sample format of an audio frame and account for the frames that have
+    /// been dropped since the previous one. Returns `None` if the frame has been rejected
+    pub fn decode_frame(&mut self, frame: &protocol::AudioFrame) -> Option<Vec<i16>> {
+        let bytes_per_sample = constants::AUDIO_BITS_PER_SAMPLE as usize / 8;
+
+        if frame.sample_rate != constants::AUDIO_SAMPLE_RATE
+            || frame.channels as usize != constants::AUDIO_CHANNELS
+            || frame.bits_per_sample != constants::AUDIO_BITS_PER_SAMPLE
+            || !frame
+                .data
+                .len()
+                .is_multiple_of(constants::AUDIO_CHANNELS * bytes_per_sample)
+        {
+            debug!(
+                "Rejected audio frame: {} Hz, {} channels, {} bits per sample, {} bytes",
+                frame.sample_rate,
+                frame.channels,
+                frame.bits_per_sample,
+                frame.data.len()
+            );
+
+            self.stats.frames_rejected.fetch_add(1, Ordering::SeqCst);
+
+            return None;
+        }
+
+        if frame.sequence < self.next_sequence {
+            debug!("Rejected out of order audio frame: {}", frame.sequence);
+
+            self.stats.frames_rejected.fetch_add(1, Ordering::SeqCst);
+
+            return None;
+        }
+
+        let dropped = frame.sequence - self.next_sequence;
+
+        if dropped > 0 {
+            trace!("Dropped {} audio frames", dropped);
+
+            self.stats
+                .frames_dropped
+                .fetch_add(dropped, Ordering::SeqCst);
+        }
+
+        self.next_sequence = frame.sequence + 1;
+        self.stats.frames_received.fetch_add(1, Ordering::SeqCst);
+
+        Some(
+            frame
+                .data
+                .chunks_exact(bytes_per_sample)
+                .map(|c| i16::from_ne_bytes([c[0], c[1]]))
+                .collect(),
+        )
+    }
+
+    /// The audio proxy sends status updates periodically, so a proxy that stopped sending
+    /// has most likely hung, e.g. while its user session is being torn down
+    pub fn is_stale(&self) -> bool {
+        self.last_receive.elapsed() >= Duration::from_millis(constants::AUDIO_PROXY_TIMEOUT_MILLIS)
+    }
+
+    fn wait_readable(&self, timeout: Duration) -> Result<bool> {
+        let mut poll_fds = [PollFd::new(self.socket.as_raw_fd(), PollFlags::POLLIN)];
+
+        let result = poll(&mut poll_fds, timeout.as_millis() as i32)?;
+
+        Ok(result > 0)
+    }
+}
+
+impl AsRawFd for Connection {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.stats.connected.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use socket2::{Domain, Type};
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(1000);
+
+    /// Plays the part of the audio proxy on the other end of a socket pair
+    struct FakeProxy {
+        socket: Socket,
+    }
+
+    impl FakeProxy {
+        fn send(&self, response_type: protocol::CommandType, payload: protocol::response::Payload) {
+            let mut response = protocol::Response::default();
+            response.set_response_type(response_type);
+            response.payload = Some(payload);
+
+            let mut buf = Vec::new();
+            response.encode_length_delimited(&mut buf).unwrap();
+
+            self.socket.send(&buf).unwrap();
+        }
+
+        fn hello(&self, protocol_version: u32) {
+            self.send(
+                protocol::CommandType::Hello,
+                protocol::response::Payload::Hello(protocol::Hello {
+                    protocol_version,
+                    peer: "fake-proxy".to_owned(),
+                }),
+            );
+        }
+
+        fn frame(&self, sequence: u64, sample_rate: u32, samples: &[i16]) {
+            self.send(
+                protocol::CommandType::AudioData,
+                protocol::response::Payload::Frame(protocol::AudioFrame {
+                    sequence,
+                    sample_rate,
+                    channels: constants::AUDIO_CHANNELS as u32,
+                    bits_per_sample: constants::AUDIO_BITS_PER_SAMPLE,
+                    data: samples.iter().flat_map(|s| s.to_ne_bytes()).collect(),
+                }),
+            );
+        }
+
+        fn receive(&self) -> protocol::Command {
+            let mut buf = [MaybeUninit::zeroed(); constants::NET_BUFFER_CAPACITY];
+            let n = self.socket.recv(&mut buf).unwrap();
+
+            let buf = buf[..n]
+                .iter()
+                .map(|b| unsafe { b.assume_init() })
+                .collect::<Vec<u8>>();
+
+            protocol::Command::decode_length_delimited(&buf[..]).unwrap()
+        }
+    }
+
+    /// Run `script` as an in-process audio proxy, connected to the returned socket
+    fn spawn_proxy<F>(script: F) -> (Socket, thread::JoinHandle<()>)
+    where
+        F: FnOnce(FakeProxy) + Send + 'static,
+    {
+        let (socket, proxy) = Socket::pair(Domain::UNIX, Type::SEQPACKET, None).unwrap();
+
+        (
+            socket,
+            thread::spawn(move || script(FakeProxy { socket: proxy })),
+        )
+    }
+
+    /// Receive packets until the proxy disconnects, returns the decoded audio frames
+    fn receive_frames(connection: &mut Connection) -> Vec<Vec<i16>> {
+        let mut result = Vec::new();
+
+        while let Ok(response) = connection.receive() {
+            if let Some(protocol::response::Payload::Frame(frame)) = response.unwrap().payload {
+                if let Some(samples) = connection.decode_frame(&frame) {
+                    result.push(samples);
+                }
+            }
+        }
+
+        result
+    }
+
+    #[test]
+    fn handshake_and_frames() {
+        let (socket, proxy) = spawn_proxy(|proxy| {
+            proxy.hello(constants::AUDIO_PROXY_PROTOCOL_VERSION);
+
+            let command = proxy.receive();
+            assert_eq!(command.command_type(), protocol::CommandType::Hello);
+
+            proxy.frame(0, constants::AUDIO_SAMPLE_RATE, &[1, -1]);
+            proxy.frame(1, constants::AUDIO_SAMPLE_RATE, &[2, -2]);
+
+            // frames 2 and 3 have been dropped by the proxy
+            proxy.frame(4, constants::AUDIO_SAMPLE_RATE, &[3, -3]);
+
+            // unsupported sample rate
+            proxy.frame(5, 48000, &[4, -4]);
+
+            // out of order
+            proxy.frame(3, constants::AUDIO_SAMPLE_RATE, &[5, -5]);
+        });
+
+        let stats = Arc::new(TransportStats::default());
+        let mut connection = Connection::accept(socket, stats.clone(), TIMEOUT).unwrap();
+
+        assert_eq!(connection.peer(), "fake-proxy");
+        assert!(stats.is_connected());
+
+        let frames = receive_frames(&mut connection);
+        proxy.join().unwrap();
+
+        assert_eq!(frames, vec![vec![1, -1], vec![2, -2], vec![3, -3]]);
+
+        let counters = stats.counters();
+        assert_eq!(counters["frames-received"], 3);
+        assert_eq!(counters["frames-dropped"], 2);
+        assert_eq!(counters["frames-rejected"], 2);
+
+        drop(connection);
+        assert!(!stats.is_connected());
+    }
+
+    #[test]
+    fn incompatible_version() {
+        let (socket, proxy) = spawn_proxy(|proxy| {
+            proxy.hello(constants::AUDIO_PROXY_PROTOCOL_VERSION + 1);
+
+            // the daemon replies with its own version before it disconnects
+            let command = proxy.receive();
+            match command.payload {
+                Some(protocol::command::Payload::Hello(hello)) => assert_eq!(
+                    hello.protocol_version,
+                    constants::AUDIO_PROXY_PROTOCOL_VERSION
+                ),
+
+                _ => panic!("Expected a HELLO packet"),
+            }
+        });
+
+        let stats = Arc::new(TransportStats::default());
+        let result = Connection::accept(socket, stats.clone(), TIMEOUT);
+        proxy.join().unwrap();
+
+        assert!(matches!(
+            result.err().unwrap().downcast_ref::<TransportError>(),
+            Some(TransportError::IncompatibleVersion { .. })
+        ));
+
+        assert!(!stats.is_connected());
+        assert_eq!(stats.counters()["connections"], 0);
+    }
+
+    #[test]
+    fn handshake_timeout() {
+        let (socket, proxy) = Socket::pair(Domain::UNIX, Type::SEQPACKET, None).unwrap();
+
+        // a legacy proxy does not send a handshake
+        let stats = Arc::new(TransportStats::default());
+        let result = Connection::accept(socket, stats, Duration::from_millis(50));
+
+        assert!(matches!(
+            result.err().unwrap().downcast_ref::<TransportError>(),
+            Some(TransportError::HandshakeTimeout)
+        ));
+
+        drop(proxy);
+    }
+
+    #[test]
+    fn reconnect() {
+        let stats = Arc::new(TransportStats::default());
+
+        for _ in 0..2 {
+            // the proxy of a new user session starts over with the first frame
+            let (socket, proxy) = spawn_proxy(|proxy| {
+                proxy.hello(constants::AUDIO_PROXY_PROTOCOL_VERSION);
+                proxy.receive();
+
+                proxy.frame(0, constants::AUDIO_SAMPLE_RATE, &[1, -1]);
+                proxy.frame(1, constants::AUDIO_SAMPLE_RATE, &[2, -2]);
+            });
+
+            let mut connection = Connection::accept(socket, stats.clone(), TIMEOUT).unwrap();
+
+            assert_eq!(receive_frames(&mut connection).len(), 2);
+            proxy.join().unwrap();
+        }
+
+        let counters = stats.counters();
+        assert_eq!(counters["connections"], 2);
+        assert_eq!(counters["frames-received"], 4);
+        assert_eq!(counters["frames-dropped"], 0);
+        assert_eq!(counters["frames-rejected"], 0);
+    }
+}
@@ -0,0 +1,1025 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+#![allow(dead_code)]
+
+//! Portable profile bundles: A gzip compressed tar archive that holds a profile, along with the
+//! scripts, manifests, Lua libraries, keymaps and color schemes that it depends on
+
+use crate::color_scheme::{self, ColorScheme};
+use crate::profiles::{self, ConfigParam, Profile};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{btree_map::Entry, BTreeMap, HashMap};
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use uuid::Uuid;
+
+pub type Result<T> = std::result::Result<T, eyre::Error>;
+
+/// Extension of profile bundle files
+pub const BUNDLE_EXTENSION: &str = "bundle";
+
+/// Version of the bundle format
+const BUNDLE_FORMAT_VERSION: u32 = 1;
+
+/// Name of the file in the archive that lists the contents of the bundle
+const BUNDLE_MANIFEST: &str = "bundle.toml";
+
+/// Name of the file in the archive that holds the color schemes
+const COLOR_SCHEMES_FILE: &str = "color-schemes.toml";
+
+/// Max. size of a single file in a bundle
+const MAX_ENTRY_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Max. number of files in a bundle
+const MAX_ENTRIES: usize = 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BundleError {
+    #[error("Could not read the bundle: {msg}")]
+    ReadError { msg: String },
+
+    #[error("Could not write the bundle: {msg}")]
+    WriteError { msg: String },
+
+    #[error("Unsupported bundle format version: {version}")]
+    UnsupportedVersion { version: u32 },
+
+    #[error("Invalid bundle: {msg}")]
+    InvalidBundle { msg: String },
+
+    #[error("Checksum mismatch: {path}")]
+    ChecksumMismatch { path: String },
+
+    #[error("File not found: {msg}")]
+    FileNotFound { msg: String },
+
+    #[error("Conflicts with installed files: {}", .conflicts.join(", "))]
+    Conflict { conflicts: Vec<String> },
+}
+
+/// The directories that bundles are exported from and imported to
+#[derive(Debug, Clone)]
+pub struct Locations {
+    /// The directories that are searched for installed files, in order of precedence. The
+    /// respective subdirectory of `import_dir` is expected to come first
+    pub profile_dirs: Vec<PathBuf>,
+    pub script_dirs: Vec<PathBuf>,
+    pub keymap_dirs: Vec<PathBuf>,
+
+    /// The writable directory that bundles are installed to, it mirrors the layout of a bundle.
+    /// Installed files outside of this directory are never modified, but shadowed
+    pub import_dir: PathBuf,
+}
+
+/// A file in a bundle, along with its checksum
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleEntry {
+    pub path: String,
+    pub sha256: String,
+}
+
+/// Describes the contents of a bundle, stored as `bundle.toml` in the archive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleManifest {
+    pub version: u32,
+    pub profile_id: Uuid,
+    pub profile_name: String,
+    pub files: Vec<BundleEntry>,
+}
+
+/// The result of importing a bundle
+#[derive(Debug, Clone)]
+pub struct ImportedBundle {
+    pub manifest: BundleManifest,
+
+    /// The installed profile
+    pub profile_file: PathBuf,
+
+    /// All files that have been written
+    pub installed_files: Vec<PathBuf>,
+
+    /// The color schemes of the bundle, these have to be registered by the caller
+    pub color_schemes: HashMap<String, ColorScheme>,
+}
+
+/// Export the profile `profile_file` and everything it depends on to `bundle_file`
+pub fn export(
+    profile_file: &Path,
+    locations: &Locations,
+    color_schemes: &HashMap<String, ColorScheme>,
+    bundle_file: &Path,
+) -> Result<BundleManifest> {
+    let mut files = BTreeMap::new();
+
+    // inherited profiles and fragments are merged, so that the bundle is self-contained
+    let mut profile = Profile::load(profile_file)?;
+    profile.extends = None;
+    profile.include.clear();
+
+    let profile_name = file_name(profile_file)?;
+
+    // scripts, their manifests and the Lua libraries they require
+    for script in profile.active_scripts.iter_mut() {
+        let script_file = if script.is_absolute() {
+            Some(script.clone()).filter(|f| f.is_file())
+        } else {
+            find_installed(script, &locations.script_dirs)
+        }
+        .ok_or_else(|| BundleError::FileNotFound {
+            msg: format!("Could not find script {}", script.display()),
+        })?;
+
+        // scripts outside of the script directories are installed to the first script directory
+        if !is_safe_path(script) {
+            *script = PathBuf::from(file_name(&script_file)?);
+        }
+
+        let source = fs::read(&script_file)?;
+
+        add_libraries(&source, &locations.script_dirs, &mut files)?;
+        files.insert(format!("scripts/{}", script.display()), source);
+
+        let manifest_file = PathBuf::from(format!("{}.manifest", script_file.display()));
+
+        if manifest_file.is_file() {
+            files.insert(
+                format!("scripts/{}.manifest", script.display()),
+                fs::read(&manifest_file)?,
+            );
+        }
+    }
+
+    if let Some(keymap) = profile.keymap.as_mut() {
+        // relative paths refer to the keymap directories
+        let keymap_file = if Path::new(keymap.as_str()).components().count() > 1 {
+            PathBuf::from(keymap.as_str())
+        } else {
+            find_installed(Path::new(keymap.as_str()), &locations.keymap_dirs).ok_or_else(|| {
+                BundleError::FileNotFound {
+                    msg: format!("Could not find keymap {}", keymap),
+                }
+            })?
+        };
+
+        let keymap_name = file_name(&keymap_file)?;
+
+        files.insert(format!("keymaps/{}", keymap_name), fs::read(&keymap_file)?);
+
+        *keymap = keymap_name;
+    }
+
+    // the color schemes that are referred to by the parameters of the profile
+    let state_file = profile_file.with_extension("profile.state");
+    let state = fs::read(&state_file).ok();
+
+    let state_config = state
+        .as_ref()
+        .and_then(|data| serde_json::from_slice::<HashMap<String, Vec<ConfigParam>>>(data).ok());
+
+    let used_color_schemes = [profile.config.as_ref(), state_config.as_ref()]
+        .iter()
+        .flatten()
+        .flat_map(|config| config.values().flatten())
+        .filter_map(|param| match param {
+            ConfigParam::String { value, .. } => color_schemes.get_key_value(value),
+            _ => None,
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    if !used_color_schemes.is_empty() {
+        files.insert(
+            COLOR_SCHEMES_FILE.to_string(),
+            toml::to_string_pretty(&used_color_schemes)?.into_bytes(),
+        );
+    }
+
+    if let Some(state) = state {
+        files.insert(format!("profiles/{}.state", profile_name), state);
+    }
+
+    files.insert(
+        format!("profiles/{}", profile_name),
+        toml::ser::to_string_pretty(&profile)?.into_bytes(),
+    );
+
+    let manifest = BundleManifest {
+        version: BUNDLE_FORMAT_VERSION,
+        profile_id: profile.id,
+        profile_name: profile.name.clone(),
+        files: files
+            .iter()
+            .map(|(path, data)| BundleEntry {
+                path: path.clone(),
+                sha256: sha256(data),
+            })
+            .collect(),
+    };
+
+    write_archive(bundle_file, &manifest, &files).map_err(|e| BundleError::WriteError {
+        msg: format!("{}: {}", bundle_file.display(), e),
+    })?;
+
+    Ok(manifest)
+}
+
+/// Read and verify a bundle file, without installing it
+pub fn read(bundle_file: &Path) -> Result<(BundleManifest, BTreeMap<String, Vec<u8>>)> {
+    let bundle = fs::read(bundle_file).map_err(|e| BundleError::ReadError {
+        msg: format!("{}: {}", bundle_file.display(), e),
+    })?;
+
+    parse(&bundle)
+}
+
+/// Verify the contents of a bundle, without installing it
+pub fn parse(bundle: &[u8]) -> Result<(BundleManifest, BTreeMap<String, Vec<u8>>)> {
+    let mut files =
+        read_archive(bundle).map_err(|e| BundleError::ReadError { msg: e.to_string() })?;
+
+    let manifest = files
+        .remove(BUNDLE_MANIFEST)
+        .ok_or_else(|| BundleError::InvalidBundle {
+            msg: format!("Missing {}", BUNDLE_MANIFEST),
+        })?;
+
+    let manifest: BundleManifest = toml::from_slice(&manifest)?;
+
+    if manifest.version != BUNDLE_FORMAT_VERSION {
+        return Err(BundleError::UnsupportedVersion {
+            version: manifest.version,
+        }
+        .into());
+    }
+
+    for entry in manifest.files.iter() {
+        let data = files
+            .get(&entry.path)
+            .ok_or_else(|| BundleError::InvalidBundle {
+                msg: format!("Missing file {}", entry.path),
+            })?;
+
+        if sha256(data) != entry.sha256 {
+            return Err(BundleError::ChecksumMismatch {
+                path: entry.path.clone(),
+            }
+            .into());
+        }
+    }
+
+    if let Some(path) = files
+        .keys()
+        .find(|path| !manifest.files.iter().any(|entry| &entry.path == *path))
+    {
+        return Err(BundleError::InvalidBundle {
+            msg: format!("Unexpected file {}", path),
+        }
+        .into());
+    }
+
+    Ok((manifest, files))
+}
+
+/// Install the contents of `bundle` below the import directory. Files that are already installed
+/// with identical contents are skipped; differing files, a profile with the same UUID and color
+/// schemes with the same name are reported as conflicts, unless `overwrite` is set. Nothing is
+/// installed unless all files could be written
+pub fn import(
+    bundle: &[u8],
+    locations: &Locations,
+    color_schemes: &HashMap<String, ColorScheme>,
+    overwrite: bool,
+) -> Result<ImportedBundle> {
+    let (manifest, files) = parse(bundle)?;
+
+    let mut conflicts = Vec::new();
+    let mut pending = Vec::new();
+
+    // `installed` is the copy that is currently in effect, it may be located outside of the
+    // import directory; `target` is the file below the import directory that shadows it
+    let mut plan = |data: &Vec<u8>, installed: Option<PathBuf>, target: PathBuf| match installed
+        .or_else(|| Some(target.clone()).filter(|f| f.exists()))
+    {
+        Some(existing) if is_installed(&existing, data) => {}
+
+        Some(existing) if !overwrite => conflicts.push(existing.display().to_string()),
+
+        _ => pending.push((target, data.clone())),
+    };
+
+    let mut bundled_profiles = Vec::new();
+    let mut bundled_color_schemes: HashMap<String, ColorScheme> = HashMap::new();
+
+    for (path, data) in files.iter() {
+        let (dir, relative_path) = path.split_once('/').unwrap_or(("", path));
+        let relative_path = Path::new(relative_path);
+
+        match dir {
+            "scripts" => {
+                plan(
+                    data,
+                    find_installed(relative_path, &locations.script_dirs),
+                    locations.import_dir.join(path),
+                );
+            }
+
+            "keymaps" => {
+                plan(
+                    data,
+                    find_installed(relative_path, &locations.keymap_dirs),
+                    locations.import_dir.join(path),
+                );
+            }
+
+            "profiles" if path.ends_with(".profile") => {
+                bundled_profiles.push((relative_path, data));
+            }
+
+            // state files are installed along with their profile
+            "profiles" if path.ends_with(".profile.state") => {}
+
+            "" if path == COLOR_SCHEMES_FILE => {
+                bundled_color_schemes = toml::from_slice(data)?;
+
+                if let Some(name) = bundled_color_schemes
+                    .keys()
+                    .find(|name| !color_scheme::is_valid_name(name))
+                {
+                    return Err(BundleError::InvalidBundle {
+                        msg: format!("Invalid color scheme name {}", name),
+                    }
+                    .into());
+                }
+            }
+
+            _ => {
+                return Err(BundleError::InvalidBundle {
+                    msg: format!("Unexpected file {}", path),
+                }
+                .into())
+            }
+        }
+    }
+
+    let (relative_path, data) = match bundled_profiles[..] {
+        [profile] => profile,
+
+        _ => {
+            return Err(BundleError::InvalidBundle {
+                msg: "A bundle has to contain exactly one profile".to_string(),
+            }
+            .into())
+        }
+    };
+
+    verify_profile(data, &manifest)?;
+
+    for (name, color_scheme) in bundled_color_schemes.iter() {
+        if let Some(installed) = color_schemes.get(name) {
+            if toml::to_string(installed)? != toml::to_string(color_scheme)? && !overwrite {
+                conflicts.push(format!("color scheme {}", name));
+            }
+        }
+    }
+
+    // the profile is installed along with its state; an imported profile keeps its file name
+    // when it is replaced
+    let state = files.get(&format!("profiles/{}.state", relative_path.display()));
+    let installed = profiles::find_path_by_uuid_from(manifest.profile_id, &locations.profile_dirs);
+
+    let import_profile_dir = locations.import_dir.join("profiles");
+    let target = installed
+        .clone()
+        .filter(|f| f.starts_with(&import_profile_dir))
+        .unwrap_or_else(|| import_profile_dir.join(relative_path));
+
+    let profile_file = match installed.or_else(|| Some(target.clone()).filter(|f| f.exists())) {
+        Some(existing)
+            if is_installed(&existing, data)
+                && state.into_iter().all(|state| {
+                    is_installed(&existing.with_extension("profile.state"), state)
+                }) =>
+        {
+            existing
+        }
+
+        Some(existing) if !overwrite => {
+            conflicts.push(existing.display().to_string());
+            existing
+        }
+
+        _ => {
+            // install the profile last, so that everything it depends on is in place when it is loaded
+            if let Some(state) = state {
+                pending.push((target.with_extension("profile.state"), state.clone()));
+            }
+
+            pending.push((target.clone(), data.clone()));
+            target
+        }
+    };
+
+    if !conflicts.is_empty() {
+        return Err(BundleError::Conflict { conflicts }.into());
+    }
+
+    install(&locations.import_dir, &pending)?;
+
+    Ok(ImportedBundle {
+        manifest,
+        profile_file,
+        installed_files: pending.into_iter().map(|(target, _)| target).collect(),
+        color_schemes: bundled_color_schemes,
+    })
+}
+
+/// Write `files` below `import_dir`. The files are written to a staging directory first, and
+/// are only moved into place after all of them have been written successfully
+fn install(import_dir: &Path, files: &[(PathBuf, Vec<u8>)]) -> Result<()> {
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let staging_dir = import_dir.join(format!(".staging-{}", Uuid::new_v4()));
+
+    let result = stage(&staging_dir, import_dir, files).and_then(|staged| {
+        for (staged_file, target) in staged {
+            fs::rename(&staged_file, target).map_err(|e| BundleError::WriteError {
+                msg: format!("{}: {}", target.display(), e),
+            })?;
+        }
+
+        Ok(())
+    });
+
+    let _ = fs::remove_dir_all(&staging_dir);
+
+    result
+}
+
+/// Write `files` to `staging_dir` and create the directories of their targets, returns the
+/// staged files along with their targets
+fn stage<'a>(
+    staging_dir: &Path,
+    import_dir: &Path,
+    files: &'a [(PathBuf, Vec<u8>)],
+) -> Result<Vec<(PathBuf, &'a PathBuf)>> {
+    let mut result = Vec::new();
+
+    for (target, data) in files {
+        let relative_path =
+            target
+                .strip_prefix(import_dir)
+                .map_err(|_| BundleError::WriteError {
+                    msg: format!("{} is outside of the import directory", target.display()),
+                })?;
+
+        let staged_file = staging_dir.join(relative_path);
+
+        for dir in [staged_file.parent(), target.parent()]
+            .into_iter()
+            .flatten()
+        {
+            fs::create_dir_all(dir).map_err(|e| BundleError::WriteError {
+                msg: format!("{}: {}", dir.display(), e),
+            })?;
+        }
+
+        if target.is_dir() {
+            return Err(BundleError::WriteError {
+                msg: format!("{} is a directory", target.display()),
+            }
+            .into());
+        }
+
+        fs::write(&staged_file, data).map_err(|e| BundleError::WriteError {
+            msg: format!("{}: {}", staged_file.display(), e),
+        })?;
+
+        result.push((staged_file, target));
+    }
+
+    Ok(result)
+}
+
+/// Returns `true` if `file` exists and holds `data`
+fn is_installed(file: &Path, data: &[u8]) -> bool {
+    fs::read(file).ok().as_deref() == Some(data)
+}
+
+/// Make sure that the bundled profile is self-contained and matches the bundle manifest
+fn verify_profile(data: &[u8], manifest: &BundleManifest) -> Result<()> {
+    let profile: toml::value::Table = toml::from_slice(data)?;
+
+    if profile.contains_key("extends") || profile.contains_key("include") {
+        return Err(BundleError::InvalidBundle {
+            msg: "The bundled profile must not inherit from other files".to_string(),
+        }
+        .into());
+    }
+
+    let id = profile
+        .get("id")
+        .and_then(|id| id.as_str())
+        .and_then(|id| Uuid::parse_str(id).ok());
+
+    if id != Some(manifest.profile_id) {
+        return Err(BundleError::InvalidBundle {
+            msg: "The UUID of the bundled profile does not match".to_string(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Add the Lua libraries that are required by `source` to `files`, recursively
+fn add_libraries(
+    source: &[u8],
+    script_dirs: &[PathBuf],
+    files: &mut BTreeMap<String, Vec<u8>>,
+) -> Result<()> {
+    for module in required_modules(&String::from_utf8_lossy(source)) {
+        let module = module.replace('.', "/");
+
+        // mirrors the `package.path` that is set up for the scripts
+        let library = [format!("lib/{}", module), format!("lib/{}.lua", module)]
+            .iter()
+            .map(PathBuf::from)
+            .filter(|library| is_safe_path(library))
+            .find_map(|library| {
+                find_installed(&library, script_dirs).map(|installed| (library, installed))
+            });
+
+        // modules that are not found are assumed to be provided by the system, e.g. 'socket'
+        if let Some((library, installed)) = library {
+            let path = format!("scripts/{}", library.display());
+
+            if let Entry::Vacant(entry) = files.entry(path) {
+                let source = entry.insert(fs::read(&installed)?).clone();
+
+                add_libraries(&source, script_dirs, files)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Find the names of the modules that are loaded with `require "name"` or `require("name")`
+fn required_modules(source: &str) -> Vec<String> {
+    let mut result = Vec::new();
+
+    for line in source.lines() {
+        // ignore comments
+        let line = line.split("--").next().unwrap_or_default();
+
+        for (index, _) in line.match_indices("require") {
+            let preceding = line[..index].chars().next_back();
+
+            if matches!(preceding, Some(c) if c.is_alphanumeric() || c == '_' || c == '.') {
+                continue;
+            }
+
+            let rest = line[index + "require".len()..].trim_start();
+            let rest = rest.strip_prefix('(').unwrap_or(rest).trim_start();
+
+            if let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') {
+                if let Some(end) = rest[1..].find(quote) {
+                    result.push(rest[1..=end].to_string());
+                }
+            }
+        }
+    }
+
+    result
+}
+
+/// Find an installed copy of the file `relative_path` in one of `dirs`
+fn find_installed(relative_path: &Path, dirs: &[PathBuf]) -> Option<PathBuf> {
+    dirs.iter()
+        .map(|dir| dir.join(relative_path))
+        .find(|file| file.is_file())
+}
+
+fn file_name(path: &Path) -> Result<String> {
+    Ok(path
+        .file_name()
+        .ok_or_else(|| BundleError::FileNotFound {
+            msg: path.display().to_string(),
+        })?
+        .to_string_lossy()
+        .to_string())
+}
+
+/// Returns `true` if `path` is relative and stays below the directory it is joined to
+fn is_safe_path(path: &Path) -> bool {
+    path.components().next().is_some()
+        && path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn write_archive(
+    bundle_file: &Path,
+    manifest: &BundleManifest,
+    files: &BTreeMap<String, Vec<u8>>,
+) -> Result<()> {
+    let mtime = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let manifest = toml::to_string_pretty(manifest)?.into_bytes();
+
+    let mut archive = tar::Builder::new(GzEncoder::new(
+        fs::File::create(bundle_file)?,
+        Compression::default(),
+    ));
+
+    for (path, data) in [(BUNDLE_MANIFEST, &manifest)]
+        .into_iter()
+        .chain(files.iter().map(|(path, data)| (path.as_str(), data)))
+    {
+        let mut header = tar::Header::new_gnu();
+
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+
+        archive.append_data(&mut header, path, data.as_slice())?;
+    }
+
+    archive.into_inner()?.finish()?;
+
+    Ok(())
+}
+
+fn read_archive(bundle: &[u8]) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut result = BTreeMap::new();
+
+    let mut archive = tar::Archive::new(GzDecoder::new(bundle));
+
+    for entry in archive.entries()? {
+        let entry = entry?;
+
+        if entry.header().entry_type().is_dir() {
+            continue;
+        }
+
+        let path = entry.path()?.into_owned();
+
+        if !entry.header().entry_type().is_file() || !is_safe_path(&path) {
+            return Err(BundleError::InvalidBundle {
+                msg: format!("Invalid entry {}", path.display()),
+            }
+            .into());
+        }
+
+        if result.len() >= MAX_ENTRIES || entry.header().size()? > MAX_ENTRY_SIZE {
+            return Err(BundleError::InvalidBundle {
+                msg: "The bundle is too large".to_string(),
+            }
+            .into());
+        }
+
+        let mut data = Vec::new();
+        entry.take(MAX_ENTRY_SIZE).read_to_end(&mut data)?;
+
+        result.insert(path.to_string_lossy().to_string(), data);
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("eruption-bundle-{}", Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+
+            Self(dir)
+        }
+
+        /// The package directories of `name`, preceded by the subdirectories of its import directory
+        fn locations(&self, name: &str) -> Locations {
+            let root = self.0.join(name);
+            let import_dir = root.join("imported");
+
+            for dir in ["profiles", "scripts/lib", "keymaps"] {
+                fs::create_dir_all(root.join(dir)).unwrap();
+            }
+
+            Locations {
+                profile_dirs: vec![import_dir.join("profiles"), root.join("profiles")],
+                script_dirs: vec![import_dir.join("scripts"), root.join("scripts")],
+                keymap_dirs: vec![import_dir.join("keymaps"), root.join("keymaps")],
+                import_dir,
+            }
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn color_schemes() -> HashMap<String, ColorScheme> {
+        let mut result = HashMap::new();
+
+        result.insert(
+            "sunset".to_string(),
+            ColorScheme {
+                colors: vec!["#ff8000".parse().unwrap(), "#800080".parse().unwrap()],
+            },
+        );
+
+        result
+    }
+
+    /// Install a profile with a script that requires a library, a keymap and a color scheme
+    fn setup_source(locations: &Locations) -> PathBuf {
+        let scripts = &locations.script_dirs[1];
+
+        fs::write(scripts.join("fx.lua"), "require \"declarations\"\n").unwrap();
+        fs::write(scripts.join("fx.lua.manifest"), "name = 'FX'\n").unwrap();
+        fs::write(
+            scripts.join("lib/declarations.lua"),
+            "require(\"themes.dark\") -- require 'unused'\n",
+        )
+        .unwrap();
+        fs::create_dir_all(scripts.join("lib/themes")).unwrap();
+        fs::write(scripts.join("lib/themes/dark.lua"), "local x = 1\n").unwrap();
+        fs::write(locations.keymap_dirs[1].join("fx.keymap"), "[mappings]\n").unwrap();
+
+        let profile_file = locations.profile_dirs[1].join("fx.profile");
+
+        fs::write(
+            &profile_file,
+            "id = '5dc62fa6-e965-45cb-a0da-e87d29713080'
+name = 'FX'
+description = 'FX'
+active_scripts = ['fx.lua']
+keymap = 'fx.keymap'
+
+[[config.FX]]
+type = 'string'
+name = 'gradient'
+value = 'sunset'
+default = 'rainbow'
+",
+        )
+        .unwrap();
+
+        profile_file
+    }
+
+    #[test]
+    fn required_modules_are_found() {
+        let source = "require \"a\"\nrequire('b.c')\nlocal s = pcall(require, name)\nmy_require \"d\"\n-- require \"e\"\n";
+
+        assert_eq!(required_modules(source), vec!["a", "b.c"]);
+    }
+
+    #[test]
+    fn export_and_import() -> Result<()> {
+        let dir = TestDir::new();
+        let source = dir.locations("source");
+        let target = dir.locations("target");
+        let bundle_file = dir.0.join(format!("fx.{}", BUNDLE_EXTENSION));
+
+        let profile_file = setup_source(&source);
+        let manifest = export(&profile_file, &source, &color_schemes(), &bundle_file)?;
+
+        let paths = manifest
+            .files
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            paths,
+            vec![
+                "color-schemes.toml",
+                "keymaps/fx.keymap",
+                "profiles/fx.profile",
+                "scripts/fx.lua",
+                "scripts/fx.lua.manifest",
+                "scripts/lib/declarations.lua",
+                "scripts/lib/themes/dark.lua",
+            ]
+        );
+
+        let bundle = fs::read(&bundle_file)?;
+        let imported = import(&bundle, &target, &HashMap::new(), false)?;
+
+        assert_eq!(
+            imported.profile_file,
+            target.import_dir.join("profiles/fx.profile")
+        );
+        assert_eq!(imported.installed_files.len(), 6);
+        assert!(imported.color_schemes.contains_key("sunset"));
+        assert!(target
+            .import_dir
+            .join("scripts/lib/themes/dark.lua")
+            .is_file());
+
+        // the staging directory has been removed
+        assert_eq!(fs::read_dir(&target.import_dir)?.count(), 3);
+
+        let profile = Profile::from(&imported.profile_file)?;
+        assert_eq!(profile.keymap, Some("fx.keymap".to_string()));
+
+        // importing the same bundle again does not change anything
+        let imported = import(&bundle, &target, &color_schemes(), false)?;
+        assert!(imported.installed_files.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn conflicts_are_detected() -> Result<()> {
+        let dir = TestDir::new();
+        let source = dir.locations("source");
+        let target = dir.locations("target");
+        let bundle_file = dir.0.join(format!("fx.{}", BUNDLE_EXTENSION));
+
+        let profile_file = setup_source(&source);
+        export(&profile_file, &source, &color_schemes(), &bundle_file)?;
+
+        // an installed profile with the same UUID, under a different name
+        let installed_profile = target.profile_dirs[1].join("other.profile");
+        fs::write(
+            &installed_profile,
+            fs::read_to_string(&profile_file)?.replace("name = 'FX'", "name = 'Other'"),
+        )?;
+        fs::write(target.script_dirs[1].join("fx.lua"), "-- modified\n")?;
+
+        let bundle = fs::read(&bundle_file)?;
+        let err = import(&bundle, &target, &HashMap::new(), false).unwrap_err();
+
+        match err.downcast_ref::<BundleError>() {
+            Some(BundleError::Conflict { conflicts }) => {
+                assert_eq!(conflicts.len(), 2);
+            }
+
+            _ => panic!("Unexpected error: {}", err),
+        }
+
+        assert!(!target.import_dir.exists());
+
+        // the conflicting files are shadowed by the imported ones, but are never modified
+        let imported = import(&bundle, &target, &HashMap::new(), true)?;
+
+        assert_eq!(
+            imported.profile_file,
+            target.import_dir.join("profiles/fx.profile")
+        );
+        assert_eq!(
+            fs::read_to_string(target.import_dir.join("scripts/fx.lua"))?,
+            "require \"declarations\"\n"
+        );
+        assert_eq!(
+            fs::read_to_string(target.script_dirs[1].join("fx.lua"))?,
+            "-- modified\n"
+        );
+        assert!(fs::read_to_string(&installed_profile)?.contains("name = 'Other'"));
+
+        // an imported profile keeps its file name when it is replaced
+        let imported_profile = target.import_dir.join("profiles/other.profile");
+        fs::rename(&imported.profile_file, &imported_profile)?;
+        fs::write(target.import_dir.join("scripts/fx.lua"), "-- modified\n")?;
+
+        let imported = import(&bundle, &target, &HashMap::new(), true)?;
+
+        assert_eq!(imported.profile_file, imported_profile);
+        assert_eq!(
+            fs::read_to_string(target.import_dir.join("scripts/fx.lua"))?,
+            "require \"declarations\"\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn failed_import_installs_nothing() -> Result<()> {
+        let dir = TestDir::new();
+        let source = dir.locations("source");
+        let target = dir.locations("target");
+        let bundle_file = dir.0.join(format!("fx.{}", BUNDLE_EXTENSION));
+
+        let profile_file = setup_source(&source);
+        export(&profile_file, &source, &color_schemes(), &bundle_file)?;
+
+        // the libraries can not be written
+        fs::create_dir_all(target.import_dir.join("scripts"))?;
+        fs::write(target.import_dir.join("scripts/lib"), "")?;
+
+        assert!(import(&fs::read(&bundle_file)?, &target, &HashMap::new(), false).is_err());
+
+        assert!(!target.import_dir.join("keymaps/fx.keymap").exists());
+        assert!(!target.import_dir.join("scripts/fx.lua").exists());
+        assert!(!target.import_dir.join("profiles/fx.profile").exists());
+        assert_eq!(fs::read_dir(&target.import_dir)?.count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn tampered_bundle_is_rejected() -> Result<()> {
+        let dir = TestDir::new();
+        let source = dir.locations("source");
+        let bundle_file = dir.0.join(format!("fx.{}", BUNDLE_EXTENSION));
+
+        let profile_file = setup_source(&source);
+        let manifest = export(&profile_file, &source, &HashMap::new(), &bundle_file)?;
+
+        let (_, mut files) = read(&bundle_file)?;
+        files.insert("scripts/fx.lua".to_string(), b"-- tampered\n".to_vec());
+        write_archive(&bundle_file, &manifest, &files)?;
+
+        let err = read(&bundle_file).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BundleError>(),
+            Some(BundleError::ChecksumMismatch { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_color_scheme_names_are_rejected() -> Result<()> {
+        let dir = TestDir::new();
+        let source = dir.locations("source");
+        let target = dir.locations("target");
+        let bundle_file = dir.0.join(format!("fx.{}", BUNDLE_EXTENSION));
+
+        let profile_file = setup_source(&source);
+        let mut manifest = export(&profile_file, &source, &color_schemes(), &bundle_file)?;
+
+        // a valid bundle, that registers a color scheme which can not be referred to by scripts
+        let (_, mut files) = read(&bundle_file)?;
+        let data = String::from_utf8_lossy(&files[COLOR_SCHEMES_FILE]).replace("sunset", "1sunset");
+
+        for entry in manifest.files.iter_mut() {
+            if entry.path == COLOR_SCHEMES_FILE {
+                entry.sha256 = sha256(data.as_bytes());
+            }
+        }
+
+        files.insert(COLOR_SCHEMES_FILE.to_string(), data.into_bytes());
+        write_archive(&bundle_file, &manifest, &files)?;
+
+        let err = import(&fs::read(&bundle_file)?, &target, &HashMap::new(), false).unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<BundleError>(),
+            Some(BundleError::InvalidBundle { msg }) if msg.contains("1sunset")
+        ));
+        assert!(!target.import_dir.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn unsafe_paths_are_rejected() {
+        assert!(is_safe_path(Path::new("lib/themes/dark.lua")));
+        assert!(!is_safe_path(Path::new("../../etc/passwd")));
+        assert!(!is_safe_path(Path::new("/etc/passwd")));
+        assert!(!is_safe_path(Path::new("lib/../../x.lua")));
+        assert!(!is_safe_path(Path::new("")));
+    }
+}
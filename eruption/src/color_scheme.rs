@@ -42,6 +42,14 @@ pub enum ColorSchemeError {
     InvalidIndex { description: String },
 }
 
+/// Returns `true` if `name` is a valid identifier for a color scheme
+pub fn is_valid_name(name: &str) -> bool {
+    !name.chars().take(1).all(char::is_numeric)
+        && name
+            .chars()
+            .all(|c| c == '_' || char::is_ascii_alphanumeric(&c))
+}
+
 pub trait ColorSchemeExt {
     fn num_colors(&self) -> usize;
     fn color_rgba_at(&self, index: usize) -> Result<Color>;
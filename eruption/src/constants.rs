@@ -31,6 +31,52 @@ pub const DEFAULT_SCRIPT_DIR: &str = "/usr/share/eruption/scripts/";
 /// Default script directory
 pub const DEFAULT_KEYMAP_DIR: &str = "/usr/share/eruption/scripts/lib/keymaps";
 
+/// Directory that profile bundles are imported to, its subdirectories are searched ahead of the
+/// directories of the installed packages
+pub const IMPORT_DIR: &str = "/var/lib/eruption/imported/";
+
+/// Directory of the profiles that have been imported from bundles
+pub const IMPORTED_PROFILE_DIR: &str = "/var/lib/eruption/imported/profiles/";
+
+/// Directory of the scripts that have been imported from bundles
+pub const IMPORTED_SCRIPT_DIR: &str = "/var/lib/eruption/imported/scripts/";
+
+/// Directory of the keymaps that have been imported from bundles
+pub const IMPORTED_KEYMAP_DIR: &str = "/var/lib/eruption/imported/keymaps/";
+
+/// Default keymap, relative to the keymap directory
+pub const DEFAULT_KEYMAP: &str = "default.keymap";
+
+/// Directory of the xkeyboard-config data files, used to resolve keycap symbols
+pub const XKB_DATA_DIR: &str = "/usr/share/X11/xkb";
+
+/// Default threshold that distinguishes a tap from a hold, in milliseconds
+pub const DEFAULT_TAP_HOLD_THRESHOLD_MILLIS: u64 = 200;
+
+/// Default maximum time between the taps of a double or triple tap, in milliseconds
+pub const DEFAULT_MULTI_TAP_TIMEOUT_MILLIS: u64 = 250;
+
+/// Default maximum time between the keys of a chord, in milliseconds
+pub const DEFAULT_CHORD_TIMEOUT_MILLIS: u64 = 50;
+
+/// Default maximum time between the keys of a sequence, in milliseconds
+pub const DEFAULT_SEQUENCE_TIMEOUT_MILLIS: u64 = 1000;
+
+/// Default distance the mouse has to be moved to perform a gesture, in device units
+pub const DEFAULT_GESTURE_DISTANCE: u32 = 100;
+
+/// Default maximum time between the wheel events of a single wheel tilt, in milliseconds
+pub const DEFAULT_WHEEL_TILT_TIMEOUT_MILLIS: u64 = 300;
+
+/// Default number of wheel events within the window of a fast wheel source
+pub const DEFAULT_FAST_WHEEL_NOTCHES: usize = 3;
+
+/// Default time window of a fast wheel source, in milliseconds
+pub const DEFAULT_FAST_WHEEL_WINDOW_MILLIS: u64 = 150;
+
+/// Maximum number of events of a recorded macro
+pub const MAX_RECORDED_MACRO_EVENTS: usize = 4096;
+
 /// The `/run/eruption/` directory
 pub const RUN_ERUPTION_DIR: &str = "/run/eruption/";
 
@@ -52,6 +98,12 @@ pub const UNIT_NAME_AUDIO_PROXY: &str = "eruption-audio-proxy.service";
 /// Eruption daemon control UNIX domain socket (SDK support)
 pub const CONTROL_SOCKET_NAME: &str = "/run/eruption/control.sock";
 
+/// Maximum number of spectrum bands that are published to SDK clients
+pub const SDK_AUDIO_MAX_BANDS: u32 = 256;
+
+/// Maximum number of audio analysis updates per second that are published to SDK clients
+pub const SDK_AUDIO_MAX_UPDATE_RATE: u32 = 60;
+
 /// Eruption daemon audio data UNIX domain socket
 pub const AUDIO_SOCKET_NAME: &str = "/run/eruption/audio.sock";
 
@@ -76,8 +128,8 @@ pub const PROCESS_SPAWN_WAIT_MILLIS: u64 = 800;
 /// Target frames per second
 pub const TARGET_FPS: u64 = 24;
 
-/// Fade in on profile switch for n frames
-pub const FADE_FRAMES: u64 = 32;
+/// Default duration of the transition between two profiles, in milliseconds
+pub const PROFILE_TRANSITION_MILLIS: u64 = 500;
 
 /// The number of "pixels" on the canvas
 pub const CANVAS_SIZE: usize = 144 + 36;
@@ -89,11 +141,44 @@ pub const CANVAS_WIDTH: usize = 22 + 8;
 pub const CANVAS_HEIGHT: usize = 6;
 
 /// The capacity of the buffer used for receiving audio samples
-pub const NET_BUFFER_CAPACITY: usize = 4096;
+pub const NET_BUFFER_CAPACITY: usize = 8192;
+
+/// Version of the protocol spoken with the audio proxy
+pub const AUDIO_PROXY_PROTOCOL_VERSION: u32 = 1;
+
+/// Time to wait for the handshake of a newly connected audio proxy, in milliseconds
+pub const AUDIO_PROXY_HANDSHAKE_TIMEOUT_MILLIS: u64 = 2000;
+
+/// The audio proxy is disconnected if it has not sent anything for this long, in milliseconds
+pub const AUDIO_PROXY_TIMEOUT_MILLIS: u64 = 5000;
+
+/// The sample rate of the audio data sent by the audio proxy
+pub const AUDIO_SAMPLE_RATE: u32 = 44100;
+
+/// The number of interleaved channels in the audio data sent by the audio proxy
+pub const AUDIO_CHANNELS: usize = 2;
+
+/// The number of bits per sample in the audio data sent by the audio proxy
+pub const AUDIO_BITS_PER_SAMPLE: u32 = 16;
+
+/// Default crossover frequency between the bass and the mid band, in Hz
+pub const DEFAULT_AUDIO_CROSSOVER_LOW_HZ: f32 = 250.0;
+
+/// Default crossover frequency between the mid and the treble band, in Hz
+pub const DEFAULT_AUDIO_CROSSOVER_HIGH_HZ: f32 = 4000.0;
+
+/// Slowest tempo that will be considered by the BPM estimator
+pub const MIN_AUDIO_BPM: f32 = 60.0;
+
+/// Fastest tempo that will be considered by the BPM estimator
+pub const MAX_AUDIO_BPM: f32 = 200.0;
 
 /// Timeout for waiting on condition variables of Lua upcalls
 pub const TIMEOUT_CONDITION_MILLIS: u64 = 100;
 
+/// Maximum time to wait for the injected keys to be released on shutdown, in milliseconds
+pub const RELEASE_HELD_KEYS_TIMEOUT_MILLIS: u64 = 250;
+
 /// Max number of events that will be processed in each iteration of the main loop
 pub const MAX_EVENTS_PER_ITERATION: u64 = 128;
 
@@ -24,13 +24,17 @@ use dbus_tree::{
 };
 use flume::Sender;
 use log::*;
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
-use crate::{color_scheme::ColorScheme, scripting::manifest::ParseConfig};
+use crate::{bundle, hwdevices, profiles};
+use crate::{
+    color_scheme::{self, ColorScheme},
+    scripting::manifest::ParseConfig,
+};
 use crate::{constants, plugins};
-use crate::{hwdevices, profiles};
 use crate::{plugins::audio, scripting::manifest};
 use crate::{profiles::FindConfig, script};
 
@@ -391,6 +395,29 @@ impl DbusApi {
                                 ), _>(
                                     "values"
                                 ),
+                            )
+                            .add_m(
+                                f.method("GetAudioProxyStatus", (), move |m| {
+                                    if perms::has_monitor_permission_cached(
+                                        &m.msg.sender().unwrap(),
+                                    )
+                                    .unwrap_or(false)
+                                    {
+                                        let (connected, protocol_version, counters) =
+                                            plugins::AudioPlugin::get_audio_proxy_status();
+
+                                        Ok(vec![m.msg.method_return().append3(
+                                            connected,
+                                            protocol_version,
+                                            counters,
+                                        )])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .outarg::<bool, _>("connected")
+                                .outarg::<u32, _>("protocol_version")
+                                .outarg::<HashMap<String, u64>, _>("counters"),
                             ),
                     ),
             )
@@ -649,11 +676,7 @@ impl DbusApi {
                                     {
                                         let (name, data): (String, Vec<u8>) = m.msg.read2()?;
 
-                                        if name.chars().take(1).all(char::is_numeric)
-                                            || !name.chars().all(|c| {
-                                                c == '_' || char::is_ascii_alphanumeric(&c)
-                                            })
-                                        {
+                                        if !color_scheme::is_valid_name(&name) {
                                             Err(MethodErr::failed("Invalid identifier name"))
                                         } else {
                                             let mut color_schemes =
@@ -953,6 +976,168 @@ impl DbusApi {
                                 .inarg::<&str, _>("param_name")
                                 .inarg::<&str, _>("value")
                                 .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("ImportBundle", (), move |m| {
+                                    if perms::has_manage_permission_cached(&m.msg.sender().unwrap())
+                                        .unwrap_or(false)
+                                    {
+                                        let (bundle, overwrite): (Vec<u8>, bool) = m.msg.read2()?;
+
+                                        let imported =
+                                            import_bundle(&bundle, overwrite).map_err(|e| {
+                                                MethodErr::failed(&format!(
+                                                    "Could not import bundle: {}",
+                                                    e
+                                                ))
+                                            })?;
+
+                                        let s = imported.profile_file.to_string_lossy().to_string();
+                                        Ok(vec![m.msg.method_return().append1(s)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .inarg::<Vec<u8>, _>("bundle")
+                                .inarg::<bool, _>("overwrite")
+                                .outarg::<String, _>("profile_file"),
+                            ),
+                    ),
+            )
+            .add(
+                f.object_path("/org/eruption/keymap", ())
+                    .introspectable()
+                    .add(
+                        f.interface("org.eruption.Keymap", ())
+                            .add_m(
+                                f.method("StartRecording", (), move |m| {
+                                    if perms::has_manage_permission_cached(&m.msg.sender().unwrap())
+                                        .unwrap_or(false)
+                                    {
+                                        plugins::KeymapsPlugin::start_recording();
+
+                                        Ok(vec![m.msg.method_return().append1(true)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("StopRecording", (), move |m| {
+                                    if perms::has_manage_permission_cached(&m.msg.sender().unwrap())
+                                        .unwrap_or(false)
+                                    {
+                                        let steps = plugins::KeymapsPlugin::stop_recording()
+                                            .ok_or_else(|| {
+                                                MethodErr::failed("No recording in progress")
+                                            })?;
+
+                                        let result = serde_json::to_string(&steps)
+                                            .map_err(|e| MethodErr::failed(&format!("{}", e)))?;
+
+                                        Ok(vec![m.msg.method_return().append1(result)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .outarg::<String, _>("macro"),
+                            )
+                            .add_m(
+                                f.method("SetApplicationKeymap", (), move |m| {
+                                    if perms::has_settings_permission_cached(
+                                        &m.msg.sender().unwrap(),
+                                    )
+                                    .unwrap_or(false)
+                                    {
+                                        let keymap: &str = m.msg.read1()?;
+
+                                        plugins::KeymapsPlugin::set_application_keymap(Some(
+                                            keymap.to_string(),
+                                        ))
+                                        .map_err(|e| MethodErr::failed(&format!("{}", e)))?;
+
+                                        Ok(vec![m.msg.method_return().append1(true)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .inarg::<&str, _>("keymap")
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("GetApplicationKeymap", (), move |m| {
+                                    if perms::has_monitor_permission_cached(
+                                        &m.msg.sender().unwrap(),
+                                    )
+                                    .unwrap_or(false)
+                                    {
+                                        let keymap = plugins::keymaps::APPLICATION_KEYMAP
+                                            .read()
+                                            .clone()
+                                            .unwrap_or_default();
+
+                                        Ok(vec![m.msg.method_return().append1(keymap)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .outarg::<String, _>("keymap"),
+                            )
+                            .add_m(
+                                f.method("GetActiveKeymap", (), move |m| {
+                                    if perms::has_monitor_permission_cached(
+                                        &m.msg.sender().unwrap(),
+                                    )
+                                    .unwrap_or(false)
+                                    {
+                                        let (keymap, selector) =
+                                            match &*plugins::keymaps::ACTIVE_KEYMAP_NAME.read() {
+                                                Some((keymap, selector)) => {
+                                                    (keymap.clone(), selector.to_string())
+                                                }
+
+                                                None => (String::new(), String::new()),
+                                            };
+
+                                        Ok(vec![m.msg.method_return().append2(keymap, selector)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .outarg::<String, _>("keymap")
+                                .outarg::<String, _>("selector"),
+                            )
+                            .add_m(
+                                f.method("GetHeldKeys", (), move |m| {
+                                    if perms::has_monitor_permission_cached(
+                                        &m.msg.sender().unwrap(),
+                                    )
+                                    .unwrap_or(false)
+                                    {
+                                        let (keys, buttons) = plugins::MacrosPlugin::held_keys();
+
+                                        Ok(vec![m.msg.method_return().append2(keys, buttons)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .outarg::<Vec<u32>, _>("keys")
+                                .outarg::<Vec<u32>, _>("buttons"),
+                            )
+                            .add_m(
+                                f.method("ReleaseHeldKeys", (), move |m| {
+                                    if perms::has_manage_permission_cached(&m.msg.sender().unwrap())
+                                        .unwrap_or(false)
+                                    {
+                                        plugins::MacrosPlugin::release_held_keys();
+
+                                        Ok(vec![m.msg.method_return().append1(true)])
+                                    } else {
+                                        Err(MethodErr::failed("Authentication failed"))
+                                    }
+                                })
+                                .outarg::<bool, _>("status"),
                             ),
                     ),
             );
@@ -1157,6 +1342,43 @@ pub fn initialize(dbus_tx: Sender<Message>) -> Result<DbusApi> {
     DbusApi::new(dbus_tx)
 }
 
+/// Install a profile bundle, and register the color schemes that it contains
+fn import_bundle(bundle: &[u8], overwrite: bool) -> Result<bundle::ImportedBundle> {
+    let locations = bundle::Locations {
+        profile_dirs: profiles::get_profile_dirs(),
+        script_dirs: crate::util::get_script_dirs(),
+        keymap_dirs: vec![
+            PathBuf::from(constants::IMPORTED_KEYMAP_DIR),
+            PathBuf::from(constants::DEFAULT_KEYMAP_DIR),
+        ],
+        import_dir: PathBuf::from(constants::IMPORT_DIR),
+    };
+
+    let imported = bundle::import(
+        bundle,
+        &locations,
+        &crate::NAMED_COLOR_SCHEMES.read(),
+        overwrite,
+    )?;
+
+    info!(
+        "Imported the profile bundle {} ({}): {}",
+        imported.manifest.profile_name,
+        imported.manifest.profile_id,
+        imported.profile_file.display()
+    );
+
+    if !imported.color_schemes.is_empty() {
+        crate::NAMED_COLOR_SCHEMES
+            .write()
+            .extend(imported.color_schemes.clone());
+
+        crate::REQUEST_PROFILE_RELOAD.store(true, Ordering::SeqCst);
+    }
+
+    Ok(imported)
+}
+
 fn apply_parameter(
     profile_file: &str,
     script_file: &str,
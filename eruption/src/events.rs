@@ -17,6 +17,9 @@
     Copyright (c) 2019-2022, The Eruption Development Team
 */
 
+use crate::keymaps::mapping::MacroEvent;
+use crate::keymaps::Input;
+use crate::plugins::{KeymapsPlugin, MacrosPlugin};
 use crate::{
     constants, dbus_interface, events, macros, script, switch_profile, DbusApiEvent,
     FileSystemEvent, KeyboardDevice, KeyboardHidEvent, MouseDevice, MouseHidEvent, ACTIVE_SLOT,
@@ -58,6 +61,14 @@ pub enum Event {
     MouseButtonUp(u8),
     MouseMove(u8, i32),
     MouseWheelEvent(u8),
+
+    ProfileSwitched,
+    SlotSwitched(usize),
+
+    DeviceAdded,
+    DeviceRemoved,
+
+    MacroFired,
 }
 
 pub type Callback = dyn Fn(&Event) -> Result<bool> + Sync + Send + 'static;
@@ -111,6 +122,15 @@ pub fn process_filesystem_event(
             // TODO: maybe make this more fine grained
             REQUEST_PROFILE_RELOAD.store(true, Ordering::SeqCst);
         }
+
+        FileSystemEvent::KeymapChanged => {
+            events::notify_observers(events::Event::FileSystemEvent(fsevent.clone()))
+                .unwrap_or_else(|e| error!("Error during notification of observers: {}", e));
+
+            // keymaps are executed natively, so there is no need to reload the Lua VMs
+            KeymapsPlugin::load_keymap()
+                .unwrap_or_else(|e| error!("Could not reload the keymap: {}", e));
+        }
     }
 
     Ok(())
@@ -183,7 +203,10 @@ pub fn process_keyboard_hid_events(keyboard_device: &KeyboardDevice) -> Result<(
     let mut event_processed = false;
 
     'HID_EVENTS_LOOP: loop {
-        match keyboard_device.read().get_next_event_timeout(0) {
+        // do not hold the lock on the device while the event is being processed
+        let event = keyboard_device.read().get_next_event_timeout(0);
+
+        match event {
             Ok(result) if result != KeyboardHidEvent::Unknown => {
                 event_processed = true;
 
@@ -362,7 +385,10 @@ pub fn process_mouse_hid_events(mouse_device: &MouseDevice) -> Result<()> {
     let mut event_processed = false;
 
     'HID_EVENTS_LOOP: loop {
-        match mouse_device.read().get_next_event_timeout(0) {
+        // do not hold the lock on the device while the event is being processed
+        let event = mouse_device.read().get_next_event_timeout(0);
+
+        match event {
             Ok(result) if result != MouseHidEvent::Unknown => {
                 event_processed = true;
 
@@ -411,8 +437,22 @@ pub fn process_mouse_hid_events(mouse_device: &MouseDevice) -> Result<()> {
                     }
                 }
 
-                //     _ => { /* ignore other events */ }
-                // }
+                // execute mappings of the active keymap; HID events can not be consumed
+                match result {
+                    MouseHidEvent::ButtonDown(index) => {
+                        KeymapsPlugin::process_input(Input::MouseHidButtonDown(index));
+                    }
+
+                    MouseHidEvent::ButtonUp(index) => {
+                        KeymapsPlugin::process_input(Input::MouseHidButtonUp(index));
+                    }
+
+                    MouseHidEvent::DpiChange(slot) => {
+                        KeymapsPlugin::process_input(Input::MouseDpi(slot));
+                    }
+
+                    _ => { /* ignore other events */ }
+                }
             }
 
             Ok(_) => { /* Ignore unknown events */ }
@@ -528,6 +568,25 @@ pub fn process_mouse_event(
                     }*/
                 }
 
+                // mouse gestures of the active keymap
+                let input = match direction {
+                    1 => Some(Input::MouseMove {
+                        dx: raw_event.value,
+                        dy: 0,
+                    }),
+                    2 => Some(Input::MouseMove {
+                        dx: 0,
+                        dy: raw_event.value,
+                    }),
+                    _ => None,
+                };
+
+                if let Some(input) = input {
+                    if KeymapsPlugin::is_mapped(input) {
+                        KeymapsPlugin::process_input(input);
+                    }
+                }
+
                 events::notify_observers(events::Event::MouseMove(direction, raw_event.value))
                     .unwrap_or_else(|e| {
                         error!(
@@ -564,6 +623,24 @@ pub fn process_mouse_event(
                     direction = 5;
                 }
 
+                // high resolution wheel events accompany the regular ones, so they
+                // are only dropped, without executing the mapping a second time
+                let consumed = if *code == evdev_rs::enums::EV_REL::REL_WHEEL_HI_RES
+                    || *code == evdev_rs::enums::EV_REL::REL_HWHEEL_HI_RES
+                {
+                    KeymapsPlugin::is_mapped(Input::MouseWheel(direction))
+                } else {
+                    KeymapsPlugin::record(MacroEvent::MouseWheel {
+                        direction: direction as u32,
+                    });
+
+                    KeymapsPlugin::process_input(Input::MouseWheel(direction))
+                };
+
+                if consumed {
+                    macros::DROP_CURRENT_MOUSE_INPUT.store(true, Ordering::SeqCst);
+                }
+
                 *UPCALL_COMPLETED_ON_MOUSE_EVENT.0.lock() =
                     LUA_TXS.read().len() - FAILED_TXS.read().len();
 
@@ -615,6 +692,26 @@ pub fn process_mouse_event(
 
         let is_pressed = raw_event.value > 0;
         let index = mouse_device.read().ev_key_to_button_index(code).unwrap();
+        let button = MacrosPlugin::ev_key_to_button_index(code).ok();
+
+        if let Some(button) = button {
+            KeymapsPlugin::record(MacroEvent::MouseButton {
+                button,
+                down: is_pressed,
+            });
+        }
+
+        let button = button.unwrap_or_default();
+
+        let input = if is_pressed {
+            Input::MouseButtonDown { index, button }
+        } else {
+            Input::MouseButtonUp { index, button }
+        };
+
+        if KeymapsPlugin::process_input(input) {
+            macros::DROP_CURRENT_MOUSE_INPUT.store(true, Ordering::SeqCst);
+        }
 
         if is_pressed {
             *UPCALL_COMPLETED_ON_MOUSE_BUTTON_DOWN.0.lock() =
@@ -855,6 +952,32 @@ pub fn process_keyboard_event(
 
         trace!("Key index: {:#x}", index);
 
+        // execute mappings of the active keymap, before any Lua VM sees the event;
+        // auto-repeated events of a mapped key are dropped
+        let code = *code as u32;
+
+        if raw_event.value != 2 {
+            KeymapsPlugin::record(MacroEvent::Key {
+                code,
+                down: is_pressed,
+            });
+        }
+
+        let consumed = if raw_event.value == 2 {
+            KeymapsPlugin::is_mapped(Input::KeyDown { index, code })
+        } else if is_pressed {
+            KeymapsPlugin::process_input(Input::KeyDown { index, code })
+        } else {
+            KeymapsPlugin::process_input(Input::KeyUp { index, code })
+        };
+
+        // the panic hotkey releases all keys that are stuck in the pressed state
+        let panic = raw_event.value != 2 && MacrosPlugin::process_panic_hotkey(code, is_pressed);
+
+        if consumed || panic {
+            macros::DROP_CURRENT_KEY.store(true, Ordering::SeqCst);
+        }
+
         if is_pressed {
             *UPCALL_COMPLETED_ON_KEY_DOWN.0.lock() = LUA_TXS.read().len() - FAILED_TXS.read().len();
 
@@ -0,0 +1,1693 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+//! Executes the mappings of a keymap table on the input path of the daemon
+//!
+//! The engine itself does not perform any I/O, it translates input events into a list of
+//! [`Output`]s, that are then executed by the keymaps plugin
+
+use std::collections::{BTreeSet, HashMap};
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use super::mapping::{Action, Adjustment, Event, KeyMappingTable, MacroEvent};
+use super::mouse::MouseSources;
+use super::timed::{Effect, TimedSources};
+
+/// An input event, as seen by the keymap engine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    /// A key has been pressed; `code` is the evdev key code, that is used to
+    /// replay keys that have been held back
+    KeyDown {
+        index: u8,
+        code: u32,
+    },
+    KeyUp {
+        index: u8,
+        code: u32,
+    },
+
+    /// A mouse button has been pressed; `button` is the button of the virtual mouse,
+    /// that is used to replay buttons that have been held back
+    MouseButtonDown {
+        index: u8,
+        button: u32,
+    },
+    MouseButtonUp {
+        index: u8,
+        button: u32,
+    },
+
+    MouseHidButtonDown(u8),
+    MouseHidButtonUp(u8),
+
+    MouseWheel(u8),
+    MouseDpi(u8),
+
+    /// Relative motion of the mouse
+    MouseMove {
+        dx: i32,
+        dy: i32,
+    },
+}
+
+/// An action that shall be performed as the result of an input event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    /// Inject a key event on the virtual keyboard
+    InjectKey {
+        code: u32,
+        down: bool,
+    },
+
+    /// Inject a button event on the virtual mouse
+    InjectMouseButton {
+        button: u32,
+        down: bool,
+    },
+
+    /// Inject a mouse wheel event on the virtual mouse
+    InjectMouseWheel {
+        direction: u32,
+    },
+
+    /// Call a Lua macro function
+    Call {
+        function_name: String,
+        down: bool,
+    },
+
+    /// Pause before executing the following outputs
+    Sleep {
+        millis: u64,
+    },
+
+    /// Type a string on the virtual keyboard
+    TypeText(String),
+
+    /// Switch to a slot (1-4)
+    SwitchSlot(usize),
+    SwitchProfile(String),
+
+    /// Change the global brightness
+    Brightness(Adjustment),
+
+    /// Change the DPI setting of the mouse
+    Dpi(Adjustment),
+}
+
+/// The result of processing a single input event
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    /// The original input event shall not be mirrored to the virtual device
+    pub consumed: bool,
+
+    pub outputs: Vec<Output>,
+}
+
+/// The physical key, button or direction an input event refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Trigger {
+    Key(usize),
+    MouseButton(usize),
+    MouseHidButton(usize),
+    MouseWheel(u8),
+    MouseDpi(u8),
+    MouseMove,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edge {
+    Down,
+    Up,
+}
+
+#[derive(Debug, Clone)]
+struct Binding {
+    easy_shift: bool,
+    layers: BTreeSet<usize>,
+    edge: Edge,
+    action: Action,
+}
+
+#[derive(Debug)]
+pub struct KeymapEngine {
+    table: KeyMappingTable,
+
+    bindings: HashMap<Trigger, Vec<Binding>>,
+
+    /// The bindings that were selected when a key or button has been pressed. The release
+    /// is always handled by the same bindings, even if the Easy Shift+ state changed
+    /// in the meantime, so that no injected key is left in the pressed state
+    pressed: HashMap<Trigger, Vec<Binding>>,
+
+    /// Tap/hold, multi-tap, chord and sequence sources
+    timed: TimedSources,
+
+    /// Mouse gesture, wheel tilt and fast wheel sources
+    mouse: MouseSources,
+
+    /// Layer that has been toggled on by a keymap action
+    toggled_layer: Option<usize>,
+
+    /// Layers that are held down, the most recent one is in effect
+    momentary_layers: Vec<usize>,
+
+    /// Layer that is in effect for the next key press only
+    one_shot_layer: Option<usize>,
+
+    /// Actions that are repeated while their source is held down, along with
+    /// their interval and the time of the next repetition
+    repeating: Vec<(Action, Duration, Instant)>,
+
+    /// The time of the input event that is currently being processed
+    now: Instant,
+}
+
+impl KeymapEngine {
+    pub fn new(table: KeyMappingTable) -> Self {
+        let mut bindings: HashMap<Trigger, Vec<Binding>> = HashMap::new();
+        let mut timed = TimedSources::default();
+        let mut mouse = MouseSources::default();
+
+        for (source, rule) in table.mappings().iter() {
+            if !rule.enabled || rule.action == Action::Null {
+                continue;
+            }
+
+            let action = rule.action.clone();
+
+            let (trigger, easy_shift, edge) = match &source.event {
+                Event::SimpleKeyDown(key) => (Trigger::Key(key.key_index), false, Edge::Down),
+                Event::SimpleKeyUp(key) => (Trigger::Key(key.key_index), false, Edge::Up),
+                Event::SimpleMouseDown(key) => {
+                    (Trigger::MouseButton(key.key_index), false, Edge::Down)
+                }
+                Event::SimpleMouseUp(key) => (Trigger::MouseButton(key.key_index), false, Edge::Up),
+                Event::SimpleMouseWheel(direction) => {
+                    (Trigger::MouseWheel(direction.as_int()), false, Edge::Down)
+                }
+                Event::SimpleMouseDpi(direction) => {
+                    (Trigger::MouseDpi(direction.as_int()), false, Edge::Down)
+                }
+
+                Event::EasyShiftKeyDown(key) => (Trigger::Key(key.key_index), true, Edge::Down),
+                Event::EasyShiftKeyUp(key) => (Trigger::Key(key.key_index), true, Edge::Up),
+                Event::EasyShiftMouseDown(key) => {
+                    (Trigger::MouseButton(key.key_index), true, Edge::Down)
+                }
+                Event::EasyShiftMouseUp(key) => {
+                    (Trigger::MouseButton(key.key_index), true, Edge::Up)
+                }
+                Event::EasyShiftMouseWheel(direction) => {
+                    (Trigger::MouseWheel(direction.as_int()), true, Edge::Down)
+                }
+                Event::EasyShiftMouseDpi(direction) => {
+                    (Trigger::MouseDpi(direction.as_int()), true, Edge::Down)
+                }
+
+                Event::HidMouseDown(key) => {
+                    (Trigger::MouseHidButton(key.key_index), false, Edge::Down)
+                }
+                Event::HidMouseUp(key) => (Trigger::MouseHidButton(key.key_index), false, Edge::Up),
+
+                Event::HidKeyDown(_) | Event::HidKeyUp(_) => {
+                    warn!("Keymap: HID key events are not supported: {:?}", source);
+                    continue;
+                }
+
+                Event::KeyTap(tap) => {
+                    let threshold = Duration::from_millis(tap.threshold_millis);
+                    timed.add_tap(tap.key.key_index, threshold, action);
+                    continue;
+                }
+
+                Event::KeyHold(hold) => {
+                    let threshold = Duration::from_millis(hold.threshold_millis);
+                    timed.add_hold(hold.key.key_index, threshold, action);
+                    continue;
+                }
+
+                Event::KeyMultiTap(taps) => {
+                    let timeout = Duration::from_millis(taps.timeout_millis);
+                    timed.add_multi_tap(taps.key.key_index, taps.count, timeout, action);
+                    continue;
+                }
+
+                Event::KeyChord(chord) => {
+                    let keys = chord.keys.iter().map(|key| key.key_index).collect();
+                    let timeout = Duration::from_millis(chord.timeout_millis);
+                    timed.add_chord(keys, timeout, action);
+                    continue;
+                }
+
+                Event::KeySequence(sequence) => {
+                    let keys = sequence.keys.iter().map(|key| key.key_index).collect();
+                    let timeout = Duration::from_millis(sequence.timeout_millis);
+                    timed.add_sequence(keys, timeout, action);
+                    continue;
+                }
+
+                Event::MouseGesture(gesture) => {
+                    mouse.add_gesture(
+                        gesture.button.key_index,
+                        gesture.direction.as_int(),
+                        gesture.distance,
+                        action,
+                    );
+                    continue;
+                }
+
+                Event::MouseWheelTilt(tilt) => {
+                    let timeout = Duration::from_millis(tilt.timeout_millis);
+                    mouse.add_tilt(tilt.direction.as_int(), timeout, action);
+                    continue;
+                }
+
+                Event::MouseWheelSpeed(speed) => {
+                    let window = Duration::from_millis(speed.window_millis);
+                    mouse.add_speed(speed.direction.as_int(), speed.notches, window, action);
+                    continue;
+                }
+
+                Event::Null => continue,
+            };
+
+            bindings.entry(trigger).or_default().push(Binding {
+                easy_shift,
+                layers: source.layers.0.clone(),
+                edge,
+                action,
+            });
+        }
+
+        Self {
+            table,
+            bindings,
+            pressed: HashMap::new(),
+            timed,
+            mouse,
+            toggled_layer: None,
+            momentary_layers: vec![],
+            one_shot_layer: None,
+            repeating: vec![],
+            now: Instant::now(),
+        }
+    }
+
+    pub fn table(&self) -> &KeyMappingTable {
+        &self.table
+    }
+
+    /// Returns true if the input event would be handled by a mapping
+    pub fn is_mapped(&self, input: Input, easy_shift_layer: Option<usize>) -> bool {
+        let (trigger, _) = Self::trigger(input);
+
+        match trigger {
+            Trigger::Key(index) if self.timed.consumes(index) => return true,
+            Trigger::MouseButton(index) if self.mouse.consumes_button(index) => return true,
+            Trigger::MouseWheel(direction) if self.mouse.consumes_wheel(direction) => return true,
+            Trigger::MouseMove => return self.mouse.tracking(),
+
+            _ => {}
+        }
+
+        self.pressed.contains_key(&trigger)
+            || !self
+                .select(trigger, self.active_layer(easy_shift_layer))
+                .is_empty()
+    }
+
+    /// Returns the layer that is currently in effect. Layers that have been activated by
+    /// a keymap action take precedence over the Easy Shift+ layer
+    pub fn active_layer(&self, easy_shift_layer: Option<usize>) -> Option<usize> {
+        self.action_layer().or(easy_shift_layer)
+    }
+
+    /// Returns the layer that has been activated by a keymap action, if any
+    pub fn action_layer(&self) -> Option<usize> {
+        self.momentary_layers
+            .last()
+            .copied()
+            .or(self.one_shot_layer)
+            .or(self.toggled_layer)
+    }
+
+    /// Returns the indices of the keys that are mapped on `layer`
+    pub fn layer_keys(&self, layer: usize) -> Vec<usize> {
+        let mut result: Vec<usize> = self
+            .bindings
+            .iter()
+            .filter_map(|(trigger, bindings)| match trigger {
+                Trigger::Key(index)
+                    if bindings
+                        .iter()
+                        .any(|b| b.easy_shift && b.layers.contains(&layer)) =>
+                {
+                    Some(*index)
+                }
+
+                _ => None,
+            })
+            .collect();
+
+        result.sort_unstable();
+
+        result
+    }
+
+    /// Process an input event that occurred at `now`. `easy_shift_layer` holds the active
+    /// Easy Shift+ layer, if Easy Shift+ is currently engaged
+    pub fn process(
+        &mut self,
+        input: Input,
+        easy_shift_layer: Option<usize>,
+        now: Instant,
+    ) -> Outcome {
+        let mut outcome = Outcome {
+            consumed: false,
+            outputs: self.poll(easy_shift_layer, now),
+        };
+
+        if let Input::KeyDown { index, code } | Input::KeyUp { index, code } = input {
+            let down = matches!(input, Input::KeyDown { .. });
+
+            let mut effects = vec![];
+            let consumed = self
+                .timed
+                .process_key(index as usize, code, down, now, &mut effects);
+
+            self.apply(effects, easy_shift_layer, &mut outcome.outputs);
+
+            if consumed {
+                outcome.consumed = true;
+
+                return outcome;
+            }
+        }
+
+        let mut effects = vec![];
+        let consumed = match input {
+            Input::MouseButtonDown { index, button } | Input::MouseButtonUp { index, button } => {
+                let down = matches!(input, Input::MouseButtonDown { .. });
+
+                self.mouse
+                    .process_button(index as usize, button, down, &mut effects)
+            }
+
+            Input::MouseWheel(direction) => self.mouse.process_wheel(direction, now, &mut effects),
+
+            Input::MouseMove { dx, dy } => {
+                self.mouse.process_motion(dx, dy, &mut effects);
+
+                false
+            }
+
+            _ => false,
+        };
+
+        self.apply(effects, easy_shift_layer, &mut outcome.outputs);
+
+        if consumed {
+            outcome.consumed = true;
+
+            return outcome;
+        }
+
+        let (trigger, edge) = Self::trigger(input);
+
+        self.process_simple(trigger, edge, easy_shift_layer, &mut outcome);
+
+        outcome
+    }
+
+    /// Resolve the timed sources whose time is up and repeat the actions that are held
+    /// down. This has to be called periodically
+    pub fn poll(&mut self, easy_shift_layer: Option<usize>, now: Instant) -> Vec<Output> {
+        self.now = now;
+
+        let mut effects = vec![];
+        self.timed.poll(now, &mut effects);
+
+        let mut outputs = vec![];
+        self.apply(effects, easy_shift_layer, &mut outputs);
+
+        let mut due = vec![];
+
+        for (action, interval, next) in self.repeating.iter_mut() {
+            if *next <= now {
+                due.push(action.clone());
+                *next = now + *interval;
+            }
+        }
+
+        for action in due {
+            self.fire(&action, &mut outputs);
+        }
+
+        outputs
+    }
+
+    /// Release all keys and actions that are held down by this engine, so that a
+    /// different keymap can take over without leaving injected keys stuck
+    pub fn release_all(&mut self, easy_shift_layer: Option<usize>) -> Vec<Output> {
+        let mut effects = vec![];
+        self.timed.release_all(&mut effects);
+        self.mouse.release_all();
+
+        let mut outputs = vec![];
+        self.apply(effects, easy_shift_layer, &mut outputs);
+
+        for (_, bindings) in std::mem::take(&mut self.pressed) {
+            for binding in bindings {
+                if Self::follows_source(&binding.action) {
+                    self.stop(&binding.action, &mut outputs);
+                }
+            }
+        }
+
+        self.repeating.clear();
+
+        outputs
+    }
+
+    fn process_simple(
+        &mut self,
+        trigger: Trigger,
+        edge: Option<Edge>,
+        easy_shift_layer: Option<usize>,
+        outcome: &mut Outcome,
+    ) {
+        let layer = self.active_layer(easy_shift_layer);
+
+        match edge {
+            // wheel and DPI events are not paired, so we handle them like a key that is
+            // pressed and released immediately
+            None => {
+                for binding in self.select(trigger, layer) {
+                    self.press(&binding, &mut outcome.outputs);
+                    self.release(&binding, &mut outcome.outputs);
+
+                    outcome.consumed = true;
+                }
+            }
+
+            Some(Edge::Down) => {
+                let selected = self.select(trigger, layer);
+
+                // a one-shot layer is used up by the key press, regardless of whether
+                // the key is mapped on that layer
+                self.one_shot_layer = None;
+
+                for binding in selected.iter() {
+                    self.press(binding, &mut outcome.outputs);
+                }
+
+                if !selected.is_empty() {
+                    outcome.consumed = true;
+
+                    self.pressed.insert(trigger, selected);
+                }
+            }
+
+            Some(Edge::Up) => {
+                let selected = self
+                    .pressed
+                    .remove(&trigger)
+                    .unwrap_or_else(|| self.select(trigger, layer));
+
+                for binding in selected.iter() {
+                    self.release(binding, &mut outcome.outputs);
+
+                    outcome.consumed = true;
+                }
+            }
+        }
+    }
+
+    /// Execute the decisions of the timed sources
+    fn apply(
+        &mut self,
+        effects: Vec<Effect>,
+        easy_shift_layer: Option<usize>,
+        outputs: &mut Vec<Output>,
+    ) {
+        for effect in effects {
+            match effect {
+                Effect::Press(action) => self.start(&action, outputs),
+
+                Effect::Release(action) => self.stop(&action, outputs),
+
+                Effect::Pulse(action) => self.fire(&action, outputs),
+
+                // a replayed key is subject to the simple mappings, just like any other key
+                Effect::Replay { index, code, down } => {
+                    let edge = if down { Edge::Down } else { Edge::Up };
+
+                    let mut replayed = Outcome::default();
+                    self.process_simple(
+                        Trigger::Key(index),
+                        Some(edge),
+                        easy_shift_layer,
+                        &mut replayed,
+                    );
+
+                    if replayed.consumed {
+                        outputs.extend(replayed.outputs);
+                    } else {
+                        outputs.push(Output::InjectKey { code, down });
+                    }
+                }
+
+                Effect::ReplayButton {
+                    index,
+                    button,
+                    down,
+                } => {
+                    let edge = if down { Edge::Down } else { Edge::Up };
+
+                    let mut replayed = Outcome::default();
+                    self.process_simple(
+                        Trigger::MouseButton(index),
+                        Some(edge),
+                        easy_shift_layer,
+                        &mut replayed,
+                    );
+
+                    if replayed.consumed {
+                        outputs.extend(replayed.outputs);
+                    } else {
+                        outputs.push(Output::InjectMouseButton { button, down });
+                    }
+                }
+            }
+        }
+    }
+
+    fn trigger(input: Input) -> (Trigger, Option<Edge>) {
+        match input {
+            Input::KeyDown { index, .. } => (Trigger::Key(index as usize), Some(Edge::Down)),
+            Input::KeyUp { index, .. } => (Trigger::Key(index as usize), Some(Edge::Up)),
+
+            Input::MouseButtonDown { index, .. } => {
+                (Trigger::MouseButton(index as usize), Some(Edge::Down))
+            }
+            Input::MouseButtonUp { index, .. } => {
+                (Trigger::MouseButton(index as usize), Some(Edge::Up))
+            }
+
+            Input::MouseHidButtonDown(index) => {
+                (Trigger::MouseHidButton(index as usize), Some(Edge::Down))
+            }
+            Input::MouseHidButtonUp(index) => {
+                (Trigger::MouseHidButton(index as usize), Some(Edge::Up))
+            }
+
+            Input::MouseWheel(direction) => (Trigger::MouseWheel(direction), None),
+            Input::MouseDpi(direction) => (Trigger::MouseDpi(direction), None),
+
+            // motion is only relevant for gestures
+            Input::MouseMove { .. } => (Trigger::MouseMove, None),
+        }
+    }
+
+    /// Select the bindings of `trigger` that are in effect. Bindings on the active
+    /// layer take precedence over the ones without a modifier
+    fn select(&self, trigger: Trigger, layer: Option<usize>) -> Vec<Binding> {
+        let bindings = match self.bindings.get(&trigger) {
+            Some(bindings) => bindings,
+            None => return vec![],
+        };
+
+        if let Some(layer) = layer {
+            let result: Vec<Binding> = bindings
+                .iter()
+                .filter(|b| b.easy_shift && b.layers.contains(&layer))
+                .cloned()
+                .collect();
+
+            if !result.is_empty() {
+                return result;
+            }
+        }
+
+        bindings.iter().filter(|b| !b.easy_shift).cloned().collect()
+    }
+
+    fn press(&mut self, binding: &Binding, outputs: &mut Vec<Output>) {
+        // a remapped key follows the state of the original key, regardless of
+        // whether it has been declared on the key-down or the key-up event
+        if Self::follows_source(&binding.action) {
+            self.start(&binding.action, outputs);
+        } else if binding.edge == Edge::Down {
+            self.fire(&binding.action, outputs);
+        }
+    }
+
+    fn release(&mut self, binding: &Binding, outputs: &mut Vec<Output>) {
+        if Self::follows_source(&binding.action) {
+            self.stop(&binding.action, outputs);
+        } else if binding.edge == Edge::Up {
+            match &binding.action {
+                Action::Call(call) => outputs.push(Output::Call {
+                    function_name: call.function_name.clone(),
+                    down: false,
+                }),
+
+                action => self.fire(action, outputs),
+            }
+        }
+    }
+
+    /// Returns true if the action is held down as long as its source
+    fn follows_source(action: &Action) -> bool {
+        matches!(
+            action,
+            Action::InjectKey(_)
+                | Action::KeyCombo(_)
+                | Action::Repeat(_)
+                | Action::MomentaryLayer(_)
+        )
+    }
+
+    /// Press an action; actions that do not follow their source are executed once
+    fn start(&mut self, action: &Action, outputs: &mut Vec<Output>) {
+        match action {
+            Action::InjectKey(dest) => Self::inject(dest.event, true, outputs),
+
+            Action::KeyCombo(keys) => {
+                for key in keys.iter() {
+                    Self::inject(key.event, true, outputs);
+                }
+            }
+
+            Action::Repeat(repeat) => {
+                self.fire(&repeat.action, outputs);
+
+                let interval = Duration::from_millis(repeat.interval_millis);
+                self.repeating
+                    .push((*repeat.action.clone(), interval, self.now + interval));
+            }
+
+            Action::MomentaryLayer(layer) => self.momentary_layers.push(*layer),
+
+            action => self.fire(action, outputs),
+        }
+    }
+
+    /// Release an action that has been pressed by `start()`
+    fn stop(&mut self, action: &Action, outputs: &mut Vec<Output>) {
+        match action {
+            Action::InjectKey(dest) => Self::inject(dest.event, false, outputs),
+
+            Action::KeyCombo(keys) => {
+                for key in keys.iter().rev() {
+                    Self::inject(key.event, false, outputs);
+                }
+            }
+
+            Action::Repeat(repeat) => {
+                if let Some(pos) = self
+                    .repeating
+                    .iter()
+                    .position(|(action, _, _)| *action == *repeat.action)
+                {
+                    self.repeating.remove(pos);
+                }
+            }
+
+            Action::MomentaryLayer(layer) => {
+                if let Some(pos) = self.momentary_layers.iter().rposition(|l| l == layer) {
+                    self.momentary_layers.remove(pos);
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    /// Execute an action once
+    fn fire(&mut self, action: &Action, outputs: &mut Vec<Output>) {
+        match action {
+            Action::Null => {}
+
+            Action::InjectKey(_) | Action::KeyCombo(_) => {
+                self.start(action, outputs);
+                self.stop(action, outputs);
+            }
+
+            Action::Call(call) => outputs.push(Output::Call {
+                function_name: call.function_name.clone(),
+                down: true,
+            }),
+
+            Action::Sequence(actions) => {
+                for action in actions.iter() {
+                    self.fire(action, outputs);
+                }
+            }
+
+            Action::Delay(millis) => outputs.push(Output::Sleep { millis: *millis }),
+
+            Action::TypeText(text) => outputs.push(Output::TypeText(text.clone())),
+
+            Action::Repeat(repeat) => self.fire(&repeat.action, outputs),
+
+            Action::SwitchSlot(slot) => outputs.push(Output::SwitchSlot(*slot)),
+
+            Action::SwitchProfile(profile) => outputs.push(Output::SwitchProfile(profile.clone())),
+
+            Action::Brightness(adjustment) => outputs.push(Output::Brightness(*adjustment)),
+
+            Action::Dpi(adjustment) => outputs.push(Output::Dpi(*adjustment)),
+
+            Action::ToggleLayer(layer) => {
+                if self.toggled_layer == Some(*layer) {
+                    self.toggled_layer = None;
+                } else {
+                    self.toggled_layer = Some(*layer);
+                }
+            }
+
+            Action::OneShotLayer(layer) => self.one_shot_layer = Some(*layer),
+
+            Action::PlayMacro(name) => self.play_macro(name, outputs),
+
+            // a momentary layer is only active while its source is held down
+            Action::MomentaryLayer(_) => {}
+        }
+    }
+
+    /// Inject a key event, unless the key is already in the requested state
+    fn inject(code: u32, down: bool, outputs: &mut Vec<Output>) {
+        let previous = outputs.iter().rev().find_map(|output| match output {
+            Output::InjectKey { code: c, down } if *c == code => Some(*down),
+            _ => None,
+        });
+
+        if previous != Some(down) {
+            outputs.push(Output::InjectKey { code, down });
+        }
+    }
+
+    fn play_macro(&self, name: &str, outputs: &mut Vec<Output>) {
+        let recorded = match self.table.macros().get(name) {
+            Some(recorded) => recorded,
+
+            None => {
+                warn!("Keymap: Unknown macro: {}", name);
+                return;
+            }
+        };
+
+        for (index, step) in recorded.steps.iter().enumerate() {
+            // the delay before the first event is not part of the macro
+            if index > 0 && step.delay_millis > 0 {
+                outputs.push(Output::Sleep {
+                    millis: step.delay_millis,
+                });
+            }
+
+            let output = match step.event {
+                MacroEvent::Key { code, down } => Output::InjectKey { code, down },
+                MacroEvent::MouseButton { button, down } => {
+                    Output::InjectMouseButton { button, down }
+                }
+                MacroEvent::MouseWheel { direction } => Output::InjectMouseWheel { direction },
+            };
+
+            outputs.push(output);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Input, KeymapEngine, Output};
+    use crate::keymaps::mapping::{
+        Action, EvdevEvent, Event, Key, KeyMappingTable, Macro, Rule, Source,
+    };
+
+    fn key(key_index: usize) -> Key {
+        Key {
+            key_index,
+            usb_vid: 0x1e7d,
+            usb_pid: 0x3098,
+        }
+    }
+
+    fn key_down(index: u8) -> Input {
+        Input::KeyDown {
+            index,
+            code: index as u32 + 100,
+        }
+    }
+
+    fn key_up(index: u8) -> Input {
+        Input::KeyUp {
+            index,
+            code: index as u32 + 100,
+        }
+    }
+
+    fn inject(code: u32) -> Rule {
+        Rule {
+            description: String::new(),
+            enabled: true,
+            action: Action::InjectKey(EvdevEvent { event: code }),
+        }
+    }
+
+    fn call(function_name: &str) -> Rule {
+        Rule {
+            description: String::new(),
+            enabled: true,
+            action: Action::Call(Macro {
+                function_name: function_name.to_string(),
+            }),
+        }
+    }
+
+    #[test]
+    fn simple_remapping() {
+        let mut table = KeyMappingTable::new();
+        table
+            .mappings
+            .insert(Source::new(Event::SimpleKeyDown(key(10))), inject(30));
+
+        let mut engine = KeymapEngine::new(table);
+        let now = Instant::now();
+
+        let outcome = engine.process(key_down(10), None, now);
+        assert!(outcome.consumed);
+        assert_eq!(
+            outcome.outputs,
+            vec![Output::InjectKey {
+                code: 30,
+                down: true
+            }]
+        );
+
+        let outcome = engine.process(key_up(10), None, now);
+        assert!(outcome.consumed);
+        assert_eq!(
+            outcome.outputs,
+            vec![Output::InjectKey {
+                code: 30,
+                down: false
+            }]
+        );
+
+        let outcome = engine.process(key_down(11), None, now);
+        assert!(!outcome.consumed);
+        assert!(outcome.outputs.is_empty());
+    }
+
+    #[test]
+    fn easy_shift_layers_and_release() {
+        let mut table = KeyMappingTable::new();
+        table
+            .mappings
+            .insert(Source::new(Event::SimpleKeyDown(key(10))), inject(30));
+        table.mappings.insert(
+            Source {
+                event: Event::EasyShiftKeyDown(key(10)),
+                layers: crate::keymaps::mapping::LayerSet([2].into_iter().collect()),
+            },
+            call("easy_shift_macro"),
+        );
+        table
+            .mappings
+            .insert(Source::new(Event::EasyShiftKeyDown(key(10))), inject(31));
+
+        let mut engine = KeymapEngine::new(table);
+        let now = Instant::now();
+
+        // Easy Shift+ layer 2 is active
+        let outcome = engine.process(key_down(10), Some(2), now);
+        assert_eq!(
+            outcome.outputs,
+            vec![Output::Call {
+                function_name: "easy_shift_macro".to_string(),
+                down: true
+            }]
+        );
+
+        // layer 1 is active
+        let outcome = engine.process(key_down(10), Some(1), now);
+        assert_eq!(
+            outcome.outputs,
+            vec![Output::InjectKey {
+                code: 31,
+                down: true
+            }]
+        );
+
+        // Easy Shift+ has been released before the key, the remapped key has to be released
+        let outcome = engine.process(key_up(10), None, now);
+        assert_eq!(
+            outcome.outputs,
+            vec![Output::InjectKey {
+                code: 31,
+                down: false
+            }]
+        );
+
+        // layer without mappings falls back to the simple mappings
+        let outcome = engine.process(key_down(10), Some(3), now);
+        assert_eq!(
+            outcome.outputs,
+            vec![Output::InjectKey {
+                code: 30,
+                down: true
+            }]
+        );
+    }
+
+    #[test]
+    fn mouse_wheel_and_disabled_rules() {
+        let mut table = KeyMappingTable::new();
+
+        let mut rule = inject(30);
+        rule.enabled = false;
+
+        table
+            .mappings
+            .insert(Source::new(Event::SimpleKeyDown(key(10))), rule);
+        table.mappings.insert(
+            Source::new(Event::SimpleMouseWheel(
+                crate::keymaps::mapping::Direction::Up,
+            )),
+            inject(115),
+        );
+
+        let mut engine = KeymapEngine::new(table);
+        let now = Instant::now();
+
+        assert!(!engine.process(key_down(10), None, now).consumed);
+
+        let outcome = engine.process(Input::MouseWheel(1), None, now);
+        assert!(outcome.consumed);
+        assert_eq!(
+            outcome.outputs,
+            vec![
+                Output::InjectKey {
+                    code: 115,
+                    down: true
+                },
+                Output::InjectKey {
+                    code: 115,
+                    down: false
+                }
+            ]
+        );
+
+        assert!(!engine.process(Input::MouseWheel(2), None, now).consumed);
+    }
+
+    #[test]
+    fn tap_hold_and_multi_tap() {
+        use crate::keymaps::mapping::{MultiTap, TapHold};
+
+        let threshold_millis = 200;
+
+        let mut table = KeyMappingTable::new();
+        table.mappings.insert(
+            Source::new(Event::KeyHold(TapHold {
+                key: key(10),
+                threshold_millis,
+            })),
+            inject(29),
+        );
+        table.mappings.insert(
+            Source::new(Event::KeyMultiTap(MultiTap {
+                key: key(20),
+                count: 2,
+                timeout_millis: 250,
+            })),
+            call("double_tap"),
+        );
+
+        let mut engine = KeymapEngine::new(table);
+        let now = Instant::now();
+
+        // a tap replays the original key
+        assert!(engine.process(key_down(10), None, now).consumed);
+        let outcome = engine.process(key_up(10), None, now + Duration::from_millis(50));
+        assert!(outcome.consumed);
+        assert_eq!(
+            outcome.outputs,
+            vec![
+                Output::InjectKey {
+                    code: 110,
+                    down: true
+                },
+                Output::InjectKey {
+                    code: 110,
+                    down: false
+                }
+            ]
+        );
+
+        // holding the key past the threshold
+        assert!(engine.process(key_down(10), None, now).consumed);
+        assert!(engine
+            .poll(None, now + Duration::from_millis(100))
+            .is_empty());
+        assert_eq!(
+            engine.poll(None, now + Duration::from_millis(200)),
+            vec![Output::InjectKey {
+                code: 29,
+                down: true
+            }]
+        );
+        let outcome = engine.process(key_up(10), None, now + Duration::from_millis(300));
+        assert!(outcome.consumed);
+        assert_eq!(
+            outcome.outputs,
+            vec![Output::InjectKey {
+                code: 29,
+                down: false
+            }]
+        );
+
+        // another key pressed while the key is undecided, resolves it as hold
+        engine.process(key_down(10), None, now);
+        let outcome = engine.process(key_down(11), None, now + Duration::from_millis(10));
+        assert!(!outcome.consumed);
+        assert_eq!(
+            outcome.outputs,
+            vec![Output::InjectKey {
+                code: 29,
+                down: true
+            }]
+        );
+        engine.process(key_up(11), None, now + Duration::from_millis(20));
+        engine.process(key_up(10), None, now + Duration::from_millis(30));
+
+        // double tap, the key itself is not consumed
+        engine.process(key_down(20), None, now);
+        assert!(engine.process(key_up(20), None, now).outputs.is_empty());
+        engine.process(key_down(20), None, now + Duration::from_millis(100));
+        let outcome = engine.process(key_up(20), None, now + Duration::from_millis(150));
+        assert!(!outcome.consumed);
+        assert_eq!(
+            outcome.outputs,
+            vec![Output::Call {
+                function_name: "double_tap".to_string(),
+                down: true
+            }]
+        );
+
+        // taps that are too far apart
+        engine.process(key_down(20), None, now + Duration::from_millis(1000));
+        engine.process(key_up(20), None, now + Duration::from_millis(1050));
+        engine.process(key_down(20), None, now + Duration::from_millis(2000));
+        let outcome = engine.process(key_up(20), None, now + Duration::from_millis(2050));
+        assert!(outcome.outputs.is_empty());
+    }
+
+    #[test]
+    fn chords_and_sequences() {
+        use crate::keymaps::mapping::KeyCombination;
+
+        let mut table = KeyMappingTable::new();
+        table.mappings.insert(
+            Source::new(Event::KeyChord(KeyCombination {
+                keys: vec![key(10), key(11)],
+                timeout_millis: 50,
+            })),
+            inject(1),
+        );
+        table.mappings.insert(
+            Source::new(Event::KeySequence(KeyCombination {
+                keys: vec![key(30), key(31), key(32)],
+                timeout_millis: 1000,
+            })),
+            call("sequence"),
+        );
+        table
+            .mappings
+            .insert(Source::new(Event::SimpleKeyDown(key(11))), inject(40));
+
+        let mut engine = KeymapEngine::new(table);
+        let now = Instant::now();
+
+        // both keys of the chord
+        assert!(engine.process(key_down(11), None, now).consumed);
+        let outcome = engine.process(key_down(10), None, now + Duration::from_millis(20));
+        assert!(outcome.consumed);
+        assert_eq!(
+            outcome.outputs,
+            vec![Output::InjectKey {
+                code: 1,
+                down: true
+            }]
+        );
+        let outcome = engine.process(key_up(10), None, now + Duration::from_millis(100));
+        assert!(outcome.consumed);
+        assert_eq!(
+            outcome.outputs,
+            vec![Output::InjectKey {
+                code: 1,
+                down: false
+            }]
+        );
+        assert!(engine.process(key_up(11), None, now).consumed);
+
+        // a single key of the chord is replayed after the timeout, the simple
+        // mappings still apply to it
+        assert!(engine.process(key_down(11), None, now).consumed);
+        assert_eq!(
+            engine.poll(None, now + Duration::from_millis(50)),
+            vec![Output::InjectKey {
+                code: 40,
+                down: true
+            }]
+        );
+        let outcome = engine.process(key_up(11), None, now + Duration::from_millis(60));
+        assert!(outcome.consumed);
+        assert_eq!(
+            outcome.outputs,
+            vec![Output::InjectKey {
+                code: 40,
+                down: false
+            }]
+        );
+
+        // the key is released before the chord is complete
+        engine.process(key_down(10), None, now);
+        let outcome = engine.process(key_up(10), None, now + Duration::from_millis(10));
+        assert!(!outcome.consumed);
+        assert_eq!(
+            outcome.outputs,
+            vec![Output::InjectKey {
+                code: 110,
+                down: true
+            }]
+        );
+
+        // leader key sequence
+        assert!(engine.process(key_down(30), None, now).consumed);
+        assert!(engine.process(key_up(30), None, now).consumed);
+        assert!(engine.process(key_down(31), None, now).consumed);
+        assert!(engine.process(key_up(31), None, now).consumed);
+        let outcome = engine.process(key_down(32), None, now);
+        assert!(outcome.consumed);
+        assert_eq!(
+            outcome.outputs,
+            vec![Output::Call {
+                function_name: "sequence".to_string(),
+                down: true
+            }]
+        );
+        assert!(engine.process(key_up(32), None, now).consumed);
+
+        // a key that does not continue the sequence is processed as usual
+        engine.process(key_down(30), None, now);
+        engine.process(key_up(30), None, now);
+        assert!(!engine.process(key_down(33), None, now).consumed);
+
+        // the sequence times out
+        engine.process(key_down(30), None, now);
+        engine.process(key_up(30), None, now);
+        engine.poll(None, now + Duration::from_millis(1000));
+        assert!(!engine.process(key_down(31), None, now).consumed);
+    }
+
+    #[test]
+    fn combos_sequences_and_layers() {
+        use crate::keymaps::mapping::{Adjustment, LayerSet, Repeat};
+
+        let ev = |event| EvdevEvent { event };
+        let rule = |action| Rule {
+            description: String::new(),
+            enabled: true,
+            action,
+        };
+
+        let mut table = KeyMappingTable::new();
+        table.mappings.insert(
+            Source::new(Event::SimpleKeyDown(key(10))),
+            rule(Action::KeyCombo(vec![ev(29), ev(42), ev(20)])),
+        );
+        table.mappings.insert(
+            Source::new(Event::SimpleKeyDown(key(11))),
+            rule(Action::Sequence(vec![
+                Action::KeyCombo(vec![ev(29), ev(46)]),
+                Action::Delay(50),
+                Action::TypeText("x".to_string()),
+                Action::Brightness(Adjustment::Relative(-10)),
+            ])),
+        );
+        table.mappings.insert(
+            Source::new(Event::SimpleKeyDown(key(12))),
+            rule(Action::MomentaryLayer(2)),
+        );
+        table.mappings.insert(
+            Source {
+                event: Event::EasyShiftKeyDown(key(13)),
+                layers: LayerSet([2].into_iter().collect()),
+            },
+            rule(Action::Repeat(Repeat {
+                action: Box::new(Action::InjectKey(ev(103))),
+                interval_millis: 100,
+            })),
+        );
+
+        let mut engine = KeymapEngine::new(table);
+        let now = Instant::now();
+
+        let inject = |code, down| Output::InjectKey { code, down };
+
+        // combos are held down as long as the source
+        let outcome = engine.process(key_down(10), None, now);
+        assert_eq!(
+            outcome.outputs,
+            vec![inject(29, true), inject(42, true), inject(20, true)]
+        );
+        let outcome = engine.process(key_up(10), None, now);
+        assert_eq!(
+            outcome.outputs,
+            vec![inject(20, false), inject(42, false), inject(29, false)]
+        );
+
+        // sequences are executed once
+        let outcome = engine.process(key_down(11), None, now);
+        assert_eq!(
+            outcome.outputs,
+            vec![
+                inject(29, true),
+                inject(46, true),
+                inject(46, false),
+                inject(29, false),
+                Output::Sleep { millis: 50 },
+                Output::TypeText("x".to_string()),
+                Output::Brightness(Adjustment::Relative(-10)),
+            ]
+        );
+        assert!(engine.process(key_up(11), None, now).outputs.is_empty());
+
+        // without the layer, the key is not mapped
+        assert!(!engine.process(key_down(13), None, now).consumed);
+        engine.process(key_up(13), None, now);
+
+        // the momentary layer activates the repeating action
+        assert!(engine.process(key_down(12), None, now).consumed);
+        let outcome = engine.process(key_down(13), None, now);
+        assert_eq!(outcome.outputs, vec![inject(103, true), inject(103, false)]);
+        assert!(engine
+            .poll(None, now + Duration::from_millis(50))
+            .is_empty());
+        assert_eq!(
+            engine.poll(None, now + Duration::from_millis(100)),
+            vec![inject(103, true), inject(103, false)]
+        );
+        engine.process(key_up(13), None, now + Duration::from_millis(150));
+        assert!(engine
+            .poll(None, now + Duration::from_millis(300))
+            .is_empty());
+
+        engine.process(key_up(12), None, now);
+        assert_eq!(engine.active_layer(None), None);
+    }
+
+    #[test]
+    fn one_shot_and_toggled_layers() {
+        use crate::keymaps::mapping::LayerSet;
+
+        let mut table = KeyMappingTable::new();
+        table.mappings.insert(
+            Source::new(Event::SimpleKeyDown(key(20))),
+            Rule {
+                description: String::new(),
+                enabled: true,
+                action: Action::OneShotLayer(3),
+            },
+        );
+        table.mappings.insert(
+            Source::new(Event::SimpleKeyDown(key(21))),
+            Rule {
+                description: String::new(),
+                enabled: true,
+                action: Action::ToggleLayer(4),
+            },
+        );
+        for layer in [3, 4] {
+            table.mappings.insert(
+                Source {
+                    event: Event::EasyShiftKeyDown(key(30)),
+                    layers: LayerSet([layer].into_iter().collect()),
+                },
+                inject(100 + layer as u32),
+            );
+        }
+
+        let mut engine = KeymapEngine::new(table);
+        let now = Instant::now();
+
+        assert_eq!(engine.layer_keys(3), vec![30]);
+        assert!(engine.layer_keys(5).is_empty());
+
+        // the one-shot layer is in effect for the next key press only
+        engine.process(key_down(20), None, now);
+        engine.process(key_up(20), None, now);
+        assert_eq!(engine.action_layer(), Some(3));
+
+        let outcome = engine.process(key_down(30), None, now);
+        assert_eq!(
+            outcome.outputs,
+            vec![Output::InjectKey {
+                code: 103,
+                down: true
+            }]
+        );
+        assert_eq!(engine.action_layer(), None);
+
+        // the release is handled by the layer the key has been pressed on
+        let outcome = engine.process(key_up(30), None, now);
+        assert_eq!(
+            outcome.outputs,
+            vec![Output::InjectKey {
+                code: 103,
+                down: false
+            }]
+        );
+        assert!(!engine.process(key_down(30), None, now).consumed);
+        engine.process(key_up(30), None, now);
+
+        // a toggled layer stays in effect, until it is toggled off again
+        engine.process(key_down(21), None, now);
+        engine.process(key_up(21), None, now);
+        assert!(engine.process(key_down(30), None, now).consumed);
+        engine.process(key_up(30), None, now);
+        assert_eq!(engine.action_layer(), Some(4));
+
+        // a one-shot layer takes precedence over the toggled layer
+        engine.process(key_down(20), None, now);
+        engine.process(key_up(20), None, now);
+        assert_eq!(engine.active_layer(Some(1)), Some(3));
+        engine.process(key_down(30), None, now);
+        engine.process(key_up(30), None, now);
+        assert_eq!(engine.active_layer(Some(1)), Some(4));
+
+        engine.process(key_down(21), None, now);
+        engine.process(key_up(21), None, now);
+        assert_eq!(engine.active_layer(Some(1)), Some(1));
+    }
+
+    #[test]
+    fn recorded_macros() {
+        use crate::keymaps::mapping::{MacroEvent, MacroStep, RecordedMacro};
+
+        let step = |delay_millis, event| MacroStep {
+            delay_millis,
+            event,
+        };
+
+        let mut table = KeyMappingTable::new();
+        table.macros.insert(
+            "greet".to_string(),
+            RecordedMacro {
+                steps: vec![
+                    step(
+                        500,
+                        MacroEvent::Key {
+                            code: 35,
+                            down: true,
+                        },
+                    ),
+                    step(
+                        20,
+                        MacroEvent::Key {
+                            code: 35,
+                            down: false,
+                        },
+                    ),
+                    step(
+                        0,
+                        MacroEvent::Key {
+                            code: 23,
+                            down: true,
+                        },
+                    ),
+                    step(
+                        0,
+                        MacroEvent::Key {
+                            code: 23,
+                            down: false,
+                        },
+                    ),
+                    step(
+                        100,
+                        MacroEvent::MouseButton {
+                            button: 1,
+                            down: true,
+                        },
+                    ),
+                    step(0, MacroEvent::MouseWheel { direction: 2 }),
+                ],
+            },
+        );
+        table.mappings.insert(
+            Source::new(Event::SimpleKeyDown(key(10))),
+            Rule {
+                description: String::new(),
+                enabled: true,
+                action: Action::Sequence(vec![
+                    Action::PlayMacro("greet".to_string()),
+                    Action::PlayMacro("unknown".to_string()),
+                ]),
+            },
+        );
+
+        let mut engine = KeymapEngine::new(table);
+        let now = Instant::now();
+
+        let outcome = engine.process(key_down(10), None, now);
+        assert!(outcome.consumed);
+        assert_eq!(
+            outcome.outputs,
+            vec![
+                Output::InjectKey {
+                    code: 35,
+                    down: true
+                },
+                Output::Sleep { millis: 20 },
+                Output::InjectKey {
+                    code: 35,
+                    down: false
+                },
+                Output::InjectKey {
+                    code: 23,
+                    down: true
+                },
+                Output::InjectKey {
+                    code: 23,
+                    down: false
+                },
+                Output::Sleep { millis: 100 },
+                Output::InjectMouseButton {
+                    button: 1,
+                    down: true
+                },
+                Output::InjectMouseWheel { direction: 2 },
+            ]
+        );
+    }
+
+    #[test]
+    fn release_all() {
+        let mut table = KeyMappingTable::new();
+        table
+            .mappings
+            .insert(Source::new(Event::SimpleKeyDown(key(10))), inject(1));
+        table
+            .mappings
+            .insert(Source::new(Event::SimpleKeyUp(key(11))), call("up"));
+
+        let mut engine = KeymapEngine::new(table);
+        let now = Instant::now();
+
+        engine.process(key_down(10), None, now);
+        engine.process(key_down(11), None, now);
+
+        // remapped keys are released, but actions on the key-up event are not executed
+        assert_eq!(
+            engine.release_all(None),
+            vec![Output::InjectKey {
+                code: 1,
+                down: false
+            }]
+        );
+        assert!(engine.release_all(None).is_empty());
+    }
+
+    #[test]
+    fn mouse_gestures() {
+        use crate::keymaps::mapping::{Direction, Gesture};
+
+        let mut table = KeyMappingTable::new();
+        table.mappings.insert(
+            Source::new(Event::MouseGesture(Gesture {
+                button: key(3),
+                direction: Direction::Left,
+                distance: 100,
+            })),
+            call("left"),
+        );
+
+        let mut engine = KeymapEngine::new(table);
+        let now = Instant::now();
+
+        let down = Input::MouseButtonDown {
+            index: 3,
+            button: 3,
+        };
+        let up = Input::MouseButtonUp {
+            index: 3,
+            button: 3,
+        };
+
+        // motion is only tracked while the gesture button is held down
+        assert!(!engine.is_mapped(Input::MouseMove { dx: -50, dy: 0 }, None));
+
+        assert!(engine.process(down, None, now).consumed);
+        assert!(engine.is_mapped(Input::MouseMove { dx: -50, dy: 0 }, None));
+
+        let outcome = engine.process(Input::MouseMove { dx: -60, dy: 20 }, None, now);
+        assert!(!outcome.consumed);
+        assert!(outcome.outputs.is_empty());
+
+        let outcome = engine.process(Input::MouseMove { dx: -60, dy: 0 }, None, now);
+        assert_eq!(
+            outcome.outputs,
+            vec![Output::Call {
+                function_name: "left".to_string(),
+                down: true
+            }]
+        );
+
+        // the click is swallowed after a gesture has been performed
+        let outcome = engine.process(up, None, now);
+        assert!(outcome.consumed);
+        assert!(outcome.outputs.is_empty());
+
+        // a click without a gesture is replayed on release
+        assert!(engine.process(down, None, now).consumed);
+        engine.process(Input::MouseMove { dx: 0, dy: 150 }, None, now);
+
+        let outcome = engine.process(up, None, now);
+        assert!(outcome.consumed);
+        assert_eq!(
+            outcome.outputs,
+            vec![
+                Output::InjectMouseButton {
+                    button: 3,
+                    down: true
+                },
+                Output::InjectMouseButton {
+                    button: 3,
+                    down: false
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn wheel_tilt_and_speed() {
+        use crate::keymaps::mapping::{Direction, WheelSpeed, WheelTilt};
+
+        let mut table = KeyMappingTable::new();
+        table.mappings.insert(
+            Source::new(Event::MouseWheelTilt(WheelTilt {
+                direction: Direction::Left,
+                timeout_millis: 300,
+            })),
+            call("tilt"),
+        );
+        table.mappings.insert(
+            Source::new(Event::MouseWheelSpeed(WheelSpeed {
+                direction: Direction::Up,
+                notches: 3,
+                window_millis: 100,
+            })),
+            call("fast"),
+        );
+
+        let mut engine = KeymapEngine::new(table);
+        let now = Instant::now();
+        let ms = Duration::from_millis;
+
+        // a tilt generates a stream of wheel events, that execute the action once
+        assert_eq!(
+            engine
+                .process(Input::MouseWheel(3), None, now)
+                .outputs
+                .len(),
+            1
+        );
+        assert!(engine
+            .process(Input::MouseWheel(3), None, now + ms(200))
+            .outputs
+            .is_empty());
+        assert!(
+            engine
+                .process(Input::MouseWheel(3), None, now + ms(400))
+                .consumed
+        );
+        assert_eq!(
+            engine
+                .process(Input::MouseWheel(3), None, now + ms(800))
+                .outputs
+                .len(),
+            1
+        );
+
+        // slow turns of the wheel are not affected
+        assert!(!engine.process(Input::MouseWheel(1), None, now).consumed);
+        assert!(
+            !engine
+                .process(Input::MouseWheel(1), None, now + ms(200))
+                .consumed
+        );
+        assert!(!engine.is_mapped(Input::MouseWheel(1), None));
+
+        assert!(
+            !engine
+                .process(Input::MouseWheel(1), None, now + ms(210))
+                .consumed
+        );
+
+        let outcome = engine.process(Input::MouseWheel(1), None, now + ms(220));
+        assert!(outcome.consumed);
+        assert_eq!(
+            outcome.outputs,
+            vec![Output::Call {
+                function_name: "fast".to_string(),
+                down: true
+            }]
+        );
+        assert!(engine.is_mapped(Input::MouseWheel(1), None));
+    }
+}
@@ -0,0 +1 @@
+../../../eruption-keymap/src/mapping.rs
\ No newline at end of file
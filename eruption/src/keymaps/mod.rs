@@ -0,0 +1,97 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use evdev_rs::enums::int_to_ev_key;
+
+pub mod engine;
+pub mod mapping;
+pub mod mouse;
+pub mod recorder;
+pub mod text;
+pub mod timed;
+
+pub use engine::{Input, KeymapEngine, Output};
+pub use mapping::KeyMappingTable;
+
+use crate::constants;
+use mapping::{EvdevEvent, Key};
+
+pub type Result<T> = std::result::Result<T, eyre::Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum KeymapError {
+    #[error("Could not parse keymap file {path}: {description}")]
+    ParseError { path: String, description: String },
+}
+
+// the mapping types are shared with `eruption-keymap`, that displays keys by their symbols,
+// the daemon only shows them in log messages
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "key {} ({:04x}:{:04x})",
+            self.key_index, self.usb_vid, self.usb_pid
+        )
+    }
+}
+
+impl fmt::Display for EvdevEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match int_to_ev_key(self.event) {
+            Some(key) => write!(f, "{:?}", key),
+            None => write!(f, "{}", self.event),
+        }
+    }
+}
+
+/// Load a keymap table in the native format (`.keymap`) from `path`
+pub fn load_keymap<P: AsRef<Path>>(path: P) -> Result<KeyMappingTable> {
+    let path = path.as_ref();
+
+    let data = fs::read_to_string(path)?;
+    let table = serde_json::from_str(&data).map_err(|e| KeymapError::ParseError {
+        path: path.display().to_string(),
+        description: e.to_string(),
+    })?;
+
+    Ok(table)
+}
+
+/// Resolve the file name of a keymap; relative paths refer to the keymap directories, keymaps
+/// that have been imported from bundles take precedence
+pub fn keymap_path<P: AsRef<Path>>(keymap: P) -> PathBuf {
+    let keymap = keymap.as_ref();
+
+    if keymap.components().count() > 1 {
+        keymap.to_path_buf()
+    } else {
+        let imported = PathBuf::from(constants::IMPORTED_KEYMAP_DIR).join(keymap);
+
+        if imported.is_file() {
+            imported
+        } else {
+            PathBuf::from(constants::DEFAULT_KEYMAP_DIR).join(keymap)
+        }
+    }
+}
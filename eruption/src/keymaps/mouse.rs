@@ -0,0 +1,280 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+//! Sources that depend on mouse motion and on the scroll wheel: gestures, wheel tilt
+//! and fast wheel turns
+//!
+//! A mouse button that starts gestures is held back while it is pressed. If the mouse
+//! has not been moved far enough when it is released, the click is replayed
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use super::mapping::Action;
+use super::timed::Effect;
+
+#[derive(Debug, Clone)]
+struct GestureBinding {
+    direction: u8,
+    distance: u32,
+    action: Action,
+}
+
+#[derive(Debug, Clone)]
+struct TiltBinding {
+    timeout: Duration,
+    action: Action,
+}
+
+#[derive(Debug, Clone)]
+struct SpeedBinding {
+    notches: usize,
+    window: Duration,
+    action: Action,
+}
+
+/// A gesture button that is held down, along with the motion since the last gesture
+#[derive(Debug, Clone, Copy)]
+struct GestureState {
+    index: usize,
+    button: u32,
+    dx: i64,
+    dy: i64,
+
+    /// At least one gesture has been performed while the button was held down
+    performed: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct MouseSources {
+    gestures: HashMap<usize, Vec<GestureBinding>>,
+    tilts: HashMap<u8, TiltBinding>,
+    speeds: HashMap<u8, Vec<SpeedBinding>>,
+
+    gesture: Option<GestureState>,
+
+    /// The time of the last wheel event of a tilt, per direction
+    last_tilt: HashMap<u8, Instant>,
+
+    /// Recent wheel events, used to determine the speed of the wheel
+    wheel_history: VecDeque<(u8, Instant)>,
+
+    /// Direction of the last wheel event, if it has been handled as a fast wheel turn
+    fast_wheel: Option<u8>,
+}
+
+impl MouseSources {
+    pub fn add_gesture(&mut self, index: usize, direction: u8, distance: u32, action: Action) {
+        self.gestures
+            .entry(index)
+            .or_default()
+            .push(GestureBinding {
+                direction,
+                distance,
+                action,
+            });
+    }
+
+    pub fn add_tilt(&mut self, direction: u8, timeout: Duration, action: Action) {
+        self.tilts
+            .insert(direction, TiltBinding { timeout, action });
+    }
+
+    pub fn add_speed(&mut self, direction: u8, notches: usize, window: Duration, action: Action) {
+        let bindings = self.speeds.entry(direction).or_default();
+
+        bindings.push(SpeedBinding {
+            notches,
+            window,
+            action,
+        });
+
+        // the fastest matching binding wins
+        bindings.sort_by_key(|b| Reverse(b.notches));
+    }
+
+    /// Returns true if a gesture is in progress, so that mouse motion has to be tracked
+    pub fn tracking(&self) -> bool {
+        self.gesture.is_some()
+    }
+
+    /// Returns true if the button starts gestures
+    pub fn consumes_button(&self, index: usize) -> bool {
+        self.gestures.contains_key(&index)
+    }
+
+    /// Returns true if events of the wheel `direction` are currently handled by this
+    /// module; used for the high resolution wheel events that accompany the regular ones
+    pub fn consumes_wheel(&self, direction: u8) -> bool {
+        self.tilts.contains_key(&direction) || self.fast_wheel == Some(direction)
+    }
+
+    /// Process a mouse button event; returns true if the event has been consumed
+    pub fn process_button(
+        &mut self,
+        index: usize,
+        button: u32,
+        down: bool,
+        effects: &mut Vec<Effect>,
+    ) -> bool {
+        if down {
+            if !self.gestures.contains_key(&index) {
+                return false;
+            }
+
+            // a second gesture button is handled like any other button
+            if self.gesture.is_some() {
+                return false;
+            }
+
+            self.gesture = Some(GestureState {
+                index,
+                button,
+                dx: 0,
+                dy: 0,
+                performed: false,
+            });
+
+            true
+        } else {
+            match self.gesture {
+                Some(state) if state.index == index => {
+                    self.gesture = None;
+
+                    // the button has been clicked without performing a gesture
+                    if !state.performed {
+                        effects.push(Effect::ReplayButton {
+                            index,
+                            button: state.button,
+                            down: true,
+                        });
+                        effects.push(Effect::ReplayButton {
+                            index,
+                            button: state.button,
+                            down: false,
+                        });
+                    }
+
+                    true
+                }
+
+                _ => false,
+            }
+        }
+    }
+
+    /// Process relative mouse motion while a gesture button is held down
+    pub fn process_motion(&mut self, dx: i32, dy: i32, effects: &mut Vec<Effect>) {
+        let state = match self.gesture.as_mut() {
+            Some(state) => state,
+            None => return,
+        };
+
+        state.dx += dx as i64;
+        state.dy += dy as i64;
+
+        // the axis with the largest motion determines the direction
+        let (direction, distance) = if state.dx.abs() >= state.dy.abs() {
+            (if state.dx < 0 { 3 } else { 4 }, state.dx.unsigned_abs())
+        } else {
+            (if state.dy < 0 { 1 } else { 2 }, state.dy.unsigned_abs())
+        };
+
+        let binding = self.gestures.get(&state.index).and_then(|bindings| {
+            bindings
+                .iter()
+                .find(|b| b.direction == direction && distance >= b.distance as u64)
+        });
+
+        if let Some(binding) = binding {
+            effects.push(Effect::Pulse(binding.action.clone()));
+
+            // the button may be held down to perform further gestures
+            state.dx = 0;
+            state.dy = 0;
+            state.performed = true;
+        }
+    }
+
+    /// Process a wheel event; returns true if the event has been consumed
+    pub fn process_wheel(
+        &mut self,
+        direction: u8,
+        now: Instant,
+        effects: &mut Vec<Effect>,
+    ) -> bool {
+        let horizon = self
+            .speeds
+            .values()
+            .flatten()
+            .map(|b| b.window)
+            .max()
+            .unwrap_or_default();
+
+        while matches!(self.wheel_history.front(), Some((_, t)) if now.duration_since(*t) > horizon)
+        {
+            self.wheel_history.pop_front();
+        }
+
+        self.wheel_history.push_back((direction, now));
+
+        if let Some(bindings) = self.speeds.get(&direction) {
+            for binding in bindings {
+                let count = self
+                    .wheel_history
+                    .iter()
+                    .rev()
+                    .take_while(|(d, t)| {
+                        *d == direction && now.duration_since(*t) <= binding.window
+                    })
+                    .count();
+
+                if count >= binding.notches {
+                    effects.push(Effect::Pulse(binding.action.clone()));
+
+                    self.fast_wheel = Some(direction);
+
+                    return true;
+                }
+            }
+        }
+
+        self.fast_wheel = None;
+
+        if let Some(binding) = self.tilts.get(&direction) {
+            let previous = self.last_tilt.insert(direction, now);
+
+            // a tilted wheel generates a stream of events, the action is executed once
+            if !matches!(previous, Some(t) if now.duration_since(t) <= binding.timeout) {
+                effects.push(Effect::Pulse(binding.action.clone()));
+            }
+
+            return true;
+        }
+
+        false
+    }
+
+    /// Forget about a gesture in progress, without replaying the held back button
+    pub fn release_all(&mut self) {
+        self.gesture = None;
+        self.fast_wheel = None;
+    }
+}
@@ -0,0 +1,62 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+//! Records key and mouse events along with their timing, to create macros
+
+use std::time::Instant;
+
+use super::mapping::{MacroEvent, MacroStep};
+use crate::constants;
+
+#[derive(Debug, Default)]
+pub struct Recorder {
+    steps: Vec<MacroStep>,
+
+    /// The time of the previously recorded event
+    last: Option<Instant>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an event that occurred at `now`. Events beyond the maximum length
+    /// of a macro are discarded
+    pub fn record(&mut self, event: MacroEvent, now: Instant) {
+        if self.steps.len() >= constants::MAX_RECORDED_MACRO_EVENTS {
+            return;
+        }
+
+        let delay_millis = self
+            .last
+            .map(|last| now.duration_since(last).as_millis() as u64)
+            .unwrap_or(0);
+
+        self.last = Some(now);
+        self.steps.push(MacroStep {
+            delay_millis,
+            event,
+        });
+    }
+
+    pub fn finish(self) -> Vec<MacroStep> {
+        self.steps
+    }
+}
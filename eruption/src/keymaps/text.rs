@@ -0,0 +1,165 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+//! Type text on the virtual keyboard
+
+use evdev_rs::enums::EV_KEY;
+
+use super::engine::Output;
+
+/// Translate `text` into key events, assuming a US keyboard layout. Characters that are
+/// not available on the layout are entered as unicode code points, using the
+/// `Ctrl+Shift+U` input method of GTK and IBus
+pub fn type_text(text: &str) -> Vec<Output> {
+    let mut outputs = vec![];
+
+    for c in text.chars() {
+        match char_to_key(c) {
+            Some((key, shift)) => tap(key, shift, &mut outputs),
+
+            None => {
+                inject(EV_KEY::KEY_LEFTCTRL, true, &mut outputs);
+                tap(EV_KEY::KEY_U, true, &mut outputs);
+                inject(EV_KEY::KEY_LEFTCTRL, false, &mut outputs);
+
+                for digit in format!("{:x}", c as u32).chars() {
+                    if let Some((key, _)) = char_to_key(digit) {
+                        tap(key, false, &mut outputs);
+                    }
+                }
+
+                tap(EV_KEY::KEY_SPACE, false, &mut outputs);
+            }
+        }
+    }
+
+    outputs
+}
+
+fn tap(key: EV_KEY, shift: bool, outputs: &mut Vec<Output>) {
+    if shift {
+        inject(EV_KEY::KEY_LEFTSHIFT, true, outputs);
+    }
+
+    inject(key, true, outputs);
+    inject(key, false, outputs);
+
+    if shift {
+        inject(EV_KEY::KEY_LEFTSHIFT, false, outputs);
+    }
+}
+
+fn inject(key: EV_KEY, down: bool, outputs: &mut Vec<Output>) {
+    outputs.push(Output::InjectKey {
+        code: key as u32,
+        down,
+    });
+}
+
+/// Returns the key and the state of the shift modifier that produce `c` on a US layout
+fn char_to_key(c: char) -> Option<(EV_KEY, bool)> {
+    let shift = c.is_ascii_uppercase();
+
+    let key = match c.to_ascii_lowercase() {
+        'a' => EV_KEY::KEY_A,
+        'b' => EV_KEY::KEY_B,
+        'c' => EV_KEY::KEY_C,
+        'd' => EV_KEY::KEY_D,
+        'e' => EV_KEY::KEY_E,
+        'f' => EV_KEY::KEY_F,
+        'g' => EV_KEY::KEY_G,
+        'h' => EV_KEY::KEY_H,
+        'i' => EV_KEY::KEY_I,
+        'j' => EV_KEY::KEY_J,
+        'k' => EV_KEY::KEY_K,
+        'l' => EV_KEY::KEY_L,
+        'm' => EV_KEY::KEY_M,
+        'n' => EV_KEY::KEY_N,
+        'o' => EV_KEY::KEY_O,
+        'p' => EV_KEY::KEY_P,
+        'q' => EV_KEY::KEY_Q,
+        'r' => EV_KEY::KEY_R,
+        's' => EV_KEY::KEY_S,
+        't' => EV_KEY::KEY_T,
+        'u' => EV_KEY::KEY_U,
+        'v' => EV_KEY::KEY_V,
+        'w' => EV_KEY::KEY_W,
+        'x' => EV_KEY::KEY_X,
+        'y' => EV_KEY::KEY_Y,
+        'z' => EV_KEY::KEY_Z,
+
+        '1' => EV_KEY::KEY_1,
+        '2' => EV_KEY::KEY_2,
+        '3' => EV_KEY::KEY_3,
+        '4' => EV_KEY::KEY_4,
+        '5' => EV_KEY::KEY_5,
+        '6' => EV_KEY::KEY_6,
+        '7' => EV_KEY::KEY_7,
+        '8' => EV_KEY::KEY_8,
+        '9' => EV_KEY::KEY_9,
+        '0' => EV_KEY::KEY_0,
+
+        ' ' => EV_KEY::KEY_SPACE,
+        '\n' => EV_KEY::KEY_ENTER,
+        '\t' => EV_KEY::KEY_TAB,
+        '-' => EV_KEY::KEY_MINUS,
+        '=' => EV_KEY::KEY_EQUAL,
+        '[' => EV_KEY::KEY_LEFTBRACE,
+        ']' => EV_KEY::KEY_RIGHTBRACE,
+        '\\' => EV_KEY::KEY_BACKSLASH,
+        ';' => EV_KEY::KEY_SEMICOLON,
+        '\'' => EV_KEY::KEY_APOSTROPHE,
+        '`' => EV_KEY::KEY_GRAVE,
+        ',' => EV_KEY::KEY_COMMA,
+        '.' => EV_KEY::KEY_DOT,
+        '/' => EV_KEY::KEY_SLASH,
+
+        _ => {
+            let key = match c {
+                '!' => EV_KEY::KEY_1,
+                '@' => EV_KEY::KEY_2,
+                '#' => EV_KEY::KEY_3,
+                '$' => EV_KEY::KEY_4,
+                '%' => EV_KEY::KEY_5,
+                '^' => EV_KEY::KEY_6,
+                '&' => EV_KEY::KEY_7,
+                '*' => EV_KEY::KEY_8,
+                '(' => EV_KEY::KEY_9,
+                ')' => EV_KEY::KEY_0,
+                '_' => EV_KEY::KEY_MINUS,
+                '+' => EV_KEY::KEY_EQUAL,
+                '{' => EV_KEY::KEY_LEFTBRACE,
+                '}' => EV_KEY::KEY_RIGHTBRACE,
+                '|' => EV_KEY::KEY_BACKSLASH,
+                ':' => EV_KEY::KEY_SEMICOLON,
+                '"' => EV_KEY::KEY_APOSTROPHE,
+                '~' => EV_KEY::KEY_GRAVE,
+                '<' => EV_KEY::KEY_COMMA,
+                '>' => EV_KEY::KEY_DOT,
+                '?' => EV_KEY::KEY_SLASH,
+
+                _ => return None,
+            };
+
+            return Some((key, true));
+        }
+    };
+
+    Some((key, shift))
+}
@@ -0,0 +1,468 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+//! Sources that depend on the timing of key events: tap vs. hold, multi-taps,
+//! chords and leader key style sequences
+//!
+//! Keys that are held back while a decision is pending, are replayed by the engine
+//! once it is clear that they do not belong to a timed source
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use super::mapping::Action;
+
+/// The result of a decision, executed by the engine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Effect {
+    /// Press the action and keep it pressed
+    Press(Action),
+
+    /// Release a previously pressed action
+    Release(Action),
+
+    /// Press and release the action immediately
+    Pulse(Action),
+
+    /// Replay a key event that has been held back
+    Replay { index: usize, code: u32, down: bool },
+
+    /// Replay a mouse button event that has been held back
+    ReplayButton {
+        index: usize,
+        button: u32,
+        down: bool,
+    },
+}
+
+#[derive(Debug, Clone, Default)]
+struct TapHoldBinding {
+    threshold: Duration,
+
+    /// Action if the key is released before the threshold, `None` replays the key
+    tap: Option<Action>,
+
+    /// Action if the key is held down, `None` replays the key
+    hold: Option<Action>,
+}
+
+#[derive(Debug, Clone)]
+struct MultiTapBinding {
+    count: usize,
+    timeout: Duration,
+    action: Action,
+}
+
+/// A chord or a sequence of keys
+#[derive(Debug, Clone)]
+struct ComboBinding {
+    keys: Vec<usize>,
+    timeout: Duration,
+    action: Action,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct HeldKey {
+    index: usize,
+    code: u32,
+    since: Instant,
+}
+
+#[derive(Debug, Clone)]
+struct SequenceState {
+    keys: Vec<usize>,
+    deadline: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct TimedSources {
+    tap_hold: HashMap<usize, TapHoldBinding>,
+    multi_tap: HashMap<usize, Vec<MultiTapBinding>>,
+    chords: Vec<ComboBinding>,
+    sequences: Vec<ComboBinding>,
+
+    /// A tap/hold key that is pressed, but has not been decided yet
+    pending_tap_hold: Option<HeldKey>,
+
+    /// Tap/hold keys that have been decided as hold, along with their hold action
+    holding: HashMap<usize, Option<Action>>,
+
+    /// The number of taps so far and the time of the last release, per key
+    taps: HashMap<usize, (usize, Instant)>,
+
+    /// Keys that are held back, because they may be part of a chord
+    pending_chord: Vec<HeldKey>,
+
+    /// Chords that are pressed; the action is released along with the first key
+    active_chords: Vec<(Vec<usize>, Action)>,
+
+    /// Leader key sequence that is currently being entered
+    sequence: Option<SequenceState>,
+
+    /// Keys whose key-down event has been consumed, the release is consumed as well
+    swallowed: HashSet<usize>,
+}
+
+impl TimedSources {
+    pub fn add_tap(&mut self, index: usize, threshold: Duration, action: Action) {
+        let binding = self.tap_hold.entry(index).or_default();
+
+        binding.threshold = threshold;
+        binding.tap = Some(action);
+    }
+
+    pub fn add_hold(&mut self, index: usize, threshold: Duration, action: Action) {
+        let binding = self.tap_hold.entry(index).or_default();
+
+        binding.threshold = threshold;
+        binding.hold = Some(action);
+    }
+
+    pub fn add_multi_tap(&mut self, index: usize, count: usize, timeout: Duration, action: Action) {
+        self.multi_tap
+            .entry(index)
+            .or_default()
+            .push(MultiTapBinding {
+                count,
+                timeout,
+                action,
+            });
+    }
+
+    pub fn add_chord(&mut self, mut keys: Vec<usize>, timeout: Duration, action: Action) {
+        keys.sort_unstable();
+        keys.dedup();
+
+        self.chords.push(ComboBinding {
+            keys,
+            timeout,
+            action,
+        });
+    }
+
+    pub fn add_sequence(&mut self, keys: Vec<usize>, timeout: Duration, action: Action) {
+        self.sequences.push(ComboBinding {
+            keys,
+            timeout,
+            action,
+        });
+    }
+
+    /// Returns true if events of the key are currently held back or consumed
+    pub fn consumes(&self, index: usize) -> bool {
+        self.swallowed.contains(&index)
+            || self.pending_chord.iter().any(|key| key.index == index)
+            || matches!(self.pending_tap_hold, Some(key) if key.index == index)
+            || matches!(self.holding.get(&index), Some(Some(_)))
+    }
+
+    /// Resolve pending decisions whose time is up
+    pub fn poll(&mut self, now: Instant, effects: &mut Vec<Effect>) {
+        if let Some(key) = self.pending_tap_hold {
+            if now.duration_since(key.since) >= self.tap_hold[&key.index].threshold {
+                self.decide_hold(effects);
+            }
+        }
+
+        if let Some(first) = self.pending_chord.first() {
+            let timeout =
+                Self::max_timeout(self.chords.iter().filter(|c| c.keys.contains(&first.index)));
+
+            if now.duration_since(first.since) >= timeout {
+                self.flush_chord(effects);
+            }
+        }
+
+        if matches!(&self.sequence, Some(sequence) if now >= sequence.deadline) {
+            self.sequence = None;
+        }
+    }
+
+    /// Release all actions that are held down and forget about pending decisions.
+    /// Keys that are held back are discarded, their release is not consumed
+    pub fn release_all(&mut self, effects: &mut Vec<Effect>) {
+        for (_, action) in self.holding.drain() {
+            if let Some(action) = action {
+                effects.push(Effect::Release(action));
+            }
+        }
+
+        for (_, action) in self.active_chords.drain(..) {
+            effects.push(Effect::Release(action));
+        }
+
+        self.pending_tap_hold = None;
+        self.pending_chord.clear();
+        self.taps.clear();
+        self.sequence = None;
+        self.swallowed.clear();
+    }
+
+    /// Process a key event. Returns true if the event has been consumed
+    pub fn process_key(
+        &mut self,
+        index: usize,
+        code: u32,
+        down: bool,
+        now: Instant,
+        effects: &mut Vec<Effect>,
+    ) -> bool {
+        if down {
+            self.key_down(index, code, now, effects)
+        } else {
+            self.key_up(index, code, now, effects)
+        }
+    }
+
+    fn key_down(
+        &mut self,
+        index: usize,
+        code: u32,
+        now: Instant,
+        effects: &mut Vec<Effect>,
+    ) -> bool {
+        // any other key that is pressed while a tap/hold key is undecided, resolves it as hold
+        if matches!(self.pending_tap_hold, Some(key) if key.index != index) {
+            self.decide_hold(effects);
+        }
+
+        if let Some(bindings) = self.multi_tap.get(&index) {
+            let timeout = bindings.iter().map(|b| b.timeout).max().unwrap_or_default();
+
+            if matches!(self.taps.get(&index), Some((_, last)) if now.duration_since(*last) > timeout)
+            {
+                self.taps.remove(&index);
+            }
+        }
+
+        if let Some(consumed) = self.sequence_key_down(index, now, effects) {
+            return consumed;
+        }
+
+        if self.chords.iter().any(|c| c.keys.contains(&index)) {
+            self.chord_key_down(index, code, now, effects);
+
+            return true;
+        }
+
+        if self.tap_hold.contains_key(&index) {
+            self.pending_tap_hold = Some(HeldKey {
+                index,
+                code,
+                since: now,
+            });
+
+            return true;
+        }
+
+        false
+    }
+
+    fn key_up(&mut self, index: usize, code: u32, now: Instant, effects: &mut Vec<Effect>) -> bool {
+        // multi-taps are counted on release, without consuming the key
+        self.count_tap(index, now, effects);
+
+        if let Some(pos) = self
+            .active_chords
+            .iter()
+            .position(|(keys, _)| keys.contains(&index))
+        {
+            let (_, action) = self.active_chords.remove(pos);
+
+            effects.push(Effect::Release(action));
+        }
+
+        if self.swallowed.remove(&index) {
+            return true;
+        }
+
+        // the key has been released before the chord was complete, so the key-down events
+        // are replayed and the release is processed as usual
+        if self.pending_chord.iter().any(|key| key.index == index) {
+            self.flush_chord(effects);
+
+            return false;
+        }
+
+        if matches!(self.pending_tap_hold, Some(key) if key.index == index) {
+            self.pending_tap_hold = None;
+
+            match self.tap_hold[&index].tap.clone() {
+                Some(action) => effects.push(Effect::Pulse(action)),
+
+                None => {
+                    effects.push(Effect::Replay {
+                        index,
+                        code,
+                        down: true,
+                    });
+                    effects.push(Effect::Replay {
+                        index,
+                        code,
+                        down: false,
+                    });
+                }
+            }
+
+            return true;
+        }
+
+        match self.holding.remove(&index) {
+            Some(Some(action)) => {
+                effects.push(Effect::Release(action));
+
+                true
+            }
+
+            // the original key has been replayed, so its release is processed as usual
+            _ => false,
+        }
+    }
+
+    /// Returns `Some(consumed)` if the key has been handled by a leader key sequence
+    fn sequence_key_down(
+        &mut self,
+        index: usize,
+        now: Instant,
+        effects: &mut Vec<Effect>,
+    ) -> Option<bool> {
+        let mut keys = match self.sequence.take() {
+            Some(sequence) => sequence.keys,
+
+            // the first key of a sequence is the leader key, it is always consumed
+            None if self
+                .sequences
+                .iter()
+                .any(|s| s.keys.first() == Some(&index)) =>
+            {
+                vec![]
+            }
+
+            None => return None,
+        };
+
+        keys.push(index);
+
+        let candidates: Vec<&ComboBinding> = self
+            .sequences
+            .iter()
+            .filter(|s| s.keys.starts_with(&keys))
+            .collect();
+
+        if let Some(binding) = candidates.iter().find(|s| s.keys.len() == keys.len()) {
+            effects.push(Effect::Pulse(binding.action.clone()));
+        } else if !candidates.is_empty() {
+            let timeout = Self::max_timeout(candidates.into_iter());
+
+            self.sequence = Some(SequenceState {
+                keys,
+                deadline: now + timeout,
+            });
+        } else {
+            // the key does not continue any sequence, so it is processed as usual
+            return None;
+        }
+
+        self.swallowed.insert(index);
+
+        Some(true)
+    }
+
+    fn chord_key_down(&mut self, index: usize, code: u32, now: Instant, effects: &mut Vec<Effect>) {
+        self.pending_chord.push(HeldKey {
+            index,
+            code,
+            since: now,
+        });
+
+        let mut held: Vec<usize> = self.pending_chord.iter().map(|key| key.index).collect();
+        held.sort_unstable();
+
+        if let Some(chord) = self.chords.iter().find(|c| c.keys == held) {
+            effects.push(Effect::Press(chord.action.clone()));
+
+            self.swallowed.extend(chord.keys.iter());
+            self.active_chords
+                .push((chord.keys.clone(), chord.action.clone()));
+            self.pending_chord.clear();
+        } else if !self
+            .chords
+            .iter()
+            .any(|c| held.iter().all(|key| c.keys.contains(key)))
+        {
+            // the keys that have been held back can not form a chord anymore,
+            // the current key may still start a new one though
+            let current = self.pending_chord.pop();
+
+            self.flush_chord(effects);
+            self.pending_chord.extend(current);
+        }
+    }
+
+    fn count_tap(&mut self, index: usize, now: Instant, effects: &mut Vec<Effect>) {
+        let bindings = match self.multi_tap.get(&index) {
+            Some(bindings) => bindings,
+            None => return,
+        };
+
+        let count = self.taps.get(&index).map_or(0, |(count, _)| *count) + 1;
+
+        if let Some(binding) = bindings.iter().find(|b| b.count == count) {
+            effects.push(Effect::Pulse(binding.action.clone()));
+        }
+
+        if count >= bindings.iter().map(|b| b.count).max().unwrap_or(0) {
+            self.taps.remove(&index);
+        } else {
+            self.taps.insert(index, (count, now));
+        }
+    }
+
+    fn decide_hold(&mut self, effects: &mut Vec<Effect>) {
+        if let Some(key) = self.pending_tap_hold.take() {
+            let hold = self.tap_hold[&key.index].hold.clone();
+
+            match &hold {
+                Some(action) => effects.push(Effect::Press(action.clone())),
+
+                None => effects.push(Effect::Replay {
+                    index: key.index,
+                    code: key.code,
+                    down: true,
+                }),
+            }
+
+            self.holding.insert(key.index, hold);
+        }
+    }
+
+    fn flush_chord(&mut self, effects: &mut Vec<Effect>) {
+        for key in self.pending_chord.drain(..) {
+            effects.push(Effect::Replay {
+                index: key.index,
+                code: key.code,
+                down: true,
+            });
+        }
+    }
+
+    fn max_timeout<'a>(bindings: impl Iterator<Item = &'a ComboBinding>) -> Duration {
+        bindings.map(|b| b.timeout).max().unwrap_or_default()
+    }
+}
@@ -54,15 +54,19 @@ mod util;
 mod hwdevices;
 use hwdevices::{KeyboardDevice, KeyboardHidEvent, MiscDevice, MouseDevice, MouseHidEvent};
 
+mod audio;
+mod bundle;
 mod color_scheme;
 mod constants;
 mod dbus_interface;
 mod events;
+mod keymaps;
 mod plugin_manager;
 mod plugins;
 mod profiles;
 mod scripting;
 mod state;
+mod transitions;
 
 use plugins::macros;
 use profiles::Profile;
@@ -200,9 +204,6 @@ lazy_static! {
     /// Global "keyboard brightness" modifier
     pub static ref BRIGHTNESS: AtomicIsize = AtomicIsize::new(100);
 
-    /// Global "keyboard brightness" modifier
-    pub static ref BRIGHTNESS_FADER: AtomicIsize = AtomicIsize::new(0);
-
     /// AFK timer
     pub static ref LAST_INPUT_TIME: Arc<Mutex<Instant>> = Arc::new(Mutex::new(Instant::now()));
 
@@ -216,6 +217,10 @@ lazy_static! {
     pub static ref LUA_TXS: Arc<RwLock<Vec<LuaTx>>> = Arc::new(RwLock::new(vec![]));
     pub static ref FAILED_TXS: Arc<RwLock<HashSet<usize>>> = Arc::new(RwLock::new(HashSet::new()));
 
+    /// Identifier of the next Lua VM, VMs are never identified by their index in `LUA_TXS`,
+    /// since the indices are reused by the VMs of the next profile
+    pub static ref NEXT_VM_ID: AtomicUsize = AtomicUsize::new(0);
+
     /// Key states
     pub static ref KEY_STATES: Arc<RwLock<Vec<bool>>> = Arc::new(RwLock::new(vec![false; constants::MAX_KEYS]));
 
@@ -308,15 +313,18 @@ pub enum EvdevError {
 }
 
 /// A LuaTx holds a Sender<T> as well as the path to the running script file
+#[derive(Clone)]
 pub struct LuaTx {
+    pub vm_id: usize,
     pub script_file: PathBuf,
     pub sender: Sender<script::Message>,
     pub is_failed: bool,
 }
 
 impl LuaTx {
-    pub fn new(script_file: PathBuf, sender: Sender<script::Message>) -> Self {
+    pub fn new(vm_id: usize, script_file: PathBuf, sender: Sender<script::Message>) -> Self {
         Self {
+            vm_id,
             script_file,
             sender,
             is_failed: false,
@@ -343,11 +351,15 @@ pub enum EventAction {
 pub enum FileSystemEvent {
     ProfileChanged { action: EventAction, path: PathBuf },
     ScriptChanged,
+    KeymapChanged,
 }
 
 #[derive(Debug, Clone)]
 pub enum DeviceAction {
     RenderNow,
+
+    /// Adjust the DPI of all mice, e.g. on behalf of a keymap
+    AdjustDpi(keymaps::mapping::Adjustment),
 }
 
 fn print_header() {
@@ -424,20 +436,25 @@ pub fn switch_profile(
 
         let profile = profiles::get_fail_safe_profile();
 
+        // do not wait for the Lua VMs of a previous profile to complete their transition
+        transitions::abort();
+
         // now spawn a new set of Lua VMs, with scripts from the failsafe profile
         for (thread_idx, script_file) in profile.active_scripts.iter().enumerate() {
             // TODO: use path from config
             let script_path = script_dir.join(&script_file);
 
+            let vm_id = NEXT_VM_ID.fetch_add(1, Ordering::SeqCst);
+
             let (lua_tx, lua_rx) = unbounded();
-            threads::spawn_lua_thread(thread_idx, lua_rx, script_path.clone(), None)
+            threads::spawn_lua_thread(thread_idx, vm_id, lua_rx, script_path.clone(), None)
                 .unwrap_or_else(|e| {
                     errors_present = true;
 
                     error!("Could not spawn a thread: {}", e);
                 });
 
-            let mut tx = LuaTx::new(script_path.clone(), lua_tx);
+            let mut tx = LuaTx::new(vm_id, script_path.clone(), lua_tx);
 
             if errors_present {
                 tx.is_failed = true
@@ -449,6 +466,11 @@ pub fn switch_profile(
         // finally assign the globally active profile
         *ACTIVE_PROFILE.lock() = Some(profile);
 
+        plugins::KeymapsPlugin::select_keymap()
+            .unwrap_or_else(|e| error!("Could not switch the keymap: {}", e));
+
+        plugins::AudioPlugin::select_sfx_pack();
+
         if notify {
             dbus_api_tx
                 .send(DbusApiEvent::ActiveProfileChanged)
@@ -548,20 +570,13 @@ pub fn switch_profile(
                 }
             }
 
-            // now request termination of all Lua VMs
+            // the Lua VMs of the current profile keep rendering, until the transition
+            // to the new profile completed; they are terminated by the device I/O thread
+            let lua_txs = std::mem::take(&mut *LUA_TXS.write());
+            transitions::begin(lua_txs, transitions::Transition::from_config());
 
-            for lua_tx in LUA_TXS.read().iter() {
-                if !lua_tx.is_failed {
-                    lua_tx
-                        .send(script::Message::Unload)
-                        .unwrap_or_else(|e| error!("Could not send an event to a Lua VM: {}", e));
-                } else {
-                    warn!("Skipping unload of a failed tx");
-                }
-            }
-
-            // be safe and clear any leftover channels
-            LUA_TXS.write().clear();
+            // release keys that may have been left pressed by a macro of the old profile
+            plugins::MacrosPlugin::release_held_keys();
 
             // we passed the point of no return, from here on we can't just go back
             // but need to switch to failsafe mode when we encounter any critical errors
@@ -572,9 +587,12 @@ pub fn switch_profile(
             for (thread_idx, script_file) in script_files.iter().enumerate() {
                 let script_path = util::match_script_path(&script_file)?;
 
+                let vm_id = NEXT_VM_ID.fetch_add(1, Ordering::SeqCst);
+
                 let (lua_tx, lua_rx) = unbounded();
                 if let Err(e) = threads::spawn_lua_thread(
                     thread_idx,
+                    vm_id,
                     lua_rx,
                     script_path.clone(),
                     Some(profile.clone()),
@@ -584,7 +602,7 @@ pub fn switch_profile(
                     error!("Could not spawn a thread: {}", e);
                 }
 
-                let mut tx = LuaTx::new(script_path.clone(), lua_tx);
+                let mut tx = LuaTx::new(vm_id, script_path.clone(), lua_tx);
 
                 if !errors_present {
                     num_vms += 1;
@@ -608,8 +626,6 @@ pub fn switch_profile(
                 // everything is fine, finally assign the globally active profile
                 debug!("Switch successful");
 
-                crate::BRIGHTNESS_FADER.store(constants::FADE_FRAMES as isize, Ordering::SeqCst);
-
                 *ACTIVE_PROFILE.lock() = Some(profile);
 
                 if notify {
@@ -624,6 +640,12 @@ pub fn switch_profile(
                 let mut slot_profiles = SLOT_PROFILES.lock();
                 slot_profiles.as_mut().unwrap()[active_slot] = profile_file.into();
 
+                // the new profile or slot may specify a different keymap
+                plugins::KeymapsPlugin::select_keymap()
+                    .unwrap_or_else(|e| error!("Could not switch the keymap: {}", e));
+
+                plugins::AudioPlugin::select_sfx_pack();
+
                 Ok(true)
             }
         } else {
@@ -805,6 +827,9 @@ fn run_main_loop(
 
                 switch_profile(Some(&profile_path), dbus_api_tx, true)?;
 
+                events::notify_observers(events::Event::SlotSwitched(active_slot))
+                    .unwrap_or_else(|e| error!("Error during notification of observers: {}", e));
+
                 saved_slot = active_slot;
                 FAILED_TXS.write().clear();
             }
@@ -871,6 +896,9 @@ fn run_main_loop(
                     error!("Could not switch profiles: {}", e);
                 }
 
+                events::notify_observers(events::Event::ProfileSwitched)
+                    .unwrap_or_else(|e| error!("Error during notification of observers: {}", e));
+
                 FAILED_TXS.write().clear();
             }
 
@@ -989,7 +1017,9 @@ fn run_main_loop(
 
             delay_time_render = Instant::now();
 
-            // send timer tick events to the Lua VMs
+            // send timer tick events to the Lua VMs, including the VMs of the previous profile
+            transitions::tick(delta);
+
             for (index, lua_tx) in LUA_TXS.read().iter().enumerate() {
                 // if this tx failed previously, then skip it completely
                 if !FAILED_TXS.read().contains(&index) {
@@ -1013,11 +1043,6 @@ fn run_main_loop(
                 });
         }
 
-        let fader = crate::BRIGHTNESS_FADER.load(Ordering::SeqCst);
-        if fader > 0 {
-            crate::BRIGHTNESS_FADER.store(fader - 1, Ordering::SeqCst);
-        }
-
         // compute AFK time
         let afk_timeout_secs = CONFIG
             .lock()
@@ -1108,6 +1133,9 @@ fn remove_failed_devices() -> Result<bool> {
         dbus_api_tx
             .send(DbusApiEvent::DeviceHotplug((0, 0), true))
             .unwrap_or_else(|e| error!("Could not send a pending dbus API event: {}", e));
+
+        events::notify_observers(events::Event::DeviceRemoved)
+            .unwrap_or_else(|e| error!("Error during notification of observers: {}", e));
     }
 
     let mut mouse_devices = crate::MOUSE_DEVICES.write();
@@ -1134,6 +1162,9 @@ fn remove_failed_devices() -> Result<bool> {
         dbus_api_tx
             .send(DbusApiEvent::DeviceHotplug((0, 0), true))
             .unwrap_or_else(|e| error!("Could not send a pending dbus API event: {}", e));
+
+        events::notify_observers(events::Event::DeviceRemoved)
+            .unwrap_or_else(|e| error!("Error during notification of observers: {}", e));
     }
 
     let mut misc_devices = crate::MISC_DEVICES.write();
@@ -1160,6 +1191,9 @@ fn remove_failed_devices() -> Result<bool> {
         dbus_api_tx
             .send(DbusApiEvent::DeviceHotplug((0, 0), true))
             .unwrap_or_else(|e| error!("Could not send a pending dbus API event: {}", e));
+
+        events::notify_observers(events::Event::DeviceRemoved)
+            .unwrap_or_else(|e| error!("Error during notification of observers: {}", e));
     }
 
     Ok(result)
@@ -1252,6 +1286,10 @@ pub fn register_filesystem_watcher(
                                             info!("Script file, manifest or keymap changed: {:?}", event);
 
                                             fsevents_tx_c.send(FileSystemEvent::ScriptChanged).unwrap();
+                                        } else if event.extension().unwrap_or_default().to_string_lossy() == "keymap" {
+                                            info!("Keymap changed: {:?}", event);
+
+                                            fsevents_tx_c.send(FileSystemEvent::KeymapChanged).unwrap();
                                         }
                                     }
 
@@ -1779,6 +1817,8 @@ pub async fn async_main() -> std::result::Result<(), eyre::Error> {
                 // we left the main loop, so send a final message to the running Lua VMs
                 info!("Shutting down all Lua VMs now...");
 
+                transitions::abort();
+
                 *UPCALL_COMPLETED_ON_QUIT.0.lock() = LUA_TXS.read().len();
 
                 for lua_tx in LUA_TXS.read().iter() {
@@ -1805,6 +1845,9 @@ pub async fn async_main() -> std::result::Result<(), eyre::Error> {
                     }
                 }
 
+                // make sure that no injected key is left in the pressed state
+                plugins::MacrosPlugin::release_held_keys_and_wait();
+
                 // store plugin state to disk
                 plugins::PersistencePlugin::store_persistent_data()
                     .unwrap_or_else(|e| error!("Could not write persisted state: {}", e));
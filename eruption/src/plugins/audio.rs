@@ -17,19 +17,25 @@
     Copyright (c) 2019-2022, The Eruption Development Team
 */
 
+use evdev_rs::enums::{EventCode, EV_KEY};
 use lazy_static::lazy_static;
 use log::*;
 use mlua::prelude::*;
 use parking_lot::{Mutex, RwLock};
-use std::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::{
     any::Any,
     time::{Duration, Instant},
 };
 
-use crate::events;
+use crate::audio::spectrum::DEFAULT_DB_RANGE;
+use crate::audio::{
+    BandEnergy, Beat, BeatDetector, SpectrumAnalyzer, SpectrumConfig, TransportStats,
+};
 use crate::plugins::{self, Plugin};
+use crate::{constants, events, script};
 
 pub mod protocol {
     include!(concat!(env!("OUT_DIR"), "/audio_proxy.rs"));
@@ -41,6 +47,12 @@ pub type Result<T> = std::result::Result<T, eyre::Error>;
 pub enum AudioPluginError {
     #[error("Audio grabber error: {description}")]
     GrabberError { description: String },
+
+    #[error("Invalid crossover frequencies: {low} Hz and {high} Hz")]
+    InvalidCrossover { low: f32, high: f32 },
+
+    #[error("Invalid capture source: {description}")]
+    InvalidCaptureSource { description: String },
 }
 
 /// The allocated size of the audio grabber buffer
@@ -52,6 +64,9 @@ pub const FFT_SIZE: usize = 1024;
 /// Running average of the loudness of the signal in the audio grabber buffer
 static CURRENT_RMS: AtomicIsize = AtomicIsize::new(0);
 
+/// Number of beats that have been detected since startup
+static BEAT_COUNTER: AtomicU64 = AtomicU64::new(0);
+
 static ERROR_RATE_LIMIT_MILLIS: u64 = 10000;
 
 lazy_static! {
@@ -68,29 +83,144 @@ lazy_static! {
     /// Spectrum analyzer state
     static ref AUDIO_SPECTRUM: Arc<RwLock<Vec<f32>>> = Arc::new(RwLock::new(vec![0.0; FFT_SIZE / 2]));
 
+    /// Perceptual spectrum analyzer state, shared by all scripts
+    static ref SPECTRUM_ANALYZER: Arc<Mutex<SpectrumAnalyzer>> = Arc::new(Mutex::new(SpectrumAnalyzer::new(constants::AUDIO_SAMPLE_RATE, constants::AUDIO_CHANNELS)));
+
+    /// Beat detection and per-band energy state
+    static ref BEAT_DETECTOR: Arc<Mutex<BeatDetector>> = Arc::new(Mutex::new(BeatDetector::new(constants::AUDIO_SAMPLE_RATE, constants::AUDIO_CHANNELS)));
+
     /// Global "sound effects enabled" flag
     pub static ref ENABLE_SFX: AtomicBool = AtomicBool::new(false);
+
+    /// Statistics of the connection to the audio proxy
+    static ref TRANSPORT_STATS: Arc<TransportStats> = Arc::new(TransportStats::default());
 }
 
 // Record audio?
 pub static AUDIO_GRABBER_RECORD_AUDIO: AtomicBool = AtomicBool::new(false);
 static AUDIO_GRABBER_RECORDING: AtomicBool = AtomicBool::new(false);
 
-// Enable computation of RMS and Spectrum Analyzer data?
+// Enable computation of RMS, Spectrum Analyzer, perceptual spectrum and beat detection data?
 static AUDIO_GRABBER_PERFORM_RMS_COMPUTATION: AtomicBool = AtomicBool::new(false);
 static AUDIO_GRABBER_PERFORM_FFT_COMPUTATION: AtomicBool = AtomicBool::new(false);
+static AUDIO_GRABBER_PERFORM_SPECTRUM_ANALYSIS: AtomicBool = AtomicBool::new(false);
+static AUDIO_GRABBER_PERFORM_BEAT_DETECTION: AtomicBool = AtomicBool::new(false);
 
 pub fn reset_audio_backend() {
     AUDIO_GRABBER_RECORD_AUDIO.store(false, Ordering::SeqCst);
 
     AUDIO_GRABBER_PERFORM_RMS_COMPUTATION.store(false, Ordering::SeqCst);
     AUDIO_GRABBER_PERFORM_FFT_COMPUTATION.store(false, Ordering::SeqCst);
+    AUDIO_GRABBER_PERFORM_SPECTRUM_ANALYSIS.store(false, Ordering::SeqCst);
+    AUDIO_GRABBER_PERFORM_BEAT_DETECTION.store(false, Ordering::SeqCst);
 
     *RATE_LIMIT_TIME.write() = Instant::now()
         .checked_sub(Duration::from_millis(ERROR_RATE_LIMIT_MILLIS))
         .unwrap();
 }
 
+/// Discard the samples and the analysis results of the last recorded audio, e.g. after the
+/// audio proxy disconnected, so that effects do not freeze on stale audio data
+fn clear_audio_buffers() {
+    AUDIO_GRABBER_BUFFER.write().fill(0);
+    AUDIO_SPECTRUM.write().fill(0.0);
+
+    CURRENT_RMS.store(0, Ordering::SeqCst);
+}
+
+/// Notify all Lua VMs about a detected beat
+fn notify_beat(beat: Beat) {
+    BEAT_COUNTER.fetch_add(1, Ordering::SeqCst);
+
+    for (idx, lua_tx) in crate::LUA_TXS.read().iter().enumerate() {
+        if !crate::FAILED_TXS.read().contains(&idx) {
+            lua_tx
+                .send(script::Message::AudioBeat {
+                    bpm: beat.bpm,
+                    strength: beat.strength,
+                })
+                .unwrap_or_else(|e| error!("Could not send an event to a Lua VM: {}", e));
+        }
+    }
+}
+
+/// Name of the sound effect for a key event; the class allows sound effects packs
+/// to map e.g. modifier keys to a different sound, see `SoundPack::lookup` of the audio proxy
+fn key_sfx_name(code: EV_KEY, down: bool) -> String {
+    use EV_KEY::*;
+
+    let event = if down { "key-down" } else { "key-up" };
+
+    let class = match code {
+        KEY_A | KEY_B | KEY_C | KEY_D | KEY_E | KEY_F | KEY_G | KEY_H | KEY_I | KEY_J | KEY_K
+        | KEY_L | KEY_M | KEY_N | KEY_O | KEY_P | KEY_Q | KEY_R | KEY_S | KEY_T | KEY_U | KEY_V
+        | KEY_W | KEY_X | KEY_Y | KEY_Z => "letter",
+
+        KEY_1 | KEY_2 | KEY_3 | KEY_4 | KEY_5 | KEY_6 | KEY_7 | KEY_8 | KEY_9 | KEY_0 => "digit",
+
+        KEY_LEFTSHIFT | KEY_RIGHTSHIFT | KEY_LEFTCTRL | KEY_RIGHTCTRL | KEY_LEFTALT
+        | KEY_RIGHTALT | KEY_LEFTMETA | KEY_RIGHTMETA | KEY_CAPSLOCK | KEY_COMPOSE => "modifier",
+
+        KEY_ESC | KEY_F1 | KEY_F2 | KEY_F3 | KEY_F4 | KEY_F5 | KEY_F6 | KEY_F7 | KEY_F8
+        | KEY_F9 | KEY_F10 | KEY_F11 | KEY_F12 | KEY_F13 | KEY_F14 | KEY_F15 | KEY_F16
+        | KEY_F17 | KEY_F18 | KEY_F19 | KEY_F20 | KEY_F21 | KEY_F22 | KEY_F23 | KEY_F24 => {
+            "function"
+        }
+
+        KEY_UP | KEY_DOWN | KEY_LEFT | KEY_RIGHT | KEY_HOME | KEY_END | KEY_PAGEUP
+        | KEY_PAGEDOWN => "navigation",
+
+        KEY_ENTER | KEY_BACKSPACE | KEY_DELETE | KEY_INSERT | KEY_TAB | KEY_SPACE => "editing",
+
+        KEY_NUMLOCK | KEY_KP0 | KEY_KP1 | KEY_KP2 | KEY_KP3 | KEY_KP4 | KEY_KP5 | KEY_KP6
+        | KEY_KP7 | KEY_KP8 | KEY_KP9 | KEY_KPDOT | KEY_KPPLUS | KEY_KPMINUS | KEY_KPASTERISK
+        | KEY_KPSLASH | KEY_KPENTER => "numpad",
+
+        _ => return event.to_string(),
+    };
+
+    format!("{}.{}", event, class)
+}
+
+/// Name of the sound effect for an event, if the event should be audible
+fn event_sfx_name(event: &events::Event) -> Option<String> {
+    match event {
+        events::Event::RawKeyboardEvent(raw_event) => match raw_event.event_code {
+            // auto-repeated key events are not audible
+            EventCode::EV_KEY(code) if raw_event.value < 2 => {
+                Some(key_sfx_name(code, raw_event.value == 1))
+            }
+
+            _ => None,
+        },
+
+        events::Event::RawMouseEvent(raw_event) => match raw_event.event_code {
+            EventCode::EV_KEY(code) if raw_event.value == 1 => {
+                let class = match code {
+                    EV_KEY::BTN_LEFT => "left",
+                    EV_KEY::BTN_RIGHT => "right",
+                    EV_KEY::BTN_MIDDLE => "middle",
+                    _ => "extra",
+                };
+
+                Some(format!("mouse-click.{}", class))
+            }
+
+            _ => None,
+        },
+
+        events::Event::ProfileSwitched => Some("profile-switch".to_string()),
+        events::Event::SlotSwitched(_) => Some("slot-switch".to_string()),
+
+        events::Event::DeviceAdded => Some("hotplug.added".to_string()),
+        events::Event::DeviceRemoved => Some("hotplug.removed".to_string()),
+
+        events::Event::MacroFired => Some("macro".to_string()),
+
+        _ => None,
+    }
+}
+
 fn try_start_audio_backend() -> Result<()> {
     AUDIO_BACKEND
         .lock()
@@ -144,6 +274,171 @@ impl AudioPlugin {
         AUDIO_SPECTRUM.read().clone()
     }
 
+    /// Returns the bands of the spectrum with the given configuration. The FFT is shared
+    /// between all scripts that request the same FFT size and window function
+    pub fn get_audio_spectrum_bands(config: &SpectrumConfig) -> Vec<f32> {
+        AUDIO_GRABBER_RECORD_AUDIO.store(true, Ordering::SeqCst);
+        AUDIO_GRABBER_PERFORM_SPECTRUM_ANALYSIS.store(true, Ordering::Relaxed);
+
+        SPECTRUM_ANALYZER.lock().get(config, Instant::now())
+    }
+
+    /// Build a spectrum configuration from the options table passed by a Lua script
+    fn parse_spectrum_config(
+        bands: usize,
+        options: Option<mlua::Table>,
+    ) -> LuaResult<SpectrumConfig> {
+        let mut config = SpectrumConfig {
+            bands,
+            ..Default::default()
+        };
+
+        if let Some(options) = options {
+            if let Some(window) = options.get::<_, Option<String>>("window")? {
+                config.window = window.parse().map_err(LuaError::external)?;
+            }
+
+            if let Some(scale) = options.get::<_, Option<String>>("scale")? {
+                config.scale = scale.parse().map_err(LuaError::external)?;
+            }
+
+            if let Some(fft_size) = options.get("fft_size")? {
+                config.fft_size = fft_size;
+            }
+
+            if let Some(min_frequency) = options.get("min_freq")? {
+                config.min_frequency = min_frequency;
+            }
+
+            if let Some(max_frequency) = options.get("max_freq")? {
+                config.max_frequency = max_frequency;
+            }
+
+            if let Some(attack_millis) = options.get("attack")? {
+                config.attack_millis = attack_millis;
+            }
+
+            if let Some(decay_millis) = options.get("decay")? {
+                config.decay_millis = decay_millis;
+            }
+
+            if options.get::<_, Option<bool>>("db")?.unwrap_or(false) {
+                config.db_range = Some((
+                    options
+                        .get::<_, Option<i32>>("min_db")?
+                        .unwrap_or(DEFAULT_DB_RANGE.0),
+                    options
+                        .get::<_, Option<i32>>("max_db")?
+                        .unwrap_or(DEFAULT_DB_RANGE.1),
+                ));
+            }
+        }
+
+        config
+            .validate(constants::AUDIO_SAMPLE_RATE)
+            .map_err(LuaError::external)?;
+
+        Ok(config)
+    }
+
+    /// Start recording audio and detecting beats, e.g. when a script handles `on_beat`
+    pub fn enable_beat_detection() {
+        AUDIO_GRABBER_RECORD_AUDIO.store(true, Ordering::SeqCst);
+        AUDIO_GRABBER_PERFORM_BEAT_DETECTION.store(true, Ordering::Relaxed);
+    }
+
+    pub fn get_audio_bpm() -> f32 {
+        Self::enable_beat_detection();
+
+        BEAT_DETECTOR.lock().bpm()
+    }
+
+    pub fn get_audio_beat_phase() -> f32 {
+        Self::enable_beat_detection();
+
+        BEAT_DETECTOR.lock().beat_phase()
+    }
+
+    /// Returns the number of beats that have been detected since startup
+    pub fn get_audio_beat_count() -> u64 {
+        Self::enable_beat_detection();
+
+        BEAT_COUNTER.load(Ordering::SeqCst)
+    }
+
+    pub fn get_audio_onset() -> f32 {
+        Self::enable_beat_detection();
+
+        BEAT_DETECTOR.lock().onset()
+    }
+
+    pub fn get_audio_bands() -> BandEnergy {
+        Self::enable_beat_detection();
+
+        BEAT_DETECTOR.lock().bands()
+    }
+
+    pub fn set_audio_crossover(low: f32, high: f32) -> Result<()> {
+        let nyquist = constants::AUDIO_SAMPLE_RATE as f32 / 2.0;
+
+        if low <= 0.0 || low >= high || high >= nyquist {
+            return Err(AudioPluginError::InvalidCrossover { low, high }.into());
+        }
+
+        BEAT_DETECTOR.lock().set_crossover(low, high);
+
+        Ok(())
+    }
+
+    /// Select the device or application the audio proxy captures audio from, e.g.
+    /// `default-monitor`, `default-source`, `monitor:<sink>`, `source:<name>` or `application:<name>`
+    pub fn set_audio_capture_source(source: &str) -> Result<()> {
+        let is_valid = match source.split_once(':') {
+            Some(("monitor" | "source" | "application", name)) => !name.trim().is_empty(),
+            Some(_) => false,
+            None => matches!(source, "default-monitor" | "default-source"),
+        };
+
+        if !is_valid {
+            return Err(AudioPluginError::InvalidCaptureSource {
+                description: source.to_owned(),
+            }
+            .into());
+        }
+
+        if let Some(backend) = &*AUDIO_BACKEND.lock() {
+            backend.set_capture_source(source)?;
+        }
+
+        Ok(())
+    }
+
+    /// Play the sound effect `name` of the active sound effects pack. The gain is in the
+    /// range [0..4], the stereo position `pan` in the range [-1..1] from left to right
+    pub fn play_sfx(name: &str, gain: f32, pan: f32) -> Result<()> {
+        if let Some(backend) = &*AUDIO_BACKEND.lock() {
+            backend.play_sfx(name, gain, pan)?;
+        }
+
+        Ok(())
+    }
+
+    /// Select the sound effects pack of the active profile. Profiles that do not specify
+    /// a pack use the one from the configuration file of the audio proxy
+    pub fn select_sfx_pack() {
+        let sfx_pack = crate::ACTIVE_PROFILE
+            .lock()
+            .as_ref()
+            .and_then(|profile| profile.sfx_pack.clone())
+            .unwrap_or_default();
+
+        if let Some(backend) = &*AUDIO_BACKEND.lock() {
+            backend
+                .set_sfx_pack(&sfx_pack)
+                .unwrap_or_else(|e| error!("Could not select the sound effects pack: {}", e));
+        }
+    }
+
     pub fn get_audio_raw_data() -> Vec<i16> {
         AUDIO_GRABBER_RECORD_AUDIO.store(true, Ordering::SeqCst);
         AUDIO_GRABBER_BUFFER.read().to_vec()
@@ -164,6 +459,16 @@ impl AudioPlugin {
             false
         }
     }
+
+    /// Status of the connection to the audio proxy: whether a proxy is connected, the
+    /// version of its protocol, and the counters of received, dropped and rejected frames
+    pub fn get_audio_proxy_status() -> (bool, u32, HashMap<String, u64>) {
+        (
+            TRANSPORT_STATS.is_connected(),
+            TRANSPORT_STATS.protocol_version(),
+            TRANSPORT_STATS.counters(),
+        )
+    }
 }
 
 #[async_trait::async_trait]
@@ -180,25 +485,13 @@ impl Plugin for AudioPlugin {
         start_audio_proxy_thread()?;
 
         events::register_observer(|event: &events::Event| {
-            match event {
-                events::Event::KeyDown(_index) => {
-                    if ENABLE_SFX.load(Ordering::SeqCst) {
-                        if let Some(backend) = AUDIO_BACKEND.lock().as_ref() {
-                            backend.play_sfx(0)?;
-                        }
-                    }
-                }
-
-                events::Event::KeyUp(_index) => {
-                    if ENABLE_SFX.load(Ordering::SeqCst) {
-                        if let Some(backend) = AUDIO_BACKEND.lock().as_ref() {
-                            backend.play_sfx(1)?;
-                        }
+            if ENABLE_SFX.load(Ordering::SeqCst) {
+                if let Some(name) = event_sfx_name(event) {
+                    if let Some(backend) = AUDIO_BACKEND.lock().as_ref() {
+                        backend.play_sfx(&name, 1.0, 0.0)?;
                     }
                 }
-
-                _ => (),
-            };
+            }
 
             Ok(true) // event has been processed
         });
@@ -221,6 +514,52 @@ impl Plugin for AudioPlugin {
             lua_ctx.create_function(move |_, ()| Ok(AudioPlugin::get_audio_raw_data()))?;
         globals.set("get_audio_raw_data", get_audio_raw_data)?;
 
+        let get_audio_bpm =
+            lua_ctx.create_function(move |_, ()| Ok(AudioPlugin::get_audio_bpm()))?;
+        globals.set("get_audio_bpm", get_audio_bpm)?;
+
+        let get_audio_beat_phase =
+            lua_ctx.create_function(move |_, ()| Ok(AudioPlugin::get_audio_beat_phase()))?;
+        globals.set("get_audio_beat_phase", get_audio_beat_phase)?;
+
+        let get_audio_onset =
+            lua_ctx.create_function(move |_, ()| Ok(AudioPlugin::get_audio_onset()))?;
+        globals.set("get_audio_onset", get_audio_onset)?;
+
+        let get_audio_bands = lua_ctx.create_function(
+            move |lua, (bands, options): (Option<usize>, Option<mlua::Table>)| match bands {
+                // without arguments, return the energy of the bass, mid and treble bands
+                None => {
+                    let bands = AudioPlugin::get_audio_bands();
+                    lua.pack_multi((bands.bass, bands.mid, bands.treble))
+                }
+
+                Some(bands) => {
+                    let config = AudioPlugin::parse_spectrum_config(bands, options)?;
+                    lua.pack_multi(AudioPlugin::get_audio_spectrum_bands(&config))
+                }
+            },
+        )?;
+        globals.set("get_audio_bands", get_audio_bands)?;
+
+        let set_audio_crossover = lua_ctx.create_function(move |_, (low, high): (f32, f32)| {
+            AudioPlugin::set_audio_crossover(low, high).map_err(LuaError::external)
+        })?;
+        globals.set("set_audio_crossover", set_audio_crossover)?;
+
+        let play_sfx = lua_ctx.create_function(
+            move |_, (name, gain, pan): (String, Option<f32>, Option<f32>)| {
+                AudioPlugin::play_sfx(&name, gain.unwrap_or(1.0), pan.unwrap_or(0.0))
+                    .map_err(LuaError::external)
+            },
+        )?;
+        globals.set("play_sfx", play_sfx)?;
+
+        let set_audio_capture_source = lua_ctx.create_function(move |_, source: String| {
+            AudioPlugin::set_audio_capture_source(&source).map_err(LuaError::external)
+        })?;
+        globals.set("set_audio_capture_source", set_audio_capture_source)?;
+
         let is_audio_muted =
             lua_ctx.create_function(move |_, ()| Ok(AudioPlugin::is_audio_muted()))?;
         globals.set("is_audio_muted", is_audio_muted)?;
@@ -249,22 +588,24 @@ mod backends {
     use crate::plugins::audio::{protocol, AUDIO_GRABBER_RECORDING, AUDIO_GRABBER_RECORD_AUDIO};
     use crate::{constants, script};
 
+    use crate::audio::Connection;
+
     use super::AudioPluginError;
     use super::Result;
     use super::AUDIO_GRABBER_BUFFER;
-    use super::AUDIO_GRABBER_BUFFER_SIZE;
     use super::AUDIO_SPECTRUM;
+    use super::BEAT_DETECTOR;
     use super::CURRENT_RMS;
     use super::FFT_SIZE;
+    use super::SPECTRUM_ANALYZER;
+    use super::TRANSPORT_STATS;
 
     use flume::{self, unbounded, Receiver, Sender};
     use lazy_static::lazy_static;
     use log::*;
     use nix::unistd::unlink;
     use parking_lot::Mutex;
-    use prost::Message;
     use std::fs;
-    use std::io::Cursor;
     use std::os::unix::prelude::PermissionsExt;
     use std::sync::atomic::AtomicBool;
     use std::sync::atomic::AtomicI32;
@@ -277,29 +618,41 @@ mod backends {
     use rustfft::{algorithm::Radix4, FftDirection};
     use socket2::{Domain, SockAddr, Socket, Type};
     use std::f32::consts::PI;
-    use std::mem::MaybeUninit;
     use std::os::unix::io::AsRawFd;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
 
     use protocol::response::Payload;
 
     lazy_static! {
         pub static ref LISTENER: Arc<Mutex<Option<Socket>>> = Arc::new(Mutex::new(None));
 
-        pub static ref SFX_TX: Arc<Mutex<Option<Sender<u32>>>> = Arc::new(Mutex::new(None));
-        pub static ref SFX_RX: Arc<Mutex<Option<Receiver<u32>>>> = Arc::new(Mutex::new(None));
+        pub static ref SFX_TX: Arc<Mutex<Option<Sender<protocol::Sound>>>> = Arc::new(Mutex::new(None));
+        pub static ref SFX_RX: Arc<Mutex<Option<Receiver<protocol::Sound>>>> = Arc::new(Mutex::new(None));
 
         /// Audio device master volume
         static ref MASTER_VOLUME: AtomicI32 = AtomicI32::new(0);
 
         /// Audio device master volume
         static ref AUDIO_MUTED: AtomicBool = AtomicBool::new(false);
+
+        /// The capture source selected by a script, if any
+        static ref CAPTURE_SOURCE: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+
+        /// Set if the selected capture source has not yet been sent to the audio proxy
+        static ref CAPTURE_SOURCE_PENDING: AtomicBool = AtomicBool::new(false);
+
+        /// The sound effects pack of the active profile, empty for the default pack
+        static ref SFX_PACK: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+
+        /// Set if the selected sound effects pack has not yet been sent to the audio proxy
+        static ref SFX_PACK_PENDING: AtomicBool = AtomicBool::new(false);
     }
 
     /// Audio backend trait, defines an interface to the player and
     /// grabber functionality
     pub trait AudioBackend {
-        fn play_sfx(&self, id: u32) -> Result<()>;
+        fn play_sfx(&self, name: &str, gain: f32, pan: f32) -> Result<()>;
+        fn set_sfx_pack(&self, pack: &str) -> Result<()>;
 
         fn start_audio_grabber(&self) -> Result<()>;
         fn stop_audio_grabber(&self) -> Result<()>;
@@ -307,13 +660,19 @@ mod backends {
         fn get_master_volume(&self) -> Result<isize>;
 
         fn is_audio_muted(&self) -> Result<bool>;
+
+        fn set_capture_source(&self, source: &str) -> Result<()>;
     }
 
     /// An audio backend that does nothing
     pub struct NullBackend {}
 
     impl AudioBackend for NullBackend {
-        fn play_sfx(&self, _id: u32) -> Result<()> {
+        fn play_sfx(&self, _name: &str, _gain: f32, _pan: f32) -> Result<()> {
+            Ok(())
+        }
+
+        fn set_sfx_pack(&self, _pack: &str) -> Result<()> {
             Ok(())
         }
 
@@ -332,6 +691,10 @@ mod backends {
         fn is_audio_muted(&self) -> Result<bool> {
             Ok(false)
         }
+
+        fn set_capture_source(&self, _source: &str) -> Result<()> {
+            Ok(())
+        }
     }
 
     /// An audio backend that uses the eruption-audio-proxy
@@ -355,7 +718,7 @@ mod backends {
 
             LISTENER.lock().replace(listener);
 
-            let (tx, rx): (Sender<u32>, Receiver<u32>) = unbounded();
+            let (tx, rx): (Sender<protocol::Sound>, Receiver<protocol::Sound>) = unbounded();
 
             *SFX_TX.lock() = Some(tx);
             *SFX_RX.lock() = Some(rx);
@@ -364,10 +727,6 @@ mod backends {
         }
 
         fn run_io_loop() -> Result<()> {
-            unsafe fn assume_init(buf: &[MaybeUninit<u8>]) -> &[u8] {
-                &*(buf as *const [MaybeUninit<u8>] as *const [u8])
-            }
-
             'IO_LOOP: loop {
                 if crate::QUIT.load(Ordering::SeqCst) {
                     break 'IO_LOOP;
@@ -378,12 +737,28 @@ mod backends {
 
                     match listener.accept() {
                         Ok((socket, _sockaddr)) => {
-                            info!("Audio proxy connected");
-
                             // socket.set_nodelay(true)?; // not supported on AF_UNIX on Linux
                             socket.set_send_buffer_size(constants::NET_BUFFER_CAPACITY * 2)?;
                             socket.set_recv_buffer_size(constants::NET_BUFFER_CAPACITY * 2)?;
 
+                            let mut connection = match Connection::accept(
+                                socket,
+                                TRANSPORT_STATS.clone(),
+                                Duration::from_millis(
+                                    constants::AUDIO_PROXY_HANDSHAKE_TIMEOUT_MILLIS,
+                                ),
+                            ) {
+                                Ok(connection) => connection,
+
+                                Err(e) => {
+                                    error!("Could not connect to the audio proxy: {}", e);
+
+                                    continue 'IO_LOOP;
+                                }
+                            };
+
+                            info!("Audio proxy connected: {}", connection.peer());
+
                             // if the newly connected proxy has been restarted while we were already
                             // processing audio samples, we have to notify it now to resume
                             // recording of audio samples
@@ -393,20 +768,17 @@ mod backends {
                                 let mut command = protocol::Command::default();
                                 command.set_command_type(protocol::CommandType::StartRecording);
 
-                                let mut buf = Vec::new();
-                                command.encode_length_delimited(&mut buf)?;
+                                connection.send(&command)?;
+                            }
 
-                                // send data
-                                match socket.send(&buf) {
-                                    Ok(_n) => {}
+                            // the newly connected proxy starts with the capture source from its
+                            // configuration file, so re-send the capture source selected by a script
+                            if CAPTURE_SOURCE.lock().is_some() {
+                                CAPTURE_SOURCE_PENDING.store(true, Ordering::SeqCst);
+                            }
 
-                                    Err(_e) => {
-                                        return Err(AudioPluginError::GrabberError {
-                                            description: "Lost connection to proxy".to_owned(),
-                                        }
-                                        .into());
-                                    }
-                                }
+                            if SFX_PACK.lock().is_some() {
+                                SFX_PACK_PENDING.store(true, Ordering::SeqCst);
                             }
 
                             // connection successful, enter event loop now
@@ -415,26 +787,38 @@ mod backends {
                                     break 'EVENT_LOOP;
                                 }
 
-                                let pending_sfx_id = if let Some(ref rx) = *SFX_RX.lock() {
-                                    // do we have any requests to play a sound effect?
+                                // the proxy of a user session that is being torn down may hang,
+                                // disconnect it so that the proxy of the next session is able to connect
+                                if connection.is_stale() {
+                                    warn!("The audio proxy stopped responding, disconnecting");
+
+                                    break 'EVENT_LOOP;
+                                }
+
+                                let pending_sfx = if let Some(ref rx) = *SFX_RX.lock() {
+                                    // do we have any requests to play sound effects?
                                     match rx.recv_timeout(Duration::from_millis(1)) {
-                                        Ok(idx) => {
-                                            trace!("Play back SFX with ID: {}", idx);
-                                            Some(idx)
+                                        Ok(sound) => {
+                                            let pending_sfx = std::iter::once(sound)
+                                                .chain(rx.try_iter())
+                                                .collect::<Vec<_>>();
+
+                                            trace!("Play back SFX: {:?}", pending_sfx);
+                                            pending_sfx
                                         }
 
                                         Err(_e) => {
                                             // nothing to do
-                                            None
+                                            Vec::new()
                                         }
                                     }
                                 } else {
-                                    None
+                                    Vec::new()
                                 };
 
                                 // wait for socket to be ready
                                 let mut poll_fds = [PollFd::new(
-                                    socket.as_raw_fd(),
+                                    connection.as_raw_fd(),
                                     PollFlags::POLLIN
                                         | PollFlags::POLLOUT
                                         | PollFlags::POLLHUP
@@ -455,222 +839,145 @@ mod backends {
                                 if result > 0 {
                                     if poll_fds[0].revents().unwrap().contains(PollFlags::POLLIN) {
                                         // read data
-                                        let mut tmp =
-                                            [MaybeUninit::zeroed(); constants::NET_BUFFER_CAPACITY];
-                                        match socket.recv(&mut tmp) {
-                                            Ok(0) => {
-                                                info!("Audio proxy disconnected");
-
-                                                break 'EVENT_LOOP;
-                                            }
+                                        match connection.receive() {
+                                            Ok(Some(response)) => match response.response_type() {
+                                                protocol::CommandType::AudioData => {
+                                                    trace!("Received audio data");
+
+                                                    // frames with an unsupported sample format are rejected
+                                                    let samples =
+                                                        if let Some(Payload::Frame(frame)) =
+                                                            response.payload
+                                                        {
+                                                            connection.decode_frame(&frame)
+                                                        } else {
+                                                            error!("Invalid payload received");
+
+                                                            None
+                                                        };
+
+                                                    if let Some(samples) = samples {
+                                                        process_samples(samples);
+                                                    }
+                                                }
 
-                                            Ok(n) => {
-                                                trace!("Read {} bytes from audio socket", n);
+                                                protocol::CommandType::AudioVolume => {
+                                                    if let Some(Payload::Volume(val)) =
+                                                        response.payload
+                                                    {
+                                                        trace!("Master volume: {}", val);
 
-                                                let tmp = unsafe { assume_init(&tmp[..tmp.len()]) };
+                                                        let tmp =
+                                                            MASTER_VOLUME.load(Ordering::SeqCst);
 
-                                                if tmp.len() != constants::NET_BUFFER_CAPACITY {
-                                                    error!("Buffer length differs from BUFFER_CAPACITY! Length: {}", tmp.len());
-                                                }
+                                                        MASTER_VOLUME.store(val, Ordering::SeqCst);
 
-                                                let result =
-                                                    protocol::Response::decode_length_delimited(
-                                                        &mut Cursor::new(&tmp),
-                                                    );
-                                                match result {
-                                                    Ok(response) => match response.response_type() {
-                                                        protocol::CommandType::AudioData => {
-                                                            trace!("Received audio data");
-
-                                                            if let Some(Payload::Data(tmp)) =
-                                                                response.payload
-                                                            {
-                                                                let mut buffer =
-                                                                    AUDIO_GRABBER_BUFFER.write();
-                                                                buffer.clear();
-
-                                                                buffer.reserve(
-                                                                    AUDIO_GRABBER_BUFFER_SIZE / 2,
-                                                                );
-                                                                buffer.extend(
-                                                                    tmp.chunks_exact(2).map(|c| {
-                                                                        i16::from_ne_bytes([
-                                                                            c[0], c[1],
-                                                                        ])
-                                                                    }),
-                                                                );
-
-                                                                if buffer.len() < FFT_SIZE {
-                                                                    buffer.resize(FFT_SIZE, 0x0000);
-                                                                }
-
-                                                                // compute root mean square (RMS) of the recorded samples
-                                                                if super::AUDIO_GRABBER_PERFORM_RMS_COMPUTATION
-                                                                    .load(Ordering::Relaxed)
-                                                                {
-                                                                    let sqr_sum = buffer
-                                                                        .iter()
-                                                                        .map(|s| *s as f32)
-                                                                        .fold(0.0, |sqr_sum, s| sqr_sum + s * s);
-
-                                                                    let sqr_sum =
-                                                                        (sqr_sum / buffer.len() as f32).sqrt();
-
-                                                                    CURRENT_RMS.store(
-                                                                        sqr_sum.round() as isize,
-                                                                        Ordering::SeqCst,
-                                                                    );
-                                                                }
-
-                                                                // compute spectrum analyzer
-                                                                if super::AUDIO_GRABBER_PERFORM_FFT_COMPUTATION
-                                                                    .load(Ordering::Relaxed)
-                                                                {
-                                                                    let mut data: Vec<Complex<f32>> = buffer
-                                                                        .iter()
-                                                                        .take(FFT_SIZE)
-                                                                        .map(|e| Complex::from(*e as f32))
-                                                                        .collect();
-
-                                                                    let fft = Radix4::new(
-                                                                        FFT_SIZE,
-                                                                        FftDirection::Forward,
-                                                                    );
-                                                                    fft.process(&mut data);
-
-                                                                    // apply post processing steps: normalization, window function and smoothing
-                                                                    let one_over_fft_len_sqrt =
-                                                                        1.0 / ((FFT_SIZE / 2) as f32).sqrt();
-
-                                                                    let mut phase = 0.0;
-                                                                    const DELTA: f32 =
-                                                                        (2.0 * PI) / (FFT_SIZE / 2) as f32;
-
-                                                                    let result: Vec<f32> = data[(FFT_SIZE / 2)..]
-                                                                        .iter()
-                                                                        // normalize
-                                                                        .map(|e| {
-                                                                            ((e.re as f32) * one_over_fft_len_sqrt)
-                                                                                .abs()
-                                                                        })
-                                                                        // apply Hamming window
-                                                                        .map(|e| {
-                                                                            phase += DELTA;
-                                                                            e * (0.54 - 0.46 * phase.cos())
-                                                                        })
-                                                                        .collect();
-
-                                                                    for (i, e) in AUDIO_SPECTRUM
-                                                                        .write()
-                                                                        .iter_mut()
-                                                                        .enumerate()
-                                                                    {
-                                                                        *e = (*e + result[i]) / 2.0;
-                                                                    }
-                                                                }
-                                                            } else {
-                                                                error!("Invalid payload received");
-                                                            };
+                                                        if tmp != val {
+                                                            script::FRAME_GENERATION_COUNTER
+                                                                .fetch_add(1, Ordering::SeqCst);
                                                         }
+                                                    } else {
+                                                        error!("Invalid payload received");
+                                                    };
+                                                }
 
-                                                        protocol::CommandType::AudioVolume => {
-                                                            if let Some(Payload::Volume(val)) =
-                                                                response.payload
-                                                            {
-                                                                trace!("Master volume: {}", val);
-
-                                                                let tmp = MASTER_VOLUME
-                                                                    .load(Ordering::SeqCst);
-
-                                                                MASTER_VOLUME
-                                                                    .store(val, Ordering::SeqCst);
-
-                                                                if tmp != val {
-                                                                    script::FRAME_GENERATION_COUNTER
-                                                                        .fetch_add(1, Ordering::SeqCst);
-                                                                }
-                                                            } else {
-                                                                error!("Invalid payload received");
-                                                            };
-                                                        }
+                                                protocol::CommandType::AudioMutedState => {
+                                                    if let Some(Payload::Muted(val)) =
+                                                        response.payload
+                                                    {
+                                                        trace!("Audio muted: {}", val);
 
-                                                        protocol::CommandType::AudioMutedState => {
-                                                            if let Some(Payload::Muted(val)) =
-                                                                response.payload
-                                                            {
-                                                                trace!("Audio muted: {}", val);
-
-                                                                let tmp = AUDIO_MUTED
-                                                                    .load(Ordering::SeqCst);
-
-                                                                AUDIO_MUTED
-                                                                    .store(val, Ordering::SeqCst);
-
-                                                                if tmp != val {
-                                                                    script::FRAME_GENERATION_COUNTER
-                                                                        .fetch_add(1, Ordering::SeqCst);
-                                                                }
-                                                            } else {
-                                                                error!("Invalid payload received");
-                                                            };
-                                                        }
+                                                        let tmp =
+                                                            AUDIO_MUTED.load(Ordering::SeqCst);
 
-                                                        protocol::CommandType::Noop => {
-                                                            /* Do nothing */
+                                                        AUDIO_MUTED.store(val, Ordering::SeqCst);
 
-                                                            trace!("NOOP");
+                                                        if tmp != val {
+                                                            script::FRAME_GENERATION_COUNTER
+                                                                .fetch_add(1, Ordering::SeqCst);
                                                         }
+                                                    } else {
+                                                        error!("Invalid payload received");
+                                                    };
+                                                }
 
-                                                        _ => { /* Do nothing */ }
-                                                    },
-
-                                                    Err(e) => {
-                                                        error!("Protocol error: {}", e);
+                                                protocol::CommandType::Noop => {
+                                                    /* Do nothing */
 
-                                                        // break 'EVENT_LOOP;
-                                                    }
+                                                    trace!("NOOP");
                                                 }
+
+                                                _ => { /* Do nothing */ }
+                                            },
+
+                                            Ok(None) => {
+                                                // the packet could not be decoded, the error has already been logged
                                             }
 
-                                            Err(_e) => {
-                                                return Err(AudioPluginError::GrabberError {
-                                                    description: "Lost connection to proxy"
-                                                        .to_owned(),
-                                                }
-                                                .into());
+                                            Err(e) => {
+                                                info!("Audio proxy disconnected: {}", e);
+
+                                                break 'EVENT_LOOP;
                                             }
                                         }
                                     }
 
                                     if poll_fds[0].revents().unwrap().contains(PollFlags::POLLOUT) {
-                                        // pending sound effect?
-                                        if let Some(sfx_id) = pending_sfx_id {
+                                        // pending switch of the sound effects pack?
+                                        if SFX_PACK_PENDING.swap(false, Ordering::SeqCst) {
+                                            if let Some(pack) = SFX_PACK.lock().clone() {
+                                                debug!(
+                                                    "Switching the sound effects pack to: '{}'",
+                                                    pack
+                                                );
+
+                                                let mut command = protocol::Command::default();
+                                                command.set_command_type(
+                                                    protocol::CommandType::SetSfxPack,
+                                                );
+
+                                                command.payload =
+                                                    Some(protocol::command::Payload::Name(pack));
+
+                                                connection.send(&command)?;
+                                            }
+                                        }
+
+                                        // pending sound effects?
+                                        for sound in pending_sfx {
                                             debug!(
-                                                "Notifying audio proxy to play SFX with ID: {}",
-                                                sfx_id
+                                                "Notifying audio proxy to play SFX: {:?}",
+                                                sound
                                             );
 
                                             let mut command = protocol::Command::default();
                                             command
-                                                .set_command_type(protocol::CommandType::PlaySfx);
+                                                .set_command_type(protocol::CommandType::PlaySound);
 
                                             command.payload =
-                                                Some(protocol::command::Payload::Id(sfx_id));
+                                                Some(protocol::command::Payload::Sound(sound));
 
-                                            let mut buf = Vec::new();
-                                            command.encode_length_delimited(&mut buf)?;
+                                            connection.send(&command)?;
+                                        }
 
-                                            // send data
-                                            match socket.send(&buf) {
-                                                Ok(_n) => {}
+                                        // pending switch of the capture source?
+                                        if CAPTURE_SOURCE_PENDING.swap(false, Ordering::SeqCst) {
+                                            if let Some(source) = CAPTURE_SOURCE.lock().clone() {
+                                                info!(
+                                                    "Switching the capture source to: {}",
+                                                    source
+                                                );
 
-                                                Err(_e) => {
-                                                    return Err(AudioPluginError::GrabberError {
-                                                        description: "Lost connection to proxy"
-                                                            .to_owned(),
-                                                    }
-                                                    .into());
-                                                }
+                                                let mut command = protocol::Command::default();
+                                                command.set_command_type(
+                                                    protocol::CommandType::SetCaptureSource,
+                                                );
+
+                                                command.payload = Some(
+                                                    protocol::command::Payload::Source(source),
+                                                );
+
+                                                connection.send(&command)?;
                                             }
                                         }
 
@@ -686,21 +993,7 @@ mod backends {
                                                 protocol::CommandType::StartRecording,
                                             );
 
-                                            let mut buf = Vec::new();
-                                            command.encode_length_delimited(&mut buf)?;
-
-                                            // send data
-                                            match socket.send(&buf) {
-                                                Ok(_n) => {}
-
-                                                Err(_e) => {
-                                                    return Err(AudioPluginError::GrabberError {
-                                                        description: "Lost connection to proxy"
-                                                            .to_owned(),
-                                                    }
-                                                    .into());
-                                                }
-                                            }
+                                            connection.send(&command)?;
                                         }
 
                                         if !AUDIO_GRABBER_RECORD_AUDIO.load(Ordering::SeqCst)
@@ -715,21 +1008,7 @@ mod backends {
                                                 protocol::CommandType::StopRecording,
                                             );
 
-                                            let mut buf = Vec::new();
-                                            command.encode_length_delimited(&mut buf)?;
-
-                                            // send data
-                                            match socket.send(&buf) {
-                                                Ok(_n) => {}
-
-                                                Err(_e) => {
-                                                    return Err(AudioPluginError::GrabberError {
-                                                        description: "Lost connection to proxy"
-                                                            .to_owned(),
-                                                    }
-                                                    .into());
-                                                }
-                                            }
+                                            connection.send(&command)?;
                                         }
                                     }
                                 }
@@ -740,6 +1019,8 @@ mod backends {
                                     thread::sleep(Duration::from_millis(15));
                                 }
                             }
+
+                            super::clear_audio_buffers();
                         }
 
                         Err(_e) => {
@@ -756,15 +1037,97 @@ mod backends {
         }
     }
 
+    /// Store the samples of an audio frame, and perform the analyses requested by the scripts
+    fn process_samples(samples: Vec<i16>) {
+        let mut buffer = AUDIO_GRABBER_BUFFER.write();
+        *buffer = samples;
+
+        // detect onsets and beats, and compute the energy of each band
+        if super::AUDIO_GRABBER_PERFORM_BEAT_DETECTION.load(Ordering::Relaxed) {
+            let beat = BEAT_DETECTOR.lock().process(&buffer);
+
+            if let Some(beat) = beat {
+                super::notify_beat(beat);
+            }
+        }
+
+        // compute the perceptual spectra requested by the scripts
+        if super::AUDIO_GRABBER_PERFORM_SPECTRUM_ANALYSIS.load(Ordering::Relaxed) {
+            SPECTRUM_ANALYZER.lock().process(&buffer, Instant::now());
+        }
+
+        if buffer.len() < FFT_SIZE {
+            buffer.resize(FFT_SIZE, 0x0000);
+        }
+
+        // compute root mean square (RMS) of the recorded samples
+        if super::AUDIO_GRABBER_PERFORM_RMS_COMPUTATION.load(Ordering::Relaxed) {
+            let sqr_sum = buffer
+                .iter()
+                .map(|s| *s as f32)
+                .fold(0.0, |sqr_sum, s| sqr_sum + s * s);
+
+            let sqr_sum = (sqr_sum / buffer.len() as f32).sqrt();
+
+            CURRENT_RMS.store(sqr_sum.round() as isize, Ordering::SeqCst);
+        }
+
+        // compute spectrum analyzer
+        if super::AUDIO_GRABBER_PERFORM_FFT_COMPUTATION.load(Ordering::Relaxed) {
+            let mut data: Vec<Complex<f32>> = buffer
+                .iter()
+                .take(FFT_SIZE)
+                .map(|e| Complex::from(*e as f32))
+                .collect();
+
+            let fft = Radix4::new(FFT_SIZE, FftDirection::Forward);
+            fft.process(&mut data);
+
+            // apply post processing steps: normalization, window function and smoothing
+            let one_over_fft_len_sqrt = 1.0 / ((FFT_SIZE / 2) as f32).sqrt();
+
+            let mut phase = 0.0;
+            const DELTA: f32 = (2.0 * PI) / (FFT_SIZE / 2) as f32;
+
+            let result: Vec<f32> = data[(FFT_SIZE / 2)..]
+                .iter()
+                // normalize
+                .map(|e| ((e.re as f32) * one_over_fft_len_sqrt).abs())
+                // apply Hamming window
+                .map(|e| {
+                    phase += DELTA;
+                    e * (0.54 - 0.46 * phase.cos())
+                })
+                .collect();
+
+            for (i, e) in AUDIO_SPECTRUM.write().iter_mut().enumerate() {
+                *e = (*e + result[i]) / 2.0;
+            }
+        }
+    }
+
     impl AudioBackend for ProxyBackend {
-        fn play_sfx(&self, id: u32) -> Result<()> {
+        fn play_sfx(&self, name: &str, gain: f32, pan: f32) -> Result<()> {
             if let Some(ref tx) = *SFX_TX.lock() {
-                tx.send_timeout(id, Duration::from_millis(1))?;
+                let sound = protocol::Sound {
+                    name: name.to_owned(),
+                    gain,
+                    pan,
+                };
+
+                tx.send_timeout(sound, Duration::from_millis(1))?;
             }
 
             Ok(())
         }
 
+        fn set_sfx_pack(&self, pack: &str) -> Result<()> {
+            *SFX_PACK.lock() = Some(pack.to_owned());
+            SFX_PACK_PENDING.store(true, Ordering::SeqCst);
+
+            Ok(())
+        }
+
         fn start_audio_grabber(&self) -> Result<()> {
             let builder = thread::Builder::new().name("audio/proxy".into());
             builder
@@ -775,6 +1138,8 @@ mod backends {
 
                     Self::run_io_loop().unwrap_or_else(|e| {
                         error!("Audio proxy error: {}", e);
+
+                        super::clear_audio_buffers();
                     });
                 })
                 .unwrap_or_else(|e| {
@@ -796,5 +1161,12 @@ mod backends {
         fn is_audio_muted(&self) -> Result<bool> {
             Ok(AUDIO_MUTED.load(Ordering::SeqCst))
         }
+
+        fn set_capture_source(&self, source: &str) -> Result<()> {
+            *CAPTURE_SOURCE.lock() = Some(source.to_owned());
+            CAPTURE_SOURCE_PENDING.store(true, Ordering::SeqCst);
+
+            Ok(())
+        }
     }
 }
@@ -0,0 +1,549 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+use evdev_rs::enums::EV_KEY;
+use lazy_static::lazy_static;
+use log::*;
+use mlua::prelude::*;
+use parking_lot::{Condvar, Mutex, RwLock};
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::keymaps::mapping::{MacroEvent, MacroStep};
+use crate::keymaps::recorder::Recorder;
+use crate::keymaps::{self, text, Input, KeyMappingTable, KeymapEngine, Output};
+use crate::plugins::{self, macros, PersistencePlugin, Plugin, ProfilesPlugin};
+use crate::{constants, events, script};
+
+pub type Result<T> = std::result::Result<T, eyre::Error>;
+
+lazy_static! {
+    /// The keymap that is currently in effect, if any
+    pub static ref ACTIVE_KEYMAP: Arc<RwLock<Option<KeymapEngine>>> = Arc::new(RwLock::new(None));
+
+    /// The currently selected Easy Shift+ layer (1-6)
+    pub static ref EASY_SHIFT_LAYER: AtomicUsize = AtomicUsize::new(1);
+
+    /// The macro recorder, while a recording is in progress
+    pub static ref RECORDER: Arc<Mutex<Option<Recorder>>> = Arc::new(Mutex::new(None));
+
+    /// The keymap file that is in effect, along with the reason why it has been selected
+    pub static ref ACTIVE_KEYMAP_NAME: Arc<RwLock<Option<(String, KeymapSelector)>>> = Arc::new(RwLock::new(None));
+
+    /// The keymap that has been selected for the focused application, e.g. by the process monitor
+    pub static ref APPLICATION_KEYMAP: Arc<RwLock<Option<String>>> = Arc::new(RwLock::new(None));
+
+    /// Keymaps that have been loaded so far, indexed by their file name
+    static ref KEYMAP_TABLES: Arc<RwLock<HashMap<String, KeyMappingTable>>> = Arc::new(RwLock::new(HashMap::new()));
+
+    /// Remaining outputs of sequences that are paused, ordered by the time they are due
+    /// and by the order in which they have been scheduled
+    static ref DELAYED_OUTPUTS: Arc<(Mutex<BTreeMap<(Instant, u64), Vec<Output>>>, Condvar)> =
+        Arc::new((Mutex::new(BTreeMap::new()), Condvar::new()));
+
+    /// Used to keep the order of delayed outputs that are due at the same time
+    static ref DELAYED_OUTPUTS_SEQ: AtomicU64 = AtomicU64::new(0);
+}
+
+/// Specifies why a keymap has been selected; in decreasing order of precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeymapSelector {
+    /// Selected for the focused application
+    Application,
+
+    /// Specified by the active profile
+    Profile,
+
+    /// Assigned to the active slot in `eruption.conf`
+    Slot,
+
+    /// The global keymap from `eruption.conf`
+    Global,
+}
+
+impl fmt::Display for KeymapSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeymapSelector::Application => f.write_str("application"),
+            KeymapSelector::Profile => f.write_str("profile"),
+            KeymapSelector::Slot => f.write_str("slot"),
+            KeymapSelector::Global => f.write_str("global"),
+        }
+    }
+}
+
+/// Executes the mappings of a native keymap table (`.keymap`) directly on the input path,
+/// instead of interpreting compiled Lua tables in the macros script
+pub struct KeymapsPlugin {}
+
+impl KeymapsPlugin {
+    pub fn new() -> Self {
+        KeymapsPlugin {}
+    }
+
+    /// Reload all keymaps from disk, e.g. after a keymap file has been changed
+    pub fn load_keymap() -> Result<()> {
+        KEYMAP_TABLES.write().clear();
+
+        let (keymap, selector) = Self::selected_keymap();
+
+        Self::activate_keymap(&keymap, selector)
+    }
+
+    /// Switch to the keymap of the focused application, `None` falls back to the
+    /// keymap of the active profile or slot
+    pub fn set_application_keymap(keymap: Option<String>) -> Result<()> {
+        *APPLICATION_KEYMAP.write() = keymap.filter(|keymap| !keymap.is_empty());
+
+        Self::select_keymap()
+    }
+
+    /// Switch to the keymap that should be in effect, if it is not active already.
+    /// This has to be called whenever the active profile or slot changes
+    pub fn select_keymap() -> Result<()> {
+        let (keymap, selector) = Self::selected_keymap();
+
+        if ACTIVE_KEYMAP_NAME.read().as_ref() == Some(&(keymap.clone(), selector)) {
+            return Ok(());
+        }
+
+        Self::activate_keymap(&keymap, selector)
+    }
+
+    /// Determine the keymap that should be in effect, along with the reason why
+    fn selected_keymap() -> (String, KeymapSelector) {
+        if let Some(keymap) = APPLICATION_KEYMAP.read().clone() {
+            return (keymap, KeymapSelector::Application);
+        }
+
+        if let Some(keymap) = crate::ACTIVE_PROFILE
+            .lock()
+            .as_ref()
+            .and_then(|profile| profile.keymap.clone())
+        {
+            return (keymap, KeymapSelector::Profile);
+        }
+
+        let config = crate::CONFIG.lock();
+        let config = config.as_ref().unwrap();
+
+        let slot = crate::ACTIVE_SLOT.load(Ordering::SeqCst);
+
+        if let Some(keymap) = config
+            .get::<Vec<String>>("global.slot_keymaps")
+            .ok()
+            .and_then(|keymaps| keymaps.get(slot).cloned())
+            .filter(|keymap| !keymap.is_empty())
+        {
+            return (keymap, KeymapSelector::Slot);
+        }
+
+        let keymap = config
+            .get::<String>("global.keymap")
+            .unwrap_or_else(|_| constants::DEFAULT_KEYMAP.to_string());
+
+        (keymap, KeymapSelector::Global)
+    }
+
+    /// Switch to the keymap `keymap`. The switch is atomic, keys that are held down
+    /// are released first, so that no injected key is left in the pressed state
+    fn activate_keymap(keymap: &str, selector: KeymapSelector) -> Result<()> {
+        let table = KEYMAP_TABLES.read().get(keymap).cloned();
+
+        let table = match table {
+            Some(table) => Some(table),
+
+            None => {
+                let path = keymaps::keymap_path(keymap);
+
+                if path.exists() {
+                    // an invalid keymap file leaves the previous keymap in effect
+                    let table = keymaps::load_keymap(&path)?;
+
+                    info!(
+                        "Loaded keymap {} with {} mappings",
+                        path.display(),
+                        table.mappings().len()
+                    );
+
+                    KEYMAP_TABLES
+                        .write()
+                        .insert(keymap.to_string(), table.clone());
+
+                    Some(table)
+                } else {
+                    info!("Keymap {} does not exist, disabling keymap", path.display());
+
+                    None
+                }
+            }
+        };
+
+        info!("Switching to keymap {} (selected by: {})", keymap, selector);
+
+        let easy_shift_layer = Self::easy_shift_layer();
+
+        let outputs = {
+            let mut active_keymap = ACTIVE_KEYMAP.write();
+
+            let outputs = active_keymap
+                .as_mut()
+                .map(|engine| engine.release_all(easy_shift_layer))
+                .unwrap_or_default();
+
+            *active_keymap = table.map(KeymapEngine::new);
+            *ACTIVE_KEYMAP_NAME.write() = Some((keymap.to_string(), selector));
+
+            outputs
+        };
+
+        Self::execute(outputs);
+
+        Ok(())
+    }
+
+    /// Returns the active Easy Shift+ layer, if Easy Shift+ is currently engaged
+    /// (Caps Lock is held while the keyboard is in game mode)
+    fn easy_shift_layer() -> Option<usize> {
+        let game_mode =
+            PersistencePlugin::load_bool_transient("global.game_mode_enabled").unwrap_or(false);
+
+        if !game_mode {
+            return None;
+        }
+
+        let engaged = crate::KEYBOARD_DEVICES.read().iter().any(|device| {
+            let index = device.read().ev_key_to_key_index(EV_KEY::KEY_CAPSLOCK) as usize;
+
+            index > 0
+                && crate::KEY_STATES
+                    .read()
+                    .get(index)
+                    .copied()
+                    .unwrap_or(false)
+        });
+
+        if engaged {
+            Some(EASY_SHIFT_LAYER.load(Ordering::SeqCst))
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if `input` would be handled by the active keymap
+    pub fn is_mapped(input: Input) -> bool {
+        match ACTIVE_KEYMAP.read().as_ref() {
+            Some(engine) => engine.is_mapped(input, Self::easy_shift_layer()),
+
+            None => false,
+        }
+    }
+
+    /// Run `input` through the active keymap and execute the resulting actions.
+    /// Returns true if the original input event has been consumed
+    pub fn process_input(input: Input) -> bool {
+        let easy_shift_layer = Self::easy_shift_layer();
+
+        let outcome = match ACTIVE_KEYMAP.write().as_mut() {
+            Some(engine) => engine.process(input, easy_shift_layer, Instant::now()),
+
+            None => return false,
+        };
+
+        trace!("Keymap: {:?} => {:?}", input, outcome.outputs);
+
+        Self::execute(outcome.outputs);
+
+        outcome.consumed
+    }
+
+    /// Resolve pending tap/hold, chord and sequence decisions whose time is up
+    pub fn poll() {
+        let easy_shift_layer = Self::easy_shift_layer();
+
+        let outputs = match ACTIVE_KEYMAP.write().as_mut() {
+            Some(engine) => engine.poll(easy_shift_layer, Instant::now()),
+
+            None => return,
+        };
+
+        Self::execute(outputs);
+    }
+
+    /// Start recording a macro; a recording that is already in progress is discarded
+    pub fn start_recording() {
+        info!("Recording a macro");
+
+        *RECORDER.lock() = Some(Recorder::new());
+    }
+
+    /// Stop recording and return the recorded events, if a recording is in progress
+    pub fn stop_recording() -> Option<Vec<MacroStep>> {
+        let steps = RECORDER.lock().take().map(|recorder| recorder.finish());
+
+        if let Some(steps) = &steps {
+            info!("Recorded a macro with {} events", steps.len());
+        }
+
+        steps
+    }
+
+    /// Record an input event, if a recording is in progress
+    pub fn record(event: MacroEvent) {
+        if let Some(recorder) = RECORDER.lock().as_mut() {
+            recorder.record(event, Instant::now());
+        }
+    }
+
+    /// Execute `outputs` on the sequence thread, after `delay` has elapsed
+    fn schedule(delay: Duration, outputs: Vec<Output>) {
+        let seq = DELAYED_OUTPUTS_SEQ.fetch_add(1, Ordering::SeqCst);
+
+        let (queue, condvar) = &**DELAYED_OUTPUTS;
+
+        queue.lock().insert((Instant::now() + delay, seq), outputs);
+        condvar.notify_one();
+    }
+
+    /// Spawn the thread that executes the delayed outputs of sequences, one after another
+    fn spawn_sequence_thread() -> Result<()> {
+        thread::Builder::new()
+            .name("keymaps/sequence".into())
+            .spawn(|| {
+                let (queue, condvar) = &**DELAYED_OUTPUTS;
+
+                loop {
+                    let mut pending = queue.lock();
+
+                    if crate::QUIT.load(Ordering::SeqCst) {
+                        break;
+                    }
+
+                    match pending.keys().next().copied() {
+                        Some(key) if key.0 <= Instant::now() => {
+                            let outputs = pending.remove(&key).unwrap();
+
+                            // other outputs may be scheduled while this one is executed
+                            drop(pending);
+
+                            Self::execute(outputs);
+                        }
+
+                        Some((due, _)) => {
+                            condvar.wait_until(&mut pending, due);
+                        }
+
+                        None => {
+                            condvar.wait_for(
+                                &mut pending,
+                                Duration::from_millis(constants::TIMEOUT_CONDITION_MILLIS),
+                            );
+                        }
+                    }
+                }
+            })?;
+
+        Ok(())
+    }
+
+    fn execute(outputs: Vec<Output>) {
+        let mut outputs = outputs.into_iter();
+
+        while let Some(output) = outputs.next() {
+            match output {
+                // the remaining outputs are executed by the sequence thread,
+                // so that the input path is never blocked
+                Output::Sleep { millis } => {
+                    Self::schedule(Duration::from_millis(millis), outputs.collect());
+
+                    return;
+                }
+
+                Output::TypeText(text) => Self::execute(text::type_text(&text)),
+
+                Output::SwitchSlot(slot) => {
+                    ProfilesPlugin::switch_to_slot(slot.saturating_sub(1));
+                }
+
+                Output::SwitchProfile(profile) => ProfilesPlugin::switch_to_profile(profile),
+
+                Output::Brightness(adjustment) => {
+                    let brightness = crate::BRIGHTNESS.load(Ordering::SeqCst) as i64;
+                    let brightness = adjustment.apply(brightness).clamp(0, 100);
+
+                    crate::BRIGHTNESS.store(brightness as isize, Ordering::SeqCst);
+                    script::FRAME_GENERATION_COUNTER.fetch_add(1, Ordering::SeqCst);
+                }
+
+                // the device I/O thread owns the mice, we may be running on an input thread
+                // that holds a lock on one of them
+                Output::Dpi(adjustment) => {
+                    crate::DEV_IO_TX
+                        .lock()
+                        .as_ref()
+                        .unwrap()
+                        .send(crate::DeviceAction::AdjustDpi(adjustment))
+                        .unwrap_or_else(|e| error!("Could not adjust the DPI: {}", e));
+                }
+
+                Output::InjectKey { code, down } => {
+                    macros::UINPUT_TX
+                        .read()
+                        .as_ref()
+                        .unwrap()
+                        .send(macros::Message::InjectKey {
+                            key: code,
+                            down,
+                            vm_id: None,
+                        })
+                        .unwrap_or_else(|e| error!("Could not inject a key: {}", e));
+                }
+
+                Output::InjectMouseButton { button, down } => {
+                    macros::UINPUT_TX
+                        .read()
+                        .as_ref()
+                        .unwrap()
+                        .send(macros::Message::InjectButtonEvent {
+                            button,
+                            down,
+                            vm_id: None,
+                        })
+                        .unwrap_or_else(|e| error!("Could not inject a mouse button: {}", e));
+                }
+
+                Output::InjectMouseWheel { direction } => {
+                    macros::UINPUT_TX
+                        .read()
+                        .as_ref()
+                        .unwrap()
+                        .send(macros::Message::InjectMouseWheelEvent { direction })
+                        .unwrap_or_else(|e| error!("Could not inject a mouse wheel event: {}", e));
+                }
+
+                Output::Call {
+                    function_name,
+                    down,
+                } => {
+                    if down {
+                        events::notify_observers(events::Event::MacroFired).unwrap_or_else(|e| {
+                            error!("Error during notification of observers: {}", e)
+                        });
+                    }
+
+                    for (idx, lua_tx) in crate::LUA_TXS.read().iter().enumerate() {
+                        if !crate::FAILED_TXS.read().contains(&idx) {
+                            lua_tx
+                                .send(script::Message::CallMacro {
+                                    function_name: function_name.clone(),
+                                    down,
+                                })
+                                .unwrap_or_else(|e| {
+                                    error!("Could not send an event to a Lua VM: {}", e)
+                                });
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Plugin for KeymapsPlugin {
+    fn get_name(&self) -> String {
+        "Keymaps".to_string()
+    }
+
+    fn get_description(&self) -> String {
+        "Remap keys and mouse buttons using native keymap tables".to_string()
+    }
+
+    fn initialize(&mut self) -> plugins::Result<()> {
+        Self::spawn_sequence_thread()?;
+
+        Self::load_keymap().unwrap_or_else(|e| error!("Could not load the keymap: {}", e));
+
+        Ok(())
+    }
+
+    fn register_lua_funcs(&self, lua_ctx: &Lua) -> mlua::Result<()> {
+        let globals = lua_ctx.globals();
+
+        let get_easy_shift_layer =
+            lua_ctx.create_function(|_, ()| Ok(EASY_SHIFT_LAYER.load(Ordering::SeqCst)))?;
+        globals.set("get_easy_shift_layer", get_easy_shift_layer)?;
+
+        let set_easy_shift_layer = lua_ctx.create_function(|_, layer: usize| {
+            EASY_SHIFT_LAYER.store(layer, Ordering::SeqCst);
+            Ok(())
+        })?;
+        globals.set("set_easy_shift_layer", set_easy_shift_layer)?;
+
+        let is_native_keymap_active =
+            lua_ctx.create_function(|_, ()| Ok(ACTIVE_KEYMAP.read().is_some()))?;
+        globals.set("is_native_keymap_active", is_native_keymap_active)?;
+
+        let get_keymap_layer = lua_ctx.create_function(|_, ()| {
+            Ok(ACTIVE_KEYMAP
+                .read()
+                .as_ref()
+                .and_then(|engine| engine.action_layer()))
+        })?;
+        globals.set("get_keymap_layer", get_keymap_layer)?;
+
+        let get_keymap_layer_keys = lua_ctx.create_function(|_, layer: usize| {
+            Ok(ACTIVE_KEYMAP
+                .read()
+                .as_ref()
+                .map(|engine| engine.layer_keys(layer))
+                .unwrap_or_default())
+        })?;
+        globals.set("get_keymap_layer_keys", get_keymap_layer_keys)?;
+
+        let get_keymap_layer_color = lua_ctx.create_function(|_, layer: usize| {
+            Ok(ACTIVE_KEYMAP
+                .read()
+                .as_ref()
+                .and_then(|engine| engine.table().layers().get(&layer).and_then(|l| l.color)))
+        })?;
+        globals.set("get_keymap_layer_color", get_keymap_layer_color)?;
+
+        Ok(())
+    }
+
+    async fn main_loop_hook(&self, _ticks: u64) {}
+
+    fn sync_main_loop_hook(&self, _ticks: u64) {
+        Self::poll();
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -18,17 +18,20 @@
 */
 
 use evdev_rs::{enums::*, DeviceWrapper, InputEvent, TimeVal, UInputDevice, UninitDevice};
-use flume::{unbounded, Sender};
+use flume::{bounded, unbounded, Sender};
 use lazy_static::lazy_static;
 use log::*;
 use mlua::prelude::*;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{any::Any, thread};
 
+use crate::constants;
 use crate::plugins::{self, Plugin};
 
 pub type Result<T> = std::result::Result<T, eyre::Error>;
@@ -36,13 +39,33 @@ pub type Result<T> = std::result::Result<T, eyre::Error>;
 pub enum Message {
     // keyboard related
     MirrorKey(evdev_rs::InputEvent),
-    InjectKey { key: u32, down: bool },
+    InjectKey {
+        key: u32,
+        down: bool,
+        vm_id: Option<usize>,
+    },
 
     // mouse related
     MirrorMouseEvent(evdev_rs::InputEvent),
     MirrorMouseEventImmediate(evdev_rs::InputEvent),
-    InjectButtonEvent { button: u32, down: bool },
-    InjectMouseWheelEvent { direction: u32 },
+    InjectButtonEvent {
+        button: u32,
+        down: bool,
+        vm_id: Option<usize>,
+    },
+    InjectMouseWheelEvent {
+        direction: u32,
+    },
+
+    // release all injected keys and buttons that are still held down
+    ReleaseAll {
+        ack: Option<Sender<()>>,
+    },
+
+    // release the injected keys and buttons that are held down by a Lua VM
+    ReleaseHeldBy {
+        vm_id: usize,
+    },
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -58,6 +81,22 @@ lazy_static! {
     pub static ref UINPUT_TX: Arc<RwLock<Option<Sender<Message>>>> = Arc::new(RwLock::new(None));
     pub static ref DROP_CURRENT_KEY: AtomicBool = AtomicBool::new(false);
     pub static ref DROP_CURRENT_MOUSE_INPUT: AtomicBool = AtomicBool::new(false);
+
+    /// Keys that have been pressed by an injection, but not released yet,
+    /// along with the Lua VM that injected them (if any)
+    pub static ref HELD_KEYS: Arc<RwLock<BTreeMap<u32, Option<usize>>>> =
+        Arc::new(RwLock::new(BTreeMap::new()));
+
+    /// Mouse buttons that have been pressed by an injection, but not released yet,
+    /// along with the Lua VM that injected them (if any)
+    pub static ref HELD_BUTTONS: Arc<RwLock<BTreeMap<u32, Option<usize>>>> =
+        Arc::new(RwLock::new(BTreeMap::new()));
+
+    /// The key codes of the panic hotkey, that releases all injected keys
+    static ref PANIC_HOTKEY: Arc<RwLock<Vec<u32>>> = Arc::new(RwLock::new(vec![]));
+
+    /// Physical keys that are currently pressed, used to detect the panic hotkey
+    static ref PRESSED_KEYS: Arc<Mutex<BTreeSet<u32>>> = Arc::new(Mutex::new(BTreeSet::new()));
 }
 
 thread_local! {
@@ -75,6 +114,94 @@ impl MacrosPlugin {
         MacrosPlugin {}
     }
 
+    /// Release all injected keys and buttons that are still held down, e.g. because a
+    /// Lua macro failed between the press and the release of a key
+    pub fn release_held_keys() {
+        if let Some(tx) = UINPUT_TX.read().as_ref() {
+            tx.send(Message::ReleaseAll { ack: None })
+                .unwrap_or_else(|e| error!("Could not release the held keys: {}", e));
+        }
+    }
+
+    /// Release the injected keys and buttons that are held down by the Lua VM `vm_id`,
+    /// e.g. because its script terminated between the press and the release of a key
+    pub fn release_held_keys_of(vm_id: usize) {
+        if let Some(tx) = UINPUT_TX.read().as_ref() {
+            tx.send(Message::ReleaseHeldBy { vm_id })
+                .unwrap_or_else(|e| error!("Could not release the held keys: {}", e));
+        }
+    }
+
+    /// Release all injected keys and buttons and wait until they have been released.
+    /// This is used on shutdown, before the virtual devices are destroyed
+    pub fn release_held_keys_and_wait() {
+        let (ack_tx, ack_rx) = bounded(1);
+
+        if let Some(tx) = UINPUT_TX.read().as_ref() {
+            if let Err(e) = tx.send(Message::ReleaseAll { ack: Some(ack_tx) }) {
+                error!("Could not release the held keys: {}", e);
+                return;
+            }
+        }
+
+        ack_rx
+            .recv_timeout(Duration::from_millis(
+                constants::RELEASE_HELD_KEYS_TIMEOUT_MILLIS,
+            ))
+            .unwrap_or_else(|_| warn!("Timeout while releasing the held keys"));
+    }
+
+    /// Returns the keys and the mouse buttons that are held down by injections
+    pub fn held_keys() -> (Vec<u32>, Vec<u32>) {
+        (
+            HELD_KEYS.read().keys().copied().collect(),
+            HELD_BUTTONS.read().keys().copied().collect(),
+        )
+    }
+
+    /// Track the state of a physical key; returns true if the panic hotkey has been
+    /// pressed, in which case all held keys are released and the key shall be dropped
+    pub fn process_panic_hotkey(code: u32, down: bool) -> bool {
+        let hotkey = PANIC_HOTKEY.read();
+
+        if hotkey.is_empty() {
+            return false;
+        }
+
+        let mut pressed = PRESSED_KEYS.lock();
+
+        if down {
+            pressed.insert(code);
+        } else {
+            pressed.remove(&code);
+        }
+
+        // the last key of the hotkey has to be pressed while the other ones are held down
+        if down && hotkey.last() == Some(&code) && hotkey.iter().all(|k| pressed.contains(k)) {
+            warn!("Panic hotkey pressed, releasing all held keys");
+
+            Self::release_held_keys();
+
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Parse the panic hotkey, e.g. `KEY_LEFTCTRL+KEY_LEFTALT+KEY_PAUSE`
+    fn parse_hotkey(hotkey: &str) -> Result<Vec<u32>> {
+        hotkey
+            .split('+')
+            .map(
+                |name| match EventCode::from_str(&EventType::EV_KEY, name.trim()) {
+                    Some(EventCode::EV_KEY(key)) => Ok(key as u32),
+
+                    _ => Err(MacrosPluginError::MappingError {}.into()),
+                },
+            )
+            .collect()
+    }
+
     fn initialize_thread_locals() -> Result<()> {
         info!("Initializing Linux virtual input devices");
 
@@ -413,6 +540,13 @@ impl MacrosPlugin {
         }
     }
 
+    /// Converts an EV_KEY value to the button index of the virtual mouse
+    pub fn ev_key_to_button_index(key: EV_KEY) -> Result<u32> {
+        (1..=18)
+            .find(|index| matches!(Self::button_index_to_ev_key(*index), Ok(k) if k == key))
+            .ok_or_else(|| MacrosPluginError::MappingError {}.into())
+    }
+
     /// Inject a press or release of key `key` into to output of the virtual keyboard
     fn inject_single_key(key: EV_KEY, value: i32, time: &TimeVal) -> Result<()> {
         // let mut do_initialize = false;
@@ -564,6 +698,45 @@ impl MacrosPlugin {
         Ok(())
     }
 
+    /// Remove the keys or buttons that are held down by the Lua VM `vm_id` from `held`
+    fn take_held_by(held: &mut BTreeMap<u32, Option<usize>>, vm_id: usize) -> Vec<u32> {
+        let result: Vec<u32> = held
+            .iter()
+            .filter(|(_, owner)| **owner == Some(vm_id))
+            .map(|(code, _)| *code)
+            .collect();
+
+        held.retain(|_, owner| *owner != Some(vm_id));
+
+        result
+    }
+
+    /// Inject release events for `keys` and `buttons`
+    fn release_keys(keys: Vec<u32>, buttons: Vec<u32>) -> Result<()> {
+        let time = evdev_rs::TimeVal {
+            tv_sec: 0,
+            tv_usec: 0,
+        };
+
+        if !keys.is_empty() || !buttons.is_empty() {
+            info!("Releasing held keys: {:?}, buttons: {:?}", keys, buttons);
+        }
+
+        for ev_key in keys {
+            if let Some(key) = evdev_rs::enums::int_to_ev_key(ev_key) {
+                Self::inject_single_key(key, 0, &time)?;
+            }
+        }
+
+        for button in buttons {
+            let key = Self::button_index_to_ev_key(button)?;
+
+            Self::inject_single_mouse_event(key, 0, &time)?;
+        }
+
+        Ok(())
+    }
+
     fn spawn_uinput_thread() -> Result<()> {
         let (uinput_tx, uinput_rx) = unbounded();
 
@@ -578,6 +751,13 @@ impl MacrosPlugin {
                     match message {
                         Message::MirrorKey(raw_event) => {
                             if !DROP_CURRENT_KEY.load(Ordering::SeqCst) {
+                                // a mirrored release also releases an injected key
+                                if let EventCode::EV_KEY(key) = raw_event.event_code {
+                                    if raw_event.value == 0 {
+                                        HELD_KEYS.write().remove(&(key as u32));
+                                    }
+                                }
+
                                 Self::inject_key_event(raw_event)?;
                             } else {
                                 debug!("Keyboard event has been dropped as requested");
@@ -596,7 +776,11 @@ impl MacrosPlugin {
                             Self::inject_mouse_event_immediate(raw_event)?;
                         }
 
-                        Message::InjectKey { key: ev_key, down } => {
+                        Message::InjectKey {
+                            key: ev_key,
+                            down,
+                            vm_id,
+                        } => {
                             let key = evdev_rs::enums::int_to_ev_key(ev_key)
                                 .ok_or(MacrosPluginError::MappingError {})?;
 
@@ -608,9 +792,19 @@ impl MacrosPlugin {
                             };
 
                             Self::inject_single_key(key, value, &time)?;
+
+                            if down {
+                                HELD_KEYS.write().insert(ev_key, vm_id);
+                            } else {
+                                HELD_KEYS.write().remove(&ev_key);
+                            }
                         }
 
-                        Message::InjectButtonEvent { button, down } => {
+                        Message::InjectButtonEvent {
+                            button,
+                            down,
+                            vm_id,
+                        } => {
                             let key = Self::button_index_to_ev_key(button)?;
 
                             let value = if down { 1 } else { 0 };
@@ -621,10 +815,62 @@ impl MacrosPlugin {
                             };
 
                             Self::inject_single_mouse_event(key, value, &time)?;
+
+                            if down {
+                                HELD_BUTTONS.write().insert(button, vm_id);
+                            } else {
+                                HELD_BUTTONS.write().remove(&button);
+                            }
+                        }
+
+                        Message::ReleaseAll { ack } => {
+                            let keys = std::mem::take(&mut *HELD_KEYS.write());
+                            let buttons = std::mem::take(&mut *HELD_BUTTONS.write());
+
+                            Self::release_keys(
+                                keys.into_keys().collect(),
+                                buttons.into_keys().collect(),
+                            )?;
+
+                            if let Some(ack) = ack {
+                                ack.send(()).ok();
+                            }
                         }
 
-                        Message::InjectMouseWheelEvent { direction: _ } => {
-                            // REL_RESERVED
+                        Message::ReleaseHeldBy { vm_id } => {
+                            let keys = Self::take_held_by(&mut HELD_KEYS.write(), vm_id);
+                            let buttons = Self::take_held_by(&mut HELD_BUTTONS.write(), vm_id);
+
+                            Self::release_keys(keys, buttons)?;
+                        }
+
+                        Message::InjectMouseWheelEvent { direction } => {
+                            // direction 0 is used to consume the original wheel event
+                            let (axis, value) = match direction {
+                                1 => (EV_REL::REL_WHEEL, 1),
+                                2 => (EV_REL::REL_WHEEL, -1),
+                                3 => (EV_REL::REL_HWHEEL, -1),
+                                4 => (EV_REL::REL_HWHEEL, 1),
+
+                                _ => continue,
+                            };
+
+                            let time = evdev_rs::TimeVal {
+                                tv_sec: 0,
+                                tv_usec: 0,
+                            };
+
+                            Self::inject_mouse_event(InputEvent {
+                                time,
+                                event_code: EventCode::EV_REL(axis),
+                                value,
+                            })?;
+
+                            Self::inject_mouse_event(InputEvent {
+                                time,
+                                event_code: EventCode::EV_SYN(EV_SYN::SYN_REPORT),
+                                value: 0,
+                            })?;
                         }
                     }
                 }
@@ -647,6 +893,19 @@ impl Plugin for MacrosPlugin {
     }
 
     fn initialize(&mut self) -> plugins::Result<()> {
+        let hotkey = crate::CONFIG
+            .lock()
+            .as_ref()
+            .and_then(|config| config.get::<String>("global.panic_hotkey").ok());
+
+        if let Some(hotkey) = hotkey.filter(|hotkey| !hotkey.is_empty()) {
+            match Self::parse_hotkey(&hotkey) {
+                Ok(keys) => *PANIC_HOTKEY.write() = keys,
+
+                Err(e) => error!("Invalid panic hotkey '{}': {}", hotkey, e),
+            }
+        }
+
         Self::spawn_uinput_thread()?;
 
         Ok(())
@@ -21,6 +21,7 @@ pub mod animal;
 pub mod audio;
 pub mod introspection;
 pub mod keyboard;
+pub mod keymaps;
 pub mod macros;
 pub mod mouse;
 pub mod persistence;
@@ -35,6 +36,7 @@ pub use animal::AnimalPlugin;
 pub use audio::AudioPlugin;
 pub use introspection::IntrospectionPlugin;
 pub use keyboard::KeyboardPlugin;
+pub use keymaps::KeymapsPlugin;
 pub use macros::MacrosPlugin;
 pub use mouse::MousePlugin;
 pub use persistence::PersistencePlugin;
@@ -79,6 +81,10 @@ pub fn register_plugins() -> Result<()> {
         .register_plugin(Box::new(MacrosPlugin::new()))
         .map_err(|_e| error!("An error occurred during initialization of the plugin"));
 
+    let _ = plugin_manager
+        .register_plugin(Box::new(KeymapsPlugin::new()))
+        .map_err(|_e| error!("An error occurred during initialization of the plugin"));
+
     let _ = plugin_manager
         .register_plugin(Box::new(IntrospectionPlugin::new()))
         .map_err(|_e| error!("An error occurred during initialization of the plugin"));
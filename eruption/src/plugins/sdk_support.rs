@@ -18,9 +18,9 @@
 */
 
 use crate::{
-    constants, hwdevices, init_keyboard_device, init_misc_device, init_mouse_device, script,
-    spawn_keyboard_input_thread, spawn_misc_input_thread, spawn_mouse_input_thread, DbusApiEvent,
-    SDK_SUPPORT_ACTIVE,
+    constants, events, hwdevices, init_keyboard_device, init_misc_device, init_mouse_device,
+    script, spawn_keyboard_input_thread, spawn_misc_input_thread, spawn_mouse_input_thread,
+    DbusApiEvent, SDK_SUPPORT_ACTIVE,
 };
 use flume::unbounded;
 use lazy_static::lazy_static;
@@ -34,17 +34,18 @@ use protocol::request::Payload as RequestPayload;
 use protocol::response::Payload as ResponsePayload;
 use socket2::{Domain, SockAddr, Socket, Type};
 use std::any::Any;
-use std::io::Cursor;
+use std::io::{Cursor, ErrorKind};
 use std::mem::MaybeUninit;
 use std::os::unix::fs::PermissionsExt;
 use std::os::unix::io::AsRawFd;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{fs, thread};
 
+use crate::audio::spectrum::{SpectrumConfig, DEFAULT_DB_RANGE};
 use crate::hwdevices::RGBA;
-use crate::plugins::{self, Plugin};
+use crate::plugins::{self, AudioPlugin, Plugin};
 
 pub mod protocol {
     include!(concat!(env!("OUT_DIR"), "/sdk_support.rs"));
@@ -80,6 +81,127 @@ pub struct HotplugInfo {
     pub usb_pid: u16,
 }
 
+/// Parameters of a subscription to the stream of audio analysis results
+#[derive(Debug, Default, Clone, Encode, Decode)]
+pub struct AudioSubscription {
+    /// Number of bands of the spectrum
+    pub bands: u32,
+
+    /// Number of updates per second
+    pub update_rate: u32,
+}
+
+/// Audio analysis results, as published to subscribed SDK clients
+#[derive(Debug, Default, Clone, Encode, Decode)]
+pub struct AudioData {
+    /// Loudness (RMS) of the signal, in the range [0..1]
+    pub loudness: f32,
+
+    /// Perceptual spectrum with logarithmically spaced bands, each in the range [0..1]
+    pub spectrum: Vec<f32>,
+
+    /// Energy of the bass, mid and treble bands
+    pub bass: f32,
+    pub mid: f32,
+    pub treble: f32,
+
+    /// The estimated tempo, or 0 if the tempo is not known yet
+    pub bpm: f32,
+
+    /// Position within the current beat, in the range [0..1)
+    pub beat_phase: f32,
+
+    /// Number of beats that have been detected since the previous update
+    pub beats: u32,
+}
+
+/// State of an audio subscription of the connected SDK client
+struct AudioStream {
+    config: SpectrumConfig,
+    interval: Duration,
+    last_update: Instant,
+    beat_count: u64,
+}
+
+impl AudioStream {
+    fn new(subscription: &AudioSubscription) -> Result<Self> {
+        let config = SpectrumConfig {
+            bands: subscription.bands.clamp(1, constants::SDK_AUDIO_MAX_BANDS) as usize,
+            db_range: Some(DEFAULT_DB_RANGE),
+            ..Default::default()
+        };
+
+        config.validate(constants::AUDIO_SAMPLE_RATE)?;
+
+        let update_rate = subscription
+            .update_rate
+            .clamp(1, constants::SDK_AUDIO_MAX_UPDATE_RATE);
+
+        Ok(Self {
+            config,
+            interval: Duration::from_millis(1000 / update_rate as u64),
+            last_update: Instant::now(),
+            beat_count: AudioPlugin::get_audio_beat_count(),
+        })
+    }
+
+    /// Returns the current analysis results, if the next update is due
+    fn poll(&mut self) -> Option<AudioData> {
+        if self.last_update.elapsed() < self.interval {
+            return None;
+        }
+
+        self.last_update = Instant::now();
+
+        let beat_count = AudioPlugin::get_audio_beat_count();
+        let beats = beat_count.saturating_sub(self.beat_count) as u32;
+        self.beat_count = beat_count;
+
+        let bands = AudioPlugin::get_audio_bands();
+
+        Some(AudioData {
+            loudness: AudioPlugin::get_audio_loudness() as f32 / i16::MAX as f32,
+            spectrum: AudioPlugin::get_audio_spectrum_bands(&self.config),
+            bass: bands.bass,
+            mid: bands.mid,
+            treble: bands.treble,
+            bpm: AudioPlugin::get_audio_bpm(),
+            beat_phase: AudioPlugin::get_audio_beat_phase(),
+            beats,
+        })
+    }
+}
+
+/// Send the audio analysis results to the subscribed SDK client. Updates are dropped
+/// instead of blocking the control thread, if the client does not keep up
+fn publish_audio_data(socket: &Socket, audio_data: &AudioData) -> Result<()> {
+    let config = bincode::config::standard();
+
+    let mut response = protocol::Response::default();
+    response.set_response_type(protocol::RequestType::AudioData);
+    response.payload = Some(ResponsePayload::Data(bincode::encode_to_vec(
+        audio_data, config,
+    )?));
+
+    let mut buf = Vec::new();
+    response.encode_length_delimited(&mut buf)?;
+
+    match socket.send_with_flags(&buf, libc::MSG_DONTWAIT) {
+        Ok(_n) => Ok(()),
+
+        Err(e) if e.kind() == ErrorKind::WouldBlock => {
+            trace!("SDK client does not keep up, dropping audio data update");
+
+            Ok(())
+        }
+
+        Err(_e) => Err(SdkPluginError::PluginError {
+            description: "Lost connection to Eruption SDK client".to_owned(),
+        }
+        .into()),
+    }
+}
+
 pub fn claim_hotplugged_devices(_hotplug_info: &HotplugInfo) -> Result<()> {
     if crate::QUIT.load(Ordering::SeqCst) {
         info!("Ignoring device hotplug event since Eruption is shutting down");
@@ -134,6 +256,10 @@ pub fn claim_hotplugged_devices(_hotplug_info: &HotplugInfo) -> Result<()> {
                         .unwrap_or_else(|e| {
                             error!("Could not send a pending dbus API event: {}", e)
                         });
+
+                    events::notify_observers(events::Event::DeviceAdded).unwrap_or_else(|e| {
+                        error!("Error during notification of observers: {}", e)
+                    });
                 }
             }
 
@@ -211,6 +337,10 @@ pub fn claim_hotplugged_devices(_hotplug_info: &HotplugInfo) -> Result<()> {
                             .unwrap_or_else(|e| {
                                 error!("Could not send a pending dbus API event: {}", e)
                             });
+
+                        events::notify_observers(events::Event::DeviceAdded).unwrap_or_else(|e| {
+                            error!("Error during notification of observers: {}", e)
+                        });
                     }
                 } else {
                     info!("Found mouse device, but mouse support is DISABLED by configuration");
@@ -263,6 +393,10 @@ pub fn claim_hotplugged_devices(_hotplug_info: &HotplugInfo) -> Result<()> {
                             .unwrap_or_else(|e| {
                                 error!("Could not send a pending dbus API event: {}", e)
                             });
+
+                        events::notify_observers(events::Event::DeviceAdded).unwrap_or_else(|e| {
+                            error!("Error during notification of observers: {}", e)
+                        });
                     } else {
                         // insert an unused rx
                         let (_misc_tx, misc_rx) = unbounded();
@@ -278,6 +412,10 @@ pub fn claim_hotplugged_devices(_hotplug_info: &HotplugInfo) -> Result<()> {
                             .unwrap_or_else(|e| {
                                 error!("Could not send a pending dbus API event: {}", e)
                             });
+
+                        events::notify_observers(events::Event::DeviceAdded).unwrap_or_else(|e| {
+                            error!("Error during notification of observers: {}", e)
+                        });
                     }
 
                     crate::MISC_DEVICES.write().push(device.clone());
@@ -361,6 +499,9 @@ impl SdkSupportPlugin {
                         socket.set_send_buffer_size(constants::NET_BUFFER_CAPACITY * 2)?;
                         socket.set_recv_buffer_size(constants::NET_BUFFER_CAPACITY * 2)?;
 
+                        // audio analysis results are only published after the client subscribed
+                        let mut audio_stream: Option<AudioStream> = None;
+
                         // connection successful, enter event loop now
                         'EVENT_LOOP: loop {
                             if crate::QUIT.load(Ordering::SeqCst) {
@@ -552,6 +693,84 @@ impl SdkSupportPlugin {
                                                     }
                                                 }
 
+                                                protocol::RequestType::SubscribeAudio => {
+                                                    trace!("Subscribe audio");
+
+                                                    let RequestPayload::Data(payload_subscription) =
+                                                        request.payload.unwrap();
+
+                                                    let config = bincode::config::standard();
+                                                    let subscription: AudioSubscription =
+                                                        bincode::decode_from_slice(
+                                                            &payload_subscription,
+                                                            config,
+                                                        )?
+                                                        .0;
+
+                                                    debug!(
+                                                        "SDK client subscribed to audio data: {:?}",
+                                                        subscription
+                                                    );
+
+                                                    audio_stream =
+                                                        Some(AudioStream::new(&subscription)?);
+
+                                                    let mut response =
+                                                        protocol::Response::default();
+                                                    response.set_response_type(
+                                                        protocol::RequestType::Noop,
+                                                    );
+
+                                                    let mut buf = Vec::new();
+                                                    response.encode_length_delimited(&mut buf)?;
+
+                                                    // send data
+                                                    match socket.send(&buf) {
+                                                        Ok(_n) => {}
+
+                                                        Err(_e) => {
+                                                            return Err(SdkPluginError::PluginError {
+                                                                description: "Lost connection to Eruption SDK client".to_owned(),
+                                                            }
+                                                                .into());
+                                                        }
+                                                    }
+                                                }
+
+                                                protocol::RequestType::UnsubscribeAudio => {
+                                                    trace!("Unsubscribe audio");
+
+                                                    audio_stream = None;
+
+                                                    let mut response =
+                                                        protocol::Response::default();
+                                                    response.set_response_type(
+                                                        protocol::RequestType::Noop,
+                                                    );
+
+                                                    let mut buf = Vec::new();
+                                                    response.encode_length_delimited(&mut buf)?;
+
+                                                    // send data
+                                                    match socket.send(&buf) {
+                                                        Ok(_n) => {}
+
+                                                        Err(_e) => {
+                                                            return Err(SdkPluginError::PluginError {
+                                                                description: "Lost connection to Eruption SDK client".to_owned(),
+                                                            }
+                                                                .into());
+                                                        }
+                                                    }
+                                                }
+
+                                                protocol::RequestType::AudioData => {
+                                                    // only sent by the daemon
+                                                    error!(
+                                                        "Protocol error: Unexpected request type"
+                                                    );
+                                                }
+
                                                 protocol::RequestType::Noop => {
                                                     /* Do nothing */
 
@@ -577,6 +796,12 @@ impl SdkSupportPlugin {
                                 }
                             }
 
+                            if let Some(audio_data) =
+                                audio_stream.as_mut().and_then(|stream| stream.poll())
+                            {
+                                publish_audio_data(&socket, &audio_data)?;
+                            }
+
                             if SDK_SUPPORT_ACTIVE.load(Ordering::SeqCst) {
                                 thread::sleep(Duration::from_millis(1));
                             } else {
@@ -26,11 +26,18 @@ use serde::{Deserialize, Serialize};
 use std::default::Default;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::{collections::HashMap, ffi::OsStr};
 use uuid::Uuid;
 
 pub type Result<T> = std::result::Result<T, eyre::Error>;
 
+/// Extension of the files that hold reusable parameter fragments
+const FRAGMENT_EXTENSION: &str = "fragment";
+
+/// Maximum depth of a chain of inherited profiles
+const MAX_INHERITANCE_DEPTH: usize = 16;
+
 #[derive(Debug, thiserror::Error)]
 pub enum ProfileError {
     #[error("Could not open profile file for reading")]
@@ -53,6 +60,9 @@ pub enum ProfileError {
 
     #[error("Could not parse a param value")]
     ParseParamError {},
+
+    #[error("Could not resolve profile inheritance: {msg}")]
+    InheritanceError { msg: String },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, PartialOrd)]
@@ -167,7 +177,227 @@ pub struct Profile {
     #[serde(default = "default_script_file")]
     pub active_scripts: Vec<PathBuf>,
 
+    /// The keymap that is in effect while this profile is active
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keymap: Option<String>,
+
+    /// The sound effects pack that is used while this profile is active
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sfx_pack: Option<String>,
+
+    /// The profile that this profile inherits from, either its UUID or its file name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+
+    /// Fragments with reusable parameters, that are merged into this profile
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+
     pub config: Option<HashMap<String, Vec<ConfigParam>>>,
+
+    /// The file that defined the effective value of each parameter, per script
+    #[serde(skip)]
+    pub origins: HashMap<String, HashMap<String, PathBuf>>,
+
+    /// The parameters as defined by the profile files, before the profile state has been applied
+    #[serde(skip)]
+    pub resolved_config: HashMap<String, Vec<ConfigParam>>,
+}
+
+/// The contents of a profile, merged with the profile it extends and the fragments it includes
+#[derive(Debug, Default)]
+struct ResolvedProfile {
+    table: toml::value::Table,
+    origins: HashMap<String, HashMap<String, PathBuf>>,
+}
+
+impl ResolvedProfile {
+    /// Merge the contents of a profile or fragment file. Parameters are merged by
+    /// name, all other fields replace the inherited ones
+    fn merge(&mut self, table: toml::value::Table, origin: &Path) {
+        // inheritance directives only apply to the file that declares them
+        self.table.remove("extends");
+        self.table.remove("include");
+
+        for (key, value) in table {
+            match value {
+                toml::Value::Table(config) if key == "config" => self.merge_config(config, origin),
+
+                value => {
+                    self.table.insert(key, value);
+                }
+            }
+        }
+    }
+
+    fn merge_config(&mut self, config: toml::value::Table, origin: &Path) {
+        let merged = self
+            .table
+            .entry("config")
+            .or_insert_with(|| toml::Value::Table(toml::value::Table::new()));
+
+        if !merged.is_table() {
+            *merged = toml::Value::Table(toml::value::Table::new());
+        }
+
+        let merged = merged.as_table_mut().unwrap();
+
+        for (script, params) in config {
+            let inherited = merged
+                .entry(script.clone())
+                .or_insert_with(|| toml::Value::Array(vec![]));
+
+            match (inherited, params) {
+                (toml::Value::Array(inherited), toml::Value::Array(params)) => {
+                    for param in params {
+                        let name = param.get("name").and_then(|v| v.as_str()).map(String::from);
+
+                        if let Some(ref name) = name {
+                            self.origins
+                                .entry(script.clone())
+                                .or_default()
+                                .insert(name.clone(), origin.to_path_buf());
+                        }
+
+                        let existing = inherited.iter_mut().find(|p| {
+                            name.is_some()
+                                && p.get("name").and_then(|v| v.as_str()) == name.as_deref()
+                        });
+
+                        match existing {
+                            Some(existing) => *existing = param,
+                            None => inherited.push(param),
+                        }
+                    }
+                }
+
+                // not a list of parameters, this will be reported when deserializing the profile
+                (inherited, params) => *inherited = params,
+            }
+        }
+    }
+}
+
+fn read_table(file: &Path) -> Result<toml::value::Table> {
+    let toml = fs::read_to_string(file).map_err(|_e| ProfileError::OpenError {})?;
+    let table = toml::de::from_str(&toml).map_err(|_e| ProfileError::ParseError {})?;
+
+    Ok(table)
+}
+
+/// Read a profile file, and resolve the profile it `extends` and the fragments it `include`s
+fn resolve(profile_file: &Path, chain: &mut Vec<PathBuf>) -> Result<ResolvedProfile> {
+    let canonical_file = fs::canonicalize(profile_file).unwrap_or_else(|_| profile_file.into());
+
+    if chain.contains(&canonical_file) || chain.len() >= MAX_INHERITANCE_DEPTH {
+        return Err(ProfileError::InheritanceError {
+            msg: format!("Circular inheritance of {}", profile_file.display()),
+        }
+        .into());
+    }
+
+    chain.push(canonical_file);
+
+    let table = read_table(profile_file)?;
+    let mut result = ResolvedProfile::default();
+
+    if let Some(extends) = table.get("extends") {
+        let extends = extends.as_str().ok_or(ProfileError::ParseError {})?;
+        let parent_file = find_inherited_profile(extends, profile_file)?;
+
+        result = resolve(&parent_file, chain)?;
+
+        // the identity of a profile is never inherited
+        result.table.remove("id");
+    }
+
+    if let Some(include) = table.get("include") {
+        let include = include.as_array().ok_or(ProfileError::ParseError {})?;
+
+        for fragment in include {
+            let fragment = fragment.as_str().ok_or(ProfileError::ParseError {})?;
+            let fragment_file =
+                find_file(fragment, FRAGMENT_EXTENSION, profile_file).ok_or_else(|| {
+                    ProfileError::InheritanceError {
+                        msg: format!("Could not find fragment {}", fragment),
+                    }
+                })?;
+
+            result.merge(read_table(&fragment_file)?, &fragment_file);
+        }
+    }
+
+    result.merge(table, profile_file);
+
+    chain.pop();
+
+    Ok(result)
+}
+
+/// Locate a profile or fragment file, either in the directory of the file that refers to it,
+/// or in one of the profile directories
+fn find_file(name: &str, extension: &str, referrer: &Path) -> Option<PathBuf> {
+    let mut file_name = PathBuf::from(name);
+
+    if file_name.extension().is_none() {
+        file_name.set_extension(extension);
+    }
+
+    if file_name.is_absolute() {
+        return Some(file_name).filter(|f| f.is_file());
+    }
+
+    let referrer_dir = referrer.parent().unwrap_or_else(|| Path::new(""));
+
+    Some(referrer_dir.join(&file_name))
+        .filter(|f| f.is_file())
+        .or_else(|| {
+            get_profile_dirs()
+                .iter()
+                .map(|dir| dir.join(&file_name))
+                .find(|f| f.is_file())
+        })
+}
+
+/// Locate the profile that is referred to by an `extends` directive
+fn find_inherited_profile(extends: &str, referrer: &Path) -> Result<PathBuf> {
+    let result = match Uuid::from_str(extends) {
+        Ok(uuid) => {
+            // only parse the ids here, since resolving other profiles could recurse indefinitely
+            let has_uuid = |file: &PathBuf| {
+                read_table(file)
+                    .ok()
+                    .and_then(|table| table.get("id").and_then(|id| id.as_str()).map(String::from))
+                    .and_then(|id| Uuid::from_str(&id).ok())
+                    == Some(uuid)
+            };
+
+            let referrer_dir = referrer
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .to_path_buf();
+
+            get_profile_files_from(&[referrer_dir])
+                .unwrap_or_default()
+                .into_iter()
+                .find(has_uuid)
+                .or_else(|| {
+                    get_profile_files()
+                        .unwrap_or_default()
+                        .into_iter()
+                        .find(has_uuid)
+                })
+        }
+
+        Err(_e) => find_file(extends, "profile", referrer),
+    };
+
+    result.ok_or_else(|| {
+        ProfileError::InheritanceError {
+            msg: format!("Could not find the inherited profile {}", extends),
+        }
+        .into()
+    })
 }
 
 macro_rules! get_default_value {
@@ -388,29 +618,30 @@ macro_rules! set_config_value {
 
 impl Profile {
     pub fn new(profile_file: &Path) -> Result<Self> {
-        // parse manifest
-        match fs::read_to_string(profile_file) {
-            Ok(toml) => {
-                // parse profile
-                match toml::de::from_str::<Self>(&toml) {
-                    Ok(mut result) => {
-                        // fill in required fields, after parsing
-                        result.id = Uuid::new_v4();
-                        result.profile_file = profile_file.to_path_buf();
-
-                        if result.config.is_none() {
-                            result.config = Some(HashMap::new());
-                        }
-
-                        Ok(result)
-                    }
+        let mut result = Self::load(profile_file)?;
 
-                    Err(_e) => Err(ProfileError::ParseError {}.into()),
-                }
-            }
+        // fill in required fields, after parsing
+        result.id = Uuid::new_v4();
+        result.profile_file = profile_file.to_path_buf();
 
-            Err(_e) => Err(ProfileError::OpenError {}.into()),
+        if result.config.is_none() {
+            result.config = Some(HashMap::new());
         }
+
+        Ok(result)
+    }
+
+    /// Parse a profile file, including the profile it extends and the fragments it includes
+    pub fn load(profile_file: &Path) -> Result<Self> {
+        let resolved = resolve(profile_file, &mut vec![])?;
+
+        let mut result: Self = toml::Value::Table(resolved.table)
+            .try_into()
+            .map_err(|_e| ProfileError::ParseError {})?;
+
+        result.origins = resolved.origins;
+
+        Ok(result)
     }
 
     /// Returns a failsafe profile that will work in almost all cases
@@ -426,33 +657,23 @@ impl Profile {
     }
 
     pub fn from(profile_file: &Path) -> Result<Self> {
-        // parse manifest
-        match fs::read_to_string(profile_file) {
-            Ok(toml) => {
-                // parse profile
-                match toml::de::from_str::<Self>(&toml) {
-                    Ok(mut result) => {
-                        // fill in required fields, after parsing
-                        result.profile_file = profile_file.to_path_buf();
-
-                        // load persisted profile state from disk, but ignore errors
-                        let _ = result
-                            .load_params()
-                            .map_err(|e| trace!("Error loading profile state from disk: {}", e));
-
-                        if result.config.is_none() {
-                            result.config = Some(HashMap::new());
-                        }
+        let mut result = Self::load(profile_file)?;
 
-                        Ok(result)
-                    }
+        // fill in required fields, after parsing
+        result.profile_file = profile_file.to_path_buf();
 
-                    Err(_e) => Err(ProfileError::ParseError {}.into()),
-                }
-            }
+        // load persisted profile state from disk, but ignore errors
+        result.resolved_config = result.config.clone().unwrap_or_default();
+
+        let _ = result
+            .load_params()
+            .map_err(|e| trace!("Error loading profile state from disk: {}", e));
 
-            Err(_e) => Err(ProfileError::OpenError {}.into()),
+        if result.config.is_none() {
+            result.config = Some(HashMap::new());
         }
+
+        Ok(result)
     }
 
     pub fn find_by_uuid(uuid: Uuid) -> Result<Self> {
@@ -492,20 +713,63 @@ impl Profile {
         Ok(())
     }
 
+    /// Apply the persisted profile state on top of the resolved parameters. Only the values that
+    /// differ from the resolved parameters are applied and attributed to the state file, so that
+    /// all other parameters still follow the profiles and fragments they have been resolved from
     pub fn load_params(&mut self) -> Result<()> {
         let path = self.profile_file.with_extension("profile.state");
         let json_string = fs::read_to_string(&path)?;
 
         let map: HashMap<String, Vec<ConfigParam>> = serde_json::from_str(&json_string)?;
 
-        self.config = Some(map);
+        let config = self.config.get_or_insert_with(HashMap::new);
+
+        for (script, params) in map {
+            let resolved_params = self.resolved_config.get(&script);
+
+            for param in params {
+                if matches!(resolved_params, Some(p) if p.contains(&param)) {
+                    continue;
+                }
+
+                let name = param.get_name().clone();
+                let script_params = config.entry(script.clone()).or_default();
+
+                match script_params.iter_mut().find(|p| p.get_name() == &name) {
+                    Some(p) => *p = param,
+                    None => script_params.push(param),
+                }
+
+                self.origins
+                    .entry(script.clone())
+                    .or_default()
+                    .insert(name, path.clone());
+            }
+        }
 
         Ok(())
     }
 
+    /// Persist the parameters that have been changed at runtime, i.e. those that differ from
+    /// the resolved parameters
     pub fn save_params(&self) -> Result<()> {
         if let Some(ref config) = self.config {
-            let json_string = serde_json::to_string_pretty(&config)?;
+            let mut map: HashMap<&String, Vec<&ConfigParam>> = HashMap::new();
+
+            for (script, params) in config {
+                let resolved_params = self.resolved_config.get(script);
+
+                let changed = params
+                    .iter()
+                    .filter(|param| !matches!(resolved_params, Some(p) if p.contains(param)))
+                    .collect::<Vec<_>>();
+
+                if !changed.is_empty() {
+                    map.insert(script, changed);
+                }
+            }
+
+            let json_string = serde_json::to_string_pretty(&map)?;
             let path = self.profile_file.with_extension("profile.state");
 
             fs::write(&path, json_string)?;
@@ -543,7 +807,13 @@ impl Default for Profile {
             name: "Default".into(),
             description: "Auto-generated profile".into(),
             active_scripts: vec![PathBuf::from(constants::DEFAULT_EFFECT_SCRIPT)],
+            keymap: None,
+            sfx_pack: None,
+            extends: None,
+            include: vec![],
             config,
+            origins: HashMap::new(),
+            resolved_config: HashMap::new(),
         }
     }
 }
@@ -574,6 +844,9 @@ pub fn get_profile_dirs() -> Vec<PathBuf> {
         result.push(path);
     }
 
+    // profiles that have been imported from bundles take precedence
+    result.insert(0, PathBuf::from(constants::IMPORTED_PROFILE_DIR));
+
     result
 }
 
@@ -802,4 +1075,144 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn inherited_profile() -> super::Result<()> {
+        let path = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+        let assets = path.join("../support/tests/assets/");
+
+        let profile = super::Profile::from(&assets.join("inherited.profile"))?;
+
+        // own fields are not inherited, fields the child does not declare are
+        assert_eq!(profile.name, "Organic FX (inherited)");
+        assert_eq!(
+            profile.id,
+            Uuid::from_str("5dc62fa6-e965-45cb-a1dc-e87d29713096").unwrap()
+        );
+        assert_eq!(
+            profile.active_scripts,
+            vec![
+                PathBuf::from("organic.lua"),
+                PathBuf::from("shockwave.lua"),
+                PathBuf::from("macros.lua")
+            ]
+        );
+
+        let config = profile.config.as_ref().unwrap();
+
+        // the child overrides a single parameter of the parent
+        assert_eq!(
+            config["Shockwave"].find_config_param("mouse_events"),
+            Some(&super::ConfigParam::Bool {
+                name: String::from("mouse_events"),
+                value: false,
+                default: Default::default(),
+            })
+        );
+        assert_eq!(config["Shockwave"].len(), 4);
+        assert_eq!(
+            profile.origins["Shockwave"]["mouse_events"],
+            assets.join("inherited.profile")
+        );
+        assert_eq!(
+            profile.origins["Shockwave"]["color_step_shockwave"],
+            assets.join("default.profile")
+        );
+
+        // parameters of the fragment
+        assert_eq!(config["Dim Zone"].len(), 3);
+        assert_eq!(
+            profile.origins["Dim Zone"]["opacity"],
+            assets.join("dim-zone.fragment")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn profile_state_overrides() -> super::Result<()> {
+        let path = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+        let assets = path.join("../support/tests/assets/");
+
+        let dir = std::env::temp_dir().join(format!("eruption-profiles-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir)?;
+
+        for file in ["default.profile", "inherited.profile", "dim-zone.fragment"] {
+            std::fs::copy(assets.join(file), dir.join(file))?;
+        }
+
+        let profile_file = dir.join("inherited.profile");
+        let state_file = dir.join("inherited.profile.state");
+
+        // a snapshot of all parameters, as written by previous versions
+        let mut profile = super::Profile::from(&profile_file)?;
+        profile.set_color_value("Shockwave", "color_mouse_click_flash", &0x00ff0000)?;
+
+        std::fs::write(
+            &state_file,
+            serde_json::to_string_pretty(profile.config.as_ref().unwrap())?,
+        )?;
+
+        let profile = super::Profile::from(&profile_file)?;
+
+        assert_eq!(
+            profile.origins["Shockwave"]["color_mouse_click_flash"],
+            state_file
+        );
+        assert_eq!(profile.origins["Shockwave"]["mouse_events"], profile_file);
+        assert_eq!(
+            profile.origins["Shockwave"]["color_step_shockwave"],
+            dir.join("default.profile")
+        );
+        assert_eq!(
+            profile.origins["Dim Zone"]["opacity"],
+            dir.join("dim-zone.fragment")
+        );
+
+        // only the changed parameter is persisted
+        profile.save_params()?;
+
+        let state: std::collections::HashMap<String, Vec<super::ConfigParam>> =
+            serde_json::from_str(&std::fs::read_to_string(&state_file)?)?;
+
+        assert_eq!(state.len(), 1);
+        assert_eq!(state["Shockwave"].len(), 1);
+
+        // changes to the inherited profile still apply
+        let parent = std::fs::read_to_string(dir.join("default.profile"))?
+            .replace("0x05010000", "0x06010000");
+        std::fs::write(dir.join("default.profile"), parent)?;
+
+        let profile = super::Profile::from(&profile_file)?;
+        let config = profile.config.as_ref().unwrap();
+
+        assert_eq!(
+            config["Shockwave"].find_config_param("color_step_shockwave"),
+            Some(&super::ConfigParam::Color {
+                name: String::from("color_step_shockwave"),
+                value: 0x06010000,
+                default: Default::default(),
+            })
+        );
+        assert_eq!(
+            config["Shockwave"].find_config_param("color_mouse_click_flash"),
+            Some(&super::ConfigParam::Color {
+                name: String::from("color_mouse_click_flash"),
+                value: 0x00ff0000,
+                default: Default::default(),
+            })
+        );
+
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn circular_inheritance() {
+        let path = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+        let profile_path = path.join("../support/tests/assets/circular.profile");
+
+        assert!(super::Profile::from(&profile_path).is_err());
+    }
 }
@@ -40,6 +40,7 @@ use crate::hwdevices::KeyboardHidEvent;
 use crate::hwdevices::MouseHidEvent;
 use crate::hwdevices::RGBA;
 use crate::plugin_manager;
+use crate::plugins::AudioPlugin;
 use crate::profiles::Profile;
 use crate::scripting::manifest::{ConfigParam, Manifest};
 
@@ -67,6 +68,18 @@ pub enum Message {
     MouseMove(i32, i32, i32),
     MouseWheelEvent(u8),
 
+    /// Call a macro function, as requested by a mapping of the active keymap
+    CallMacro {
+        function_name: String,
+        down: bool,
+    },
+
+    // Audio events
+    AudioBeat {
+        bpm: f32,
+        strength: f32,
+    },
+
     //LoadScript(PathBuf),
     // Abort,
     Unload,
@@ -113,6 +126,9 @@ thread_local! {
     /// True, if LED color map was modified at least once in this thread
     pub static LOCAL_LED_MAP_MODIFIED: RefCell<bool> = RefCell::new(false);
 
+    /// Identifier of the Lua VM that runs on this thread, used to track the keys it holds down
+    pub static LUA_VM_ID: RefCell<Option<usize>> = RefCell::new(None);
+
     /// Vec of allocated gradient objects
     pub static ALLOCATED_GRADIENTS: RefCell<HashMap<usize, colorgrad::Gradient>> = RefCell::new(HashMap::new());
 }
@@ -152,7 +168,7 @@ mod callbacks {
     use std::time::Duration;
     use std::{cell::RefCell, thread};
 
-    use super::{LED_MAP, LOCAL_LED_MAP, LOCAL_LED_MAP_MODIFIED};
+    use super::{LED_MAP, LOCAL_LED_MAP, LOCAL_LED_MAP_MODIFIED, LUA_VM_ID};
 
     use crate::plugins::macros;
     use crate::{constants, hwdevices::RGBA};
@@ -304,7 +320,11 @@ mod callbacks {
             .read()
             .as_ref()
             .unwrap()
-            .send(macros::Message::InjectKey { key: ev_key, down })
+            .send(macros::Message::InjectKey {
+                key: ev_key,
+                down,
+                vm_id: LUA_VM_ID.with(|id| *id.borrow()),
+            })
             .unwrap();
     }
 
@@ -322,6 +342,7 @@ mod callbacks {
             .send(macros::Message::InjectButtonEvent {
                 button: button_index,
                 down,
+                vm_id: LUA_VM_ID.with(|id| *id.borrow()),
             })
             .unwrap();
     }
@@ -348,6 +369,8 @@ mod callbacks {
         // mirrored on the virtual keyboard.
         macros::DROP_CURRENT_KEY.store(true, Ordering::SeqCst);
 
+        let vm_id = LUA_VM_ID.with(|id| *id.borrow());
+
         thread::Builder::new()
             .name("uinput/delayed".to_owned())
             .spawn(move || {
@@ -357,7 +380,11 @@ mod callbacks {
                     .read()
                     .as_ref()
                     .unwrap()
-                    .send(macros::Message::InjectKey { key: ev_key, down })
+                    .send(macros::Message::InjectKey {
+                        key: ev_key,
+                        down,
+                        vm_id,
+                    })
                     .unwrap();
             })
             .unwrap();
@@ -919,6 +946,11 @@ pub fn run_script(
                 return Ok(RunScriptResult::TerminatedWithErrors);
             }
 
+            // beats are only detected if at least one script is interested in them
+            if lua_ctx.globals().get::<_, Function>("on_beat").is_ok() {
+                AudioPlugin::enable_beat_detection();
+            }
+
             // reduce CPU load by caching the event handler status
             let mut has_tick_handler = true;
             let mut has_mouse_move_handler = true;
@@ -980,9 +1012,6 @@ pub fn run_script(
                             if LOCAL_LED_MAP_MODIFIED.with(|f| *f.borrow()) {
                                 LOCAL_LED_MAP.with(|foreground| {
                                     let brightness = crate::BRIGHTNESS.load(Ordering::SeqCst);
-                                    let fader = crate::BRIGHTNESS_FADER.load(Ordering::SeqCst);
-
-                                    let brightness = (1.0 - (fader as f32 / constants::FADE_FRAMES as f32)) * brightness as f32;
 
                                     for chunks in LED_MAP.write().chunks_exact_mut(constants::CANVAS_SIZE) {
                                         for (idx, background) in chunks.iter_mut().enumerate() {
@@ -1329,6 +1358,51 @@ pub fn run_script(
                             }
                         }
 
+                        Message::CallMacro {
+                            function_name,
+                            down,
+                        } => {
+                            // the macro function is only defined in one of the scripts
+                            if let Ok(handler) = lua_ctx.globals().get::<_, Function>(function_name)
+                            {
+                                let mut errors_present = false;
+
+                                handler.call::<_, ()>(down).unwrap_or_else(|e| {
+                                    error!(
+                                        "Lua error in file {}: {}\n\t{:?}",
+                                        file.display(),
+                                        e,
+                                        e.source().unwrap_or(&UnknownError {})
+                                    );
+                                    errors_present = true;
+                                });
+
+                                if errors_present {
+                                    return Ok(RunScriptResult::TerminatedWithErrors);
+                                }
+                            }
+                        }
+
+                        Message::AudioBeat { bpm, strength } => {
+                            if let Ok(handler) = lua_ctx.globals().get::<_, Function>("on_beat") {
+                                let mut errors_present = false;
+
+                                handler.call::<_, ()>((bpm, strength)).unwrap_or_else(|e| {
+                                    error!(
+                                        "Lua error in file {}: {}\n\t{:?}",
+                                        file.display(),
+                                        e,
+                                        e.source().unwrap_or(&UnknownError {})
+                                    );
+                                    errors_present = true;
+                                });
+
+                                if errors_present {
+                                    return Ok(RunScriptResult::TerminatedWithErrors);
+                                }
+                            }
+                        }
+
                         //Message::LoadScript(script_path) => {
                         //return Ok(RunScriptResult::ReExecuteOtherScript(script_path))
                         //}
@@ -1416,17 +1490,12 @@ pub fn run_script(
 fn register_support_globals(lua_ctx: &Lua) -> mlua::Result<()> {
     let globals = lua_ctx.globals();
 
-    let config = crate::CONFIG.lock();
-    let script_dirs = config
-        .as_ref()
-        .unwrap()
-        .get::<Vec<String>>("global.script_dirs")
-        .unwrap_or_else(|_| vec![constants::DEFAULT_SCRIPT_DIR.to_string()]);
+    let script_dirs = crate::util::get_script_dirs();
 
     let mut path_spec = String::from("package.path = package.path .. '");
 
     for script_dir in script_dirs {
-        path_spec += &format!(";{0}/lib/?;{0}/lib/?.lua", &script_dir);
+        path_spec += &format!(";{0}/lib/?;{0}/lib/?.lua", &script_dir.display());
     }
 
     path_spec += "'";
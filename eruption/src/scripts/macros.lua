@@ -83,9 +83,13 @@ if not status then
     require("macros/failsafe-macros")
 end
 
--- import keymap definitions sub-modules
-local status, message = pcall(require, keymap)
-if not status then error("Error loading the keymap definition: " .. message) end
+-- import keymap definitions sub-modules, unless the daemon executes a native keymap
+if not is_native_keymap_active() then
+    local status, message = pcall(require, keymap)
+    if not status then
+        error("Error loading the keymap definition: " .. message)
+    end
+end
 
 -- global state variables --
 ticks = 0
@@ -660,6 +664,9 @@ function do_switch_easy_shift_layer(index)
     consume_key()
 
     ACTIVE_EASY_SHIFT_LAYER = index + 1
+
+    -- keep the native keymap engine in sync
+    set_easy_shift_layer(ACTIVE_EASY_SHIFT_LAYER)
 end
 
 function update_overlay_state()
@@ -690,6 +697,23 @@ function update_overlay_state()
     end
 end
 
+-- highlight the keys that are mapped on the layer of the native keymap,
+-- that has been activated by a keymap action
+function update_keymap_layer_state()
+    local layer = get_keymap_layer()
+    if layer == nil then return end
+
+    local color = get_keymap_layer_color(layer) or COLOR_REMAPPED_KEY
+
+    for i = 1, canvas_size do color_map_highlight[i] = color_highlight end
+
+    for _, key_index in ipairs(get_keymap_layer_keys(layer)) do
+        color_map_highlight[key_index] = color
+    end
+
+    highlight_ttl = highlight_max_ttl
+end
+
 function on_tick(delta)
     ticks = ticks + delta
 
@@ -712,6 +736,7 @@ function on_tick(delta)
 
     update_overlay_state()
     update_color_state()
+    update_keymap_layer_state()
 
     if effect_ttl <= 0 and highlight_ttl <= 0 and overlay_ttl <= 0 then
         return
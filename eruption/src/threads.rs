@@ -28,8 +28,8 @@ use std::thread;
 use std::time::{Duration, Instant};
 
 use crate::{
-    constants, dbus_interface, hwdevices, macros, plugins, script, sdk_support, uleds, util,
-    DeviceAction, EvdevError, KeyboardDevice, MainError, MouseDevice, Profile,
+    constants, dbus_interface, hwdevices, macros, plugins, script, sdk_support, transitions, uleds,
+    util, DeviceAction, EvdevError, KeyboardDevice, MainError, MouseDevice, Profile,
     COLOR_MAPS_READY_CONDITION, FAILED_TXS, KEY_STATES, LUA_TXS, QUIT, REQUEST_FAILSAFE_MODE, RGBA,
     SDK_SUPPORT_ACTIVE, ULEDS_SUPPORT_ACTIVE,
 };
@@ -657,6 +657,7 @@ pub fn spawn_misc_input_thread(
 
 pub fn spawn_lua_thread(
     thread_idx: usize,
+    vm_id: usize,
     lua_rx: Receiver<script::Message>,
     script_path: PathBuf,
     profile: Option<Profile>,
@@ -695,18 +696,23 @@ pub fn spawn_lua_thread(
         #[cfg(feature = "profiling")]
         coz::thread_init();
 
+        script::LUA_VM_ID.with(|id| *id.borrow_mut() = Some(vm_id));
+
         #[allow(clippy::never_loop)]
         loop {
             let result = script::run_script(script_path.clone(), profile, &lua_rx);
 
+            // a terminated script must not leave any keys in the pressed state, that
+            // it injected; keys of the other scripts are left alone
+            plugins::MacrosPlugin::release_held_keys_of(vm_id);
+
             match result {
                 Ok(script::RunScriptResult::TerminatedGracefully) => return Ok(()),
 
                 Ok(script::RunScriptResult::TerminatedWithErrors) => {
                     error!("Script execution failed");
 
-                    LUA_TXS.write().get_mut(thread_idx).unwrap().is_failed = true;
-                    REQUEST_FAILSAFE_MODE.store(true, Ordering::SeqCst);
+                    mark_lua_vm_failed(vm_id);
 
                     return Err(MainError::ScriptExecError {}.into());
                 }
@@ -714,8 +720,7 @@ pub fn spawn_lua_thread(
                 Err(_e) => {
                     error!("Script execution failed due to an unknown error");
 
-                    LUA_TXS.write().get_mut(thread_idx).unwrap().is_failed = true;
-                    REQUEST_FAILSAFE_MODE.store(true, Ordering::SeqCst);
+                    mark_lua_vm_failed(vm_id);
 
                     return Err(MainError::ScriptExecError {}.into());
                 }
@@ -726,6 +731,18 @@ pub fn spawn_lua_thread(
     Ok(())
 }
 
+/// Only a failed Lua VM of the active profile requests failsafe mode, a failed VM
+/// of the previous profile is just dropped from the transition that is in progress
+fn mark_lua_vm_failed(vm_id: usize) {
+    if let Some(lua_tx) = LUA_TXS.write().iter_mut().find(|tx| tx.vm_id == vm_id) {
+        lua_tx.is_failed = true;
+
+        REQUEST_FAILSAFE_MODE.store(true, Ordering::SeqCst);
+    } else {
+        transitions::drop_lua_vm(vm_id);
+    }
+}
+
 pub fn spawn_device_io_thread(dev_io_rx: Receiver<DeviceAction>) -> Result<()> {
     let builder = thread::Builder::new().name("dev-io/all".to_owned());
 
@@ -751,9 +768,10 @@ pub fn spawn_device_io_thread(dev_io_rx: Receiver<DeviceAction>) -> Result<()> {
                 Ok(message) => match message {
                     DeviceAction::RenderNow  => {
                         let current_frame_generation = script::FRAME_GENERATION_COUNTER.load(Ordering::SeqCst);
-                        if saved_frame_generation.load(Ordering::SeqCst) < current_frame_generation {
+                        if saved_frame_generation.load(Ordering::SeqCst) < current_frame_generation || transitions::is_active() {
                             // instruct the Lua VMs to realize their color maps, but only if at least one VM
-                            // submitted a new color map (performed a frame generation increment)
+                            // submitted a new color map (performed a frame generation increment), or if we
+                            // are in the middle of a transition between two profiles
 
                             // execute render "pipeline" now...
                             let mut drop_frame = false;
@@ -802,6 +820,11 @@ pub fn spawn_device_io_thread(dev_io_rx: Receiver<DeviceAction>) -> Result<()> {
                                 }
                             }
 
+                            // blend the canvas of the previous profile, while a transition is in progress
+                            if !drop_frame && !transitions::render() {
+                                drop_frame = true;
+                            }
+
                             if ULEDS_SUPPORT_ACTIVE.load(Ordering::SeqCst) {
                                 // blend the LED map of the Userspace LEDs support plugin
                                 let uleds_led_map = uleds::LED_MAP.read();
@@ -993,6 +1016,18 @@ pub fn spawn_device_io_thread(dev_io_rx: Receiver<DeviceAction>) -> Result<()> {
                             fps_counter = 0;
                         }
                     }
+
+                    DeviceAction::AdjustDpi(adjustment) => {
+                        for mouse_device in crate::MOUSE_DEVICES.read().iter() {
+                            let mut device = mouse_device.write();
+
+                            let dpi = device.get_dpi().unwrap_or(0) as i64;
+
+                            device
+                                .set_dpi(adjustment.apply(dpi).max(0) as i32)
+                                .unwrap_or_else(|e| error!("Could not set the DPI: {}", e));
+                        }
+                    }
                 },
 
                 Err(e) => {
@@ -0,0 +1,438 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::hwdevices::RGBA;
+use crate::script;
+use crate::{constants, LuaTx, COLOR_MAPS_READY_CONDITION};
+
+/// Width of the soft edge of a wipe, in columns of the canvas
+const WIPE_EDGE_WIDTH: f32 = 3.0;
+
+/// Share of the duration of a dissolve, that each individual LED takes to fade over
+const DISSOLVE_SOFTNESS: f32 = 0.2;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TransitionError {
+    #[error("Unknown profile transition: {0}")]
+    UnknownTransition(String),
+}
+
+/// The way the canvas of the previous profile is replaced by the canvas of the new profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransitionKind {
+    /// Switch instantly
+    None,
+
+    /// Blend all LEDs at the same time
+    CrossFade,
+
+    /// Sweep the new profile across the canvas, from left to right
+    Wipe,
+
+    /// Replace the LEDs in random order
+    Dissolve,
+}
+
+impl FromStr for TransitionKind {
+    type Err = TransitionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(TransitionKind::None),
+            "cross-fade" | "crossfade" => Ok(TransitionKind::CrossFade),
+            "wipe" => Ok(TransitionKind::Wipe),
+            "dissolve" => Ok(TransitionKind::Dissolve),
+
+            _ => Err(TransitionError::UnknownTransition(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub kind: TransitionKind,
+    pub duration: Duration,
+}
+
+impl Transition {
+    /// Returns the transition that is configured in the `[global]` section of `eruption.conf`
+    pub fn from_config() -> Self {
+        let config = crate::CONFIG.lock();
+        let config = config.as_ref().unwrap();
+
+        let kind = config
+            .get::<String>("global.profile_transition")
+            .map(|kind| {
+                kind.parse().unwrap_or_else(|e| {
+                    warn!("{}, falling back to a cross-fade", e);
+
+                    TransitionKind::CrossFade
+                })
+            })
+            .unwrap_or(TransitionKind::CrossFade);
+
+        let duration = config
+            .get_int("global.profile_transition_millis")
+            .map(|millis| millis.max(0) as u64)
+            .unwrap_or(constants::PROFILE_TRANSITION_MILLIS);
+
+        Self {
+            kind,
+            duration: Duration::from_millis(duration),
+        }
+    }
+}
+
+/// A transition that is in progress, along with the Lua VMs of the previous profile
+struct ActiveTransition {
+    transition: Transition,
+    start: Instant,
+
+    /// The Lua VMs of the previous profile, they keep rendering until the transition completed
+    lua_txs: Vec<LuaTx>,
+
+    /// Point in time at which each LED starts to fade over, used by the dissolve transition
+    thresholds: Vec<f32>,
+}
+
+impl ActiveTransition {
+    fn progress(&self) -> f32 {
+        (self.start.elapsed().as_secs_f32() / self.transition.duration.as_secs_f32())
+            .clamp(0.0, 1.0)
+    }
+}
+
+lazy_static! {
+    /// The transition between two profiles that is currently in progress
+    static ref ACTIVE_TRANSITION: Arc<Mutex<Option<ActiveTransition>>> = Arc::new(Mutex::new(None));
+}
+
+/// Start a transition from the canvas rendered by `lua_txs` (the Lua VMs of the previous profile)
+/// to the canvas of the newly started Lua VMs. A transition that is still in progress is cut short
+pub fn begin(lua_txs: Vec<LuaTx>, transition: Transition) {
+    abort();
+
+    if transition.kind == TransitionKind::None || transition.duration.is_zero() {
+        unload(&lua_txs);
+    } else {
+        debug!("Starting profile transition: {:?}", transition);
+
+        let thresholds = (0..constants::CANVAS_SIZE)
+            .map(|_| rand::random::<f32>() * (1.0 - DISSOLVE_SOFTNESS))
+            .collect();
+
+        *ACTIVE_TRANSITION.lock() = Some(ActiveTransition {
+            transition,
+            start: Instant::now(),
+            lua_txs,
+            thresholds,
+        });
+    }
+}
+
+/// Quit the Lua VMs of the previous profile immediately, e.g. when entering failsafe mode
+pub fn abort() {
+    if let Some(active_transition) = ACTIVE_TRANSITION.lock().take() {
+        unload(&active_transition.lua_txs);
+    }
+}
+
+pub fn is_active() -> bool {
+    ACTIVE_TRANSITION.lock().is_some()
+}
+
+/// Send timer tick events to the Lua VMs of the previous profile, so that they keep animating
+pub fn tick(delta: u32) {
+    if let Some(active_transition) = ACTIVE_TRANSITION.lock().as_ref() {
+        for lua_tx in active_transition.lua_txs.iter().filter(|tx| !tx.is_failed) {
+            lua_tx
+                .send(script::Message::Tick(delta))
+                .unwrap_or_else(|e| error!("Send error during timer tick event: {}", e));
+        }
+    }
+}
+
+/// Drop the Lua VM `vm_id` from the transition that is in progress, e.g. after its script failed.
+/// Returns false if the VM does not belong to the previous profile
+pub fn drop_lua_vm(vm_id: usize) -> bool {
+    match ACTIVE_TRANSITION.lock().as_mut() {
+        Some(active_transition) => {
+            let len = active_transition.lua_txs.len();
+
+            active_transition.lua_txs.retain(|tx| tx.vm_id != vm_id);
+            active_transition.lua_txs.len() != len
+        }
+
+        None => false,
+    }
+}
+
+/// Blend the canvas of the previous profile with the canvas in `script::LED_MAP`, that has just
+/// been realized by the Lua VMs of the new profile. Returns false if the frame has to be dropped
+pub fn render() -> bool {
+    // the lock must not be held while we wait for the Lua VMs, since the main loop
+    // needs it to send timer tick events
+    let (start, kind, progress, thresholds, lua_txs) = match ACTIVE_TRANSITION.lock().as_ref() {
+        Some(transition) => (
+            transition.start,
+            transition.transition.kind,
+            transition.progress(),
+            transition.thresholds.clone(),
+            transition.lua_txs.clone(),
+        ),
+
+        None => return true,
+    };
+
+    let incoming = script::LED_MAP.read().clone();
+
+    let mut failed = Vec::new();
+    let outgoing = realize_color_maps(&lua_txs, &mut failed);
+
+    for lua_tx in lua_txs.iter().filter(|tx| failed.contains(&tx.vm_id)) {
+        warn!(
+            "Dropping the Lua VM of the previous profile: {}",
+            lua_tx.script_file.display()
+        );
+
+        drop_lua_vm(lua_tx.vm_id);
+        unload(std::slice::from_ref(lua_tx));
+    }
+
+    let outgoing = match outgoing {
+        Some(outgoing) => outgoing,
+        None => return false,
+    };
+
+    blend(
+        kind,
+        progress,
+        &thresholds,
+        &outgoing,
+        &incoming,
+        &mut script::LED_MAP.write(),
+    );
+
+    if progress >= 1.0 {
+        let mut active_transition = ACTIVE_TRANSITION.lock();
+
+        // the transition may have been replaced while we were waiting for the Lua VMs
+        if matches!(active_transition.as_ref(), Some(transition) if transition.start == start) {
+            debug!("Profile transition completed");
+
+            if let Some(transition) = active_transition.take() {
+                unload(&transition.lua_txs);
+            }
+        }
+    }
+
+    true
+}
+
+/// Instruct the Lua VMs of the previous profile to realize their color maps on a clear canvas.
+/// Lua VMs that could not be reached or did not respond in time are added to `failed`
+fn realize_color_maps(lua_txs: &[LuaTx], failed: &mut Vec<usize>) -> Option<Vec<RGBA>> {
+    script::LED_MAP.write().fill(RGBA {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 0,
+    });
+
+    *COLOR_MAPS_READY_CONDITION.0.lock() = lua_txs.iter().filter(|tx| !tx.is_failed).count();
+
+    for lua_tx in lua_txs.iter().filter(|tx| !tx.is_failed) {
+        // wait for the current Lua VM to complete its blending code, before continuing
+        let mut pending = COLOR_MAPS_READY_CONDITION.0.lock();
+
+        if let Err(e) = lua_tx.send(script::Message::RealizeColorMap) {
+            error!("Send error during realization of color maps: {}", e);
+
+            *pending = pending.saturating_sub(1);
+            failed.push(lua_tx.vm_id);
+
+            continue;
+        }
+
+        let result = COLOR_MAPS_READY_CONDITION.1.wait_for(
+            &mut pending,
+            Duration::from_millis(constants::TIMEOUT_CONDITION_MILLIS),
+        );
+
+        if result.timed_out() {
+            warn!("Frame dropped: Timeout while waiting for a lock!");
+
+            failed.push(lua_tx.vm_id);
+
+            return None;
+        }
+    }
+
+    let canvas = script::LED_MAP.read().clone();
+
+    Some(canvas)
+}
+
+/// Request termination of the Lua VMs
+fn unload(lua_txs: &[LuaTx]) {
+    for lua_tx in lua_txs.iter() {
+        if !lua_tx.is_failed {
+            lua_tx
+                .send(script::Message::Unload)
+                .unwrap_or_else(|e| error!("Could not send an event to a Lua VM: {}", e));
+        } else {
+            warn!("Skipping unload of a failed tx");
+        }
+    }
+}
+
+/// Blend the canvases of the previous and of the new profile into `canvas`. The
+/// `progress` of the transition lies in the range [0..1]
+fn blend(
+    kind: TransitionKind,
+    progress: f32,
+    thresholds: &[f32],
+    outgoing: &[RGBA],
+    incoming: &[RGBA],
+    canvas: &mut [RGBA],
+) {
+    for (idx, color) in canvas.iter_mut().enumerate() {
+        let factor = match kind {
+            TransitionKind::None => 1.0,
+
+            TransitionKind::CrossFade => progress,
+
+            TransitionKind::Wipe => {
+                let column = (idx % constants::CANVAS_WIDTH) as f32;
+                let edge = progress * (constants::CANVAS_WIDTH as f32 + WIPE_EDGE_WIDTH);
+
+                ((edge - column) / WIPE_EDGE_WIDTH).clamp(0.0, 1.0)
+            }
+
+            TransitionKind::Dissolve => {
+                ((progress - thresholds[idx]) / DISSOLVE_SOFTNESS).clamp(0.0, 1.0)
+            }
+        };
+
+        *color = mix(outgoing[idx], incoming[idx], factor);
+    }
+}
+
+fn mix(from: RGBA, to: RGBA, factor: f32) -> RGBA {
+    let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * factor).round() as u8;
+
+    RGBA {
+        r: lerp(from.r, to.r),
+        g: lerp(from.g, to.g),
+        b: lerp(from.b, to.b),
+        a: lerp(from.a, to.a),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{blend, TransitionKind, DISSOLVE_SOFTNESS};
+    use crate::constants;
+    use crate::hwdevices::RGBA;
+
+    fn canvas(value: u8) -> Vec<RGBA> {
+        vec![
+            RGBA {
+                r: value,
+                g: value,
+                b: value,
+                a: 255,
+            };
+            constants::CANVAS_SIZE
+        ]
+    }
+
+    fn render(kind: TransitionKind, progress: f32, thresholds: &[f32]) -> Vec<u8> {
+        let mut result = canvas(0);
+        blend(
+            kind,
+            progress,
+            thresholds,
+            &canvas(0),
+            &canvas(200),
+            &mut result,
+        );
+
+        result.iter().map(|c| c.r).collect()
+    }
+
+    #[test]
+    fn parse_transition() {
+        assert_eq!(
+            "cross-fade".parse::<TransitionKind>().unwrap(),
+            TransitionKind::CrossFade
+        );
+        assert_eq!(
+            "Dissolve".parse::<TransitionKind>().unwrap(),
+            TransitionKind::Dissolve
+        );
+        assert!("fade-to-black".parse::<TransitionKind>().is_err());
+    }
+
+    #[test]
+    fn transitions_start_and_end_on_the_canvases() {
+        let thresholds: Vec<f32> = (0..constants::CANVAS_SIZE)
+            .map(|idx| idx as f32 / constants::CANVAS_SIZE as f32 * (1.0 - DISSOLVE_SOFTNESS))
+            .collect();
+
+        for kind in [
+            TransitionKind::CrossFade,
+            TransitionKind::Wipe,
+            TransitionKind::Dissolve,
+        ] {
+            assert!(render(kind, 0.0, &thresholds).iter().all(|&v| v == 0));
+            assert!(render(kind, 1.0, &thresholds).iter().all(|&v| v == 200));
+        }
+    }
+
+    #[test]
+    fn cross_fade() {
+        let result = render(TransitionKind::CrossFade, 0.5, &[]);
+
+        assert!(result.iter().all(|&v| v == 100));
+    }
+
+    #[test]
+    fn wipe_from_left_to_right() {
+        let result = render(TransitionKind::Wipe, 0.5, &[]);
+
+        // the left half shows the new profile, the right half the previous profile
+        assert_eq!(result[0], 200);
+        assert_eq!(result[constants::CANVAS_WIDTH - 1], 0);
+
+        // the edge moves across all rows at the same time
+        assert_eq!(result[0], result[constants::CANVAS_WIDTH]);
+
+        for row in result.chunks(constants::CANVAS_WIDTH) {
+            assert!(row.windows(2).all(|w| w[0] >= w[1]));
+        }
+    }
+}
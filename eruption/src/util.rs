@@ -128,6 +128,9 @@ pub fn get_script_dirs() -> Vec<PathBuf> {
         result.push(path);
     }
 
+    // scripts that have been imported from bundles take precedence
+    result.insert(0, PathBuf::from(constants::IMPORTED_SCRIPT_DIR));
+
     result
 }
 
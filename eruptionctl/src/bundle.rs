@@ -0,0 +1 @@
+../../eruption/src/bundle.rs
\ No newline at end of file
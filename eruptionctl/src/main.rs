@@ -41,12 +41,16 @@ use profiles::GetAttr as GetAttrProfile;
 use rust_embed::RustEmbed;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 use std::{env, fs, thread};
 use std::{process, sync::Arc};
 
 use crate::color_scheme::{ColorScheme, PywalColorScheme};
 
+mod bundle;
 mod color_scheme;
 mod constants;
 mod dbus_client;
@@ -308,6 +312,15 @@ pub enum StatusSubcommands {
 
     /// Shows the currently active slot
     Slot,
+
+    /// Shows the currently active keymap
+    Keymap,
+
+    /// Shows the keys and mouse buttons that are held down by macros
+    HeldKeys,
+
+    /// Shows the state of the connection to the audio proxy
+    AudioProxy,
 }
 
 /// Sub-commands of the "switch" command
@@ -331,6 +344,21 @@ pub enum ProfilesSubcommands {
 
     /// List available profiles
     List,
+
+    /// Export a profile along with the scripts, keymaps and color schemes it uses to a bundle
+    Export {
+        profile_name: String,
+        bundle_file: PathBuf,
+    },
+
+    /// Import a profile bundle
+    Import {
+        bundle_file: PathBuf,
+
+        /// Replace conflicting profiles, scripts and color schemes
+        #[clap(short, long)]
+        overwrite: bool,
+    },
 }
 
 /// Subcommands of the "names" command
@@ -419,6 +447,37 @@ pub async fn get_active_profile() -> Result<String> {
     Ok(result)
 }
 
+/// Get the file name of the currently active keymap, along with the reason why it is active
+pub async fn get_active_keymap() -> Result<(String, String)> {
+    let (keymap, selector): (String, String) = dbus_system_bus("/org/eruption/keymap")
+        .await?
+        .method_call("org.eruption.Keymap", "GetActiveKeymap", ())
+        .await?;
+
+    Ok((keymap, selector))
+}
+
+/// Get the key codes and mouse buttons that are held down by injections of macros
+pub async fn get_held_keys() -> Result<(Vec<u32>, Vec<u32>)> {
+    let (keys, buttons): (Vec<u32>, Vec<u32>) = dbus_system_bus("/org/eruption/keymap")
+        .await?
+        .method_call("org.eruption.Keymap", "GetHeldKeys", ())
+        .await?;
+
+    Ok((keys, buttons))
+}
+
+/// Get the state of the connection to the audio proxy, along with its frame counters
+pub async fn get_audio_proxy_status() -> Result<(bool, u32, HashMap<String, u64>)> {
+    let (connected, protocol_version, counters): (bool, u32, HashMap<String, u64>) =
+        dbus_system_bus("/org/eruption/status")
+            .await?
+            .method_call("org.eruption.Status", "GetAudioProxyStatus", ())
+            .await?;
+
+    Ok((connected, protocol_version, counters))
+}
+
 /// Switch the currently active slot
 pub async fn switch_slot(index: usize) -> Result<()> {
     let (_result,): (bool,) = dbus_system_bus("/org/eruption/slot")
@@ -471,6 +530,16 @@ pub async fn get_profiles() -> Result<Vec<(String, String)>> {
     Ok(result)
 }
 
+/// Import a profile bundle, returns the file name of the installed profile
+pub async fn import_bundle(bundle: Vec<u8>, overwrite: bool) -> Result<String> {
+    let (result,): (String,) = dbus_system_bus("/org/eruption/profile")
+        .await?
+        .method_call("org.eruption.Profile", "ImportBundle", (bundle, overwrite))
+        .await?;
+
+    Ok(result)
+}
+
 /// Enumerate all available devices
 pub async fn get_devices() -> Result<(Vec<(u16, u16)>, Vec<(u16, u16)>, Vec<(u16, u16)>)> {
     let ((keyboards, mice, misc),): ((Vec<(u16, u16)>, Vec<(u16, u16)>, Vec<(u16, u16)>),) =
@@ -1208,6 +1277,73 @@ pub async fn async_main() -> std::result::Result<(), eyre::Error> {
                 }
             }
 
+            ProfilesSubcommands::Export {
+                profile_name,
+                bundle_file,
+            } => {
+                let profile_file = if Path::new(&profile_name).is_file() {
+                    PathBuf::from(&profile_name)
+                } else {
+                    util::match_profile_path(&profile_name)?
+                };
+
+                let locations = bundle::Locations {
+                    profile_dirs: util::get_profile_dirs(),
+                    script_dirs: util::get_script_dirs(),
+                    keymap_dirs: vec![
+                        PathBuf::from(constants::IMPORTED_KEYMAP_DIR),
+                        PathBuf::from(constants::DEFAULT_KEYMAP_DIR),
+                    ],
+                    import_dir: PathBuf::from(constants::IMPORT_DIR),
+                };
+
+                // the color schemes are persisted by the daemon
+                let color_schemes: HashMap<String, ColorScheme> = fs::read_to_string(
+                    PathBuf::from(constants::STATE_DIR).join("color-schemes.state"),
+                )
+                .ok()
+                .and_then(|data| toml::from_str(&data).ok())
+                .unwrap_or_default();
+
+                let manifest =
+                    bundle::export(&profile_file, &locations, &color_schemes, &bundle_file)
+                        .wrap_err("Could not export the profile")?;
+
+                println!(
+                    "Exported {} ({}) to {}",
+                    manifest.profile_name.bold(),
+                    manifest.profile_id,
+                    bundle_file.display()
+                );
+
+                for entry in manifest.files.iter() {
+                    println!("    {}", entry.path);
+                }
+            }
+
+            ProfilesSubcommands::Import {
+                bundle_file,
+                overwrite,
+            } => {
+                // the daemon is sent the contents of the bundle, it does not open files on our behalf
+                let bundle = fs::read(&bundle_file)
+                    .wrap_err_with(|| format!("Could not open {}", bundle_file.display()))?;
+
+                // verify the bundle before sending it off to the daemon
+                let (manifest, _files) = bundle::parse(&bundle)?;
+
+                let profile_file = import_bundle(bundle, overwrite)
+                    .await
+                    .wrap_err("Could not import the bundle")?;
+
+                println!(
+                    "Imported {} ({}) to {}",
+                    manifest.profile_name.bold(),
+                    manifest.profile_id,
+                    profile_file
+                );
+            }
+
             ProfilesSubcommands::Info { profile_name } => {
                 let profiles = util::enumerate_profiles().unwrap_or_else(|_| vec![]);
 
@@ -1218,13 +1354,47 @@ pub async fn async_main() -> std::result::Result<(), eyre::Error> {
                     .find(|p| *p.profile_file.to_string_lossy() == profile_name)
                 {
                     println!(
-                        "Profile:\t{} ({})\nDescription:\t{}\nScripts:\t{:?}\n\n{:#?}",
-                        profile.name,
-                        profile.id,
-                        profile.description,
-                        profile.active_scripts,
-                        profile.config.as_ref().unwrap_or(&empty),
+                        "Profile:\t{} ({})\nDescription:\t{}\nScripts:\t{:?}",
+                        profile.name, profile.id, profile.description, profile.active_scripts,
                     );
+
+                    if let Some(extends) = &profile.extends {
+                        println!("Extends:\t{}", extends);
+                    }
+
+                    if !profile.include.is_empty() {
+                        println!("Includes:\t{:?}", profile.include);
+                    }
+
+                    // print the effective parameters, along with the file that defined them
+                    let config = profile.config.as_ref().unwrap_or(&empty);
+
+                    let mut scripts = config.keys().collect::<Vec<_>>();
+                    scripts.sort();
+
+                    for script in scripts {
+                        println!("\n{}", script.bold());
+
+                        for param in config[script].iter() {
+                            let origin = profile
+                                .origins
+                                .get(script)
+                                .and_then(|origins| origins.get(param.get_name()));
+
+                            match origin {
+                                Some(origin) => println!(
+                                    "    {} = {} ({})",
+                                    param.get_name(),
+                                    param.get_value(),
+                                    origin.display()
+                                ),
+
+                                None => {
+                                    println!("    {} = {}", param.get_name(), param.get_value())
+                                }
+                            }
+                        }
+                    }
                 } else {
                     eprintln!("No matches found");
                 }
@@ -1555,6 +1725,69 @@ pub async fn async_main() -> std::result::Result<(), eyre::Error> {
                     + 1;
                 println!("Current slot: {}", format!("{}", index).bold());
             }
+
+            StatusSubcommands::Keymap => {
+                let (keymap, selector) = get_active_keymap()
+                    .await
+                    .wrap_err("Could not connect to the Eruption daemon")
+                    .suggestion("Please verify that the Eruption daemon is running")?;
+
+                if keymap.is_empty() {
+                    println!("Current keymap: {}", "none".bold());
+                } else {
+                    println!(
+                        "Current keymap: {} (selected by: {})",
+                        keymap.bold(),
+                        selector
+                    );
+                }
+            }
+
+            StatusSubcommands::HeldKeys => {
+                let (keys, buttons) = get_held_keys()
+                    .await
+                    .wrap_err("Could not connect to the Eruption daemon")
+                    .suggestion("Please verify that the Eruption daemon is running")?;
+
+                let format = |values: Vec<u32>| {
+                    if values.is_empty() {
+                        "none".to_string()
+                    } else {
+                        values
+                            .iter()
+                            .map(|v| v.to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    }
+                };
+
+                println!("Held keys: {}", format(keys).bold());
+                println!("Held mouse buttons: {}", format(buttons).bold());
+            }
+
+            StatusSubcommands::AudioProxy => {
+                let (connected, protocol_version, counters) = get_audio_proxy_status()
+                    .await
+                    .wrap_err("Could not connect to the Eruption daemon")
+                    .suggestion("Please verify that the Eruption daemon is running")?;
+
+                if connected {
+                    println!(
+                        "Audio proxy: {} (protocol version {})",
+                        "connected".bold(),
+                        protocol_version
+                    );
+                } else {
+                    println!("Audio proxy: {}", "disconnected".bold());
+                }
+
+                let mut counters = counters.into_iter().collect::<Vec<_>>();
+                counters.sort();
+
+                for (name, value) in counters {
+                    println!("{}: {}", name, format!("{}", value).bold());
+                }
+            }
         },
 
         // convenience operations: switch profile or slot
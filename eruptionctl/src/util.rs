@@ -61,6 +61,9 @@ pub fn get_profile_dirs() -> Vec<PathBuf> {
         result.push(path);
     }
 
+    // profiles that have been imported from bundles take precedence
+    result.insert(0, PathBuf::from(constants::IMPORTED_PROFILE_DIR));
+
     result
 }
 
@@ -90,6 +93,9 @@ pub fn get_script_dirs() -> Vec<PathBuf> {
         result.push(path);
     }
 
+    // scripts that have been imported from bundles take precedence
+    result.insert(0, PathBuf::from(constants::IMPORTED_SCRIPT_DIR));
+
     result
 }
 
@@ -0,0 +1,86 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+use eruption_rs::audio::AudioSubscription;
+use eruption_rs::canvas::Canvas;
+use eruption_rs::color::Color;
+use eruption_rs::connection::{Connection, ConnectionType};
+use std::time::{Duration, Instant};
+
+const EXAMPLE_NAME: &str = "Audio Rust Example #1";
+
+/// Run the example for n seconds
+const RUN_TIME_SECS: u64 = 30;
+
+fn main() -> Result<(), eyre::Error> {
+    println!(
+        "Welcome to the Eruption SDK!\nYou are running the \"{}\" \
+        from the Eruption SDK version {}\n",
+        EXAMPLE_NAME,
+        eruption_rs::SDK_VERSION
+    );
+
+    println!("Connecting to the Eruption daemon...");
+    let connection = Connection::new(ConnectionType::Local)?;
+
+    connection.connect()?;
+    println!("Successfully connected to the Eruption daemon");
+
+    // receive 16 spectrum bands, 30 times per second
+    connection.subscribe_audio(&AudioSubscription {
+        bands: 16,
+        update_rate: 30,
+    })?;
+
+    let mut canvas = Canvas::new();
+    let start = Instant::now();
+
+    while start.elapsed() < Duration::from_secs(RUN_TIME_SECS) {
+        if let Some(audio_data) = connection.receive_audio_data(Duration::from_millis(500))? {
+            // flash white on each beat, otherwise map bass and treble to red and blue
+            let color = if audio_data.beats > 0 {
+                Color::new(255, 255, 255, 255)
+            } else {
+                Color::new(
+                    (audio_data.bass.clamp(0.0, 1.0) * 255.0) as u8,
+                    0,
+                    (audio_data.treble.clamp(0.0, 1.0) * 255.0) as u8,
+                    (audio_data.loudness.clamp(0.0, 1.0) * 255.0) as u8,
+                )
+            };
+
+            canvas.fill(color);
+            connection.submit_canvas(&canvas)?;
+
+            println!(
+                "Loudness: {:.2} BPM: {:.1} Spectrum: {:.2?}",
+                audio_data.loudness, audio_data.bpm, audio_data.spectrum
+            );
+        }
+    }
+
+    connection.unsubscribe_audio()?;
+
+    canvas.fill(Color::new(0, 0, 0, 0));
+    connection.submit_canvas(&canvas)?;
+
+    println!("Exiting now");
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+    Copyright (c) 2019-2022, The Eruption Development Team
+*/
+
+use bincode::{Decode, Encode};
+
+/// Parameters of a subscription to the stream of audio analysis results
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct AudioSubscription {
+    /// Number of bands of the spectrum, in the range [1..256]
+    pub bands: u32,
+
+    /// Number of updates per second, in the range [1..60]
+    pub update_rate: u32,
+}
+
+impl Default for AudioSubscription {
+    fn default() -> Self {
+        Self {
+            bands: 32,
+            update_rate: 30,
+        }
+    }
+}
+
+/// Audio analysis results, as published by the Eruption daemon
+#[derive(Debug, Default, Clone, Encode, Decode)]
+pub struct AudioData {
+    /// Loudness (RMS) of the signal, in the range [0..1]
+    pub loudness: f32,
+
+    /// Perceptual spectrum with logarithmically spaced bands, each in the range [0..1]
+    pub spectrum: Vec<f32>,
+
+    /// Energy of the bass, mid and treble bands
+    pub bass: f32,
+    pub mid: f32,
+    pub treble: f32,
+
+    /// The estimated tempo, or 0 if the tempo is not known yet
+    pub bpm: f32,
+
+    /// Position within the current beat, in the range [0..1)
+    pub beat_phase: f32,
+
+    /// Number of beats that have been detected since the previous update
+    pub beats: u32,
+}
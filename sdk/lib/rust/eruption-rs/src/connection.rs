@@ -17,12 +17,14 @@
     Copyright (c) 2019-2022, The Eruption Development Team
 */
 
+use crate::audio::{AudioData, AudioSubscription};
 use crate::canvas::Canvas;
 use crate::hardware::HotplugInfo;
 use crate::transport::{LocalTransport, ServerStatus, Transport};
 use crate::Result;
 use parking_lot::Mutex;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Clone)]
 pub struct Connection {
@@ -55,6 +57,20 @@ impl Connection {
     pub fn notify_device_hotplug(&self, hotplug_info: &HotplugInfo) -> Result<()> {
         self.con.lock().notify_device_hotplug(hotplug_info)
     }
+
+    /// Subscribe to the stream of audio analysis results of the Eruption daemon
+    pub fn subscribe_audio(&self, subscription: &AudioSubscription) -> Result<()> {
+        self.con.lock().subscribe_audio(subscription)
+    }
+
+    pub fn unsubscribe_audio(&self) -> Result<()> {
+        self.con.lock().unsubscribe_audio()
+    }
+
+    /// Wait for the next audio analysis update, returns `None` if the timeout elapsed
+    pub fn receive_audio_data(&self, timeout: Duration) -> Result<Option<AudioData>> {
+        self.con.lock().receive_audio_data(timeout)
+    }
 }
 
 impl Drop for Connection {
@@ -17,6 +17,7 @@
     Copyright (c) 2019-2022, The Eruption Development Team
 */
 
+pub mod audio;
 pub mod canvas;
 pub mod color;
 pub mod connection;
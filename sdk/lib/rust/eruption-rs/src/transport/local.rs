@@ -17,6 +17,7 @@
     Copyright (c) 2019-2022, The Eruption Development Team
 */
 
+use crate::audio::{AudioData, AudioSubscription};
 use crate::canvas::Canvas;
 use crate::hardware::HotplugInfo;
 use crate::transport::{ServerStatus, Transport};
@@ -27,9 +28,11 @@ use prost::Message;
 use protocol::request::Payload as RequestPayload;
 use protocol::response::Payload as ResponsePayload;
 use socket2::{Domain, SockAddr, Socket, Type};
-use std::io::{Cursor, Write};
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Write};
 use std::mem::MaybeUninit;
 use std::sync::Arc;
+use std::time::Duration;
 
 pub mod protocol {
     include!(concat!(env!("OUT_DIR"), "/sdk_support.rs"));
@@ -38,9 +41,15 @@ pub mod protocol {
 const SOCKET_ADDRESS: &str = "/run/eruption/control.sock";
 const MAX_BUF: usize = 4096;
 
+/// Maximum number of audio analysis updates that are queued while waiting for a response
+const MAX_PENDING_AUDIO_DATA: usize = 64;
+
 #[derive(Debug, Clone)]
 pub struct LocalTransport {
     pub(crate) socket: Arc<Mutex<Socket>>,
+
+    /// Audio analysis updates that arrived while waiting for the response to a request
+    pending_audio_data: Arc<Mutex<VecDeque<AudioData>>>,
 }
 
 impl LocalTransport {
@@ -51,8 +60,60 @@ impl LocalTransport {
                 Type::SEQPACKET,
                 None,
             )?)),
+            pending_audio_data: Arc::new(Mutex::new(VecDeque::new())),
         })
     }
+
+    /// Receive the next packet from the Eruption daemon
+    fn receive_packet(socket: &Socket) -> Result<protocol::Response> {
+        let mut tmp = [MaybeUninit::zeroed(); MAX_BUF];
+
+        match socket.recv(&mut tmp) {
+            Ok(0) => Err(eyre!("Lost connection to Eruption")),
+
+            Ok(n) => {
+                let tmp = unsafe { util::assume_init(&tmp[..n]) };
+                let result = protocol::Response::decode_length_delimited(tmp)?;
+
+                Ok(result)
+            }
+
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Receive the response to a request; audio analysis updates that are received
+    /// in the meantime are queued, so that they can be picked up by `receive_audio_data`
+    fn receive_response(&self, socket: &Socket) -> Result<protocol::Response> {
+        loop {
+            let response =
+                Self::receive_packet(socket).map_err(|_e| eyre!("Lost connection to Eruption"))?;
+
+            if response.response_type() == protocol::RequestType::AudioData {
+                let audio_data = Self::decode_audio_data(response)?;
+
+                let mut pending_audio_data = self.pending_audio_data.lock();
+                if pending_audio_data.len() >= MAX_PENDING_AUDIO_DATA {
+                    pending_audio_data.pop_front();
+                }
+
+                pending_audio_data.push_back(audio_data);
+            } else {
+                return Ok(response);
+            }
+        }
+    }
+
+    fn decode_audio_data(response: protocol::Response) -> Result<AudioData> {
+        let ResponsePayload::Data(payload) = response
+            .payload
+            .ok_or_else(|| eyre!("Invalid audio data received"))?;
+
+        let config = bincode::config::standard();
+        let audio_data: AudioData = bincode::decode_from_slice(&payload, config)?.0;
+
+        Ok(audio_data)
+    }
 }
 
 impl Transport for LocalTransport {
@@ -81,25 +142,12 @@ impl Transport for LocalTransport {
         let socket = self.socket.lock();
         match socket.send(&buf) {
             Ok(_n) => {
-                // read response
-                let mut tmp = [MaybeUninit::zeroed(); MAX_BUF];
-
-                match socket.recv(&mut tmp) {
-                    Ok(0) => Err(eyre!("Lost connection to Eruption")),
-
-                    Ok(_n) => {
-                        let tmp = unsafe { util::assume_init(&tmp[..tmp.len()]) };
-                        let result =
-                            protocol::Response::decode_length_delimited(&mut Cursor::new(&tmp))?;
-                        let ResponsePayload::Data(payload) = result.payload.unwrap();
+                let result = self.receive_response(&socket)?;
+                let ResponsePayload::Data(payload) = result.payload.unwrap();
 
-                        Ok(ServerStatus {
-                            server: String::from_utf8_lossy(&payload).to_string(),
-                        })
-                    }
-
-                    Err(_e) => Err(eyre!("Lost connection to Eruption")),
-                }
+                Ok(ServerStatus {
+                    server: String::from_utf8_lossy(&payload).to_string(),
+                })
             }
 
             Err(_e) => Err(eyre!("Lost connection to Eruption")),
@@ -125,22 +173,9 @@ impl Transport for LocalTransport {
         let socket = self.socket.lock();
         match socket.send(&buf) {
             Ok(_n) => {
-                // read response
-                let mut tmp = [MaybeUninit::zeroed(); MAX_BUF];
-
-                match socket.recv(&mut tmp) {
-                    Ok(0) => Err(eyre!("Lost connection to Eruption")),
-
-                    Ok(_n) => {
-                        let tmp = unsafe { util::assume_init(&tmp[..tmp.len()]) };
-                        let _result =
-                            protocol::Response::decode_length_delimited(&mut Cursor::new(&tmp))?;
-
-                        Ok(())
-                    }
+                let _result = self.receive_response(&socket)?;
 
-                    Err(_e) => Err(eyre!("Lost connection to Eruption")),
-                }
+                Ok(())
             }
 
             Err(_e) => Err(eyre!("Lost connection to Eruption")),
@@ -163,27 +198,89 @@ impl Transport for LocalTransport {
         let socket = self.socket.lock();
         match socket.send(&buf) {
             Ok(_n) => {
-                // read response
-                let mut tmp = [MaybeUninit::zeroed(); MAX_BUF];
+                let _result = self.receive_response(&socket)?;
 
-                match socket.recv(&mut tmp) {
-                    Ok(0) => Err(eyre!("Lost connection to Eruption")),
+                Ok(())
+            }
 
-                    Ok(_n) => {
-                        let tmp = unsafe { util::assume_init(&tmp[..tmp.len()]) };
-                        let _result =
-                            protocol::Response::decode_length_delimited(&mut Cursor::new(&tmp))?;
+            Err(_e) => Err(eyre!("Lost connection to Eruption")),
+        }
+    }
 
-                        Ok(())
-                    }
+    fn subscribe_audio(&self, subscription: &AudioSubscription) -> Result<()> {
+        let mut request = protocol::Request::default();
+        request.set_request_type(protocol::RequestType::SubscribeAudio);
 
-                    Err(_e) => Err(eyre!("Lost connection to Eruption")),
-                }
+        let config = bincode::config::standard();
+        let bytes: Vec<u8> = bincode::encode_to_vec(subscription, config)?;
+
+        request.payload = Some(RequestPayload::Data(bytes));
+
+        let mut buf = Vec::new();
+        request.encode_length_delimited(&mut buf)?;
+
+        // send data
+        let socket = self.socket.lock();
+        match socket.send(&buf) {
+            Ok(_n) => {
+                let _result = self.receive_response(&socket)?;
+
+                Ok(())
             }
 
             Err(_e) => Err(eyre!("Lost connection to Eruption")),
         }
     }
+
+    fn unsubscribe_audio(&self) -> Result<()> {
+        let mut request = protocol::Request::default();
+        request.set_request_type(protocol::RequestType::UnsubscribeAudio);
+
+        let mut buf = Vec::new();
+        request.encode_length_delimited(&mut buf)?;
+
+        // send data
+        let socket = self.socket.lock();
+        match socket.send(&buf) {
+            Ok(_n) => {
+                let _result = self.receive_response(&socket)?;
+
+                // discard updates that have been sent before the subscription ended
+                self.pending_audio_data.lock().clear();
+
+                Ok(())
+            }
+
+            Err(_e) => Err(eyre!("Lost connection to Eruption")),
+        }
+    }
+
+    fn receive_audio_data(&self, timeout: Duration) -> Result<Option<AudioData>> {
+        if let Some(audio_data) = self.pending_audio_data.lock().pop_front() {
+            return Ok(Some(audio_data));
+        }
+
+        let socket = self.socket.lock();
+
+        socket.set_read_timeout(Some(timeout))?;
+        let result = Self::receive_packet(&socket);
+        socket.set_read_timeout(None)?;
+
+        match result {
+            Ok(response) if response.response_type() == protocol::RequestType::AudioData => {
+                Ok(Some(Self::decode_audio_data(response)?))
+            }
+
+            // a stale response, e.g. to a request that timed out; ignore it
+            Ok(_response) => Ok(None),
+
+            Err(e) => match e.downcast_ref::<std::io::Error>().map(|e| e.kind()) {
+                Some(ErrorKind::WouldBlock) | Some(ErrorKind::TimedOut) => Ok(None),
+
+                _ => Err(eyre!("Lost connection to Eruption")),
+            },
+        }
+    }
 }
 
 impl Drop for LocalTransport {
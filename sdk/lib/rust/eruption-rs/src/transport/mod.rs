@@ -17,9 +17,11 @@
     Copyright (c) 2019-2022, The Eruption Development Team
 */
 
+use crate::audio::{AudioData, AudioSubscription};
 use crate::canvas::Canvas;
 use crate::hardware::HotplugInfo;
 use crate::Result;
+use std::time::Duration;
 
 mod local;
 pub use local::*;
@@ -32,6 +34,11 @@ pub trait Transport {
     fn submit_canvas(&self, canvas: &Canvas) -> Result<()>;
 
     fn notify_device_hotplug(&self, hotplug_info: &HotplugInfo) -> Result<()>;
+
+    fn subscribe_audio(&self, subscription: &AudioSubscription) -> Result<()>;
+    fn unsubscribe_audio(&self) -> Result<()>;
+
+    fn receive_audio_data(&self, timeout: Duration) -> Result<Option<AudioData>>;
 }
 
 #[derive(Debug, Default, Clone)]
@@ -81,6 +81,7 @@ cargo build --all --release --verbose --features=sourceview
 %{__mkdir_p} %{buildroot}/%{_docdir}/%{ShortName}
 %{__mkdir_p} %{buildroot}/%{_datarootdir}/icons/hicolor/scalable/apps
 %{__mkdir_p} %{buildroot}/%{_datarootdir}/%{ShortName}/sfx
+%{__mkdir_p} %{buildroot}/%{_datarootdir}/%{ShortName}/sfx/default
 %{__mkdir_p} %{buildroot}/%{_datarootdir}/%{ShortName}/i18n
 %{__mkdir_p} %{buildroot}/%{_datarootdir}/applications/
 %{__mkdir_p} %{buildroot}/%{_datarootdir}/icons/hicolor/64x64/apps/
@@ -94,6 +95,7 @@ cp -a %{_builddir}/%{name}-releases-v%{version}/support/man/eruption-cmd.8 %{bui
 cp -a %{_builddir}/%{name}-releases-v%{version}/support/man/eruption-hwutil.8 %{buildroot}/%{_mandir}/man8/
 cp -a %{_builddir}/%{name}-releases-v%{version}/support/man/eruption.conf.5 %{buildroot}/%{_mandir}/man5/
 cp -a %{_builddir}/%{name}-releases-v%{version}/support/man/process-monitor.conf.5 %{buildroot}/%{_mandir}/man5/
+cp -a %{_builddir}/%{name}-releases-v%{version}/support/man/audio-proxy.conf.5 %{buildroot}/%{_mandir}/man5/
 cp -a %{_builddir}/%{name}-releases-v%{version}/support/man/eruptionctl.1 %{buildroot}/%{_mandir}/man1/
 cp -a %{_builddir}/%{name}-releases-v%{version}/support/man/eruption-keymap.1 %{buildroot}/%{_mandir}/man1/
 cp -a %{_builddir}/%{name}-releases-v%{version}/support/man/eruption-netfx.1 %{buildroot}/%{_mandir}/man1/
@@ -207,8 +209,10 @@ cp -a %{_builddir}/%{name}-releases-v%{version}/support/profiles/turbulence.prof
 cp -a %{_builddir}/%{name}-releases-v%{version}/support/sfx/typewriter1.wav %{buildroot}/%{_datarootdir}/%{ShortName}/sfx/typewriter1.wav
 cp -a %{_builddir}/%{name}-releases-v%{version}/support/sfx/phaser1.wav %{buildroot}/%{_datarootdir}/%{ShortName}/sfx/phaser1.wav
 cp -a %{_builddir}/%{name}-releases-v%{version}/support/sfx/phaser2.wav %{buildroot}/%{_datarootdir}/%{ShortName}/sfx/phaser2.wav
+cp -a %{_builddir}/%{name}-releases-v%{version}/support/sfx/default/manifest.toml %{buildroot}/%{_datarootdir}/%{ShortName}/sfx/default/manifest.toml
 ln -s phaser1.wav %{buildroot}/%{_datarootdir}/%{ShortName}/sfx/key-down.wav
 ln -s phaser2.wav %{buildroot}/%{_datarootdir}/%{ShortName}/sfx/key-up.wav
+%{_datarootdir}/%{ShortName}/sfx/default/manifest.toml
 
 cp -ra %{_builddir}/%{name}-releases-v%{version}/eruption/src/scripts %{buildroot}/%{_datarootdir}/%{ShortName}/
 
@@ -257,6 +261,7 @@ install -Dp -m 0755 %{_builddir}/%{name}-releases-v%{version}/target/release/eru
 %doc %{_mandir}/man1/eruption-audio-proxy.1.gz
 %doc %{_mandir}/man1/eruption-process-monitor.1.gz
 %doc %{_mandir}/man5/process-monitor.conf.5.gz
+%doc %{_mandir}/man5/audio-proxy.conf.5.gz
 %dir %{_datarootdir}/icons/hicolor/scalable/apps/
 %config(noreplace) %{_sysconfdir}/%{ShortName}/%{ShortName}.conf
 %config(noreplace) %{_sysconfdir}/%{ShortName}/audio-proxy.conf